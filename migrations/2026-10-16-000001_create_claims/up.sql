@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE claims (
+  user_id VARCHAR NOT NULL PRIMARY KEY,
+  token VARCHAR NOT NULL,
+  created_at BIGINT NOT NULL
+);
+
+CREATE TABLE owner_keys (
+  key_hash VARCHAR NOT NULL PRIMARY KEY,
+  user_id VARCHAR NOT NULL,
+  created_at BIGINT NOT NULL
+);
+
+CREATE INDEX owner_keys_user_id ON owner_keys (user_id);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE daily_views;
+DROP TABLE visitor_settings
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE visitor_settings (
+  user_id VARCHAR NOT NULL PRIMARY KEY,
+  timezone VARCHAR NOT NULL DEFAULT 'UTC'
+);
+
+CREATE TABLE daily_views (
+  user_id VARCHAR NOT NULL,
+  day VARCHAR NOT NULL,
+  view_count INTEGER NOT NULL DEFAULT 0,
+  PRIMARY KEY (user_id, day)
+);
+
+CREATE INDEX daily_views_day ON daily_views (day);
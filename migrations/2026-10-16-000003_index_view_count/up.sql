@@ -0,0 +1,2 @@
+-- Your SQL goes here
+CREATE INDEX visitors_view_count ON visitors (view_count);
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE recent_views (
+  user_id VARCHAR NOT NULL,
+  fingerprint VARCHAR NOT NULL,
+  last_seen BIGINT NOT NULL,
+  PRIMARY KEY (user_id, fingerprint)
+);
+
+CREATE INDEX recent_views_last_seen ON recent_views (last_seen);
@@ -0,0 +1,14 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE visitors_old (
+  id VARCHAR NOT NULL PRIMARY KEY,
+  view_count INTEGER NOT NULL DEFAULT 0
+);
+
+INSERT INTO visitors_old (id, view_count)
+SELECT id, MIN(view_count, 2147483647) FROM visitors;
+
+DROP TABLE visitors;
+
+ALTER TABLE visitors_old RENAME TO visitors;
+
+CREATE INDEX visitors_view_count ON visitors (view_count);
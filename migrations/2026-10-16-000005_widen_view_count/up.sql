@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE visitors_new (
+  id VARCHAR NOT NULL PRIMARY KEY,
+  view_count BIGINT NOT NULL DEFAULT 0
+);
+
+INSERT INTO visitors_new (id, view_count)
+SELECT id, view_count FROM visitors;
+
+DROP TABLE visitors;
+
+ALTER TABLE visitors_new RENAME TO visitors;
+
+CREATE INDEX visitors_view_count ON visitors (view_count);
@@ -0,0 +1,46 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE visitors_old (
+  id VARCHAR NOT NULL PRIMARY KEY,
+  view_count BIGINT NOT NULL DEFAULT 0
+);
+
+INSERT INTO visitors_old (id, view_count)
+SELECT id, view_count FROM visitors WHERE page = '';
+
+DROP TABLE visitors;
+
+ALTER TABLE visitors_old RENAME TO visitors;
+
+CREATE INDEX visitors_view_count ON visitors (view_count);
+
+CREATE TABLE daily_views_old (
+  user_id VARCHAR NOT NULL,
+  day VARCHAR NOT NULL,
+  view_count INTEGER NOT NULL DEFAULT 0,
+  PRIMARY KEY (user_id, day)
+);
+
+INSERT INTO daily_views_old (user_id, day, view_count)
+SELECT user_id, day, view_count FROM daily_views WHERE page = '';
+
+DROP TABLE daily_views;
+
+ALTER TABLE daily_views_old RENAME TO daily_views;
+
+CREATE INDEX daily_views_day ON daily_views (day);
+
+CREATE TABLE recent_views_old (
+  user_id VARCHAR NOT NULL,
+  fingerprint VARCHAR NOT NULL,
+  last_seen BIGINT NOT NULL,
+  PRIMARY KEY (user_id, fingerprint)
+);
+
+INSERT INTO recent_views_old (user_id, fingerprint, last_seen)
+SELECT user_id, fingerprint, last_seen FROM recent_views WHERE page = '';
+
+DROP TABLE recent_views;
+
+ALTER TABLE recent_views_old RENAME TO recent_views;
+
+CREATE INDEX recent_views_last_seen ON recent_views (last_seen);
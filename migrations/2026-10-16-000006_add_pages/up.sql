@@ -0,0 +1,50 @@
+-- Your SQL goes here
+CREATE TABLE visitors_new (
+  id VARCHAR NOT NULL,
+  page VARCHAR NOT NULL DEFAULT '',
+  view_count BIGINT NOT NULL DEFAULT 0,
+  PRIMARY KEY (id, page)
+);
+
+INSERT INTO visitors_new (id, page, view_count)
+SELECT id, '', view_count FROM visitors;
+
+DROP TABLE visitors;
+
+ALTER TABLE visitors_new RENAME TO visitors;
+
+CREATE INDEX visitors_view_count ON visitors (view_count);
+
+CREATE TABLE daily_views_new (
+  user_id VARCHAR NOT NULL,
+  page VARCHAR NOT NULL DEFAULT '',
+  day VARCHAR NOT NULL,
+  view_count INTEGER NOT NULL DEFAULT 0,
+  PRIMARY KEY (user_id, page, day)
+);
+
+INSERT INTO daily_views_new (user_id, page, day, view_count)
+SELECT user_id, '', day, view_count FROM daily_views;
+
+DROP TABLE daily_views;
+
+ALTER TABLE daily_views_new RENAME TO daily_views;
+
+CREATE INDEX daily_views_day ON daily_views (day);
+
+CREATE TABLE recent_views_new (
+  user_id VARCHAR NOT NULL,
+  page VARCHAR NOT NULL DEFAULT '',
+  fingerprint VARCHAR NOT NULL,
+  last_seen BIGINT NOT NULL,
+  PRIMARY KEY (user_id, page, fingerprint)
+);
+
+INSERT INTO recent_views_new (user_id, page, fingerprint, last_seen)
+SELECT user_id, '', fingerprint, last_seen FROM recent_views;
+
+DROP TABLE recent_views;
+
+ALTER TABLE recent_views_new RENAME TO recent_views;
+
+CREATE INDEX recent_views_last_seen ON recent_views (last_seen);
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE api_keys (
+  key_hash VARCHAR NOT NULL PRIMARY KEY,
+  name VARCHAR NOT NULL UNIQUE,
+  scopes VARCHAR NOT NULL,
+  created_at BIGINT NOT NULL
+);
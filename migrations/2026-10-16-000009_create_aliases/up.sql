@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE aliases (
+  alias VARCHAR NOT NULL PRIMARY KEY,
+  target VARCHAR NOT NULL,
+  created_at BIGINT NOT NULL
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE unique_views;
+
+ALTER TABLE visitors DROP COLUMN unique_count;
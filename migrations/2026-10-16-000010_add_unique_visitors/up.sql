@@ -0,0 +1,12 @@
+-- Your SQL goes here
+ALTER TABLE visitors ADD COLUMN unique_count BIGINT NOT NULL DEFAULT 0;
+
+CREATE TABLE unique_views (
+  user_id VARCHAR NOT NULL,
+  page VARCHAR NOT NULL DEFAULT '',
+  fingerprint VARCHAR NOT NULL,
+  last_seen BIGINT NOT NULL,
+  PRIMARY KEY (user_id, page, fingerprint)
+);
+
+CREATE INDEX unique_views_last_seen ON unique_views (last_seen);
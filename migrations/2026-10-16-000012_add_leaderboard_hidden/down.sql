@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE visitor_settings DROP COLUMN leaderboard_hidden;
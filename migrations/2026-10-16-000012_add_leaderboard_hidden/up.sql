@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE visitor_settings ADD COLUMN leaderboard_hidden BOOLEAN NOT NULL DEFAULT 0;
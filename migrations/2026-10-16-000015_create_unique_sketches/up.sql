@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE unique_sketches (
+  user_id VARCHAR NOT NULL,
+  page VARCHAR NOT NULL DEFAULT '',
+  period BIGINT NOT NULL,
+  registers BLOB NOT NULL,
+  estimate BIGINT NOT NULL,
+  PRIMARY KEY (user_id, page)
+);
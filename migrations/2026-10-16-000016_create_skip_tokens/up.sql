@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE skip_tokens (
+  token_hash VARCHAR NOT NULL PRIMARY KEY,
+  user_id VARCHAR NOT NULL,
+  created_at BIGINT NOT NULL
+);
+
+CREATE INDEX skip_tokens_user_id ON skip_tokens (user_id);
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE claims ADD COLUMN secret_hash VARCHAR NOT NULL DEFAULT '';
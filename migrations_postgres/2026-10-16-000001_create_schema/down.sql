@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE recent_views;
+DROP TABLE daily_views;
+DROP TABLE visitor_settings;
+DROP TABLE owner_keys;
+DROP TABLE claims;
+DROP TABLE visitors;
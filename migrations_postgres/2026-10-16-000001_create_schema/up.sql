@@ -0,0 +1,47 @@
+-- Your SQL goes here
+CREATE TABLE visitors (
+  id VARCHAR NOT NULL PRIMARY KEY,
+  view_count BIGINT NOT NULL DEFAULT 0
+);
+
+CREATE INDEX visitors_view_count ON visitors (view_count);
+
+INSERT INTO visitors
+VALUES ('me', 0);
+
+CREATE TABLE claims (
+  user_id VARCHAR NOT NULL PRIMARY KEY,
+  token VARCHAR NOT NULL,
+  created_at BIGINT NOT NULL
+);
+
+CREATE TABLE owner_keys (
+  key_hash VARCHAR NOT NULL PRIMARY KEY,
+  user_id VARCHAR NOT NULL,
+  created_at BIGINT NOT NULL
+);
+
+CREATE INDEX owner_keys_user_id ON owner_keys (user_id);
+
+CREATE TABLE visitor_settings (
+  user_id VARCHAR NOT NULL PRIMARY KEY,
+  timezone VARCHAR NOT NULL DEFAULT 'UTC'
+);
+
+CREATE TABLE daily_views (
+  user_id VARCHAR NOT NULL,
+  day VARCHAR NOT NULL,
+  view_count INTEGER NOT NULL DEFAULT 0,
+  PRIMARY KEY (user_id, day)
+);
+
+CREATE INDEX daily_views_day ON daily_views (day);
+
+CREATE TABLE recent_views (
+  user_id VARCHAR NOT NULL,
+  fingerprint VARCHAR NOT NULL,
+  last_seen BIGINT NOT NULL,
+  PRIMARY KEY (user_id, fingerprint)
+);
+
+CREATE INDEX recent_views_last_seen ON recent_views (last_seen);
@@ -0,0 +1,15 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM recent_views WHERE page <> '';
+ALTER TABLE recent_views DROP CONSTRAINT recent_views_pkey;
+ALTER TABLE recent_views DROP COLUMN page;
+ALTER TABLE recent_views ADD PRIMARY KEY (user_id, fingerprint);
+
+DELETE FROM daily_views WHERE page <> '';
+ALTER TABLE daily_views DROP CONSTRAINT daily_views_pkey;
+ALTER TABLE daily_views DROP COLUMN page;
+ALTER TABLE daily_views ADD PRIMARY KEY (user_id, day);
+
+DELETE FROM visitors WHERE page <> '';
+ALTER TABLE visitors DROP CONSTRAINT visitors_pkey;
+ALTER TABLE visitors DROP COLUMN page;
+ALTER TABLE visitors ADD PRIMARY KEY (id);
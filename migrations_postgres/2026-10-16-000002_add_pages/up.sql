@@ -0,0 +1,12 @@
+-- Your SQL goes here
+ALTER TABLE visitors ADD COLUMN page VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE visitors DROP CONSTRAINT visitors_pkey;
+ALTER TABLE visitors ADD PRIMARY KEY (id, page);
+
+ALTER TABLE daily_views ADD COLUMN page VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE daily_views DROP CONSTRAINT daily_views_pkey;
+ALTER TABLE daily_views ADD PRIMARY KEY (user_id, page, day);
+
+ALTER TABLE recent_views ADD COLUMN page VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE recent_views DROP CONSTRAINT recent_views_pkey;
+ALTER TABLE recent_views ADD PRIMARY KEY (user_id, page, fingerprint);
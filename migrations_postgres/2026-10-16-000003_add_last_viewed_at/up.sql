@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE visitors ADD COLUMN last_viewed_at BIGINT;
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TABLE milestone_hooks (
+  user_id VARCHAR NOT NULL PRIMARY KEY,
+  url VARCHAR NOT NULL,
+  thresholds VARCHAR NOT NULL,
+  created_at BIGINT NOT NULL
+);
+
+CREATE TABLE milestones (
+  user_id VARCHAR NOT NULL,
+  page VARCHAR NOT NULL DEFAULT '',
+  threshold BIGINT NOT NULL,
+  reached_at BIGINT NOT NULL,
+  PRIMARY KEY (user_id, page, threshold)
+);
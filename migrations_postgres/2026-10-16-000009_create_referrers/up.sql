@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE referrers (
+  user_id VARCHAR NOT NULL,
+  page VARCHAR NOT NULL DEFAULT '',
+  referrer VARCHAR NOT NULL,
+  view_count BIGINT NOT NULL,
+  PRIMARY KEY (user_id, page, referrer)
+);
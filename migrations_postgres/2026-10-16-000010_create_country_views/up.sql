@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE country_views (
+  user_id VARCHAR NOT NULL,
+  page VARCHAR NOT NULL DEFAULT '',
+  country VARCHAR NOT NULL,
+  view_count BIGINT NOT NULL,
+  PRIMARY KEY (user_id, page, country)
+);
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE visitor_settings ADD COLUMN display_step BIGINT NOT NULL DEFAULT 1;
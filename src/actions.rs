@@ -1,31 +1,1155 @@
+use diesel::dsl::{sql, AsExprOf};
+use diesel::expression::{SqlLiteral, UncheckedBind};
 use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Nullable};
 
-use crate::models;
+use crate::daily::Granularity;
+use crate::{hll, models};
 
-type DbError = Box<dyn std::error::Error + Send + Sync>;
+pub type DbError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Connection type of the database backend selected at build time.
+#[cfg(not(feature = "postgres"))]
+pub type DbConnection = SqliteConnection;
+#[cfg(feature = "postgres")]
+pub type DbConnection = PgConnection;
+
+/// SQLite spells the two-argument minimum `MIN`, PostgreSQL `LEAST`. The
+/// column is qualified because in an upsert PostgreSQL could also read it
+/// as the `excluded` row's.
+#[cfg(not(feature = "postgres"))]
+const MIN_VIEW_COUNT: &str = "MIN(visitors.view_count, ";
+#[cfg(feature = "postgres")]
+const MIN_VIEW_COUNT: &str = "LEAST(visitors.view_count, ";
+
+type BoundBigInt = AsExprOf<i64, BigInt>;
+type SaturatingAdd =
+    UncheckedBind<SqlLiteral<BigInt, UncheckedBind<SqlLiteral<BigInt>, BoundBigInt>>, BoundBigInt>;
 
 /// Run query using Diesel to find user by uid and return it.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
 pub fn get_user_viewcount(
-    conn: &mut SqliteConnection,
-    user: &String,
+    conn: &mut DbConnection,
+    user: &str,
+    page_name: &str,
 ) -> Result<Option<models::Visitors>, DbError> {
     use crate::schema::visitors::dsl::*;
 
     let user = visitors
-        .filter(id.eq(user))
+        .filter(id.eq(user).and(page.eq(page_name)))
         .first::<models::Visitors>(conn)
         .optional()?;
     Ok(user)
 }
 
-pub fn update_user_viewcount(
-    conn: &mut SqliteConnection,
-    user: &String,
+/// Add `delta` views to the user's counter in one statement, stamping it as
+/// last viewed at `now`.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn add_user_viewcount(
+    conn: &mut DbConnection,
+    user: &str,
+    page_name: &str,
+    delta: i32,
+    now: i64,
+) -> Result<usize, DbError> {
+    use crate::schema::visitors::dsl::*;
+
+    let updated_row = diesel::update(visitors.filter(id.eq(user).and(page.eq(page_name))))
+        .set((view_count.eq(saturating_add(delta)), last_viewed_at.eq(now)))
+        .execute(conn)?;
+    Ok(updated_row)
+}
+
+/// Add `delta` views to the user's counter, creating it with `delta` views
+/// when it does not exist yet, and return the resulting row in one statement.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn upsert_and_get_user_viewcount(
+    conn: &mut DbConnection,
+    user: &str,
+    page_name: &str,
+    delta: i32,
+    now: i64,
+) -> Result<models::Visitors, DbError> {
+    use crate::schema::visitors::dsl::*;
+
+    let new_visitor = models::NewVisitor {
+        id: user,
+        page: page_name,
+        view_count: i64::from(delta),
+        last_viewed_at: Some(now),
+    };
+    let visitor = diesel::insert_into(visitors)
+        .values(&new_visitor)
+        .on_conflict((id, page))
+        .do_update()
+        .set((view_count.eq(saturating_add(delta)), last_viewed_at.eq(now)))
+        .get_result::<models::Visitors>(conn)?;
+    Ok(visitor)
+}
+
+/// `view_count + delta`, clamped at `i64::MAX`. SQLite turns an overflowing
+/// integer sum into a REAL and PostgreSQL rejects it, so the guard has to
+/// happen in the statement.
+fn saturating_add(delta: i32) -> SaturatingAdd {
+    let delta = i64::from(delta);
+    sql::<BigInt>(MIN_VIEW_COUNT)
+        .bind::<BigInt, _>(i64::MAX.saturating_sub(delta))
+        .sql(") + ")
+        .bind::<BigInt, _>(delta)
+}
+
+/// Insert a new counter starting at zero.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn create_user(
+    conn: &mut DbConnection,
+    user: &str,
+    page_name: &str,
+) -> Result<models::Visitors, DbError> {
+    use crate::schema::visitors::dsl::*;
+
+    let visitor = diesel::insert_into(visitors)
+        .values(&models::NewVisitor { id: user, page: page_name, view_count: 0, last_viewed_at: None })
+        .get_result::<models::Visitors>(conn)?;
+    Ok(visitor)
+}
+
+/// Remove a counter, returning whether it existed.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn delete_user(
+    conn: &mut DbConnection,
+    user: &str,
+    page_name: &str,
+) -> Result<bool, DbError> {
+    use crate::schema::visitors::dsl::*;
+
+    let deleted = diesel::delete(visitors.filter(id.eq(user).and(page.eq(page_name)))).execute(conn)?;
+    Ok(deleted > 0)
+}
+
+/// Set the user's counter to `count`, creating it if needed.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn set_user_viewcount(
+    conn: &mut DbConnection,
+    user: &str,
+    page_name: &str,
+    count: i64,
+) -> Result<models::Visitors, DbError> {
+    use crate::schema::visitors::dsl::*;
+
+    let visitor = diesel::insert_into(visitors)
+        .values(&models::NewVisitor { id: user, page: page_name, view_count: count, last_viewed_at: None })
+        .on_conflict((id, page))
+        .do_update()
+        .set(view_count.eq(count))
+        .get_result::<models::Visitors>(conn)?;
+    Ok(visitor)
+}
+
+/// Read one counter row, to prove the schema is in place and queryable.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn check_schema(conn: &mut DbConnection) -> Result<(), DbError> {
+    use crate::schema::visitors::dsl::*;
+
+    visitors.select(id).first::<String>(conn).optional()?;
+    Ok(())
+}
+
+/// List every counter ordered by id and page.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn list_users(
+    conn: &mut DbConnection,
+) -> Result<Vec<models::Visitors>, DbError> {
+    use crate::schema::visitors::dsl::*;
+
+    let users = visitors
+        .order((id.asc(), page.asc()))
+        .load::<models::Visitors>(conn)?;
+    Ok(users)
+}
+
+/// Up to `limit` of the user's counters in page order, after page `after`
+/// if given.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn list_pages(
+    conn: &mut DbConnection,
+    user: &str,
+    after: Option<&str>,
+    limit: i64,
+) -> Result<Vec<models::Visitors>, DbError> {
+    use crate::schema::visitors::dsl::*;
+
+    let mut query = visitors.filter(id.eq(user)).into_boxed();
+    if let Some(after) = after {
+        query = query.filter(page.gt(after));
+    }
+    let pages = query
+        .order(page.asc())
+        .limit(limit)
+        .load::<models::Visitors>(conn)?;
+    Ok(pages)
+}
+
+/// Views summed over all of the user's pages, or `None` when the user has no
+/// counters.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn get_user_total(
+    conn: &mut DbConnection,
+    user: &str,
+) -> Result<Option<i64>, DbError> {
+    use crate::schema::visitors::dsl::*;
+
+    let total = visitors
+        .filter(id.eq(user))
+        .select(sql::<Nullable<BigInt>>("CAST(SUM(view_count) AS BIGINT)"))
+        .first::<Option<i64>>(conn)?;
+    Ok(total)
+}
+
+/// Open a claim for a counter after dropping claims created before
+/// `expired_before`. Returns `false`, storing nothing, while another claim
+/// on the counter is still open.
+#[tracing::instrument(level = "debug", skip(conn, claim), err(level = "warn"))]
+pub fn put_claim(
+    conn: &mut DbConnection,
+    claim: &models::Claim,
+    expired_before: i64,
+) -> Result<bool, DbError> {
+    use crate::schema::claims::dsl::*;
+
+    conn.transaction(|conn| {
+        diesel::delete(claims.filter(created_at.lt(expired_before))).execute(conn)?;
+        let inserted = diesel::insert_into(claims)
+            .values(claim)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        Ok(inserted == 1)
+    })
+}
+
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn get_claim(
+    conn: &mut DbConnection,
+    user: &str,
+) -> Result<Option<models::Claim>, DbError> {
+    use crate::schema::claims::dsl::*;
+
+    let claim = claims
+        .filter(user_id.eq(user))
+        .first::<models::Claim>(conn)
+        .optional()?;
+    Ok(claim)
+}
+
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn delete_claim(
+    conn: &mut DbConnection,
+    user: &str,
+) -> Result<(), DbError> {
+    use crate::schema::claims::dsl::*;
+
+    diesel::delete(claims.filter(user_id.eq(user))).execute(conn)?;
+    Ok(())
+}
+
+/// Replace the user's skip token with `token`.
+#[tracing::instrument(level = "debug", skip(conn, token), err(level = "warn"))]
+pub fn replace_skip_token(
+    conn: &mut DbConnection,
+    token: &models::SkipToken,
+) -> Result<(), DbError> {
+    use crate::schema::skip_tokens::dsl::*;
+
+    conn.transaction(|conn| {
+        diesel::delete(skip_tokens.filter(user_id.eq(&token.user_id))).execute(conn)?;
+        diesel::insert_into(skip_tokens).values(token).execute(conn)?;
+        Ok(())
+    })
+}
+
+/// Find which counter a skip token hash belongs to.
+#[tracing::instrument(level = "debug", skip(conn, hash), err(level = "warn"))]
+pub fn find_skip_token(
+    conn: &mut DbConnection,
+    hash: &str,
+) -> Result<Option<models::SkipToken>, DbError> {
+    use crate::schema::skip_tokens::dsl::*;
+
+    let token = skip_tokens
+        .filter(token_hash.eq(hash))
+        .first::<models::SkipToken>(conn)
+        .optional()?;
+    Ok(token)
+}
+
+/// Revoke the user's skip token, returning whether there was one.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn delete_skip_tokens(
+    conn: &mut DbConnection,
+    user: &str,
+) -> Result<bool, DbError> {
+    use crate::schema::skip_tokens::dsl::*;
+
+    let deleted = diesel::delete(skip_tokens.filter(user_id.eq(user))).execute(conn)?;
+    Ok(deleted > 0)
+}
+
+/// Store an owner key.
+#[tracing::instrument(level = "debug", skip(conn, key), err(level = "warn"))]
+pub fn issue_owner_key(
+    conn: &mut DbConnection,
+    key: &models::OwnerKey,
+) -> Result<(), DbError> {
+    diesel::insert_into(crate::schema::owner_keys::table)
+        .values(key)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Consume a verified claim and issue its owner key. Returns `false`,
+/// issuing nothing, if the claim was already consumed or replaced, so each
+/// claim yields at most one key.
+#[tracing::instrument(level = "debug", skip(conn, claim, key), err(level = "warn"))]
+pub fn redeem_claim(
+    conn: &mut DbConnection,
+    claim: &models::Claim,
+    key: &models::OwnerKey,
+) -> Result<bool, DbError> {
+    use crate::schema::claims::dsl::*;
+
+    conn.transaction(|conn| {
+        let consumed = diesel::delete(
+            claims.filter(user_id.eq(&claim.user_id)).filter(token.eq(&claim.token)),
+        )
+        .execute(conn)?;
+        if consumed == 0 {
+            return Ok(false);
+        }
+        issue_owner_key(conn, key)?;
+        Ok(true)
+    })
+}
+
+/// Time zone the user's daily counts roll over in, UTC unless configured.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn get_user_timezone(
+    conn: &mut DbConnection,
+    user: &str,
+) -> Result<String, DbError> {
+    use crate::schema::visitor_settings::dsl::*;
+
+    let tz = visitor_settings
+        .filter(user_id.eq(user))
+        .select(timezone)
+        .first::<String>(conn)
+        .optional()?;
+    Ok(tz.unwrap_or_else(|| "UTC".to_string()))
+}
+
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn set_user_timezone(
+    conn: &mut DbConnection,
+    user: &str,
+    tz: &str,
+) -> Result<(), DbError> {
+    use crate::schema::visitor_settings::dsl::*;
+
+    diesel::insert_into(visitor_settings)
+        .values((user_id.eq(user), timezone.eq(tz)))
+        .on_conflict(user_id)
+        .do_update()
+        .set(timezone.eq(tz))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Multiple the user's public counts are rounded to, 1 unless configured.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn get_display_step(
+    conn: &mut DbConnection,
+    user: &str,
+) -> Result<i64, DbError> {
+    use crate::schema::visitor_settings::dsl::*;
+
+    let step = visitor_settings
+        .filter(user_id.eq(user))
+        .select(display_step)
+        .first::<i64>(conn)
+        .optional()?;
+    Ok(step.unwrap_or(1))
+}
+
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn set_display_step(
+    conn: &mut DbConnection,
+    user: &str,
+    step: i64,
+) -> Result<(), DbError> {
+    use crate::schema::visitor_settings::dsl::*;
+
+    diesel::insert_into(visitor_settings)
+        .values((user_id.eq(user), display_step.eq(step)))
+        .on_conflict(user_id)
+        .do_update()
+        .set(display_step.eq(step))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Hide the user's counter from the public leaderboard, or list it again.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn set_leaderboard_hidden(
+    conn: &mut DbConnection,
+    user: &str,
+    hidden: bool,
+) -> Result<(), DbError> {
+    use crate::schema::visitor_settings::dsl::*;
+
+    diesel::insert_into(visitor_settings)
+        .values((user_id.eq(user), leaderboard_hidden.eq(hidden)))
+        .on_conflict(user_id)
+        .do_update()
+        .set(leaderboard_hidden.eq(hidden))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Counters with the most views summed over their pages, leaving out those
+/// whose owners opted out. Totals are rounded half up to each counter's
+/// display step, like `format::round_count`, and ranked as rounded.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn public_leaderboard(
+    conn: &mut DbConnection,
+    limit: i64,
+) -> Result<Vec<models::LeaderboardEntry>, DbError> {
+    #[cfg(not(feature = "postgres"))]
+    const QUERY: &str =
+        "SELECT id, CASE WHEN step < 2 THEN total ELSE (total + step / 2) / step * step END AS view_count \
+         FROM (SELECT id, CAST(SUM(view_count) AS BIGINT) AS total, \
+               COALESCE((SELECT display_step FROM visitor_settings WHERE user_id = visitors.id), 1) AS step \
+               FROM visitors \
+               WHERE id NOT IN (SELECT user_id FROM visitor_settings WHERE leaderboard_hidden) \
+               GROUP BY id) AS totals \
+         ORDER BY view_count DESC, id ASC LIMIT ?";
+    #[cfg(feature = "postgres")]
+    const QUERY: &str =
+        "SELECT id, CASE WHEN step < 2 THEN total ELSE (total + step / 2) / step * step END AS view_count \
+         FROM (SELECT id, CAST(SUM(view_count) AS BIGINT) AS total, \
+               COALESCE((SELECT display_step FROM visitor_settings WHERE user_id = visitors.id), 1) AS step \
+               FROM visitors \
+               WHERE id NOT IN (SELECT user_id FROM visitor_settings WHERE leaderboard_hidden) \
+               GROUP BY id) AS totals \
+         ORDER BY view_count DESC, id ASC LIMIT $1";
+
+    let entries = diesel::sql_query(QUERY)
+        .bind::<BigInt, _>(limit)
+        .load::<models::LeaderboardEntry>(conn)?;
+    Ok(entries)
+}
+
+/// Count a view of the user's page as coming from `source`.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn add_referrer_viewcount(
+    conn: &mut DbConnection,
+    user: &str,
+    page_name: &str,
+    source: &str,
+) -> Result<(), DbError> {
+    use crate::schema::referrers::dsl::*;
+
+    diesel::insert_into(referrers)
+        .values((user_id.eq(user), page.eq(page_name), referrer.eq(source), view_count.eq(1i64)))
+        .on_conflict((user_id, page, referrer))
+        .do_update()
+        .set(view_count.eq(view_count + 1i64))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// The referrers of the user's page with the most views, most first.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn top_referrers(
+    conn: &mut DbConnection,
+    user: &str,
+    page_name: &str,
+    limit: i64,
+) -> Result<Vec<models::ReferrerCount>, DbError> {
+    use crate::schema::referrers::dsl::*;
+
+    let rows = referrers
+        .filter(user_id.eq(user).and(page.eq(page_name)))
+        .order((view_count.desc(), referrer.asc()))
+        .limit(limit)
+        .select((referrer, view_count))
+        .load::<models::ReferrerCount>(conn)?;
+    Ok(rows)
+}
+
+/// Count a view of the user's page as coming from `code`.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn add_country_viewcount(
+    conn: &mut DbConnection,
+    user: &str,
+    page_name: &str,
+    code: &str,
+) -> Result<(), DbError> {
+    use crate::schema::country_views::dsl::*;
+
+    diesel::insert_into(country_views)
+        .values((user_id.eq(user), page.eq(page_name), country.eq(code), view_count.eq(1i64)))
+        .on_conflict((user_id, page, country))
+        .do_update()
+        .set(view_count.eq(view_count + 1i64))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Views of the user's page per country, most first.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn get_country_views(
+    conn: &mut DbConnection,
+    user: &str,
+    page_name: &str,
+) -> Result<Vec<models::CountryCount>, DbError> {
+    use crate::schema::country_views::dsl::*;
+
+    let rows = country_views
+        .filter(user_id.eq(user).and(page.eq(page_name)))
+        .order((view_count.desc(), country.asc()))
+        .select((country, view_count))
+        .load::<models::CountryCount>(conn)?;
+    Ok(rows)
+}
+
+/// Add `delta` views to the user's row for `local_day`, creating it if needed.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn add_daily_viewcount(
+    conn: &mut DbConnection,
+    user: &str,
+    page_name: &str,
+    local_day: &str,
+    delta: i32,
+) -> Result<(), DbError> {
+    use crate::schema::daily_views::dsl::*;
+
+    diesel::insert_into(daily_views)
+        .values((user_id.eq(user), page.eq(page_name), day.eq(local_day), view_count.eq(delta)))
+        .on_conflict((user_id, page, day))
+        .do_update()
+        .set(view_count.eq(view_count + delta))
+        .execute(conn)?;
+    Ok(())
+}
+
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn get_daily_viewcount(
+    conn: &mut DbConnection,
+    user: &str,
+    page_name: &str,
+    local_day: &str,
+) -> Result<i32, DbError> {
+    use crate::schema::daily_views::dsl::*;
+
+    let count = daily_views
+        .filter(user_id.eq(user).and(page.eq(page_name)).and(day.eq(local_day)))
+        .select(view_count)
+        .first::<i32>(conn)
+        .optional()?;
+    Ok(count.unwrap_or(0))
+}
+
+/// The user's daily rows from `first_day` on, oldest first. Days without
+/// views have no row.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn get_daily_history(
+    conn: &mut DbConnection,
+    user: &str,
+    page_name: &str,
+    first_day: &str,
+) -> Result<Vec<(String, i32)>, DbError> {
+    use crate::schema::daily_views::dsl::*;
+
+    let rows = daily_views
+        .filter(user_id.eq(user).and(page.eq(page_name)).and(day.ge(first_day)))
+        .order(day.asc())
+        .select((day, view_count))
+        .load::<(String, i32)>(conn)?;
+    Ok(rows)
+}
+
+#[derive(QueryableByName)]
+struct PeriodRow {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    start: String,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    view_count: i64,
+}
+
+/// The user's daily rows from `first_day` on summed per `granularity`
+/// bucket, as (first day of the bucket, views), oldest first. Buckets
+/// without views have no row.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn get_period_history(
+    conn: &mut DbConnection,
+    user: &str,
+    page_name: &str,
+    first_day: &str,
+    granularity: Granularity,
+) -> Result<Vec<(String, i64)>, DbError> {
+    use diesel::sql_types::Text;
+
+    #[cfg(not(feature = "postgres"))]
+    const WEEK: &str = "date(day, '-' || ((CAST(strftime('%w', day) AS INTEGER) + 6) % 7) || ' days')";
+    #[cfg(feature = "postgres")]
+    const WEEK: &str = "to_char(date_trunc('week', day::date), 'YYYY-MM-DD')";
+    #[cfg(not(feature = "postgres"))]
+    const FILTER: &str = "user_id = ? AND page = ? AND day >= ?";
+    #[cfg(feature = "postgres")]
+    const FILTER: &str = "user_id = $1 AND page = $2 AND day >= $3";
+
+    let bucket = match granularity {
+        Granularity::Day => "day",
+        Granularity::Week => WEEK,
+        Granularity::Month => "substr(day, 1, 7) || '-01'",
+    };
+    let query = format!(
+        "SELECT {} AS start, CAST(SUM(view_count) AS BIGINT) AS view_count FROM daily_views \
+         WHERE {} GROUP BY 1 ORDER BY 1",
+        bucket, FILTER,
+    );
+    let rows = diesel::sql_query(query)
+        .bind::<Text, _>(user)
+        .bind::<Text, _>(page_name)
+        .bind::<Text, _>(first_day)
+        .load::<PeriodRow>(conn)?;
+    Ok(rows.into_iter().map(|row| (row.start, row.view_count)).collect())
+}
+
+/// Delete daily rows for days before `cutoff_day`.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn prune_daily_views(
+    conn: &mut DbConnection,
+    cutoff_day: &str,
 ) -> Result<usize, DbError> {
+    use crate::schema::daily_views::dsl::*;
+
+    let deleted = diesel::delete(daily_views.filter(day.lt(cutoff_day))).execute(conn)?;
+    Ok(deleted)
+}
+
+/// Find which counter an owner key hash belongs to.
+#[tracing::instrument(level = "debug", skip(conn, hash), err(level = "warn"))]
+pub fn find_owner_key(
+    conn: &mut DbConnection,
+    hash: &str,
+) -> Result<Option<models::OwnerKey>, DbError> {
+    use crate::schema::owner_keys::dsl::*;
+
+    let key = owner_keys
+        .filter(key_hash.eq(hash))
+        .first::<models::OwnerKey>(conn)
+        .optional()?;
+    Ok(key)
+}
+
+#[tracing::instrument(level = "debug", skip(conn, key), err(level = "warn"))]
+pub fn insert_api_key(
+    conn: &mut DbConnection,
+    key: &models::ApiKey,
+) -> Result<(), DbError> {
+    diesel::insert_into(crate::schema::api_keys::table)
+        .values(key)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Find the API key with this hash.
+#[tracing::instrument(level = "debug", skip(conn, hash), err(level = "warn"))]
+pub fn find_api_key(
+    conn: &mut DbConnection,
+    hash: &str,
+) -> Result<Option<models::ApiKey>, DbError> {
+    use crate::schema::api_keys::dsl::*;
+
+    let key = api_keys
+        .filter(key_hash.eq(hash))
+        .first::<models::ApiKey>(conn)
+        .optional()?;
+    Ok(key)
+}
+
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn list_api_keys(
+    conn: &mut DbConnection,
+) -> Result<Vec<models::ApiKey>, DbError> {
+    use crate::schema::api_keys::dsl::*;
+
+    let keys = api_keys
+        .order(name.asc())
+        .load::<models::ApiKey>(conn)?;
+    Ok(keys)
+}
+
+/// Revoke the API key called `key_name`, returning whether it existed.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn delete_api_key(
+    conn: &mut DbConnection,
+    key_name: &str,
+) -> Result<bool, DbError> {
+    use crate::schema::api_keys::dsl::*;
+
+    let deleted = diesel::delete(api_keys.filter(name.eq(key_name))).execute(conn)?;
+    Ok(deleted > 0)
+}
+
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn insert_alias(
+    conn: &mut DbConnection,
+    new_alias: &models::Alias,
+) -> Result<(), DbError> {
+    diesel::insert_into(crate::schema::aliases::table)
+        .values(new_alias)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// The alias record for `alias_name`, if it is an alias.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn find_alias(
+    conn: &mut DbConnection,
+    alias_name: &str,
+) -> Result<Option<models::Alias>, DbError> {
+    use crate::schema::aliases::dsl::*;
+
+    let found = aliases
+        .filter(alias.eq(alias_name))
+        .first::<models::Alias>(conn)
+        .optional()?;
+    Ok(found)
+}
+
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn list_aliases(
+    conn: &mut DbConnection,
+) -> Result<Vec<models::Alias>, DbError> {
+    use crate::schema::aliases::dsl::*;
+
+    let found = aliases
+        .order(alias.asc())
+        .load::<models::Alias>(conn)?;
+    Ok(found)
+}
+
+/// Remove the alias `alias_name`, returning whether it existed.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn delete_alias(
+    conn: &mut DbConnection,
+    alias_name: &str,
+) -> Result<bool, DbError> {
+    use crate::schema::aliases::dsl::*;
+
+    let deleted = diesel::delete(aliases.filter(alias.eq(alias_name))).execute(conn)?;
+    Ok(deleted > 0)
+}
+
+/// Register or replace the milestone webhook of `hook.user_id`.
+#[tracing::instrument(level = "debug", skip(conn, hook), err(level = "warn"))]
+pub fn upsert_milestone_hook(
+    conn: &mut DbConnection,
+    hook: &models::MilestoneHook,
+) -> Result<(), DbError> {
+    use crate::schema::milestone_hooks::dsl::*;
+
+    diesel::insert_into(milestone_hooks)
+        .values(hook)
+        .on_conflict(user_id)
+        .do_update()
+        .set((url.eq(&hook.url), thresholds.eq(&hook.thresholds), created_at.eq(hook.created_at)))
+        .execute(conn)?;
+    Ok(())
+}
+
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn list_milestone_hooks(
+    conn: &mut DbConnection,
+) -> Result<Vec<models::MilestoneHook>, DbError> {
+    use crate::schema::milestone_hooks::dsl::*;
+
+    let hooks = milestone_hooks.order(user_id.asc()).load::<models::MilestoneHook>(conn)?;
+    Ok(hooks)
+}
+
+/// Remove the milestone webhook of `user`, returning whether it existed.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn delete_milestone_hook(
+    conn: &mut DbConnection,
+    user: &str,
+) -> Result<bool, DbError> {
+    use crate::schema::milestone_hooks::dsl::*;
+
+    let deleted = diesel::delete(milestone_hooks.filter(user_id.eq(user))).execute(conn)?;
+    Ok(deleted > 0)
+}
+
+/// Record that a page passed a threshold. Returns false when it was already
+/// recorded, so each milestone is announced once.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn record_milestone(
+    conn: &mut DbConnection,
+    milestone: &models::Milestone,
+) -> Result<bool, DbError> {
+    let inserted = diesel::insert_into(crate::schema::milestones::table)
+        .values(milestone)
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+    Ok(inserted > 0)
+}
+
+/// Every milestone the user's pages have reached, newest first.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn list_milestones(
+    conn: &mut DbConnection,
+    user: &str,
+) -> Result<Vec<models::Milestone>, DbError> {
+    use crate::schema::milestones::dsl::*;
+
+    let reached = milestones
+        .filter(user_id.eq(user))
+        .order((reached_at.desc(), threshold.desc()))
+        .load::<models::Milestone>(conn)?;
+    Ok(reached)
+}
+
+/// Milestones reached at or after `since`, for any counter.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn recent_milestones(
+    conn: &mut DbConnection,
+    since: i64,
+) -> Result<Vec<models::Milestone>, DbError> {
+    use crate::schema::milestones::dsl::*;
+
+    let recent = milestones.filter(reached_at.ge(since)).load::<models::Milestone>(conn)?;
+    Ok(recent)
+}
+
+/// Number of counters and their summed lifetime views.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn count_totals(
+    conn: &mut DbConnection,
+) -> Result<(i64, i64), DbError> {
+    use crate::schema::visitors::dsl::*;
+
+    // Diesel types SUM over BIGINT as NUMERIC, which neither backend loads
+    // without extra features.
+    let (counters, views) = visitors
+        .select((diesel::dsl::count_star(), sql::<Nullable<BigInt>>("CAST(SUM(view_count) AS BIGINT)")))
+        .first::<(i64, Option<i64>)>(conn)?;
+    Ok((counters, views.unwrap_or(0)))
+}
+
+/// Counters with the most lifetime views.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn top_users(
+    conn: &mut DbConnection,
+    limit: i64,
+) -> Result<Vec<models::Visitors>, DbError> {
+    use crate::schema::visitors::dsl::*;
+
+    let users = visitors
+        .order((view_count.desc(), id.asc()))
+        .limit(limit)
+        .load::<models::Visitors>(conn)?;
+    Ok(users)
+}
+
+/// Views recorded in daily rows on or after `first_day`, across all counters.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn views_since(
+    conn: &mut DbConnection,
+    first_day: &str,
+) -> Result<i64, DbError> {
+    use crate::schema::daily_views::dsl::*;
+
+    let views = daily_views
+        .filter(day.ge(first_day))
+        .select(diesel::dsl::sum(view_count))
+        .first::<Option<i64>>(conn)?;
+    Ok(views.unwrap_or(0))
+}
+
+/// Remember that `print` viewed the user's badge at `now`. Returns false,
+/// leaving the stored time alone, when the same fingerprint was already seen
+/// less than `window_secs` ago.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn mark_seen_if_not_recent(
+    conn: &mut DbConnection,
+    user: &str,
+    page_name: &str,
+    print: &str,
+    now: i64,
+    window_secs: i64,
+) -> Result<bool, DbError> {
+    use diesel::sql_types::{BigInt, Text};
+
+    #[cfg(not(feature = "postgres"))]
+    const QUERY: &str =
+        "INSERT INTO recent_views (user_id, page, fingerprint, last_seen) VALUES (?, ?, ?, ?) \
+         ON CONFLICT (user_id, page, fingerprint) DO UPDATE SET last_seen = excluded.last_seen \
+         WHERE recent_views.last_seen <= ?";
+    #[cfg(feature = "postgres")]
+    const QUERY: &str =
+        "INSERT INTO recent_views (user_id, page, fingerprint, last_seen) VALUES ($1, $2, $3, $4) \
+         ON CONFLICT (user_id, page, fingerprint) DO UPDATE SET last_seen = excluded.last_seen \
+         WHERE recent_views.last_seen <= $5";
+
+    let changed = diesel::sql_query(QUERY)
+    .bind::<Text, _>(user)
+    .bind::<Text, _>(page_name)
+    .bind::<Text, _>(print)
+    .bind::<BigInt, _>(now)
+    .bind::<BigInt, _>(now - window_secs)
+    .execute(conn)?;
+    Ok(changed > 0)
+}
+
+/// Forget fingerprints last seen before `cutoff`.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn prune_recent_views(
+    conn: &mut DbConnection,
+    cutoff: i64,
+) -> Result<usize, DbError> {
+    use crate::schema::recent_views::dsl::*;
+
+    let deleted = diesel::delete(recent_views.filter(last_seen.lt(cutoff))).execute(conn)?;
+    Ok(deleted)
+}
+
+/// Remember that `print` visited a counter at `now` and, when it had not
+/// within the last `window_secs`, count it as a unique visitor. Returns
+/// whether it was counted.
+#[tracing::instrument(level = "debug", skip(conn, print), err(level = "warn"))]
+pub fn record_unique_visit(
+    conn: &mut DbConnection,
+    user: &str,
+    page_name: &str,
+    print: &str,
+    now: i64,
+    window_secs: i64,
+) -> Result<bool, DbError> {
     use crate::schema::visitors::dsl::*;
+    use diesel::sql_types::{BigInt, Text};
+
+    #[cfg(not(feature = "postgres"))]
+    const QUERY: &str =
+        "INSERT INTO unique_views (user_id, page, fingerprint, last_seen) VALUES (?, ?, ?, ?) \
+         ON CONFLICT (user_id, page, fingerprint) DO UPDATE SET last_seen = excluded.last_seen \
+         WHERE unique_views.last_seen <= ?";
+    #[cfg(feature = "postgres")]
+    const QUERY: &str =
+        "INSERT INTO unique_views (user_id, page, fingerprint, last_seen) VALUES ($1, $2, $3, $4) \
+         ON CONFLICT (user_id, page, fingerprint) DO UPDATE SET last_seen = excluded.last_seen \
+         WHERE unique_views.last_seen <= $5";
+
+    conn.transaction(|conn| {
+        let changed = diesel::sql_query(QUERY)
+        .bind::<Text, _>(user)
+        .bind::<Text, _>(page_name)
+        .bind::<Text, _>(print)
+        .bind::<BigInt, _>(now)
+        .bind::<BigInt, _>(now - window_secs)
+        .execute(conn)?;
+        if changed == 0 {
+            return Ok(false);
+        }
+
+        let updated = diesel::update(visitors.filter(id.eq(user)).filter(page.eq(page_name)))
+            .set(unique_count.eq(unique_count + 1))
+            .execute(conn)?;
+        Ok(updated > 0)
+    })
+}
+
+/// Add `print` to the HyperLogLog sketch of the counter's unique window
+/// `window_period` and raise its `unique_count` by however much the
+/// sketch's estimate grew. A sketch from an earlier window starts over, and
+/// the counter's exact fingerprints are dropped once it has a sketch.
+/// Returns how much `unique_count` was raised.
+#[tracing::instrument(level = "debug", skip(conn, print), err(level = "warn"))]
+pub fn record_sketched_visit(
+    conn: &mut DbConnection,
+    user: &str,
+    page_name: &str,
+    print: &str,
+    window_period: i64,
+) -> Result<i64, DbError> {
+    use crate::schema::unique_sketches;
+    use crate::schema::visitors::dsl::*;
+
+    conn.transaction(|conn| {
+        let stored = unique_sketches::table
+            .filter(unique_sketches::user_id.eq(user).and(unique_sketches::page.eq(page_name)))
+            .first::<models::UniqueSketch>(conn)
+            .optional()?;
+        if stored.is_none() {
+            use crate::schema::unique_views;
+            diesel::delete(
+                unique_views::table.filter(unique_views::user_id.eq(user).and(unique_views::page.eq(page_name))),
+            )
+            .execute(conn)?;
+        }
+        let (mut sketch, counted) = match stored {
+            Some(stored) if stored.period == window_period => {
+                (hll::Sketch::from_bytes(stored.registers), stored.estimate)
+            },
+            _ => (hll::Sketch::default(), 0),
+        };
+        sketch.insert(print);
+        let added = (sketch.estimate() - counted).max(0);
+
+        let row = models::UniqueSketch {
+            user_id: user.to_string(),
+            page: page_name.to_string(),
+            period: window_period,
+            registers: sketch.as_bytes().to_vec(),
+            estimate: counted + added,
+        };
+        diesel::insert_into(unique_sketches::table)
+            .values(&row)
+            .on_conflict((unique_sketches::user_id, unique_sketches::page))
+            .do_update()
+            .set((
+                unique_sketches::period.eq(row.period),
+                unique_sketches::registers.eq(&row.registers),
+                unique_sketches::estimate.eq(row.estimate),
+            ))
+            .execute(conn)?;
+        if added > 0 {
+            diesel::update(visitors.filter(id.eq(user)).filter(page.eq(page_name)))
+                .set(unique_count.eq(unique_count + added))
+                .execute(conn)?;
+        }
+        Ok(added)
+    })
+}
+
+/// Forget unique visits older than `cutoff`.
+#[tracing::instrument(level = "debug", skip(conn), err(level = "warn"))]
+pub fn prune_unique_views(
+    conn: &mut DbConnection,
+    cutoff: i64,
+) -> Result<usize, DbError> {
+    use crate::schema::unique_views::dsl::*;
+
+    let deleted = diesel::delete(unique_views.filter(last_seen.lt(cutoff))).execute(conn)?;
+    Ok(deleted)
+}
+
+#[cfg(all(test, not(feature = "postgres")))]
+mod tests {
+    use diesel_migrations::MigrationHarness;
+
+    use super::*;
+
+    fn conn() -> DbConnection {
+        let mut conn = DbConnection::establish(":memory:").unwrap();
+        conn.run_pending_migrations(crate::MIGRATIONS).unwrap();
+        conn
+    }
+
+    #[test]
+    fn upsert_creates_then_increments() {
+        let mut conn = conn();
+        assert_eq!(add_user_viewcount(&mut conn, "octocat", "", 1, 100).unwrap(), 0);
+        assert!(get_user_viewcount(&mut conn, "octocat", "").unwrap().is_none());
+
+        let created = upsert_and_get_user_viewcount(&mut conn, "octocat", "", 1, 100).unwrap();
+        assert_eq!((created.id.as_str(), created.view_count, created.last_viewed_at), ("octocat", 1, Some(100)));
+        let incremented = upsert_and_get_user_viewcount(&mut conn, "octocat", "", 1, 200).unwrap();
+        assert_eq!((incremented.view_count, incremented.last_viewed_at), (2, Some(200)));
+        assert_eq!(get_user_viewcount(&mut conn, "octocat", "").unwrap().unwrap().view_count, 2);
+    }
+
+    #[test]
+    fn increments_saturate_at_the_maximum() {
+        let mut conn = conn();
+        set_user_viewcount(&mut conn, "octocat", "", i64::MAX - 1).unwrap();
+
+        let visitor = upsert_and_get_user_viewcount(&mut conn, "octocat", "", 5, 100).unwrap();
+        assert_eq!(visitor.view_count, i64::MAX);
+        let visitor = upsert_and_get_user_viewcount(&mut conn, "octocat", "", 1, 200).unwrap();
+        assert_eq!(visitor.view_count, i64::MAX);
+        assert_eq!(add_user_viewcount(&mut conn, "octocat", "", 1, 300).unwrap(), 1);
+        assert_eq!(get_user_viewcount(&mut conn, "octocat", "").unwrap().unwrap().view_count, i64::MAX);
+    }
+
+    #[test]
+    fn views_either_side_of_midnight_land_on_separate_days() {
+        let mut conn = conn();
+        let before = crate::daily::local_day(chrono_tz::Tz::UTC, "2024-03-09T23:59:59Z".parse().unwrap());
+        let after = crate::daily::local_day(chrono_tz::Tz::UTC, "2024-03-10T00:00:00Z".parse().unwrap());
+        add_daily_viewcount(&mut conn, "octocat", "", &before.to_string(), 2).unwrap();
+        add_daily_viewcount(&mut conn, "octocat", "", &after.to_string(), 1).unwrap();
+        add_daily_viewcount(&mut conn, "octocat", "", &after.to_string(), 1).unwrap();
+
+        assert_eq!(
+            get_daily_history(&mut conn, "octocat", "", "2024-03-01").unwrap(),
+            vec![("2024-03-09".to_string(), 2), ("2024-03-10".to_string(), 2)],
+        );
+        assert_eq!(get_daily_history(&mut conn, "octocat", "", "2024-03-10").unwrap().len(), 1);
+        assert_eq!(get_daily_viewcount(&mut conn, "octocat", "", "2024-03-09").unwrap(), 2);
+    }
+
+    #[test]
+    fn views_around_dst_changes_land_on_local_days() {
+        let mut conn = conn();
+        set_user_timezone(&mut conn, "octocat", "America/New_York").unwrap();
+        let tz = crate::daily::parse_timezone(&get_user_timezone(&mut conn, "octocat").unwrap()).unwrap();
+        for now in [
+            // Spring forward: 02:00 EST becomes 03:00 EDT on 2024-03-10.
+            "2024-03-10T04:59:59Z", // 23:59:59 EST, the 9th
+            "2024-03-10T05:00:00Z", // midnight EST
+            "2024-03-10T07:30:00Z", // 03:30 EDT, right after the gap
+            "2024-03-11T03:59:59Z", // 23:59:59 EDT
+            "2024-03-11T04:00:00Z", // midnight EDT, the 11th
+            // Fall back: 02:00 EDT becomes 01:00 EST on 2024-11-03.
+            "2024-11-03T05:30:00Z", // 01:30 EDT
+            "2024-11-03T06:30:00Z", // 01:30 EST, the repeated hour
+            "2024-11-04T04:59:59Z", // 23:59:59 EST
+            "2024-11-04T05:00:00Z", // midnight EST, the 4th
+        ] {
+            let day = crate::daily::local_day(tz, now.parse().unwrap());
+            add_daily_viewcount(&mut conn, "octocat", "", &day.to_string(), 1).unwrap();
+        }
+
+        assert_eq!(
+            get_daily_history(&mut conn, "octocat", "", "2024-03-01").unwrap(),
+            vec![
+                ("2024-03-09".to_string(), 1),
+                ("2024-03-10".to_string(), 3),
+                ("2024-03-11".to_string(), 1),
+                ("2024-11-03".to_string(), 3),
+                ("2024-11-04".to_string(), 1),
+            ],
+        );
+        // The local days themselves are 23 and 25 hours long.
+        let day_start = |day: &str| crate::daily::day_start(tz, day.parse().unwrap());
+        assert_eq!(day_start("2024-03-11") - day_start("2024-03-10"), 23 * 60 * 60);
+        assert_eq!(day_start("2024-11-04") - day_start("2024-11-03"), 25 * 60 * 60);
+    }
+
+    #[test]
+    fn pages_of_a_user_count_independently() {
+        let mut conn = conn();
+        assert_eq!(get_user_total(&mut conn, "octocat").unwrap(), None);
+
+        upsert_and_get_user_viewcount(&mut conn, "octocat", "repo-a", 1, 100).unwrap();
+        upsert_and_get_user_viewcount(&mut conn, "octocat", "repo-a", 1, 100).unwrap();
+        upsert_and_get_user_viewcount(&mut conn, "octocat", "repo-b", 1, 100).unwrap();
+        upsert_and_get_user_viewcount(&mut conn, "hubot", "repo-a", 5, 100).unwrap();
 
-	let updated_row = diesel::update(visitors.filter(id.eq(user)))
-		.set(view_count.eq(view_count + 1))
-		.execute(conn)?;
-	Ok(updated_row)
+        assert_eq!(get_user_viewcount(&mut conn, "octocat", "repo-a").unwrap().unwrap().view_count, 2);
+        assert_eq!(get_user_viewcount(&mut conn, "octocat", "repo-b").unwrap().unwrap().view_count, 1);
+        assert!(get_user_viewcount(&mut conn, "octocat", "").unwrap().is_none());
+        assert_eq!(add_user_viewcount(&mut conn, "octocat", "repo-c", 1, 100).unwrap(), 0);
+        assert_eq!(get_user_total(&mut conn, "octocat").unwrap(), Some(3));
+    }
 }
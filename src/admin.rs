@@ -0,0 +1,477 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use ab_glyph::FontArc;
+use actix_web::{
+    delete, error, get, http::StatusCode, post, web, HttpMessage, HttpRequest, HttpResponse, Responder, Result,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::ToSchema;
+
+use crate::actions;
+use crate::api_keys::{self, Granted, Scope};
+use crate::badge::BadgeSpec;
+use crate::hot_cache::{HitRate, HotBadgeCache};
+use crate::models::Visitors;
+use crate::render::OgImageCache;
+use crate::response::{svg_response, CachePolicy};
+use crate::signing;
+use crate::store::{AlreadyExists, CounterStore};
+use crate::DbPool;
+
+/// When the server started, for the uptime figure.
+pub struct StartedAt(pub Instant);
+
+/// Aggregates over the whole instance.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct InstanceStats {
+    pub total_counters: i64,
+    pub total_views: i64,
+    /// Views since the start of yesterday, in each counter's local days, so
+    /// between one and two days of traffic.
+    pub views_since_yesterday: i64,
+    pub top_counters: Vec<Visitors>,
+    pub db_file_size: Option<u64>,
+}
+
+/// Last computed `InstanceStats`, reused for `ttl` so the endpoint cannot
+/// be used to hammer the database.
+pub struct StatsCache {
+    ttl: Duration,
+    cached: Mutex<Option<(Instant, InstanceStats)>>,
+}
+
+impl StatsCache {
+    pub fn new(ttl: Duration) -> Self {
+        StatsCache { ttl, cached: Mutex::new(None) }
+    }
+
+    /// Reuse stats for `ADMIN_STATS_TTL_SECS` (default 60); 0 recomputes
+    /// them on every request.
+    pub fn from_env() -> Result<Self, String> {
+        let ttl = match std::env::var("ADMIN_STATS_TTL_SECS") {
+            Ok(secs) => secs.parse::<u64>()
+                .map_err(|_| format!("ADMIN_STATS_TTL_SECS should be a non-negative number of seconds, got {:?}", secs))?,
+            Err(_) => 60,
+        };
+        Ok(StatsCache::new(Duration::from_secs(ttl)))
+    }
+}
+
+/// Reject the request unless it carries `Authorization: Bearer
+/// <ADMIN_TOKEN>` or an API key with the admin scope: 401 without a token,
+/// 403 with the wrong one.
+pub fn check_admin(http_req: &HttpRequest) -> Result<(), HttpResponse> {
+    if http_req.extensions().get::<Granted>().is_some_and(|granted| granted.allows(Scope::Admin)) {
+        return Ok(());
+    }
+    let expected = match std::env::var("ADMIN_TOKEN") {
+        Ok(token) if !token.is_empty() => token,
+        _ => return Err(HttpResponse::Forbidden().json(json!({ "error": "admin API is disabled" }))),
+    };
+    match api_keys::bearer(http_req.headers()) {
+        None => Err(HttpResponse::Unauthorized()
+            .insert_header(("WWW-Authenticate", "Bearer"))
+            .json(json!({ "error": "missing admin token" }))),
+        Some(token) if signing::tokens_match(&expected, token) => Ok(()),
+        Some(_) => Err(HttpResponse::Forbidden().json(json!({ "error": "invalid admin token" }))),
+    }
+}
+
+async fn instance_stats(pool: web::Data<DbPool>, cache: &StatsCache) -> Result<InstanceStats> {
+    if let Some((computed_at, stats)) = cache.cached.lock().unwrap().as_ref() {
+        if computed_at.elapsed() < cache.ttl {
+            return Ok(stats.clone());
+        }
+    }
+
+    let since = (Utc::now() - chrono::Duration::days(1)).date_naive().to_string();
+    let stats = web::block(move || {
+        let mut conn = pool.get()?;
+        let (total_counters, total_views) = actions::count_totals(&mut conn)?;
+        Ok::<_, actions::DbError>(InstanceStats {
+            total_counters,
+            total_views,
+            views_since_yesterday: actions::views_since(&mut conn, &since)?,
+            top_counters: actions::top_users(&mut conn, 10)?,
+            db_file_size: db_file_size(),
+        })
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+
+    *cache.cached.lock().unwrap() = Some((Instant::now(), stats.clone()));
+    Ok(stats)
+}
+
+/// Size of the SQLite file named by `DATABASE_URL`, if it is a local file.
+fn db_file_size() -> Option<u64> {
+    let url = std::env::var("DATABASE_URL").ok()?;
+    let path = url.strip_prefix("sqlite://").unwrap_or(&url);
+    std::fs::metadata(path).ok().map(|meta| meta.len())
+}
+
+#[derive(Debug, Serialize)]
+struct CacheRates {
+    hot_badge: HitRate,
+    og_image: HitRate,
+}
+
+/// Instance-wide statistics for operators.
+#[utoipa::path(
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Instance statistics, cache hit rates and uptime"),
+        (status = 401, description = "Missing admin token"),
+        (status = 403, description = "Wrong admin token or admin API disabled"),
+    ),
+)]
+#[get("/admin/stats")]
+async fn get_stats(
+    pool: web::Data<DbPool>,
+    stats_cache: web::Data<StatsCache>,
+    hot_cache: web::Data<HotBadgeCache>,
+    og_cache: web::Data<OgImageCache>,
+    started_at: web::Data<StartedAt>,
+    http_req: HttpRequest,
+) -> Result<impl Responder> {
+    if let Err(response) = check_admin(&http_req) {
+        return Ok(response);
+    }
+    let stats = instance_stats(pool, &stats_cache).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "stats": stats,
+        "cache": CacheRates {
+            hot_badge: hot_cache.stats.snapshot(),
+            og_image: og_cache.stats.snapshot(),
+        },
+        "uptime_secs": started_at.0.elapsed().as_secs(),
+    })))
+}
+
+/// Badge showing total views across the instance.
+#[get("/admin/stats/badge")]
+async fn get_stats_badge(
+    pool: web::Data<DbPool>,
+    stats_cache: web::Data<StatsCache>,
+    font: web::Data<FontArc>,
+    http_req: HttpRequest,
+) -> Result<impl Responder> {
+    if let Err(response) = check_admin(&http_req) {
+        return Ok(response);
+    }
+    let stats = instance_stats(pool, &stats_cache).await?;
+    let spec = BadgeSpec::new("Total views", stats.total_views.to_string());
+    let badge_output = crate::render_spec(font.get_ref(), &spec);
+    Ok(svg_response(StatusCode::OK, badge_output, CachePolicy::NoCache))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetCountRequest {
+    view_count: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PageQuery {
+    page: Option<String>,
+}
+
+/// Set a counter to an exact value, creating it if needed.
+#[utoipa::path(
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(
+        ("user" = String, Path, description = "Counter id"),
+        ("page" = Option<String>, Query, description = "Page namespace"),
+    ),
+    request_body = SetCountRequest,
+    responses(
+        (status = 200, description = "The updated counter", body = Visitors),
+        (status = 400, description = "Negative count"),
+        (status = 401, description = "Missing admin token"),
+        (status = 403, description = "Wrong admin token or admin API disabled"),
+    ),
+)]
+#[post("/admin/count/{user}")]
+async fn set_count(
+    store: web::Data<dyn CounterStore>,
+    path: web::Path<String>,
+    query: web::Query<PageQuery>,
+    body: web::Json<SetCountRequest>,
+    http_req: HttpRequest,
+) -> Result<impl Responder> {
+    if let Err(response) = check_admin(&http_req) {
+        return Ok(response);
+    }
+    let page = match crate::page_param(query.page.as_deref()) {
+        Ok(page) => page,
+        Err(response) => return Ok(response),
+    };
+    let view_count = body.into_inner().view_count;
+    if view_count < 0 {
+        return Ok(HttpResponse::BadRequest().json(json!({ "error": "view_count must not be negative" })));
+    }
+    let user = path.into_inner();
+    let visitor = web::block(move || store.set(&user, &page, view_count))
+        .await?
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(visitor))
+}
+
+/// Remove a counter entirely.
+#[utoipa::path(
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(
+        ("user" = String, Path, description = "Counter id"),
+        ("page" = Option<String>, Query, description = "Page namespace"),
+    ),
+    responses(
+        (status = 204, description = "Counter deleted"),
+        (status = 404, description = "Counter not found"),
+        (status = 401, description = "Missing admin token"),
+        (status = 403, description = "Wrong admin token or admin API disabled"),
+    ),
+)]
+#[delete("/admin/count/{user}")]
+async fn delete_count(
+    store: web::Data<dyn CounterStore>,
+    path: web::Path<String>,
+    query: web::Query<PageQuery>,
+    http_req: HttpRequest,
+) -> Result<impl Responder> {
+    if let Err(response) = check_admin(&http_req) {
+        return Ok(response);
+    }
+    let page = match crate::page_param(query.page.as_deref()) {
+        Ok(page) => page,
+        Err(response) => return Ok(response),
+    };
+    remove_counter(store, path.into_inner(), page).await
+}
+
+async fn remove_counter(store: web::Data<dyn CounterStore>, user: String, page: String) -> Result<HttpResponse> {
+    let deleted = web::block(move || store.delete(&user, &page))
+        .await?
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(if deleted {
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::NotFound().json(json!({ "error": "counter not found" }))
+    })
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateCounterRequest {
+    id: String,
+    page: Option<String>,
+    #[serde(default)]
+    view_count: i64,
+}
+
+/// Create a counter, optionally starting above zero.
+#[utoipa::path(
+    tag = "admin",
+    security(("admin_token" = [])),
+    request_body = CreateCounterRequest,
+    responses(
+        (status = 201, description = "The new counter", body = Visitors),
+        (status = 400, description = "Invalid id, page or count"),
+        (status = 409, description = "Counter already exists"),
+        (status = 401, description = "Missing admin token"),
+        (status = 403, description = "Wrong admin token or admin API disabled"),
+    ),
+)]
+#[post("/admin/counters")]
+async fn create_counter(
+    store: web::Data<dyn CounterStore>,
+    body: web::Json<CreateCounterRequest>,
+    http_req: HttpRequest,
+) -> Result<impl Responder> {
+    if let Err(response) = check_admin(&http_req) {
+        return Ok(response);
+    }
+    let CreateCounterRequest { id, page, view_count } = body.into_inner();
+    if !crate::is_valid_page(&id) {
+        return Ok(HttpResponse::BadRequest().json(json!({ "error": "invalid counter id" })));
+    }
+    let page = match crate::page_param(page.as_deref()) {
+        Ok(page) => page,
+        Err(response) => return Ok(response),
+    };
+    if view_count < 0 {
+        return Ok(HttpResponse::BadRequest().json(json!({ "error": "view_count must not be negative" })));
+    }
+    let visitor = web::block(move || {
+        let visitor = match store.create(&id, &page) {
+            Ok(visitor) => visitor,
+            Err(err) if err.is::<AlreadyExists>() => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        if view_count == 0 {
+            return Ok(Some(visitor));
+        }
+        store.set(&id, &page, view_count).map(Some)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    Ok(match visitor {
+        Some(visitor) => HttpResponse::Created().json(visitor),
+        None => HttpResponse::Conflict().json(json!({ "error": "counter already exists" })),
+    })
+}
+
+/// Every counter, ordered by id and page.
+#[utoipa::path(
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "All counters", body = [Visitors]),
+        (status = 401, description = "Missing admin token"),
+        (status = 403, description = "Wrong admin token or admin API disabled"),
+    ),
+)]
+#[get("/admin/counters")]
+async fn list_counters(
+    store: web::Data<dyn CounterStore>,
+    http_req: HttpRequest,
+) -> Result<impl Responder> {
+    if let Err(response) = check_admin(&http_req) {
+        return Ok(response);
+    }
+    let visitors = web::block(move || store.iter())
+        .await?
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(visitors))
+}
+
+/// One counter with its count and last view time.
+#[utoipa::path(
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(
+        ("id" = String, Path, description = "Counter id"),
+        ("page" = Option<String>, Query, description = "Page namespace"),
+    ),
+    responses(
+        (status = 200, description = "The counter", body = Visitors),
+        (status = 404, description = "Counter not found"),
+        (status = 401, description = "Missing admin token"),
+        (status = 403, description = "Wrong admin token or admin API disabled"),
+    ),
+)]
+#[get("/admin/counters/{id}")]
+async fn get_counter(
+    store: web::Data<dyn CounterStore>,
+    path: web::Path<String>,
+    query: web::Query<PageQuery>,
+    http_req: HttpRequest,
+) -> Result<impl Responder> {
+    if let Err(response) = check_admin(&http_req) {
+        return Ok(response);
+    }
+    let page = match crate::page_param(query.page.as_deref()) {
+        Ok(page) => page,
+        Err(response) => return Ok(response),
+    };
+    let user = path.into_inner();
+    let visitor = web::block(move || store.get(&user, &page))
+        .await?
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(match visitor {
+        Some(visitor) => HttpResponse::Ok().json(visitor),
+        None => HttpResponse::NotFound().json(json!({ "error": "counter not found" })),
+    })
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ResetRequest {
+    view_count: i64,
+}
+
+/// Start a counter over at zero, or at `view_count` when a body is sent.
+#[utoipa::path(
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(
+        ("id" = String, Path, description = "Counter id"),
+        ("page" = Option<String>, Query, description = "Page namespace"),
+    ),
+    request_body(content = ResetRequest, description = "Optional count to start from, 0 by default"),
+    responses(
+        (status = 200, description = "The reset counter", body = Visitors),
+        (status = 400, description = "Negative count"),
+        (status = 404, description = "Counter not found"),
+        (status = 401, description = "Missing admin token"),
+        (status = 403, description = "Wrong admin token or admin API disabled"),
+    ),
+)]
+#[post("/admin/counters/{id}/reset")]
+async fn reset_counter(
+    store: web::Data<dyn CounterStore>,
+    path: web::Path<String>,
+    query: web::Query<PageQuery>,
+    body: Option<web::Json<ResetRequest>>,
+    http_req: HttpRequest,
+) -> Result<impl Responder> {
+    if let Err(response) = check_admin(&http_req) {
+        return Ok(response);
+    }
+    let page = match crate::page_param(query.page.as_deref()) {
+        Ok(page) => page,
+        Err(response) => return Ok(response),
+    };
+    let view_count = body.map_or(0, |body| body.view_count);
+    if view_count < 0 {
+        return Ok(HttpResponse::BadRequest().json(json!({ "error": "view_count must not be negative" })));
+    }
+    let user = path.into_inner();
+    // Unlike `POST /admin/count/{user}`, resetting never creates a counter.
+    let visitor = web::block(move || {
+        if store.get(&user, &page)?.is_none() {
+            return Ok(None);
+        }
+        store.set(&user, &page, view_count).map(Some)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    Ok(match visitor {
+        Some(visitor) => HttpResponse::Ok().json(visitor),
+        None => HttpResponse::NotFound().json(json!({ "error": "counter not found" })),
+    })
+}
+
+/// Remove a counter entirely; same as `DELETE /admin/count/{user}`.
+#[utoipa::path(
+    tag = "admin",
+    security(("admin_token" = [])),
+    params(
+        ("id" = String, Path, description = "Counter id"),
+        ("page" = Option<String>, Query, description = "Page namespace"),
+    ),
+    responses(
+        (status = 204, description = "Counter deleted"),
+        (status = 404, description = "Counter not found"),
+        (status = 401, description = "Missing admin token"),
+        (status = 403, description = "Wrong admin token or admin API disabled"),
+    ),
+)]
+#[delete("/admin/counters/{id}")]
+async fn delete_counter(
+    store: web::Data<dyn CounterStore>,
+    path: web::Path<String>,
+    query: web::Query<PageQuery>,
+    http_req: HttpRequest,
+) -> Result<impl Responder> {
+    if let Err(response) = check_admin(&http_req) {
+        return Ok(response);
+    }
+    let page = match crate::page_param(query.page.as_deref()) {
+        Ok(page) => page,
+        Err(response) => return Ok(response),
+    };
+    remove_counter(store, path.into_inner(), page).await
+}
@@ -0,0 +1,112 @@
+use actix_web::{delete, error, get, post, web, HttpRequest, HttpResponse, Responder, Result};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::actions;
+use crate::admin::check_admin;
+use crate::models::Alias;
+use crate::store::CounterStore;
+use crate::DbPool;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateAliasRequest {
+    alias: String,
+    target: String,
+}
+
+/// Why an alias could not be created.
+enum Rejected {
+    TargetMissing,
+    TargetIsAlias,
+    AliasTaken,
+}
+
+/// Make `alias` a second id for the counter `target`, e.g. the new name of
+/// a renamed repository, so both count on the old history.
+#[post("/admin/aliases")]
+async fn create_alias(
+    pool: web::Data<DbPool>,
+    store: web::Data<dyn CounterStore>,
+    body: web::Json<CreateAliasRequest>,
+    http_req: HttpRequest,
+) -> Result<impl Responder> {
+    if let Err(response) = check_admin(&http_req) {
+        return Ok(response);
+    }
+    let CreateAliasRequest { alias, target } = body.into_inner();
+    if !crate::is_valid_page(&alias) || !crate::is_valid_page(&target) {
+        return Ok(HttpResponse::BadRequest().json(json!({ "error": "invalid counter id" })));
+    }
+    if alias == target {
+        return Ok(HttpResponse::BadRequest().json(json!({ "error": "a counter cannot alias itself" })));
+    }
+
+    let new_alias = Alias { alias, target, created_at: Utc::now().timestamp() };
+    let created = new_alias.clone();
+    let result = web::block(move || {
+        // Aliases point straight at counters, never at other aliases.
+        if store.user_total(&new_alias.target)?.is_none() {
+            return Ok(Err(Rejected::TargetMissing));
+        }
+        if store.alias_target(&new_alias.target)?.is_some() {
+            return Ok(Err(Rejected::TargetIsAlias));
+        }
+        if store.user_total(&new_alias.alias)?.is_some() || store.alias_target(&new_alias.alias)?.is_some() {
+            return Ok(Err(Rejected::AliasTaken));
+        }
+        let mut conn = pool.get()?;
+        actions::insert_alias(&mut conn, &new_alias).map(Ok)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    Ok(match result {
+        Ok(()) => HttpResponse::Created().json(created),
+        Err(Rejected::TargetMissing) => HttpResponse::NotFound().json(json!({ "error": "target counter not found" })),
+        Err(Rejected::TargetIsAlias) => {
+            HttpResponse::BadRequest().json(json!({ "error": "target is itself an alias" }))
+        },
+        Err(Rejected::AliasTaken) => {
+            HttpResponse::Conflict().json(json!({ "error": "a counter or alias with this id exists" }))
+        },
+    })
+}
+
+/// Every alias with its target.
+#[get("/admin/aliases")]
+async fn list_aliases(pool: web::Data<DbPool>, http_req: HttpRequest) -> Result<impl Responder> {
+    if let Err(response) = check_admin(&http_req) {
+        return Ok(response);
+    }
+    let aliases = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::list_aliases(&mut conn)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(aliases))
+}
+
+/// Remove an alias. The target counter and its history stay.
+#[delete("/admin/aliases/{alias}")]
+async fn delete_alias(
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+    http_req: HttpRequest,
+) -> Result<impl Responder> {
+    if let Err(response) = check_admin(&http_req) {
+        return Ok(response);
+    }
+    let alias = path.into_inner();
+    let deleted = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::delete_alias(&mut conn, &alias)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    Ok(if deleted {
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::NotFound().json(json!({ "error": "alias not found" }))
+    })
+}
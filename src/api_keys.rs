@@ -0,0 +1,311 @@
+use std::fmt;
+use std::str::FromStr;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::HeaderMap;
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{delete, error, get, post, web, HttpMessage, HttpRequest, HttpResponse, Responder, Result};
+use chrono::Utc;
+use diesel::result::DatabaseErrorKind;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::actions;
+use crate::admin::check_admin;
+use crate::claim::{hash_key, random_hex};
+use crate::models::ApiKey;
+use crate::signing;
+use crate::DbPool;
+
+/// What an API key may do. `Admin` covers everything `Write` does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// `/admin/*` endpoints.
+    Admin,
+    /// Counter mutations under `/api/counters`.
+    Write,
+}
+
+impl Scope {
+    pub fn name(self) -> &'static str {
+        match self {
+            Scope::Admin => "admin",
+            Scope::Write => "write",
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "admin" => Ok(Scope::Admin),
+            "write" => Ok(Scope::Write),
+            other => Err(format!("unknown scope {:?}, expected admin or write", other)),
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Scopes the request was authenticated for, left in the request extensions
+/// by `guard`.
+#[derive(Debug, Clone)]
+pub struct Granted(pub Vec<Scope>);
+
+impl Granted {
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.0.iter().any(|granted| *granted == scope || *granted == Scope::Admin)
+    }
+}
+
+/// Scope a route needs, if any.
+fn required_scope(method: &Method, path: &str) -> Option<Scope> {
+    let base_path = crate::base_path();
+    let path = path.strip_prefix(base_path.as_str()).unwrap_or(path);
+    let path = path.strip_prefix("/v1").unwrap_or(path);
+    if path.starts_with("/admin/") {
+        return Some(Scope::Admin);
+    }
+    let is_write = !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS);
+    (is_write && path.starts_with("/api/counters/")).then_some(Scope::Write)
+}
+
+pub fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers.get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// Middleware guarding admin and write routes. `ADMIN_TOKEN` grants every
+/// scope; otherwise the bearer token is looked up among the hashed API keys.
+pub async fn guard(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>> {
+    // The percent-decoded path the router matches on, so `/api/%63ounters`
+    // cannot reach a guarded route unchecked.
+    let scope = match required_scope(req.method(), req.match_info().as_str()) {
+        Some(scope) => scope,
+        None => return next.call(req).await,
+    };
+    let token = bearer(req.headers()).map(str::to_string);
+    let granted = match token.as_deref() {
+        None => Granted(Vec::new()),
+        Some(token) => granted(req.app_data::<web::Data<DbPool>>(), token).await?,
+    };
+    if !granted.allows(scope) {
+        return Err(rejection(scope, token.is_some()));
+    }
+    req.extensions_mut().insert(granted);
+    next.call(req).await
+}
+
+/// Refuse the request unless it was granted `scope`: 401 without a token,
+/// 403 with one that lacks the scope. Write handlers call this themselves
+/// instead of trusting that `guard` recognized their path.
+pub async fn check_scope(http_req: &HttpRequest, scope: Scope) -> Result<()> {
+    let checked = http_req.extensions().get::<Granted>().cloned();
+    let token = bearer(http_req.headers());
+    let granted = match (checked, token) {
+        (Some(granted), _) => granted,
+        (None, None) => Granted(Vec::new()),
+        (None, Some(token)) => granted(http_req.app_data::<web::Data<DbPool>>(), token).await?,
+    };
+    if granted.allows(scope) {
+        Ok(())
+    } else {
+        Err(rejection(scope, token.is_some()))
+    }
+}
+
+fn rejection(scope: Scope, has_token: bool) -> error::Error {
+    let response = if has_token {
+        HttpResponse::Forbidden().json(json!({ "error": format!("API key lacks the {} scope", scope) }))
+    } else {
+        HttpResponse::Unauthorized()
+            .insert_header(("WWW-Authenticate", "Bearer"))
+            .json(json!({ "error": "missing API key" }))
+    };
+    error::InternalError::from_response("unauthorized", response).into()
+}
+
+/// Scopes `token` grants: all of them for `ADMIN_TOKEN`, otherwise those of
+/// the matching API key, if any.
+pub async fn granted(pool: Option<&web::Data<DbPool>>, token: &str) -> Result<Granted> {
+    let admin_token = std::env::var("ADMIN_TOKEN").unwrap_or_default();
+    if !admin_token.is_empty() && signing::tokens_match(&admin_token, token) {
+        return Ok(Granted(vec![Scope::Admin, Scope::Write]));
+    }
+    // API keys need the SQL store.
+    let pool = match pool {
+        Some(pool) => pool.clone(),
+        None => return Ok(Granted(Vec::new())),
+    };
+    let hash = hash_key(token);
+    let key = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::find_api_key(&mut conn, &hash)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    Ok(Granted(key.map_or_else(Vec::new, |key| parse_scopes(&key.scopes))))
+}
+
+/// Scopes from their stored comma-separated form, skipping unknown ones.
+fn parse_scopes(scopes: &str) -> Vec<Scope> {
+    scopes.split(',').filter_map(|scope| scope.parse().ok()).collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateKeyRequest {
+    name: String,
+    scopes: Vec<String>,
+}
+
+/// Issue a new API key. The key itself is only ever shown in this response.
+#[post("/admin/keys")]
+async fn create_key(
+    pool: web::Data<DbPool>,
+    body: web::Json<CreateKeyRequest>,
+    http_req: HttpRequest,
+) -> Result<impl Responder> {
+    if let Err(response) = check_admin(&http_req) {
+        return Ok(response);
+    }
+    let CreateKeyRequest { name, scopes } = body.into_inner();
+    if name.trim().is_empty() || name.len() > 64 {
+        return Ok(HttpResponse::BadRequest().json(json!({ "error": "name must be 1 to 64 characters" })));
+    }
+    let scopes = match scopes.iter().map(|scope| scope.parse::<Scope>()).collect::<Result<Vec<_>, _>>() {
+        Ok(scopes) if !scopes.is_empty() => scopes,
+        Ok(_) => return Ok(HttpResponse::BadRequest().json(json!({ "error": "at least one scope is required" }))),
+        Err(err) => return Ok(HttpResponse::BadRequest().json(json!({ "error": err }))),
+    };
+    let scope_names: Vec<&str> = scopes.iter().map(|scope| scope.name()).collect();
+
+    let api_key = format!("vbk_{}", random_hex());
+    let key = ApiKey {
+        key_hash: hash_key(&api_key),
+        name: name.clone(),
+        scopes: scope_names.join(","),
+        created_at: Utc::now().timestamp(),
+    };
+    // Names are unique in the table, so a taken name fails the insert
+    // rather than being checked first, which two requests could both pass.
+    let created = web::block(move || {
+        let mut conn = pool.get()?;
+        match actions::insert_api_key(&mut conn, &key) {
+            Ok(()) => Ok(true),
+            Err(err) if matches!(
+                err.downcast_ref::<diesel::result::Error>(),
+                Some(diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)),
+            ) => Ok(false),
+            Err(err) => Err(err),
+        }
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    if !created {
+        return Ok(HttpResponse::Conflict().json(json!({ "error": "an API key with this name exists" })));
+    }
+    Ok(HttpResponse::Created().json(json!({ "name": name, "scopes": scope_names, "api_key": api_key })))
+}
+
+/// Names and scopes of all API keys.
+#[get("/admin/keys")]
+async fn list_keys(pool: web::Data<DbPool>, http_req: HttpRequest) -> Result<impl Responder> {
+    if let Err(response) = check_admin(&http_req) {
+        return Ok(response);
+    }
+    let keys = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::list_api_keys(&mut conn)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    let keys: Vec<_> = keys.into_iter()
+        .map(|key| {
+            let scopes: Vec<&str> = parse_scopes(&key.scopes).into_iter().map(Scope::name).collect();
+            json!({ "name": key.name, "scopes": scopes, "created_at": key.created_at })
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(keys))
+}
+
+/// Revoke an API key by name.
+#[delete("/admin/keys/{name}")]
+async fn delete_key(
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+    http_req: HttpRequest,
+) -> Result<impl Responder> {
+    if let Err(response) = check_admin(&http_req) {
+        return Ok(response);
+    }
+    let name = path.into_inner();
+    let deleted = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::delete_api_key(&mut conn, &name)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    Ok(if deleted {
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::NotFound().json(json!({ "error": "API key not found" }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::middleware::from_fn;
+    use actix_web::test::{init_service, try_call_service, TestRequest};
+    use actix_web::{App, http::StatusCode};
+
+    use super::*;
+
+    #[test]
+    fn writes_under_counters_need_the_write_scope() {
+        assert_eq!(required_scope(&Method::POST, "/api/counters/me/increment"), Some(Scope::Write));
+        assert_eq!(required_scope(&Method::POST, "/v1/api/counters/me/increment"), Some(Scope::Write));
+        assert_eq!(required_scope(&Method::GET, "/api/counters/me/events"), None);
+        assert_eq!(required_scope(&Method::GET, "/admin/stats"), Some(Scope::Admin));
+        assert_eq!(required_scope(&Method::GET, "/count/me"), None);
+    }
+
+    #[actix_web::test]
+    async fn encoded_paths_do_not_skip_the_guard() {
+        let app = init_service(
+            App::new()
+                .wrap(from_fn(guard))
+                .route("/api/counters/{id}/increment", web::post().to(HttpResponse::Ok)),
+        )
+        .await;
+        for uri in ["/api/counters/me/increment", "/api/%63ounters/me/increment", "/%61pi/counters/me/increment"] {
+            let err = try_call_service(&app, TestRequest::post().uri(uri).to_request()).await.err().unwrap();
+            assert_eq!(err.as_response_error().status_code(), StatusCode::UNAUTHORIZED, "{}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn handlers_refuse_requests_the_guard_never_saw() {
+        let request = TestRequest::post().uri("/api/counters/me/increment").to_http_request();
+        let err = check_scope(&request, Scope::Write).await.unwrap_err();
+        assert_eq!(err.as_response_error().status_code(), StatusCode::UNAUTHORIZED);
+
+        let request = TestRequest::post().insert_header(("Authorization", "Bearer vbk_test")).to_http_request();
+        request.extensions_mut().insert(Granted(vec![Scope::Write]));
+        assert!(check_scope(&request, Scope::Write).await.is_ok());
+        let err = check_scope(&request, Scope::Admin).await.unwrap_err();
+        assert_eq!(err.as_response_error().status_code(), StatusCode::FORBIDDEN);
+    }
+}
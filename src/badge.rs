@@ -0,0 +1,501 @@
+use std::fmt;
+use std::str::FromStr;
+
+use ab_glyph::FontArc;
+use serde::{Deserialize, Serialize};
+use shield_maker::{FontFamily, Metadata, Style};
+
+use crate::daily::Granularity;
+use crate::store::DEFAULT_PAGE;
+
+/// Named colors understood by shield_maker on top of CSS colors.
+const SHIELDS_COLORS: &[&str] = &[
+    "brightgreen", "green", "yellowgreen", "yellow", "orange", "red", "blue",
+    "grey", "gray", "lightgrey", "lightgray", "success", "important",
+    "critical", "informational", "inactive",
+];
+
+pub const DEFAULT_COLOR: &str = "orange";
+
+/// Longest label accepted from a query string, in characters.
+pub const MAX_LABEL_CHARS: usize = 64;
+
+/// Owned, serializable description of a badge, e.g. from a config file or
+/// an API payload.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BadgeSpec {
+    pub label: String,
+    pub message: String,
+    #[serde(default)]
+    pub style: Option<String>,
+    #[serde(default)]
+    pub color: Option<String>,
+    #[serde(default)]
+    pub label_color: Option<String>,
+    #[serde(default)]
+    pub font_family: Option<String>,
+}
+
+impl BadgeSpec {
+    /// Spec for a counter badge in the default look.
+    pub fn new(label: impl Into<String>, message: impl Into<String>) -> Self {
+        BadgeSpec {
+            label: label.into(),
+            message: message.into(),
+            style: None,
+            color: Some(DEFAULT_COLOR.to_string()),
+            label_color: None,
+            font_family: None,
+        }
+    }
+
+    /// Validate the spec and borrow it as shield_maker `Metadata`.
+    pub fn to_metadata(&self, font: FontArc) -> Result<Metadata<'_>, SpecError> {
+        let style = match &self.style {
+            Some(style) => style.parse::<BadgeStyle>()?,
+            None => BadgeStyle::default(),
+        };
+        let font_family = match self.font_family.as_deref() {
+            None | Some("default") => FontFamily::Default,
+            Some(other) => return Err(SpecError::UnknownFontFamily(other.to_string())),
+        };
+        for color in [&self.color, &self.label_color].into_iter().flatten() {
+            if !is_valid_color(color) {
+                return Err(SpecError::InvalidColor(color.clone()));
+            }
+        }
+
+        Ok(Metadata {
+            style: style.to_style(),
+            label: &self.label,
+            message: &self.message,
+            font,
+            font_family,
+            label_color: self.label_color.as_deref(),
+            color: self.color.as_deref(),
+        })
+    }
+}
+
+/// Badge styles supported by shield_maker, parseable from their shields.io
+/// names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BadgeStyle {
+    Plastic,
+    Flat,
+    #[default]
+    FlatSquare,
+}
+
+impl BadgeStyle {
+    pub const NAMES: &'static [&'static str] = &["plastic", "flat", "flat-square"];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BadgeStyle::Plastic => "plastic",
+            BadgeStyle::Flat => "flat",
+            BadgeStyle::FlatSquare => "flat-square",
+        }
+    }
+
+    pub fn to_style(self) -> Style {
+        match self {
+            BadgeStyle::Plastic => Style::Plastic,
+            BadgeStyle::Flat => Style::Flat,
+            BadgeStyle::FlatSquare => Style::FlatSquare,
+        }
+    }
+}
+
+impl FromStr for BadgeStyle {
+    type Err = SpecError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "plastic" => Ok(BadgeStyle::Plastic),
+            "flat" => Ok(BadgeStyle::Flat),
+            "flat-square" | "flat_square" | "flatsquare" => Ok(BadgeStyle::FlatSquare),
+            _ => Err(SpecError::UnknownStyle(s.to_string())),
+        }
+    }
+}
+
+/// Which number a counter badge displays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+    /// Lifetime views.
+    #[default]
+    Total,
+    /// Views since local midnight in the counter's time zone.
+    Today,
+    /// Distinct visitors, each counted once per `UNIQUE_WINDOW_SECS`.
+    Unique,
+    /// Views since Monday in the counter's time zone.
+    Week,
+    /// Views since the first of the month in the counter's time zone.
+    Month,
+    /// How long ago the previous view was counted, e.g. "3 hours ago". The
+    /// bare count is its Unix time, or 0 when there was none.
+    #[serde(rename = "last_visit")]
+    LastVisit,
+    /// Consecutive days with views, up to the retained history.
+    Streak,
+    /// Distinct countries views came from, known while a GeoIP database is
+    /// configured.
+    Countries,
+}
+
+impl From<Granularity> for Metric {
+    /// The metric `?period=` asks for.
+    fn from(period: Granularity) -> Self {
+        match period {
+            Granularity::Day => Metric::Today,
+            Granularity::Week => Metric::Week,
+            Granularity::Month => Metric::Month,
+        }
+    }
+}
+
+/// Format of a badge response: an image, or the bare count for API
+/// consumers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Svg,
+    Png,
+    Json,
+    Text,
+}
+
+impl OutputFormat {
+    /// Formats in order of preference when the client likes several equally.
+    const PREFERENCE: &'static [(OutputFormat, &'static str)] = &[
+        (OutputFormat::Svg, "image/svg+xml"),
+        (OutputFormat::Png, "image/png"),
+        (OutputFormat::Json, "application/json"),
+        (OutputFormat::Text, "text/plain"),
+    ];
+
+    /// `?format=` wins. Otherwise the format with the highest `Accept`
+    /// q-value, with `type/*` and `*/*` counting for every format they
+    /// cover. Ties and clients that accept none of them get SVG, so browsers
+    /// and GitHub keep getting SVG.
+    pub fn negotiate(requested: Option<OutputFormat>, accept: Option<&str>) -> Self {
+        if let Some(format) = requested {
+            return format;
+        }
+        let ranges = parse_accept(accept.unwrap_or(""));
+        let mut best = (OutputFormat::Svg, 0.0);
+        for (format, media_type) in Self::PREFERENCE {
+            let q = accept_quality(&ranges, media_type);
+            if q > best.1 {
+                best = (*format, q);
+            }
+        }
+        best.0
+    }
+}
+
+/// Media ranges of an `Accept` header with their q-values.
+fn parse_accept(header: &str) -> Vec<(String, f32)> {
+    header
+        .split(',')
+        .filter_map(|part| {
+            let mut pieces = part.split(';');
+            let range = pieces.next()?.trim().to_ascii_lowercase();
+            if range.is_empty() {
+                return None;
+            }
+            let q = pieces
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .map(|q| q.trim().parse::<f32>().unwrap_or(0.0))
+                .unwrap_or(1.0);
+            Some((range, q))
+        })
+        .collect()
+}
+
+/// q-value of `media_type` under the most specific matching range, 0 when
+/// nothing matches.
+fn accept_quality(ranges: &[(String, f32)], media_type: &str) -> f32 {
+    let type_wildcard = format!("{}/*", media_type.split('/').next().unwrap_or(""));
+    [media_type, type_wildcard.as_str(), "*/*"]
+        .iter()
+        .find_map(|candidate| ranges.iter().find(|(range, _)| range == candidate).map(|(_, q)| *q))
+        .unwrap_or(0.0)
+}
+
+/// One counter badge served by the app: where it is mounted, which counter
+/// it counts and how it looks when the query string does not say otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BadgeConfig {
+    /// Route the badge is served at, e.g. `/` or `/downloads`.
+    pub path: String,
+    /// Counter id the badge counts.
+    pub user: String,
+    /// Page namespace of the counter, unless the query string names one.
+    pub page: String,
+    /// Label used instead of the localized one.
+    pub label: Option<String>,
+    pub color: Option<String>,
+    pub style: BadgeStyle,
+    /// Counts below this are shown as a placeholder such as "< 100".
+    pub min_count: Option<i64>,
+    /// Whether the first view creates the counter; otherwise unknown
+    /// counters get a "not found" badge.
+    pub create: bool,
+}
+
+impl Default for BadgeConfig {
+    fn default() -> Self {
+        BadgeConfig {
+            path: "/".to_string(),
+            user: "me".to_string(),
+            page: DEFAULT_PAGE.to_string(),
+            label: None,
+            color: None,
+            style: BadgeStyle::default(),
+            min_count: None,
+            create: true,
+        }
+    }
+}
+
+impl BadgeConfig {
+    /// Badges named in `BADGES` (comma-separated), each configured through
+    /// `BADGE_<NAME>_PATH` (default `/<name>`), `_COUNTER` (default the
+    /// name), `_PAGE`, `_LABEL`, `_COLOR`, `_STYLE` and `_MIN_COUNT`.
+    /// Without `BADGES` there is a single `me` badge at `/`, labelled
+    /// `BADGE_LABEL` if set.
+    pub fn list_from_env() -> Result<Vec<BadgeConfig>, String> {
+        let names = match std::env::var("BADGES") {
+            Ok(names) if !names.trim().is_empty() => names,
+            _ => {
+                let label = std::env::var("BADGE_LABEL").ok().filter(|label| !label.is_empty());
+                return Ok(vec![BadgeConfig { label, ..BadgeConfig::default() }]);
+            },
+        };
+        let mut configs: Vec<BadgeConfig> = Vec::new();
+        for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let config = BadgeConfig::from_env(name)?;
+            if configs.iter().any(|other| other.path == config.path) {
+                return Err(format!("BADGES: more than one badge is mounted at {}", config.path));
+            }
+            configs.push(config);
+        }
+        Ok(configs)
+    }
+
+    fn from_env(name: &str) -> Result<BadgeConfig, String> {
+        let prefix = format!("BADGE_{}_", name.to_ascii_uppercase().replace('-', "_"));
+        let var = |key: &str| std::env::var(format!("{}{}", prefix, key)).ok().filter(|value| !value.is_empty());
+
+        let path = var("PATH").unwrap_or_else(|| format!("/{}", name));
+        if !path.starts_with('/') {
+            return Err(format!("{}PATH should start with '/', got {:?}", prefix, path));
+        }
+        let color = var("COLOR");
+        if let Some(color) = color.as_deref().filter(|color| !is_valid_color(color)) {
+            return Err(format!("{}COLOR: invalid color {:?}", prefix, color));
+        }
+        let page = var("PAGE").unwrap_or_else(|| DEFAULT_PAGE.to_string());
+        if page != DEFAULT_PAGE && !crate::is_valid_page(&page) {
+            return Err(format!("{}PAGE: invalid page {:?}", prefix, page));
+        }
+        let style = match var("STYLE") {
+            Some(style) => style.parse::<BadgeStyle>().map_err(|err| format!("{}STYLE: {}", prefix, err))?,
+            None => BadgeStyle::default(),
+        };
+        let min_count = match var("MIN_COUNT") {
+            Some(min_count) => match min_count.parse::<i64>() {
+                Ok(min_count) if min_count > 0 => Some(min_count),
+                _ => return Err(format!("{}MIN_COUNT: expected a positive number, got {:?}", prefix, min_count)),
+            },
+            None => None,
+        };
+        Ok(BadgeConfig {
+            path,
+            user: var("COUNTER").unwrap_or_else(|| name.to_string()),
+            page,
+            label: var("LABEL"),
+            color,
+            style,
+            min_count,
+            create: true,
+        })
+    }
+}
+
+/// Whether the first view of an unknown counter id creates it, starting at
+/// one. On unless `AUTO_CREATE_COUNTERS` is `false` or `0`, in which case
+/// only counters created through the admin API or a configured badge count.
+pub fn auto_create() -> bool {
+    std::env::var("AUTO_CREATE_COUNTERS")
+        .map(|value| value != "false" && value != "0")
+        .unwrap_or(true)
+}
+
+/// Accept shields.io color names, CSS colors and bare hex codes.
+pub fn is_valid_color(color: &str) -> bool {
+    let color = color.trim();
+    if SHIELDS_COLORS.contains(&color.to_ascii_lowercase().as_str()) {
+        return true;
+    }
+    is_bare_hex(color) || color.parse::<css_color_parser::Color>().is_ok()
+}
+
+/// `color` as shield_maker parses it: bare hex codes get their `#`.
+pub fn css_color(color: &str) -> String {
+    let color = color.trim();
+    if is_bare_hex(color) {
+        format!("#{}", color)
+    } else {
+        color.to_string()
+    }
+}
+
+fn is_bare_hex(color: &str) -> bool {
+    matches!(color.len(), 3 | 6) && color.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpecError {
+    UnknownStyle(String),
+    InvalidColor(String),
+    UnknownFontFamily(String),
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpecError::UnknownStyle(style) => write!(
+                f,
+                "unknown style {:?}, expected one of: {}",
+                style,
+                BadgeStyle::NAMES.join(", ")
+            ),
+            SpecError::InvalidColor(color) => write!(f, "invalid color {:?}", color),
+            SpecError::UnknownFontFamily(family) => write!(f, "unknown font family {:?}", family),
+        }
+    }
+}
+
+impl std::error::Error for SpecError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn negotiate(accept: &str) -> OutputFormat {
+        OutputFormat::negotiate(None, Some(accept))
+    }
+
+    #[test]
+    fn negotiate_prefers_highest_q_value() {
+        assert_eq!(negotiate("image/png;q=0.1, image/svg+xml;q=0"), OutputFormat::Png);
+        assert_eq!(negotiate("image/svg+xml;q=0.5, image/png"), OutputFormat::Png);
+        assert_eq!(negotiate("application/json;q=0.9, text/plain"), OutputFormat::Text);
+        assert_eq!(negotiate("image/*;q=0.2, application/json;q=0.8"), OutputFormat::Json);
+    }
+
+    #[test]
+    fn negotiate_falls_back_to_svg() {
+        assert_eq!(OutputFormat::negotiate(None, None), OutputFormat::Svg);
+        assert_eq!(negotiate(""), OutputFormat::Svg);
+        assert_eq!(negotiate("*/*"), OutputFormat::Svg);
+        assert_eq!(negotiate("image/png, image/svg+xml"), OutputFormat::Svg);
+        assert_eq!(negotiate("application/json, image/*"), OutputFormat::Svg);
+        assert_eq!(negotiate("image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8"), OutputFormat::Svg);
+        assert_eq!(negotiate("text/html,application/xhtml+xml,*/*;q=0.8"), OutputFormat::Svg);
+    }
+
+    #[test]
+    fn negotiate_honours_requested_format() {
+        assert_eq!(OutputFormat::negotiate(Some(OutputFormat::Json), Some("image/svg+xml")), OutputFormat::Json);
+    }
+
+    fn font() -> FontArc {
+        let bytes = std::fs::read("src/fonts/DejaVuSans.ttf").expect("bundled font");
+        FontArc::try_from_vec(bytes).expect("valid font")
+    }
+
+    #[test]
+    fn spec_round_trips_through_json() {
+        let spec = BadgeSpec {
+            label: "views".to_string(),
+            message: "42".to_string(),
+            style: Some("flat".to_string()),
+            color: Some("#4c1".to_string()),
+            label_color: None,
+            font_family: Some("default".to_string()),
+        };
+        let json = serde_json::to_string(&spec).unwrap();
+        assert_eq!(serde_json::from_str::<BadgeSpec>(&json).unwrap(), spec);
+    }
+
+    #[test]
+    fn spec_defaults_optional_fields() {
+        let spec: BadgeSpec = serde_json::from_str(r#"{"label":"a","message":"b"}"#).unwrap();
+        assert_eq!(spec.style, None);
+        assert_eq!(spec.color, None);
+    }
+
+    #[test]
+    fn to_metadata_reports_bad_fields() {
+        let mut spec = BadgeSpec::new("views", "1");
+        spec.style = Some("rounded".to_string());
+        assert_eq!(spec.to_metadata(font()).err(), Some(SpecError::UnknownStyle("rounded".to_string())));
+
+        let mut spec = BadgeSpec::new("views", "1");
+        spec.color = Some("notacolor".to_string());
+        assert_eq!(spec.to_metadata(font()).err(), Some(SpecError::InvalidColor("notacolor".to_string())));
+
+        let mut spec = BadgeSpec::new("views", "1");
+        spec.font_family = Some("comic".to_string());
+        assert_eq!(spec.to_metadata(font()).err(), Some(SpecError::UnknownFontFamily("comic".to_string())));
+
+        let mut spec = BadgeSpec::new("views", "1");
+        spec.style = Some("Flat_Square".to_string());
+        assert!(spec.to_metadata(font()).is_ok());
+    }
+
+    #[test]
+    fn style_parses_case_insensitively() {
+        assert_eq!("flat".parse::<BadgeStyle>(), Ok(BadgeStyle::Flat));
+        assert_eq!("PLASTIC".parse::<BadgeStyle>(), Ok(BadgeStyle::Plastic));
+        assert_eq!(" Flat-Square ".parse::<BadgeStyle>(), Ok(BadgeStyle::FlatSquare));
+        assert_eq!("flat_square".parse::<BadgeStyle>(), Ok(BadgeStyle::FlatSquare));
+        assert_eq!(BadgeStyle::default(), BadgeStyle::FlatSquare);
+        for name in BadgeStyle::NAMES {
+            assert_eq!(name.parse::<BadgeStyle>().unwrap().name(), *name);
+        }
+    }
+
+    #[test]
+    fn unknown_style_lists_the_valid_ones() {
+        let err = "rounded".parse::<BadgeStyle>().unwrap_err();
+        assert_eq!(err.to_string(), r#"unknown style "rounded", expected one of: plastic, flat, flat-square"#);
+    }
+
+    #[test]
+    fn named_badges_read_their_own_env_prefix() {
+        std::env::set_var("BADGE_TEST_DOWNLOADS_LABEL", "Downloads");
+        std::env::set_var("BADGE_TEST_DOWNLOADS_COLOR", "blue");
+        std::env::set_var("BADGE_TEST_DOWNLOADS_STYLE", "flat");
+        let config = BadgeConfig::from_env("test-downloads").unwrap();
+        assert_eq!(config, BadgeConfig {
+            path: "/test-downloads".to_string(),
+            user: "test-downloads".to_string(),
+            label: Some("Downloads".to_string()),
+            color: Some("blue".to_string()),
+            style: BadgeStyle::Flat,
+            ..BadgeConfig::default()
+        });
+
+        std::env::set_var("BADGE_TEST_BROKEN_PATH", "broken");
+        assert!(BadgeConfig::from_env("test-broken").unwrap_err().contains("BADGE_TEST_BROKEN_PATH"));
+    }
+}
@@ -0,0 +1,187 @@
+use ab_glyph::FontArc;
+use actix_web::{get, http::StatusCode, web, HttpResponse, Responder, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::badge::{BadgeSpec, BadgeStyle};
+use crate::format::{format_count, round_count, CountFormat};
+use crate::params;
+use crate::response::{svg_response, CachePolicy};
+use crate::store::{CounterStore, DEFAULT_PAGE};
+
+/// Most counters one batch request may ask for.
+const MAX_IDS: usize = 50;
+/// Space between badges on a sheet, in pixels.
+const GAP: f64 = 4.0;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchFormat {
+    /// One SVG with every badge on it.
+    #[default]
+    Svg,
+    /// A JSON array with each badge's count and SVG.
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    #[default]
+    Row,
+    Column,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchRequest {
+    key: String,
+    /// Comma-separated counter ids.
+    ids: String,
+    page: Option<String>,
+    style: Option<String>,
+    color: Option<String>,
+    label_color: Option<String>,
+    count_format: Option<CountFormat>,
+    format: Option<BatchFormat>,
+    layout: Option<Layout>,
+}
+
+impl BatchRequest {
+    fn ids(&self) -> Vec<&str> {
+        self.ids.split(',').map(str::trim).filter(|id| !id.is_empty()).collect()
+    }
+
+    fn validate(&self) -> Result<(), HttpResponse> {
+        let ids = self.ids();
+        let mut validator = params::Validator::default();
+        validator
+            .check(!ids.is_empty(), "ids", "must name at least one counter")
+            .check(ids.len() <= MAX_IDS, "ids", format!("must name at most {} counters", MAX_IDS));
+        for id in ids {
+            validator.check_opt(Some(id), "ids", params::slug);
+        }
+        validator
+            .check_opt(self.page.as_deref(), "page", params::slug)
+            .check_opt(self.style.as_deref(), "style", |style: &str| {
+                style.parse::<BadgeStyle>().map(drop).map_err(|err| err.to_string())
+            })
+            .check_opt(self.color.as_deref(), "color", crate::color_check)
+            .check_opt(self.label_color.as_deref(), "label_color", crate::color_check)
+            .finish()
+    }
+}
+
+/// One badge of a batch in the JSON format.
+#[derive(Debug, Serialize)]
+pub struct RenderedBadge {
+    pub id: String,
+    /// `None` when the counter does not exist.
+    pub count: Option<i64>,
+    pub svg: String,
+}
+
+/// Badges for many counters in one round trip, labelled with their ids:
+/// one SVG sheet by default, or `?format=json` for each badge separately.
+/// Does not count views. Unknown counters get a "not found" badge.
+#[get("/badges")]
+async fn get_badges(
+    store: web::Data<dyn CounterStore>,
+    font: web::Data<FontArc>,
+    req: web::Query<BatchRequest>,
+) -> Result<impl Responder> {
+    let badge_key = std::env::var("BADGE_KEY").expect("BADGE_KEY should be set");
+    if req.key != badge_key {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({ "error": "counter not found" })));
+    }
+    if let Err(response) = req.validate() {
+        return Ok(response);
+    }
+
+    let ids: Vec<String> = req.ids().into_iter().map(str::to_string).collect();
+    let page = req.page.clone().unwrap_or_else(|| DEFAULT_PAGE.to_string());
+    let lookup_ids = ids.clone();
+    let counts = web::block(move || {
+        lookup_ids.iter()
+            .map(|id| {
+                let display_step = store.display_step(id)?;
+                Ok(store.get(id, &page)?.map(|visitor| round_count(visitor.view_count, display_step)))
+            })
+            .collect::<Result<Vec<_>, crate::actions::DbError>>()
+    })
+    .await?
+    .map_err(crate::store_error)?;
+
+    let badges: Vec<RenderedBadge> = ids.into_iter()
+        .zip(counts)
+        .map(|(id, count)| {
+            let svg = render(&req, font.get_ref(), &id, count);
+            RenderedBadge { id, count, svg }
+        })
+        .collect();
+    Ok(match req.format.unwrap_or_default() {
+        BatchFormat::Json => HttpResponse::Ok().json(badges),
+        BatchFormat::Svg => {
+            let svgs: Vec<&str> = badges.iter().map(|badge| badge.svg.as_str()).collect();
+            let cache = CachePolicy::for_request(None, None, None);
+            svg_response(StatusCode::OK, sheet(&svgs, req.layout.unwrap_or_default()), cache)
+        },
+    })
+}
+
+fn render(req: &BatchRequest, font: &FontArc, id: &str, count: Option<i64>) -> String {
+    let mut spec = match count {
+        Some(count) => BadgeSpec::new(crate::sanitize_label(id), format_count(count, req.count_format.unwrap_or_default())),
+        None => {
+            let mut spec = BadgeSpec::new(crate::sanitize_label(id), "not found");
+            spec.color = Some("red".to_string());
+            spec
+        },
+    };
+    spec.style = req.style.clone();
+    if let (Some(color), Some(_)) = (&req.color, count) {
+        spec.color = Some(color.clone());
+    }
+    spec.label_color = req.label_color.clone();
+    crate::render_spec(font, &spec)
+}
+
+/// Place rendered badges next to each other, or one per line, in a single
+/// SVG document.
+fn sheet(svgs: &[&str], layout: Layout) -> String {
+    let (mut width, mut height) = (0.0_f64, 0.0_f64);
+    let mut body = String::new();
+    for (i, svg) in svgs.iter().enumerate() {
+        let (w, h) = svg_size(svg);
+        let gap = if i == 0 { 0.0 } else { GAP };
+        let (x, y) = match layout {
+            Layout::Row => (width + gap, 0.0),
+            Layout::Column => (0.0, height + gap),
+        };
+        body.push_str(&format!("<g transform=\"translate({},{})\">{}</g>", x, y, svg));
+        match layout {
+            Layout::Row => {
+                width = x + w;
+                height = height.max(h);
+            },
+            Layout::Column => {
+                width = width.max(w);
+                height = y + h;
+            },
+        }
+    }
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">{}</svg>",
+        width, height, body
+    )
+}
+
+/// `width` and `height` of a rendered badge's root element.
+fn svg_size(svg: &str) -> (f64, f64) {
+    let root = svg.split('>').next().unwrap_or("");
+    let attribute = |name: &str| {
+        root.split_once(&format!(" {}=\"", name))
+            .and_then(|(_, rest)| rest.split('"').next())
+            .and_then(|value| value.parse::<f64>().ok())
+            .unwrap_or(0.0)
+    };
+    (attribute("width"), attribute("height"))
+}
@@ -0,0 +1,71 @@
+use std::sync::OnceLock;
+
+use actix_web::HttpRequest;
+
+/// User-Agent fragments of crawlers, uptime checkers and link previewers.
+/// GitHub's camo proxy is not on the list: README views arrive through it.
+const DEFAULT_DENYLIST: &[&str] = &[
+    "bot",
+    "crawler",
+    "spider",
+    "slurp",
+    "curl/",
+    "wget/",
+    "python-requests",
+    "go-http-client",
+    "headlesschrome",
+    "lighthouse",
+    "uptimerobot",
+    "pingdom",
+    "statuscake",
+    "site24x7",
+    "facebookexternalhit",
+    "slackbot",
+    "discordbot",
+    "twitterbot",
+    "telegrambot",
+    "whatsapp",
+    "linkedinbot",
+    "embedly",
+    "skypeuripreview",
+];
+
+/// Lowercase User-Agent fragments that mark a request as automated:
+/// `BOT_USER_AGENTS` (comma-separated) when set, an empty value turning
+/// filtering off, or the built-in list. Read once, on first use.
+fn denylist() -> &'static [String] {
+    static DENYLIST: OnceLock<Vec<String>> = OnceLock::new();
+    DENYLIST.get_or_init(|| parse_denylist(std::env::var("BOT_USER_AGENTS").ok().as_deref()))
+}
+
+fn parse_denylist(list: Option<&str>) -> Vec<String> {
+    match list {
+        Some(list) => list.split(',')
+            .map(|fragment| fragment.trim().to_ascii_lowercase())
+            .filter(|fragment| !fragment.is_empty())
+            .collect(),
+        None => DEFAULT_DENYLIST.iter().map(|fragment| fragment.to_string()).collect(),
+    }
+}
+
+/// Whether the request comes from a crawler or other automated client whose
+/// views should not be counted. Requests without a User-Agent count.
+pub fn is_bot(http_req: &HttpRequest) -> bool {
+    let user_agent = match http_req.headers().get("User-Agent").and_then(|value| value.to_str().ok()) {
+        Some(user_agent) => user_agent.to_ascii_lowercase(),
+        None => return false,
+    };
+    denylist().iter().any(|fragment| user_agent.contains(fragment.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn denylist_from_env_value() {
+        assert_eq!(parse_denylist(Some(" FooBot, ,curl/ ")), vec!["foobot", "curl/"]);
+        assert!(parse_denylist(Some("")).is_empty());
+        assert_eq!(parse_denylist(None).len(), DEFAULT_DENYLIST.len());
+    }
+}
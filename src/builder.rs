@@ -0,0 +1,81 @@
+use ab_glyph::FontArc;
+use actix_web::{get, http::StatusCode, web, HttpResponse, Responder};
+use serde::Deserialize;
+
+use crate::badge::{self, BadgeSpec};
+use crate::{i18n, params};
+use crate::response::{svg_response, CachePolicy};
+
+const BUILDER_HTML: &str = include_str!("static/builder.html");
+
+/// Count shown in previews.
+const PREVIEW_COUNT: &str = "1234";
+
+/// Page for picking a badge's look with a live preview and copying its URL.
+#[get("/builder")]
+async fn get_builder() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .insert_header(("Cache-Control", "public, max-age=3600"))
+        .body(BUILDER_HTML)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PreviewRequest {
+    style: Option<String>,
+    label: Option<String>,
+    color: Option<String>,
+    label_color: Option<String>,
+}
+
+/// A badge with a sample count in the requested look, for the builder. Does
+/// not touch any counter.
+#[get("/builder/preview")]
+async fn get_preview(font: web::Data<FontArc>, req: web::Query<PreviewRequest>) -> impl Responder {
+    let req = req.into_inner();
+    if let Some(Err(message)) = req.label.as_deref().map(params::text(badge::MAX_LABEL_CHARS)) {
+        return crate::render_error_badge(&font, StatusCode::BAD_REQUEST, &format!("label {}", message));
+    }
+    let label = match req.label.as_deref() {
+        Some(label) => crate::sanitize_label(label),
+        None => i18n::ENGLISH.label.to_string(),
+    };
+    let mut spec = BadgeSpec::new(label, PREVIEW_COUNT);
+    spec.style = req.style;
+    if req.color.is_some() {
+        spec.color = req.color;
+    }
+    spec.label_color = req.label_color;
+    if let Err(err) = spec.to_metadata(font.get_ref().clone()) {
+        return crate::render_error_badge(&font, StatusCode::BAD_REQUEST, &err.to_string());
+    }
+    svg_response(StatusCode::OK, crate::render_spec(&font, &spec), CachePolicy::NoStore)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::App;
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn previews_render_or_explain_what_is_wrong() {
+        let font = FontArc::try_from_vec(std::fs::read("src/fonts/DejaVuSans.ttf").unwrap()).unwrap();
+        let app = init_service(App::new().app_data(web::Data::new(font)).service(get_preview)).await;
+        for (uri, status, text) in [
+            ("/builder/preview", StatusCode::OK, ">Profile views</text>"),
+            ("/builder/preview?label=Stars&style=flat", StatusCode::OK, ">Stars</text>"),
+            ("/builder/preview?label=", StatusCode::BAD_REQUEST, ">label must not be empty</text>"),
+            ("/builder/preview?label=%20", StatusCode::BAD_REQUEST, ">label must not be empty</text>"),
+            ("/builder/preview?style=round", StatusCode::BAD_REQUEST, ">unknown style"),
+        ] {
+            let response = call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(response.status(), status, "{}", uri);
+            let body = read_body(response).await;
+            let svg = std::str::from_utf8(&body).unwrap();
+            assert!(svg.contains(text), "{}: {}", uri, svg);
+            assert!(svg.contains(&format!(">{}</text>", PREVIEW_COUNT)) == status.is_success(), "{}", uri);
+        }
+    }
+}
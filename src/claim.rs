@@ -0,0 +1,448 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::{error, post, web, HttpRequest, HttpResponse, Responder, Result};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::actions;
+use crate::api_keys;
+use crate::models::{Claim, OwnerKey};
+use crate::signing;
+use crate::store::CounterStore;
+use crate::DbPool;
+
+/// Hosts a `gist_url` may point at.
+const ALLOWED_HOSTS: &[&str] = &["raw.githubusercontent.com", "gist.githubusercontent.com"];
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+const FETCH_LIMIT: usize = 512 * 1024;
+
+/// How claims are checked, read once at startup.
+#[derive(Debug, Clone)]
+pub struct ClaimConfig {
+    /// Seconds a claim stays open, `CLAIM_TTL_SECS` (default a day).
+    ttl: i64,
+    /// Where profile READMEs are fetched from.
+    readme_base: String,
+}
+
+impl ClaimConfig {
+    pub fn from_env() -> Result<Self, String> {
+        let ttl = match std::env::var("CLAIM_TTL_SECS") {
+            Ok(secs) => match secs.parse::<i64>() {
+                Ok(secs) if secs > 0 => secs,
+                _ => return Err(format!("CLAIM_TTL_SECS should be a positive number of seconds, got {:?}", secs)),
+            },
+            Err(_) => 24 * 60 * 60,
+        };
+        Ok(ClaimConfig { ttl, readme_base: "https://raw.githubusercontent.com".to_string() })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyRequest {
+    /// The secret `create_claim` answered with, never published.
+    secret: Option<String>,
+    gist_url: Option<String>,
+}
+
+/// Start claiming a counter: hand out a token the owner must publish in
+/// their GitHub profile README or a public gist, and a secret only the
+/// claimant sees, so whoever reads the published token cannot verify in
+/// their place. Answers 409 while an earlier claim is still open.
+#[post("/claim/{user}")]
+async fn create_claim(
+    pool: web::Data<DbPool>,
+    store: web::Data<dyn CounterStore>,
+    config: web::Data<ClaimConfig>,
+    path: web::Path<String>,
+) -> Result<impl Responder> {
+    let user = path.into_inner();
+    if !is_github_login(&user) {
+        return Ok(HttpResponse::BadRequest().json(json!({ "error": "counter id is not a GitHub login" })));
+    }
+    let lookup_user = user.clone();
+    let exists = web::block(move || store.user_total(&lookup_user))
+        .await?
+        .map_err(error::ErrorInternalServerError)?
+        .is_some();
+    if !exists {
+        return Ok(HttpResponse::NotFound().json(json!({ "error": "counter not found" })));
+    }
+
+    let secret = random_hex();
+    let claim = Claim {
+        user_id: user,
+        token: format!("visitor-badge-claim-{}", random_hex()),
+        created_at: now(),
+        secret_hash: hash_key(&secret),
+    };
+    let expired_before = claim.created_at - config.ttl;
+    let stored = claim.clone();
+    let opened = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::put_claim(&mut conn, &stored, expired_before)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    if !opened {
+        return Ok(HttpResponse::Conflict().json(json!({ "error": "a claim is already pending" })));
+    }
+
+    Ok(HttpResponse::Created().json(json!({
+        "user": claim.user_id,
+        "token": claim.token,
+        "secret": secret,
+        "expires_in": config.ttl,
+        "instructions": format!(
+            "add the token to https://github.com/{0}/{0}/blob/main/README.md or a public gist, then POST {1}/claim/{0}/verify with the secret, which must stay private",
+            claim.user_id,
+            crate::base_path(),
+        ),
+    })))
+}
+
+/// Check the claim's secret and that its token was published, then issue
+/// an owner API key. The claim is consumed, so it yields one key at most.
+#[post("/claim/{user}/verify")]
+async fn verify_claim(
+    pool: web::Data<DbPool>,
+    config: web::Data<ClaimConfig>,
+    path: web::Path<String>,
+    body: Option<web::Json<VerifyRequest>>,
+) -> Result<impl Responder> {
+    let user = path.into_inner();
+    let lookup_pool = pool.clone();
+    let lookup_user = user.clone();
+    let claim = web::block(move || {
+        let mut conn = lookup_pool.get()?;
+        actions::get_claim(&mut conn, &lookup_user)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    let claim = match claim {
+        Some(claim) => claim,
+        None => return Ok(HttpResponse::NotFound().json(json!({ "error": "no pending claim" }))),
+    };
+
+    if claim.created_at + config.ttl < now() {
+        let expired_user = user.clone();
+        web::block(move || {
+            let mut conn = pool.get()?;
+            actions::delete_claim(&mut conn, &expired_user)
+        })
+        .await?
+        .map_err(error::ErrorInternalServerError)?;
+        return Ok(HttpResponse::Gone().json(json!({ "error": "claim expired" })));
+    }
+
+    let body = body.map(web::Json::into_inner);
+    let secret = body.as_ref().and_then(|body| body.secret.as_deref()).unwrap_or("");
+    if !signing::tokens_match(&claim.secret_hash, &hash_key(secret)) {
+        return Ok(HttpResponse::Forbidden().json(json!({ "error": "wrong claim secret" })));
+    }
+
+    let mut urls = vec![format!("{0}/{1}/{1}/main/README.md", config.readme_base, user)];
+    if let Some(gist_url) = body.and_then(|body| body.gist_url) {
+        if !is_allowed_url(&gist_url, &user) {
+            return Ok(HttpResponse::BadRequest().json(json!({
+                "error": format!("gist_url must be a raw GitHub URL under /{}/", user),
+            })));
+        }
+        urls.push(gist_url);
+    }
+
+    let mut verified = false;
+    for url in &urls {
+        match fetch(url).await {
+            Ok(text) if text.contains(&claim.token) => {
+                verified = true;
+                break;
+            },
+            Ok(_) => {},
+            Err(err) => tracing::info!("claim verification fetch of {} failed: {}", url, err),
+        }
+    }
+    if !verified {
+        return Ok(HttpResponse::Forbidden().json(json!({ "error": "token not found" })));
+    }
+
+    let api_key = format!("vb_{}", random_hex());
+    let owner_key = OwnerKey {
+        key_hash: hash_key(&api_key),
+        user_id: user.clone(),
+        created_at: now(),
+    };
+    let issued = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::redeem_claim(&mut conn, &claim, &owner_key)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    if !issued {
+        return Ok(HttpResponse::NotFound().json(json!({ "error": "no pending claim" })));
+    }
+
+    Ok(HttpResponse::Ok().json(json!({ "user": user, "api_key": api_key })))
+}
+
+/// Whether the request carries `Authorization: Bearer <owner key>` for `user`.
+pub async fn is_owner(pool: &DbPool, http_req: &HttpRequest, user: &str) -> Result<bool> {
+    let api_key = match api_keys::bearer(http_req.headers()) {
+        Some(api_key) => api_key.to_string(),
+        None => return Ok(false),
+    };
+    let pool = pool.clone();
+    let key = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::find_owner_key(&mut conn, &hash_key(&api_key))
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    Ok(key.is_some_and(|key| key.user_id == user))
+}
+
+/// Fetch a small text document. Redirects are not followed, so the URL
+/// checked is the one read.
+async fn fetch(url: &str) -> Result<String, String> {
+    let client = awc::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .disable_redirects()
+        .finish();
+    let mut response = client.get(url).send().await.map_err(|err| err.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("status {}", response.status()));
+    }
+    let body = response.body().limit(FETCH_LIMIT).await.map_err(|err| err.to_string())?;
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Whether `url` is a raw GitHub file owned by `user`: an allowed host and
+/// `user` as the first path segment, as in
+/// `https://gist.githubusercontent.com/<user>/<id>/raw/...`. Dot segments
+/// and percent-encoding are refused outright so the owner segment cannot be
+/// smuggled past the check.
+fn is_allowed_url(url: &str, user: &str) -> bool {
+    if url.contains('%') || url.contains("/.") || url.contains('\\') {
+        return false;
+    }
+    let url = match url::Url::parse(url) {
+        Ok(url) => url,
+        Err(_) => return false,
+    };
+    url.scheme() == "https"
+        && url.port().is_none()
+        && url.username().is_empty()
+        && url.host_str().is_some_and(|host| ALLOWED_HOSTS.contains(&host))
+        && url.path_segments()
+            .and_then(|mut segments| segments.next())
+            .is_some_and(|owner| owner.eq_ignore_ascii_case(user))
+}
+
+/// GitHub logins are alphanumeric with single inner hyphens, at most 39 chars.
+fn is_github_login(user: &str) -> bool {
+    !user.is_empty()
+        && user.len() <= 39
+        && !user.starts_with('-')
+        && !user.ends_with('-')
+        && user.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+pub fn hash_key(key: &str) -> String {
+    to_hex(&Sha256::digest(key.as_bytes()))
+}
+
+pub fn random_hex() -> String {
+    to_hex(&rand::random::<[u8; 16]>())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock should be after the epoch")
+        .as_secs() as i64
+}
+
+#[cfg(all(test, not(feature = "postgres")))]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::{App, HttpServer};
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use diesel_migrations::MigrationHarness;
+
+    use super::*;
+
+    const TOKEN: &str = "visitor-badge-claim-0123456789abcdef";
+    const SECRET: &str = "fedcba9876543210";
+
+    /// One in-memory database; a single connection keeps it alive.
+    fn pool() -> DbPool {
+        let pool = Pool::builder()
+            .max_size(1)
+            .build(ConnectionManager::<actions::DbConnection>::new(":memory:"))
+            .unwrap();
+        pool.get().unwrap().run_pending_migrations(crate::MIGRATIONS).unwrap();
+        pool
+    }
+
+    fn claim(user: &str, created_at: i64) -> Claim {
+        Claim { user_id: user.to_string(), token: TOKEN.to_string(), created_at, secret_hash: hash_key(SECRET) }
+    }
+
+    fn open_claim(pool: &DbPool, user: &str, created_at: i64) {
+        assert!(actions::put_claim(&mut pool.get().unwrap(), &claim(user, created_at), 0).unwrap());
+    }
+
+    /// Stand-in for raw.githubusercontent.com serving octocat's README.
+    async fn mock_github(readme: &'static str) -> String {
+        let server = HttpServer::new(move || {
+            App::new().route("/octocat/octocat/main/README.md", web::get().to(move || async move { readme }))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let base = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        base
+    }
+
+    async fn verify(
+        pool: &DbPool,
+        readme_base: &str,
+        user: &str,
+        secret: Option<&str>,
+    ) -> (StatusCode, serde_json::Value) {
+        let config = ClaimConfig { ttl: 60, readme_base: readme_base.to_string() };
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(config))
+                .service(verify_claim),
+        )
+        .await;
+        let request = TestRequest::post().uri(&format!("/claim/{}/verify", user));
+        let request = match secret {
+            Some(secret) => request.set_json(serde_json::json!({ "secret": secret })),
+            None => request,
+        }
+        .to_request();
+        let response = call_service(&app, request).await;
+        (response.status(), read_body_json(response).await)
+    }
+
+    #[actix_web::test]
+    async fn published_token_earns_an_owner_key() {
+        let pool = pool();
+        open_claim(&pool, "octocat", now());
+        let base = mock_github("Hi! visitor-badge-claim-0123456789abcdef").await;
+
+        let (status, body) = verify(&pool, &base, "octocat", Some(SECRET)).await;
+        assert_eq!(status, StatusCode::OK);
+        let api_key = body["api_key"].as_str().unwrap();
+        let owner_key = actions::find_owner_key(&mut pool.get().unwrap(), &hash_key(api_key)).unwrap().unwrap();
+        assert_eq!(owner_key.user_id, "octocat");
+    }
+
+    #[actix_web::test]
+    async fn unpublished_token_is_refused() {
+        let pool = pool();
+        open_claim(&pool, "octocat", now());
+        let base = mock_github("no token here").await;
+
+        let (status, body) = verify(&pool, &base, "octocat", Some(SECRET)).await;
+        assert_eq!((status, body["error"].as_str()), (StatusCode::FORBIDDEN, Some("token not found")));
+    }
+
+    #[actix_web::test]
+    async fn verify_without_a_claim_is_not_found() {
+        let pool = pool();
+        let base = mock_github(TOKEN).await;
+
+        let (status, body) = verify(&pool, &base, "octocat", Some(SECRET)).await;
+        assert_eq!((status, body["error"].as_str()), (StatusCode::NOT_FOUND, Some("no pending claim")));
+    }
+
+    #[actix_web::test]
+    async fn expired_claims_are_gone() {
+        let pool = pool();
+        open_claim(&pool, "octocat", now() - 61);
+        let base = mock_github(TOKEN).await;
+
+        let (status, body) = verify(&pool, &base, "octocat", Some(SECRET)).await;
+        assert_eq!((status, body["error"].as_str()), (StatusCode::GONE, Some("claim expired")));
+        assert!(actions::get_claim(&mut pool.get().unwrap(), "octocat").unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn key_needs_the_secret_and_is_issued_once() {
+        let pool = pool();
+        open_claim(&pool, "octocat", now());
+        let base = mock_github("Hi! visitor-badge-claim-0123456789abcdef").await;
+
+        // The published token alone is not enough.
+        for secret in [None, Some(""), Some("0123456789abcdef")] {
+            let (status, body) = verify(&pool, &base, "octocat", secret).await;
+            assert_eq!((status, body["error"].as_str()), (StatusCode::FORBIDDEN, Some("wrong claim secret")));
+        }
+        let (status, _) = verify(&pool, &base, "octocat", Some(SECRET)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = verify(&pool, &base, "octocat", Some(SECRET)).await;
+        assert_eq!((status, body["error"].as_str()), (StatusCode::NOT_FOUND, Some("no pending claim")));
+        assert!(actions::get_claim(&mut pool.get().unwrap(), "octocat").unwrap().is_none());
+    }
+
+    #[test]
+    fn open_claims_are_not_replaced() {
+        let pool = pool();
+        let mut conn = pool.get().unwrap();
+        let created_at = now();
+        assert!(actions::put_claim(&mut conn, &claim("octocat", created_at - 100), 0).unwrap());
+        let rival = Claim { secret_hash: hash_key("rival"), ..claim("octocat", created_at) };
+        assert!(!actions::put_claim(&mut conn, &rival, created_at - 200).unwrap());
+        assert_eq!(actions::get_claim(&mut conn, "octocat").unwrap().unwrap().secret_hash, hash_key(SECRET));
+        // Once the open claim expires, a new one takes its place.
+        assert!(actions::put_claim(&mut conn, &rival, created_at - 50).unwrap());
+        assert_eq!(actions::get_claim(&mut conn, "octocat").unwrap().unwrap().secret_hash, hash_key("rival"));
+    }
+
+    #[test]
+    fn gist_urls_must_belong_to_the_claimed_user() {
+        for url in [
+            "https://gist.githubusercontent.com/octocat/abc123/raw/claim.txt",
+            "https://gist.githubusercontent.com/OctoCat/abc123/raw/claim.txt",
+            "https://raw.githubusercontent.com/octocat/notes/main/claim.md",
+        ] {
+            assert!(is_allowed_url(url, "octocat"), "{}", url);
+        }
+        for url in [
+            "https://gist.githubusercontent.com/mallory/abc123/raw/claim.txt",
+            "https://gist.githubusercontent.com/octocat/../mallory/abc123/raw/claim.txt",
+            "https://gist.githubusercontent.com/octocat/%2e%2e/mallory/raw/claim.txt",
+            "https://gist.githubusercontent.com/%6fctocat/abc123/raw/claim.txt",
+            "https://gist.githubusercontent.com/octocatx/abc123/raw/claim.txt",
+            "http://gist.githubusercontent.com/octocat/abc123/raw/claim.txt",
+            "https://gist.githubusercontent.com:8443/octocat/abc123/raw/claim.txt",
+            "https://octocat@evil.example/octocat/abc123/raw/claim.txt",
+            "https://evil.example/octocat/abc123/raw/claim.txt",
+        ] {
+            assert!(!is_allowed_url(url, "octocat"), "{}", url);
+        }
+    }
+
+    #[test]
+    fn ttl_must_be_a_positive_number() {
+        std::env::set_var("CLAIM_TTL_SECS", "soon");
+        assert!(ClaimConfig::from_env().unwrap_err().contains("CLAIM_TTL_SECS"));
+        std::env::set_var("CLAIM_TTL_SECS", "0");
+        assert!(ClaimConfig::from_env().is_err());
+        std::env::set_var("CLAIM_TTL_SECS", "3600");
+        assert_eq!(ClaimConfig::from_env().unwrap().ttl, 3600);
+        std::env::remove_var("CLAIM_TTL_SECS");
+        assert_eq!(ClaimConfig::from_env().unwrap().ttl, 24 * 60 * 60);
+    }
+}
@@ -0,0 +1,53 @@
+use actix_cors::Cors;
+use actix_web::http::Method;
+
+/// Cross-origin access for browser dashboards on other domains, from
+/// `CORS_ALLOWED_ORIGINS` (comma-separated, or `*`) and
+/// `CORS_ALLOWED_METHODS` (default `GET`). Off unless origins are set.
+#[derive(Debug, Clone, Default)]
+pub struct CorsConfig {
+    origins: Vec<String>,
+    methods: Vec<Method>,
+}
+
+impl CorsConfig {
+    pub fn from_env() -> Result<Self, String> {
+        let origins = list_var("CORS_ALLOWED_ORIGINS");
+        let methods = match list_var("CORS_ALLOWED_METHODS") {
+            methods if methods.is_empty() => vec![Method::GET],
+            methods => methods.iter()
+                .map(|method| {
+                    method.to_ascii_uppercase().parse::<Method>()
+                        .map_err(|_| format!("CORS_ALLOWED_METHODS entry {:?} is not an HTTP method", method))
+                })
+                .collect::<Result<_, _>>()?,
+        };
+        Ok(CorsConfig { origins, methods })
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.origins.is_empty()
+    }
+
+    /// The middleware for one worker.
+    pub fn build(&self) -> Cors {
+        let cors = Cors::default()
+            .allowed_methods(self.methods.clone())
+            .allow_any_header()
+            .max_age(3600);
+        if self.origins.iter().any(|origin| origin == "*") {
+            return cors.allow_any_origin();
+        }
+        self.origins.iter().fold(cors, |cors, origin| cors.allowed_origin(origin))
+    }
+}
+
+fn list_var(name: &str) -> Vec<String> {
+    std::env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
@@ -0,0 +1,167 @@
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models::DayCount;
+
+/// Size of the buckets daily views are summed into.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    #[default]
+    Day,
+    /// Weeks starting on Monday.
+    Week,
+    /// Calendar months.
+    Month,
+}
+
+impl Granularity {
+    /// First day of the bucket `day` falls in.
+    pub fn bucket_start(self, day: NaiveDate) -> NaiveDate {
+        match self {
+            Granularity::Day => day,
+            Granularity::Week => day - Duration::days(i64::from(day.weekday().num_days_from_monday())),
+            Granularity::Month => day.with_day(1).unwrap_or(day),
+        }
+    }
+
+    /// First day of the bucket after the one starting on `start`.
+    pub fn next(self, start: NaiveDate) -> NaiveDate {
+        match self {
+            Granularity::Day => start + Duration::days(1),
+            Granularity::Week => start + Duration::weeks(1),
+            Granularity::Month => start.checked_add_months(Months::new(1)).unwrap_or(NaiveDate::MAX),
+        }
+    }
+
+    /// First day of the oldest of `periods` buckets ending with the one
+    /// `today` falls in.
+    pub fn first_bucket(self, today: NaiveDate, periods: u32) -> NaiveDate {
+        let back = periods.max(1) - 1;
+        let start = self.bucket_start(today);
+        match self {
+            Granularity::Day => start - Duration::days(i64::from(back)),
+            Granularity::Week => start - Duration::weeks(i64::from(back)),
+            Granularity::Month => start.checked_sub_months(Months::new(back)).unwrap_or(NaiveDate::MIN),
+        }
+    }
+
+    /// Most buckets worth asking for: enough to cover every retained day.
+    pub fn max_periods(self) -> u32 {
+        let days = u32::try_from(retention_days()).unwrap_or(u32::MAX).max(1);
+        match self {
+            Granularity::Day => days,
+            Granularity::Week => days / 7 + 1,
+            Granularity::Month => days / 28 + 1,
+        }
+    }
+}
+
+/// Parse an IANA time zone name such as `Asia/Tokyo`.
+pub fn parse_timezone(name: &str) -> Result<Tz, String> {
+    name.parse::<Tz>().map_err(|err| err.to_string())
+}
+
+/// The calendar day `now` falls on in `tz`. Converting the instant (rather
+/// than offsetting the server's date) keeps DST transitions correct.
+pub fn local_day(tz: Tz, now: DateTime<Utc>) -> NaiveDate {
+    now.with_timezone(&tz).date_naive()
+}
+
+/// Unix seconds of the first instant of `day` in `tz`. Days that start in
+/// a DST gap begin at the first valid local time.
+pub fn day_start(tz: Tz, day: NaiveDate) -> i64 {
+    let midnight = day.and_time(NaiveTime::MIN);
+    match tz.from_local_datetime(&midnight).earliest() {
+        Some(start) => start.timestamp(),
+        None => tz.from_utc_datetime(&midnight).timestamp(),
+    }
+}
+
+static RETENTION_DAYS: OnceLock<i64> = OnceLock::new();
+
+/// Read `DAILY_RETENTION_DAYS`; called once at startup so a bad value stops
+/// the server instead of pruning by the default.
+pub fn init() -> Result<(), String> {
+    let _ = RETENTION_DAYS.set(retention_days_from_env()?);
+    Ok(())
+}
+
+fn retention_days_from_env() -> Result<i64, String> {
+    match std::env::var("DAILY_RETENTION_DAYS") {
+        Ok(days) => days.parse::<u32>()
+            .map(i64::from)
+            .map_err(|_| format!("DAILY_RETENTION_DAYS should be a non-negative number of days, got {:?}", days)),
+        Err(_) => Ok(90),
+    }
+}
+
+/// Daily rows older than this many days are pruned (`DAILY_RETENTION_DAYS`,
+/// default 90).
+pub fn retention_days() -> i64 {
+    *RETENTION_DAYS.get_or_init(|| retention_days_from_env().expect("DAILY_RETENTION_DAYS should be valid"))
+}
+
+/// First day still kept under the retention policy. One extra day of slack
+/// covers zones ahead of UTC.
+pub fn retention_cutoff(now: DateTime<Utc>) -> NaiveDate {
+    now.date_naive() - Duration::days(retention_days() + 1)
+}
+
+/// Consecutive days with views ending on the last of `days`, or on the day
+/// before while the last day has none yet.
+pub fn current_streak(days: &[DayCount]) -> i64 {
+    let mut days = days.iter().rev().peekable();
+    if days.peek().is_some_and(|today| today.view_count == 0) {
+        days.next();
+    }
+    days.take_while(|day| day.view_count > 0).count() as i64
+}
+
+/// Percent change of the views on the last 7 of `days` over the 7 before,
+/// rounded. `None` when the earlier week had no views.
+pub fn week_over_week(days: &[DayCount]) -> Option<i64> {
+    let sum = |days: &[DayCount]| days.iter().map(|day| day.view_count).fold(0, i64::saturating_add);
+    let split = days.len().saturating_sub(7);
+    let (earlier, last) = days.split_at(split);
+    let previous = sum(&earlier[earlier.len().saturating_sub(7)..]);
+    if previous == 0 {
+        return None;
+    }
+    let change = (sum(last) - previous) as f64 / previous as f64 * 100.0;
+    Some(change.round() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn days_roll_over_at_midnight_utc() {
+        let before = local_day(Tz::UTC, utc("2024-03-09T23:59:59Z"));
+        let after = local_day(Tz::UTC, utc("2024-03-10T00:00:00Z"));
+        assert_eq!(before.to_string(), "2024-03-09");
+        assert_eq!(after.to_string(), "2024-03-10");
+        assert_eq!(day_start(Tz::UTC, after), utc("2024-03-10T00:00:00Z").timestamp());
+    }
+
+    #[test]
+    fn other_zones_roll_over_at_their_own_midnight() {
+        let tokyo = parse_timezone("Asia/Tokyo").unwrap();
+        assert_eq!(local_day(tokyo, utc("2024-03-09T14:59:59Z")).to_string(), "2024-03-09");
+        assert_eq!(local_day(tokyo, utc("2024-03-09T15:00:00Z")).to_string(), "2024-03-10");
+        // New York skips 02:00-03:00 on this day, but still starts at midnight.
+        let new_york = parse_timezone("America/New_York").unwrap();
+        let day = NaiveDate::from_ymd_opt(2024, 3, 10).unwrap();
+        assert_eq!(day_start(new_york, day), utc("2024-03-10T05:00:00Z").timestamp());
+        assert!(parse_timezone("Mars/Olympus_Mons").is_err());
+    }
+}
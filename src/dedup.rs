@@ -0,0 +1,274 @@
+use std::sync::OnceLock;
+
+use actix_web::HttpRequest;
+use sha2::{Digest, Sha256};
+
+use crate::rate_limit;
+
+/// Deduplication and unique-counting settings, read once at startup.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DedupConfig {
+    /// Seconds during which repeat views from the same requester are not
+    /// counted again (`DEDUP_WINDOW_SECS`, default 0: off).
+    pub window_secs: i64,
+    /// Seconds during which repeat fetches of a counter by GitHub's camo
+    /// image proxy are not counted again (`CAMO_DEDUP_SECS`, default 0:
+    /// off). Camo may fetch a badge several times for one README render,
+    /// from changing addresses, so these are matched on the proxy alone.
+    pub camo_window_secs: i64,
+    /// Whether `DNT: 1` and `Sec-GPC: 1` opt out of tracking (`RESPECT_DNT`).
+    pub respect_dnt: bool,
+    /// Seconds during which a returning visitor is not counted as unique
+    /// again (`UNIQUE_WINDOW_SECS`, default one day, 0 disables unique
+    /// counting).
+    pub unique_window_secs: i64,
+    /// Unique visitors from which a counter keeps a HyperLogLog sketch
+    /// instead of per-visitor fingerprints (`UNIQUE_SKETCH_THRESHOLD`,
+    /// default 100000, 0 keeps exact counts for every counter).
+    pub sketch_threshold: i64,
+}
+
+impl DedupConfig {
+    pub fn from_env() -> Result<Self, String> {
+        Ok(DedupConfig {
+            window_secs: non_negative("DEDUP_WINDOW_SECS", 0)?,
+            camo_window_secs: non_negative("CAMO_DEDUP_SECS", 0)?,
+            respect_dnt: std::env::var("RESPECT_DNT").is_ok_and(|value| value == "true" || value == "1"),
+            unique_window_secs: non_negative("UNIQUE_WINDOW_SECS", 24 * 60 * 60)?,
+            sketch_threshold: non_negative("UNIQUE_SKETCH_THRESHOLD", 100_000)?,
+        })
+    }
+}
+
+fn non_negative(name: &str, default: i64) -> Result<i64, String> {
+    match std::env::var(name) {
+        Ok(value) => value.parse::<i64>()
+            .ok()
+            .filter(|value| *value >= 0)
+            .ok_or_else(|| format!("{} should be a non-negative number, got {:?}", name, value)),
+        Err(_) => Ok(default),
+    }
+}
+
+static CONFIG: OnceLock<DedupConfig> = OnceLock::new();
+
+/// Read the settings from the environment; called once at startup so bad
+/// values stop the server instead of silently turning features off.
+pub fn init() -> Result<(), String> {
+    let config = DedupConfig::from_env()?;
+    let _ = CONFIG.set(config);
+    Ok(())
+}
+
+fn config() -> &'static DedupConfig {
+    CONFIG.get_or_init(|| DedupConfig::from_env().expect("dedup settings should be valid"))
+}
+
+/// The window and fingerprint a counted view is de-duplicated by, or `None`
+/// when it is not.
+pub fn seen_key(http_req: &HttpRequest) -> Option<(i64, String)> {
+    config().seen_key(http_req)
+}
+
+/// Whether per-visitor data (dedup and unique fingerprints, referrer,
+/// country) may be recorded for this request. With `RESPECT_DNT` set,
+/// requests sending `DNT: 1` or `Sec-GPC: 1` are only counted.
+pub fn may_track(http_req: &HttpRequest) -> bool {
+    config().may_track(http_req)
+}
+
+impl DedupConfig {
+    /// See `seen_key`. Camo fetches use the camo window, if set, and a
+    /// fingerprint of the proxy alone, so every fetch of a counter within
+    /// the window is one view.
+    fn seen_key(&self, http_req: &HttpRequest) -> Option<(i64, String)> {
+        if !self.may_track(http_req) {
+            return None;
+        }
+        if self.camo_window_secs > 0 {
+            if let Some(user_agent) = camo_user_agent(http_req) {
+                return Some((self.window_secs.max(self.camo_window_secs), hash(&[user_agent])));
+            }
+        }
+        (self.window_secs > 0).then(|| (self.window_secs, fingerprint(http_req)))
+    }
+
+    fn may_track(&self, http_req: &HttpRequest) -> bool {
+        let opted_out = ["DNT", "Sec-GPC"].iter().any(|name| {
+            http_req.headers()
+                .get(*name)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| value.trim() == "1")
+        });
+        !(self.respect_dnt && opted_out)
+    }
+}
+
+/// How long seen fingerprints must be kept for any request's window.
+pub fn retention_secs() -> i64 {
+    config().window_secs.max(config().camo_window_secs)
+}
+
+fn camo_user_agent(http_req: &HttpRequest) -> Option<&str> {
+    http_req.headers()
+        .get("User-Agent")
+        .and_then(|value| value.to_str().ok())
+        .filter(|user_agent| user_agent.starts_with("github-camo"))
+}
+
+/// See `DedupConfig::unique_window_secs`.
+pub fn unique_window_secs() -> i64 {
+    config().unique_window_secs
+}
+
+/// See `DedupConfig::sketch_threshold`.
+pub fn sketch_threshold() -> i64 {
+    config().sketch_threshold
+}
+
+/// Whether a counter with `unique_count` visitors is counted by sketch.
+pub fn is_sketched(unique_count: i64) -> bool {
+    let threshold = sketch_threshold();
+    threshold > 0 && unique_count >= threshold
+}
+
+/// Server secret mixed into every fingerprint so stored hashes cannot be
+/// matched against guessed addresses. `VISITOR_SALT` keeps fingerprints
+/// stable across restarts; without it each process picks a random salt.
+fn salt() -> &'static str {
+    static SALT: OnceLock<String> = OnceLock::new();
+    SALT.get_or_init(|| {
+        std::env::var("VISITOR_SALT")
+            .ok()
+            .filter(|salt| !salt.is_empty())
+            .unwrap_or_else(crate::claim::random_hex)
+    })
+}
+
+/// A counted view to record for unique visitor counting.
+#[derive(Debug, Clone)]
+pub struct UniqueVisit {
+    pub fingerprint: String,
+    pub now: i64,
+    pub window_secs: i64,
+}
+
+impl UniqueVisit {
+    /// `None` when unique counting is disabled.
+    pub fn from_request(http_req: &HttpRequest) -> Option<Self> {
+        let window_secs = unique_window_secs();
+        (window_secs > 0).then(|| UniqueVisit {
+            fingerprint: fingerprint(http_req),
+            now: chrono::Utc::now().timestamp(),
+            window_secs,
+        })
+    }
+}
+
+/// Salted hash of the requester's address and client headers. Only the
+/// hash is stored, never the raw values. The address comes from forwarding
+/// headers only behind a `TRUSTED_PROXY`, as for rate limiting, so clients
+/// cannot rotate it to be counted again.
+pub fn fingerprint(http_req: &HttpRequest) -> String {
+    let header = |name: &str| {
+        http_req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("")
+            .to_string()
+    };
+    let client_ip = rate_limit::client_ip(http_req, rate_limit::trusted_proxy())
+        .map(|ip| ip.to_string())
+        .unwrap_or_default();
+    hash(&[&client_ip, &header("User-Agent"), &header("Accept-Language")])
+}
+
+/// Salted hash of `parts`, hex-encoded.
+fn hash(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt().as_bytes());
+    hasher.update([0]);
+    for part in parts {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    hasher.finalize()[..16].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    #[test]
+    fn forged_forwarded_for_does_not_change_fingerprint() {
+        // No TRUSTED_PROXY: the socket address is the client.
+        let peer = "203.0.113.7:4000".parse().unwrap();
+        let plain = TestRequest::default()
+            .peer_addr(peer)
+            .insert_header(("User-Agent", "test"))
+            .to_http_request();
+        let forged = TestRequest::default()
+            .peer_addr(peer)
+            .insert_header(("User-Agent", "test"))
+            .insert_header(("X-Forwarded-For", "198.51.100.1"))
+            .to_http_request();
+        assert_eq!(fingerprint(&plain), fingerprint(&forged));
+
+        let other = TestRequest::default()
+            .peer_addr("203.0.113.8:4000".parse().unwrap())
+            .insert_header(("User-Agent", "test"))
+            .to_http_request();
+        assert_ne!(fingerprint(&plain), fingerprint(&other));
+    }
+
+    fn config(window_secs: i64, camo_window_secs: i64) -> DedupConfig {
+        DedupConfig {
+            window_secs,
+            camo_window_secs,
+            respect_dnt: false,
+            unique_window_secs: 0,
+            sketch_threshold: 0,
+        }
+    }
+
+    fn request(peer: &str, user_agent: &str) -> HttpRequest {
+        TestRequest::default()
+            .peer_addr(peer.parse().unwrap())
+            .insert_header(("User-Agent", user_agent))
+            .to_http_request()
+    }
+
+    #[test]
+    fn camo_fetches_are_one_view_whatever_their_address() {
+        let camo = "github-camo (876de43e)";
+        let config = config(0, 10);
+        let first = config.seen_key(&request("140.82.115.1:4000", camo)).unwrap();
+        let second = config.seen_key(&request("140.82.115.99:4000", camo)).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first.0, 10);
+        // Browsers are not de-duplicated unless DEDUP_WINDOW_SECS asks.
+        assert_eq!(config.seen_key(&request("203.0.113.7:4000", "Firefox")), None);
+    }
+
+    #[test]
+    fn camo_dedup_is_opt_in() {
+        let camo = request("140.82.115.1:4000", "github-camo (876de43e)");
+        assert_eq!(config(0, 0).seen_key(&camo), None);
+        // With only the general window, camo is a requester like any other.
+        assert_eq!(config(30, 0).seen_key(&camo), Some((30, fingerprint(&camo))));
+        assert_eq!(DedupConfig::from_env().unwrap().camo_window_secs, 0);
+    }
+
+    #[test]
+    fn bad_values_are_errors_not_zero() {
+        assert_eq!(non_negative("DEDUP_TEST_UNSET", 7), Ok(7));
+        std::env::set_var("DEDUP_TEST_BAD", "ten");
+        assert!(non_negative("DEDUP_TEST_BAD", 7).unwrap_err().contains("DEDUP_TEST_BAD"));
+        std::env::set_var("DEDUP_TEST_NEGATIVE", "-5");
+        assert!(non_negative("DEDUP_TEST_NEGATIVE", 7).is_err());
+        std::env::set_var("DEDUP_TEST_GOOD", "30");
+        assert_eq!(non_negative("DEDUP_TEST_GOOD", 7), Ok(30));
+    }
+}
@@ -0,0 +1,496 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ab_glyph::FontArc;
+use actix_web::{get, http::StatusCode, web, Responder};
+use serde::Deserialize;
+use serde_json::Value;
+use url::{Host, Url};
+
+use crate::badge::{self, BadgeSpec, BadgeStyle};
+use crate::params;
+use crate::response::{svg_response, CachePolicy, DEFAULT_MAX_AGE};
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+const FETCH_LIMIT: usize = 256 * 1024;
+/// Documents kept in `JsonCache` at most.
+const MAX_CACHED: usize = 256;
+
+/// Recently fetched JSON documents by URL, so a popular badge does not fetch
+/// its source on every view. Entries live for `DYNAMIC_CACHE_SECS`
+/// (default 300).
+pub struct JsonCache {
+    entries: Mutex<HashMap<String, (Instant, Arc<Value>)>>,
+    ttl: Duration,
+}
+
+impl JsonCache {
+    pub fn from_env() -> Result<Self, String> {
+        let secs = match std::env::var("DYNAMIC_CACHE_SECS") {
+            Ok(secs) => secs.parse::<u64>()
+                .map_err(|_| format!("DYNAMIC_CACHE_SECS should be a non-negative number of seconds, got {:?}", secs))?,
+            Err(_) => 300,
+        };
+        Ok(JsonCache { entries: Mutex::new(HashMap::new()), ttl: Duration::from_secs(secs) })
+    }
+
+    fn get(&self, url: &str) -> Option<Arc<Value>> {
+        let entries = self.entries.lock().unwrap();
+        entries.get(url)
+            .filter(|(fetched_at, _)| fetched_at.elapsed() < self.ttl)
+            .map(|(_, value)| value.clone())
+    }
+
+    fn store(&self, url: &str, value: Arc<Value>) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_CACHED {
+            entries.retain(|_, (fetched_at, _)| fetched_at.elapsed() < self.ttl);
+        }
+        if entries.len() >= MAX_CACHED {
+            let oldest = entries.iter().min_by_key(|(_, (fetched_at, _))| *fetched_at).map(|(url, _)| url.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(url.to_string(), (Instant::now(), value));
+    }
+}
+
+/// Hosts dynamic badges may fetch from, `DYNAMIC_BADGE_HOSTS`
+/// (comma-separated). Unset or empty allows none, so the endpoint cannot be
+/// turned against arbitrary servers unless an operator opts in; `*` allows
+/// every public host.
+#[derive(Debug, Clone, Default)]
+pub struct AllowedHosts {
+    hosts: Vec<String>,
+}
+
+impl AllowedHosts {
+    pub fn new<'a>(hosts: impl IntoIterator<Item = &'a str>) -> Self {
+        AllowedHosts {
+            hosts: hosts.into_iter()
+                .map(|host| host.trim().trim_end_matches('.').to_ascii_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        AllowedHosts::new(std::env::var("DYNAMIC_BADGE_HOSTS").unwrap_or_default().split(','))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    fn allows(&self, host: &str) -> bool {
+        self.hosts.iter().any(|allowed| allowed == "*" || allowed == host)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DynamicRequest {
+    url: String,
+    /// Path to the value, e.g. `$.release.tag_name` or `items[0].name`.
+    query: String,
+    label: Option<String>,
+    color: Option<String>,
+    label_color: Option<String>,
+    style: Option<String>,
+    prefix: Option<String>,
+    suffix: Option<String>,
+}
+
+/// Badge showing one value from a remote JSON document, like shields.io's
+/// dynamic JSON badge. Only `https` hosts listed in `AllowedHosts` and
+/// resolving to public addresses are fetched, without following redirects.
+#[get("/dynamic/json")]
+async fn get_dynamic_badge(
+    font: web::Data<FontArc>,
+    cache: web::Data<JsonCache>,
+    hosts: web::Data<AllowedHosts>,
+    req: web::Query<DynamicRequest>,
+) -> impl Responder {
+    let error = |status: StatusCode, message: &str| crate::render_error_badge(&font, status, message);
+
+    let url = match Url::parse(&req.url) {
+        Ok(url) if is_allowed_url(&url, &hosts) => url,
+        _ => return error(StatusCode::BAD_REQUEST, "url not allowed"),
+    };
+    let path = match parse_query(&req.query) {
+        Some(path) => path,
+        None => return error(StatusCode::BAD_REQUEST, "invalid query"),
+    };
+    for color in [&req.color, &req.label_color].into_iter().flatten() {
+        if !badge::is_valid_color(color) {
+            return error(StatusCode::BAD_REQUEST, "invalid color");
+        }
+    }
+    if let Some(Err(_)) = req.style.as_deref().map(str::parse::<BadgeStyle>) {
+        return error(StatusCode::BAD_REQUEST, "invalid style");
+    }
+    if let Some(Err(message)) = req.label.as_deref().map(params::text(badge::MAX_LABEL_CHARS)) {
+        return error(StatusCode::BAD_REQUEST, &format!("label {}", message));
+    }
+
+    let document = match cache.get(url.as_str()) {
+        Some(document) => document,
+        None => match fetch(&url).await {
+            Ok(document) => {
+                let document = Arc::new(document);
+                cache.store(url.as_str(), document.clone());
+                document
+            },
+            Err(err) => {
+                tracing::info!("dynamic badge fetch of {} failed: {}", url, err);
+                return error(StatusCode::BAD_GATEWAY, "inaccessible");
+            },
+        },
+    };
+    // Blank values would leave shield_maker nothing to draw.
+    let value = match select(&document, &path).and_then(display) {
+        Some(value) if !value.chars().all(|c| c.is_whitespace() || c.is_control()) => value,
+        _ => return error(StatusCode::NOT_FOUND, "no result"),
+    };
+
+    let message = format!(
+        "{}{}{}",
+        req.prefix.as_deref().unwrap_or(""),
+        value,
+        req.suffix.as_deref().unwrap_or(""),
+    );
+    let mut spec = BadgeSpec::new(
+        crate::sanitize_label(req.label.as_deref().unwrap_or("custom badge")),
+        crate::sanitize_label(&message),
+    );
+    if let Some(color) = &req.color {
+        spec.color = Some(color.clone());
+    }
+    spec.label_color = req.label_color.clone();
+    spec.style = req.style.clone();
+    svg_response(
+        StatusCode::OK,
+        crate::render_spec(font.get_ref(), &spec),
+        CachePolicy::MaxAge { max_age: DEFAULT_MAX_AGE, stale_while_revalidate: None },
+    )
+}
+
+async fn fetch(url: &Url) -> Result<Value, String> {
+    let addr = public_address(url).await?;
+    let client = awc::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .disable_redirects()
+        .finish();
+    let mut response = client.get(url.as_str())
+        .address(addr)
+        .insert_header(("Accept", "application/json"))
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("status {}", response.status()));
+    }
+    let body = response.body().limit(FETCH_LIMIT).await.map_err(|err| err.to_string())?;
+    serde_json::from_slice(&body).map_err(|err| err.to_string())
+}
+
+fn is_allowed_url(url: &Url, hosts: &AllowedHosts) -> bool {
+    if !is_public_https(url) {
+        return false;
+    }
+    let host = match url.host() {
+        Some(Host::Domain(domain)) => domain.trim_end_matches('.').to_ascii_lowercase(),
+        Some(Host::Ipv4(ip)) => ip.to_string(),
+        Some(Host::Ipv6(ip)) => ip.to_string(),
+        None => return false,
+    };
+    hosts.allows(&host)
+}
+
+/// Whether `url` is `https` on a host outside loopback, private and
+/// link-local ranges, judging by its name alone. Use `public_address` before
+/// connecting, since a public name can resolve to a private address.
+pub fn is_public_https(url: &Url) -> bool {
+    if url.scheme() != "https" {
+        return false;
+    }
+    match url.host() {
+        Some(Host::Domain(domain)) => {
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            domain != "localhost" && !domain.ends_with(".localhost")
+        },
+        Some(Host::Ipv4(ip)) => is_public(IpAddr::V4(ip)),
+        Some(Host::Ipv6(ip)) => is_public(IpAddr::V6(ip)),
+        None => false,
+    }
+}
+
+/// Resolve a public `https` URL and pick the address to connect to. Fails
+/// when any address the host resolves to is not public, so a name pointed
+/// at 127.0.0.1, 169.254.169.254 or an RFC 1918 network is refused. Pass
+/// the result to `ClientRequest::address` so the request goes to the
+/// checked address instead of resolving the name a second time.
+pub async fn public_address(url: &Url) -> Result<SocketAddr, String> {
+    if !is_public_https(url) {
+        return Err(format!("{} is not a public https URL", url));
+    }
+    let port = url.port_or_known_default().unwrap_or(443);
+    let addrs = match url.host() {
+        Some(Host::Domain(domain)) => {
+            let domain = domain.to_string();
+            web::block(move || (domain.as_str(), port).to_socket_addrs().map(Vec::from_iter))
+                .await
+                .map_err(|err| err.to_string())?
+                .map_err(|err| format!("could not resolve {}: {}", url, err))?
+        },
+        Some(Host::Ipv4(ip)) => vec![SocketAddr::new(IpAddr::V4(ip), port)],
+        Some(Host::Ipv6(ip)) => vec![SocketAddr::new(IpAddr::V6(ip), port)],
+        None => Vec::new(),
+    };
+    first_public(&addrs)
+}
+
+fn first_public(addrs: &[SocketAddr]) -> Result<SocketAddr, String> {
+    if let Some(private) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+        return Err(format!("resolves to non-public address {}", private.ip()));
+    }
+    addrs.first().copied().ok_or_else(|| "resolves to no address".to_string())
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                // 100.64.0.0/10, carrier-grade NAT
+                || (a == 100 && (b & 0xc0) == 64)
+                // 198.18.0.0/15, benchmarking
+                || (a == 198 && (b & 0xfe) == 18)
+                // 240.0.0.0/4, reserved, broadcast included
+                || a >= 240)
+        },
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || (segments[0] & 0xfe00) == 0xfc00
+                || (segments[0] & 0xffc0) == 0xfe80
+                // 64:ff9b::/96 NAT64 and 2002::/16 6to4 reach IPv4 hosts
+                // through a gateway, private ones included.
+                || segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0]
+                || segments[0] == 0x2002
+                || ip.to_ipv4_mapped().is_some_and(|ip| !is_public(IpAddr::V4(ip))))
+        },
+    }
+}
+
+/// One step into a JSON document.
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// Parse `$.a.b[0]`, `a.b[0]` or `a.b.0` into segments. `$` alone selects
+/// the whole document.
+fn parse_query(query: &str) -> Option<Vec<Segment>> {
+    let query = query.trim();
+    let query = query.strip_prefix('$').unwrap_or(query);
+    let mut segments = Vec::new();
+    for part in query.split('.').filter(|part| !part.is_empty()) {
+        let (key, mut rest) = match part.find('[') {
+            Some(bracket) => part.split_at(bracket),
+            None => (part, ""),
+        };
+        if !key.is_empty() {
+            segments.push(match key.parse::<usize>() {
+                Ok(index) => Segment::Index(index),
+                Err(_) => Segment::Key(key.to_string()),
+            });
+        }
+        while !rest.is_empty() {
+            let (inner, after) = rest.strip_prefix('[')?.split_once(']')?;
+            let inner = inner.trim_matches(|c| c == '\'' || c == '"');
+            segments.push(match inner.parse::<usize>() {
+                Ok(index) => Segment::Index(index),
+                Err(_) => Segment::Key(inner.to_string()),
+            });
+            rest = after;
+        }
+    }
+    Some(segments)
+}
+
+fn select<'a>(document: &'a Value, path: &[Segment]) -> Option<&'a Value> {
+    path.iter().try_fold(document, |value, segment| match segment {
+        Segment::Key(key) => value.get(key.as_str()),
+        Segment::Index(index) => value.get(*index),
+    })
+}
+
+/// The badge text for a selected value: strings as they are, other scalars
+/// in JSON form and arrays of scalars joined with commas.
+fn display(value: &Value) -> Option<String> {
+    match value {
+        Value::Null | Value::Object(_) => None,
+        Value::String(text) => Some(text.clone()),
+        Value::Bool(_) | Value::Number(_) => Some(value.to_string()),
+        Value::Array(items) => {
+            let items: Option<Vec<String>> = items.iter()
+                .map(|item| match item {
+                    Value::Array(_) => None,
+                    item => display(item),
+                })
+                .collect();
+            items.map(|items| items.join(", "))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::App;
+    use serde_json::json;
+
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn private_and_local_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "0.0.0.0",
+            "100.64.0.1", "::1", "fd00::1", "fe80::1", "::ffff:127.0.0.1", "::ffff:10.0.0.1",
+            "198.18.0.1", "198.19.255.255", "240.0.0.1", "255.255.255.255", "224.0.0.1",
+            "64:ff9b::a00:1", "64:ff9b::101:101", "2002:a00:1::1", "2002:101:101::1", "ff02::1", "ff0e::1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["1.1.1.1", "140.82.112.3", "198.20.0.1", "223.255.255.1", "2606:4700::1111", "64:ff9c::1"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn only_listed_hosts_are_allowed() {
+        let target = url("https://Example.com./data.json");
+        assert!(!is_allowed_url(&target, &AllowedHosts::default()));
+        assert!(!is_allowed_url(&target, &AllowedHosts::new(["", " "])));
+        assert!(!is_allowed_url(&target, &AllowedHosts::new(["api.example.com"])));
+        assert!(is_allowed_url(&target, &AllowedHosts::new(["api.github.com", " EXAMPLE.com "])));
+        assert!(is_allowed_url(&target, &AllowedHosts::new(["*"])));
+        assert!(!is_allowed_url(&url("https://127.0.0.1/data.json"), &AllowedHosts::new(["*", "127.0.0.1"])));
+    }
+
+    #[test]
+    fn any_private_address_refuses_the_host() {
+        let public: SocketAddr = "140.82.112.3:443".parse().unwrap();
+        let metadata: SocketAddr = "169.254.169.254:443".parse().unwrap();
+        assert_eq!(first_public(&[public]), Ok(public));
+        assert!(first_public(&[public, metadata]).is_err());
+        assert!(first_public(&[]).is_err());
+    }
+
+    #[actix_web::test]
+    async fn names_are_checked_by_what_they_resolve_to() {
+        for target in [
+            "http://example.com/data.json",
+            "https://localhost/data.json",
+            "https://localhost./data.json",
+            "https://127.0.0.1/data.json",
+            "https://169.254.169.254/latest/meta-data",
+            "https://[::1]/data.json",
+        ] {
+            assert!(public_address(&url(target)).await.is_err(), "{}", target);
+        }
+        // "localhost" resolves without a network; whatever it resolves to
+        // must not pass once the name check is out of the way.
+        let resolved: Vec<SocketAddr> = ("localhost", 443).to_socket_addrs().unwrap().collect();
+        assert!(first_public(&resolved).is_err());
+        assert_eq!(
+            public_address(&url("https://1.1.1.1:8443/data.json")).await,
+            Ok("1.1.1.1:8443".parse().unwrap()),
+        );
+    }
+
+    #[test]
+    fn queries_select_nested_values() {
+        let document = json!({ "release": { "tag_name": "v1.2.0", "assets": [{ "name": "a" }, { "name": "b" }] }, "tags": ["x", 2, true] });
+        for (query, expected) in [
+            ("$.release.tag_name", Some("v1.2.0")),
+            ("release.assets[1].name", Some("b")),
+            ("release.assets.0.name", Some("a")),
+            ("$['release']['tag_name']", Some("v1.2.0")),
+            ("tags", Some("x, 2, true")),
+            ("release", None),
+            ("missing", None),
+        ] {
+            let path = parse_query(query).unwrap();
+            assert_eq!(select(&document, &path).and_then(display).as_deref(), expected, "{}", query);
+        }
+        assert!(parse_query("a[0").is_none());
+    }
+
+    #[actix_web::test]
+    async fn bad_requests_get_an_error_badge_without_fetching() {
+        let font = FontArc::try_from_vec(std::fs::read("src/fonts/DejaVuSans.ttf").unwrap()).unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(font))
+                .app_data(web::Data::new(JsonCache::from_env().unwrap()))
+                .app_data(web::Data::new(AllowedHosts::new(["*"])))
+                .service(get_dynamic_badge),
+        )
+        .await;
+        for (query, message) in [
+            ("url=https://127.0.0.1/x.json&query=a", "url not allowed"),
+            ("url=https://169.254.169.254/x.json&query=a", "url not allowed"),
+            ("url=http://example.com/x.json&query=a", "url not allowed"),
+            ("url=https://example.com/x.json&query=a&label=", "label must not be empty"),
+            ("url=https://example.com/x.json&query=a[", "invalid query"),
+        ] {
+            let response = call_service(&app, TestRequest::get().uri(&format!("/dynamic/json?{}", query)).to_request()).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", query);
+            let body = read_body(response).await;
+            assert!(std::str::from_utf8(&body).unwrap().contains(&format!(">{}</text>", message)), "{}", query);
+        }
+    }
+
+    #[actix_web::test]
+    async fn blank_values_are_no_result() {
+        let font = FontArc::try_from_vec(std::fs::read("src/fonts/DejaVuSans.ttf").unwrap()).unwrap();
+        let cache = JsonCache::from_env().unwrap();
+        cache.store("https://example.com/x.json", Arc::new(json!({ "name": "ok", "empty": "", "blank": [" "] })));
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(font))
+                .app_data(web::Data::new(cache))
+                .app_data(web::Data::new(AllowedHosts::new(["example.com"])))
+                .service(get_dynamic_badge),
+        )
+        .await;
+        for (query, status, text) in [
+            ("name&label=status", StatusCode::OK, ">ok</text>"),
+            ("empty", StatusCode::NOT_FOUND, ">no result</text>"),
+            ("blank", StatusCode::NOT_FOUND, ">no result</text>"),
+        ] {
+            let uri = format!("/dynamic/json?url=https://example.com/x.json&query={}", query);
+            let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+            assert_eq!(response.status(), status, "{}", query);
+            let body = read_body(response).await;
+            assert!(std::str::from_utf8(&body).unwrap().contains(text), "{}", query);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, Result};
+use serde::{Deserialize, Serialize};
+use url::form_urlencoded;
+
+use crate::api_keys::{self, Scope};
+use crate::badge::{self, BadgeStyle};
+use crate::claim;
+use crate::i18n;
+use crate::render::escape_xml;
+use crate::signing;
+use crate::store::DEFAULT_PAGE;
+use crate::DbPool;
+
+#[derive(Debug, Deserialize)]
+pub struct EmbedRequest {
+    key: String,
+    page: Option<String>,
+    style: Option<String>,
+    label: Option<String>,
+    color: Option<String>,
+    label_color: Option<String>,
+}
+
+/// Ready-to-paste snippets for one badge URL.
+#[derive(Debug, Serialize)]
+pub struct Snippets {
+    pub url: String,
+    pub markdown: String,
+    pub html: String,
+    pub rst: String,
+}
+
+/// Markdown, HTML and reStructuredText snippets for a counter's badge with
+/// the requested look baked into the URL. Needs the badge key, since the
+/// snippets contain it. The badge key is public, so with
+/// `BADGE_SIGNING_SECRET` set only requests bearing `ADMIN_TOKEN`, an admin
+/// API key or the counter's owner key get a signed URL; everyone else gets
+/// an unsigned one, which badges reject.
+#[get("/embed/{id}")]
+async fn get_embed(
+    path: web::Path<String>,
+    req: web::Query<EmbedRequest>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse> {
+    let badge_key = std::env::var("BADGE_KEY").expect("BADGE_KEY should be set");
+    if req.key != badge_key {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({ "error": "counter not found" })));
+    }
+    if let Some(page) = req.page.as_deref().filter(|page| !crate::is_valid_page(page)) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": format!("invalid page {:?}", page) })));
+    }
+    if let Some(Err(err)) = req.style.as_deref().map(str::parse::<BadgeStyle>) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": err.to_string() })));
+    }
+    for color in [&req.color, &req.label_color].into_iter().flatten() {
+        if !badge::is_valid_color(color) {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": format!("invalid color {:?}", color) })));
+        }
+    }
+
+    let mut query = form_urlencoded::Serializer::new(String::new());
+    query.append_pair("key", &req.key);
+    let optional = [
+        ("page", &req.page),
+        ("style", &req.style),
+        ("label", &req.label),
+        ("color", &req.color),
+        ("label_color", &req.label_color),
+    ];
+    for (name, value) in optional {
+        if let Some(value) = value {
+            query.append_pair(name, value);
+        }
+    }
+    if let Some(secret) = signing::secret() {
+        if may_sign(&http_req, &path).await? {
+            let page = req.page.as_deref().unwrap_or(DEFAULT_PAGE);
+            query.append_pair("sig", &signing::sign(&secret, &path, page));
+        }
+    }
+    let connection_info = http_req.connection_info();
+    let url = format!(
+        "{}://{}{}/badge/{}?{}",
+        connection_info.scheme(),
+        connection_info.host(),
+        crate::base_path(),
+        form_urlencoded::byte_serialize(path.as_bytes()).collect::<String>(),
+        query.finish(),
+    );
+    let alt = req.label.as_deref().unwrap_or(i18n::ENGLISH.label);
+    Ok(HttpResponse::Ok().json(Snippets {
+        markdown: format!("![{}]({})", alt.replace(['[', ']'], ""), url),
+        html: format!("<img src=\"{}\" alt=\"{}\">", escape_xml(&url), escape_xml(alt)),
+        rst: format!(".. image:: {}\n   :alt: {}", url, alt.replace('\n', " ")),
+        url,
+    }))
+}
+
+/// Whether the request may be handed signatures for `user`'s badges: it
+/// bears admin credentials or, with the SQL store, `user`'s owner key.
+async fn may_sign(http_req: &HttpRequest, user: &str) -> Result<bool> {
+    let token = match api_keys::bearer(http_req.headers()) {
+        Some(token) => token.to_string(),
+        None => return Ok(false),
+    };
+    let pool = http_req.app_data::<web::Data<DbPool>>();
+    if api_keys::granted(pool, &token).await?.allows(Scope::Admin) {
+        return Ok(true);
+    }
+    match pool {
+        Some(pool) => claim::is_owner(pool, http_req, user).await,
+        None => Ok(false),
+    }
+}
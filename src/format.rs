@@ -0,0 +1,145 @@
+use serde::Deserialize;
+
+/// How a view count is written on the badge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CountFormat {
+    /// `12345`
+    #[default]
+    Plain,
+    /// `12,345`
+    Comma,
+    /// `12.3k`
+    Abbrev,
+}
+
+impl CountFormat {
+    /// Default from `COUNT_FORMAT`, plain when unset or unknown.
+    pub fn from_env() -> Self {
+        match std::env::var("COUNT_FORMAT").as_deref() {
+            Ok("comma") => CountFormat::Comma,
+            Ok("abbrev") => CountFormat::Abbrev,
+            _ => CountFormat::Plain,
+        }
+    }
+}
+
+const UNITS: &[(i64, &str)] = &[
+    (1_000_000_000_000, "T"),
+    (1_000_000_000, "B"),
+    (1_000_000, "M"),
+    (1_000, "k"),
+];
+
+/// Display steps a counter owner may round public counts to.
+pub const DISPLAY_STEPS: &[i64] = &[1, 10, 100, 1000];
+
+/// `count` rounded half up to a multiple of `step`, for counters whose
+/// owners do not want exact traffic shown. Steps below 2 keep it exact.
+pub fn round_count(count: i64, step: i64) -> i64 {
+    if step < 2 {
+        return count;
+    }
+    count.saturating_add(step / 2).div_euclid(step).saturating_mul(step)
+}
+
+pub fn format_count(count: i64, mode: CountFormat) -> String {
+    match mode {
+        CountFormat::Plain => count.to_string(),
+        CountFormat::Comma => with_separators(count),
+        CountFormat::Abbrev => abbreviate(count),
+    }
+}
+
+fn with_separators(count: i64) -> String {
+    let digits = count.unsigned_abs().to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3 + 1);
+    if count < 0 {
+        grouped.push('-');
+    }
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+/// One decimal place, rounded half up, dropping a trailing `.0`. Values
+/// that round up to the next unit (999,950 -> 1000.0k) move to that unit.
+fn abbreviate(count: i64) -> String {
+    let sign = if count < 0 { "-" } else { "" };
+    let value = count.unsigned_abs() as u128;
+    for (i, (unit, suffix)) in UNITS.iter().enumerate() {
+        let unit = *unit as u128;
+        if value < unit {
+            continue;
+        }
+        let tenths = (value * 10 + unit / 2) / unit;
+        if tenths >= 10_000 && i > 0 {
+            let (bigger_unit, bigger_suffix) = UNITS[i - 1];
+            return format_tenths(sign, (value * 10 + bigger_unit as u128 / 2) / bigger_unit as u128, bigger_suffix);
+        }
+        return format_tenths(sign, tenths, suffix);
+    }
+    count.to_string()
+}
+
+fn format_tenths(sign: &str, tenths: u128, suffix: &str) -> String {
+    if tenths.is_multiple_of(10) {
+        format!("{}{}{}", sign, tenths / 10, suffix)
+    } else {
+        format!("{}{}.{}{}", sign, tenths / 10, tenths % 10, suffix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn abbreviations_switch_units_at_the_boundaries() {
+        for (count, expected) in [
+            (0, "0"),
+            (999, "999"),
+            (1_000, "1k"),
+            (1_049, "1k"),
+            (1_050, "1.1k"),
+            (12_345, "12.3k"),
+            (999_949, "999.9k"),
+            (999_950, "1M"),
+            (1_000_000, "1M"),
+            (1_050_000, "1.1M"),
+            (2_500_000_000, "2.5B"),
+            (-1_500, "-1.5k"),
+        ] {
+            assert_eq!(format_count(count, CountFormat::Abbrev), expected, "{}", count);
+        }
+    }
+
+    #[test]
+    fn separators_group_thousands() {
+        for (count, expected) in [
+            (0, "0"),
+            (999, "999"),
+            (1_000, "1,000"),
+            (12_345, "12,345"),
+            (1_234_567, "1,234,567"),
+            (-1_234, "-1,234"),
+        ] {
+            assert_eq!(format_count(count, CountFormat::Comma), expected, "{}", count);
+        }
+        assert_eq!(format_count(12_345, CountFormat::Plain), "12345");
+    }
+
+    #[test]
+    fn counts_round_half_up_to_the_step() {
+        assert_eq!(round_count(1_234, 1), 1_234);
+        assert_eq!(round_count(1_234, 10), 1_230);
+        assert_eq!(round_count(1_235, 10), 1_240);
+        assert_eq!(round_count(1_249, 100), 1_200);
+        assert_eq!(round_count(1_250, 100), 1_300);
+        assert_eq!(round_count(i64::MAX, 1_000), i64::MAX / 1_000 * 1_000);
+    }
+}
@@ -0,0 +1,106 @@
+use std::net::IpAddr;
+
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder, Result};
+use serde::Deserialize;
+
+use crate::actions::DbError;
+use crate::rate_limit;
+use crate::store::CounterStore;
+
+/// Country lookups of client addresses in a MaxMind GeoLite2 or GeoIP2
+/// Country (or City) database. Only the resulting country code is stored,
+/// never the address.
+// Without the `geoip` feature `from_env` refuses to build one.
+#[cfg_attr(not(feature = "geoip"), allow(dead_code))]
+pub struct GeoIp {
+    #[cfg(feature = "geoip")]
+    reader: maxminddb::Reader<Vec<u8>>,
+    trusted_proxy: bool,
+}
+
+impl GeoIp {
+    /// Open the database at `GEOIP_DATABASE`, or `None` when it is unset.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        match std::env::var("GEOIP_DATABASE") {
+            Ok(path) if !path.is_empty() => Self::open(&path).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    #[cfg(feature = "geoip")]
+    fn open(path: &str) -> Result<Self, String> {
+        let reader = maxminddb::Reader::open_readfile(path)
+            .map_err(|err| format!("could not open GEOIP_DATABASE {:?}: {}", path, err))?;
+        Ok(GeoIp { reader, trusted_proxy: rate_limit::trusted_proxy() })
+    }
+
+    #[cfg(not(feature = "geoip"))]
+    fn open(_path: &str) -> Result<Self, String> {
+        Err("GEOIP_DATABASE is set but this build lacks the geoip feature".to_string())
+    }
+
+    /// ISO 3166-1 alpha-2 code of the country `ip` is located in, falling
+    /// back to the country its network is registered in.
+    #[cfg(feature = "geoip")]
+    fn lookup(&self, ip: IpAddr) -> Option<String> {
+        let record = self.reader.lookup::<maxminddb::geoip2::Country>(ip).ok()?;
+        record.country
+            .and_then(|country| country.iso_code)
+            .or_else(|| record.registered_country.and_then(|country| country.iso_code))
+            .map(str::to_string)
+    }
+
+    #[cfg(not(feature = "geoip"))]
+    fn lookup(&self, _ip: IpAddr) -> Option<String> {
+        None
+    }
+}
+
+/// Country of the requesting client, or `None` when no database is
+/// configured or the address is not in it.
+pub fn country(http_req: &HttpRequest) -> Option<String> {
+    let geoip = http_req.app_data::<web::Data<GeoIp>>()?;
+    geoip.lookup(rate_limit::client_ip(http_req, geoip.trusted_proxy)?)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CountriesRequest {
+    page: Option<String>,
+}
+
+/// Counted views per visitor country, most views first. Countries are only
+/// known while `GEOIP_DATABASE` is configured, so the counts may add up to
+/// less than the total.
+#[utoipa::path(
+    tag = "counters",
+    params(
+        ("id" = String, Path, description = "Counter id"),
+        ("page" = Option<String>, Query, description = "Page namespace"),
+    ),
+    responses(
+        (status = 200, description = "Views per country, most first", body = Countries),
+        (status = 404, description = "Counter not found"),
+    ),
+)]
+#[get("/api/stats/{id}/countries")]
+async fn get_countries(
+    store: web::Data<dyn CounterStore>,
+    path: web::Path<String>,
+    req: web::Query<CountriesRequest>,
+) -> Result<impl Responder> {
+    let user = path.into_inner();
+    let page = match crate::page_param(req.page.as_deref()) {
+        Ok(page) => page,
+        Err(response) => return Ok(response),
+    };
+    let countries = web::block(move || {
+        let display_step = store.display_step(&user)?;
+        Ok::<_, DbError>(store.countries(&user, &page)?.map(|countries| countries.rounded(display_step)))
+    })
+    .await?
+    .map_err(crate::store_error)?;
+    Ok(match countries {
+        Some(countries) => HttpResponse::Ok().json(countries),
+        None => HttpResponse::NotFound().json(serde_json::json!({ "error": "counter not found" })),
+    })
+}
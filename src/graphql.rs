@@ -0,0 +1,310 @@
+use std::sync::Arc;
+
+use actix_web::{route, web, HttpRequest, Result};
+use async_graphql::{Context, EmptySubscription, Object, Schema};
+use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
+
+use crate::actions::{self, DbError};
+use crate::api_keys::{self, Granted, Scope};
+use crate::models::{CounterStats, History, LeaderboardEntry, Visitors};
+use crate::store::{AlreadyExists, CounterStore, DEFAULT_PAGE};
+use crate::DbPool;
+
+/// Largest `leaderboard(limit:)`.
+const MAX_LEADERBOARD: usize = 100;
+
+/// Largest `pages(first:)`.
+const MAX_PAGES: usize = 100;
+
+pub type CounterSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+/// Schema with depth and complexity limits, built once at startup.
+pub fn schema() -> CounterSchema {
+    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .limit_depth(8)
+        .limit_complexity(256)
+        .finish()
+}
+
+/// GraphQL over `GET` and `POST`. Reads are public like the JSON API;
+/// every mutation needs the admin scope.
+#[route("/graphql", method = "GET", method = "POST")]
+async fn graphql(
+    schema: web::Data<CounterSchema>,
+    store: web::Data<dyn CounterStore>,
+    req: GraphQLRequest,
+    http_req: HttpRequest,
+) -> Result<GraphQLResponse> {
+    let granted = match api_keys::bearer(http_req.headers()) {
+        Some(token) => api_keys::granted(http_req.app_data::<web::Data<DbPool>>(), token).await?,
+        None => Granted(Vec::new()),
+    };
+    let pool = http_req.app_data::<web::Data<DbPool>>().cloned();
+    let request = req.into_inner().data(store.into_inner()).data(granted).data(pool);
+    Ok(schema.execute(request).await.into())
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// One counter, on the default page unless `page` is given.
+    async fn counter(&self, ctx: &Context<'_>, id: String, page: Option<String>) -> async_graphql::Result<Option<Visitors>> {
+        let page = page_arg(page)?;
+        let store = store(ctx);
+        Ok(web::block(move || {
+            let display_step = store.display_step(&id)?;
+            Ok::<_, DbError>(store.get(&id, &page)?.map(|visitor| visitor.rounded(display_step)))
+        })
+        .await??)
+    }
+
+    /// Pages of a counter, ordered by page: the first `first` (20 by
+    /// default, at most 100) after page `after`.
+    async fn pages(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        first: Option<usize>,
+        after: Option<String>,
+    ) -> async_graphql::Result<Vec<Visitors>> {
+        let first = first.unwrap_or(20).clamp(1, MAX_PAGES);
+        let store = store(ctx);
+        Ok(web::block(move || {
+            let display_step = store.display_step(&id)?;
+            let pages = store.pages(&id, after.as_deref(), first)?;
+            Ok::<_, DbError>(pages.into_iter().map(|visitor| visitor.rounded(display_step)).collect())
+        })
+        .await??)
+    }
+
+    /// Total, today, this week and last view time of one counter.
+    async fn stats(&self, ctx: &Context<'_>, id: String, page: Option<String>) -> async_graphql::Result<Option<CounterStats>> {
+        let page = page_arg(page)?;
+        let store = store(ctx);
+        Ok(web::block(move || crate::counter_stats(store.as_ref(), &id, &page)).await??)
+    }
+
+    /// Views per day over the last `days` days, oldest first.
+    async fn history(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        page: Option<String>,
+        days: Option<u32>,
+    ) -> async_graphql::Result<Option<History>> {
+        let page = page_arg(page)?;
+        let days = crate::history_days(days);
+        let store = store(ctx);
+        Ok(web::block(move || {
+            let display_step = store.display_step(&id)?;
+            Ok::<_, DbError>(store.history(&id, &page, days)?.map(|history| history.rounded(display_step)))
+        })
+        .await??)
+    }
+
+    /// Counters with the most views, 10 by default, as on
+    /// `/api/leaderboard`: views summed over pages, opted-out counters left
+    /// out. Needs a SQL database.
+    async fn leaderboard(&self, ctx: &Context<'_>, limit: Option<usize>) -> async_graphql::Result<Vec<LeaderboardEntry>> {
+        let Some(pool) = ctx.data_unchecked::<Option<web::Data<DbPool>>>().clone() else {
+            return Err("leaderboard needs a SQL database".into());
+        };
+        let limit = limit.unwrap_or(10).clamp(1, MAX_LEADERBOARD) as i64;
+        Ok(web::block(move || {
+            let mut conn = pool.get()?;
+            actions::public_leaderboard(&mut conn, limit)
+        })
+        .await??)
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// Create a counter, failing if it already exists.
+    async fn create_counter(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        page: Option<String>,
+        view_count: Option<i64>,
+    ) -> async_graphql::Result<Visitors> {
+        require_admin(ctx)?;
+        if !crate::is_valid_page(&id) {
+            return Err("invalid counter id".into());
+        }
+        let page = page_arg(page)?;
+        let view_count = count_arg(view_count.unwrap_or(0))?;
+        let store = store(ctx);
+        let visitor = web::block(move || {
+            let visitor = match store.create(&id, &page) {
+                Ok(visitor) => visitor,
+                Err(err) if err.is::<AlreadyExists>() => return Ok(None),
+                Err(err) => return Err(err),
+            };
+            if view_count == 0 {
+                return Ok(Some(visitor));
+            }
+            store.set(&id, &page, view_count).map(Some)
+        })
+        .await??;
+        visitor.ok_or_else(|| "counter already exists".into())
+    }
+
+    /// Set a counter to an exact value, creating it if needed.
+    async fn set_count(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        page: Option<String>,
+        view_count: i64,
+    ) -> async_graphql::Result<Visitors> {
+        require_admin(ctx)?;
+        let page = page_arg(page)?;
+        let view_count = count_arg(view_count)?;
+        let store = store(ctx);
+        Ok(web::block(move || store.set(&id, &page, view_count)).await??)
+    }
+
+    /// Start an existing counter over at `viewCount`, 0 by default. Null when
+    /// the counter does not exist.
+    async fn reset_counter(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        page: Option<String>,
+        view_count: Option<i64>,
+    ) -> async_graphql::Result<Option<Visitors>> {
+        require_admin(ctx)?;
+        let page = page_arg(page)?;
+        let view_count = count_arg(view_count.unwrap_or(0))?;
+        let store = store(ctx);
+        Ok(web::block(move || {
+            if store.get(&id, &page)?.is_none() {
+                return Ok(None);
+            }
+            store.set(&id, &page, view_count).map(Some)
+        })
+        .await??)
+    }
+
+    /// Remove a counter. False when it did not exist.
+    async fn delete_counter(&self, ctx: &Context<'_>, id: String, page: Option<String>) -> async_graphql::Result<bool> {
+        require_admin(ctx)?;
+        let page = page_arg(page)?;
+        let store = store(ctx);
+        Ok(web::block(move || store.delete(&id, &page)).await??)
+    }
+}
+
+fn store(ctx: &Context<'_>) -> Arc<dyn CounterStore> {
+    ctx.data_unchecked::<Arc<dyn CounterStore>>().clone()
+}
+
+fn require_admin(ctx: &Context<'_>) -> async_graphql::Result<()> {
+    if ctx.data_unchecked::<Granted>().allows(Scope::Admin) {
+        Ok(())
+    } else {
+        Err("needs an API key with the admin scope".into())
+    }
+}
+
+fn page_arg(page: Option<String>) -> async_graphql::Result<String> {
+    match page {
+        None => Ok(DEFAULT_PAGE.to_string()),
+        Some(page) if crate::is_valid_page(&page) => Ok(page),
+        Some(page) => Err(format!("invalid page {:?}", page).into()),
+    }
+}
+
+fn count_arg(view_count: i64) -> async_graphql::Result<i64> {
+    if view_count < 0 {
+        return Err("viewCount must not be negative".into());
+    }
+    Ok(view_count)
+}
+
+#[cfg(all(test, not(feature = "postgres")))]
+mod tests {
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::App;
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use diesel_migrations::MigrationHarness;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::store::SqlStore;
+
+    async fn query(pool: Option<DbPool>, store: Arc<dyn CounterStore>, query: &str) -> Value {
+        let mut app = App::new()
+            .app_data(web::Data::new(schema()))
+            .app_data(web::Data::from(store))
+            .service(graphql);
+        if let Some(pool) = pool {
+            app = app.app_data(web::Data::new(pool));
+        }
+        let app = init_service(app).await;
+        let request = TestRequest::post().uri("/graphql").set_json(json!({ "query": query }));
+        read_body_json(call_service(&app, request.to_request()).await).await
+    }
+
+    fn pool() -> DbPool {
+        let pool: DbPool = Pool::builder()
+            .max_size(1)
+            .build(ConnectionManager::<actions::DbConnection>::new(":memory:"))
+            .unwrap();
+        pool.get().unwrap().run_pending_migrations(crate::MIGRATIONS).unwrap();
+        pool
+    }
+
+    #[actix_web::test]
+    async fn leaderboard_is_public_and_leaves_out_opted_out_counters() {
+        let pool = pool();
+        let store: Arc<dyn CounterStore> = Arc::new(SqlStore::new(pool.clone()));
+        store.set("octocat", "home", 5).unwrap();
+        store.set("octocat", "blog", 4).unwrap();
+        store.set("hubot", "home", 7).unwrap();
+        store.set("shy", "home", 100).unwrap();
+        actions::set_leaderboard_hidden(&mut pool.get().unwrap(), "shy", true).unwrap();
+
+        let body = query(Some(pool), store.clone(), "{ leaderboard { id viewCount } }").await;
+        assert!(body.get("errors").is_none(), "{}", body);
+        assert_eq!(
+            body["data"]["leaderboard"],
+            json!([
+                { "id": "octocat", "viewCount": 9 },
+                { "id": "hubot", "viewCount": 7 },
+                { "id": "me", "viewCount": 0 },
+            ]),
+        );
+
+        let body = query(None, store, "{ leaderboard { id } }").await;
+        assert_eq!(body["errors"][0]["message"], "leaderboard needs a SQL database");
+    }
+
+    #[actix_web::test]
+    async fn pages_come_a_page_at_a_time() {
+        let pool = pool();
+        let store: Arc<dyn CounterStore> = Arc::new(SqlStore::new(pool));
+        for page in ["a", "b", "c", "d", "e"] {
+            store.set("octocat", page, 1).unwrap();
+        }
+        store.set("hubot", "a", 1).unwrap();
+
+        let pages = |body: Value| -> Vec<String> {
+            body["data"]["pages"].as_array().unwrap().iter()
+                .map(|visitor| visitor["page"].as_str().unwrap().to_string())
+                .collect()
+        };
+        let body = query(None, store.clone(), r#"{ pages(id: "octocat", first: 2) { page } }"#).await;
+        assert_eq!(pages(body), ["a", "b"]);
+        let body = query(None, store.clone(), r#"{ pages(id: "octocat", first: 2, after: "b") { page } }"#).await;
+        assert_eq!(pages(body), ["c", "d"]);
+        let body = query(None, store.clone(), r#"{ pages(id: "octocat", after: "d") { page } }"#).await;
+        assert_eq!(pages(body), ["e"]);
+        let body = query(None, store, r#"{ pages(id: "octocat") { page } }"#).await;
+        assert_eq!(pages(body), ["a", "b", "c", "d", "e"]);
+    }
+}
@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use ab_glyph::{Font, FontArc};
+use actix_web::{get, web, HttpResponse, Responder};
+use serde_json::json;
+
+use crate::{actions, DbPool};
+
+/// How long a health check waits for a pooled connection.
+const POOL_CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Whether the pool hands out a connection in time and, with `query`, the
+/// counters table answers through it. Backends without a pool have nothing
+/// to check.
+async fn database_ok(pool: Option<web::Data<DbPool>>, query: bool) -> bool {
+    let pool = match pool {
+        Some(pool) => pool,
+        None => return true,
+    };
+    let check = move || {
+        let mut conn = pool.get_timeout(POOL_CHECK_TIMEOUT)?;
+        if query {
+            actions::check_schema(&mut conn)?;
+        }
+        Ok::<_, actions::DbError>(())
+    };
+    match web::block(check).await {
+        Ok(Ok(())) => true,
+        Ok(Err(err)) => {
+            tracing::warn!("health check could not get a database connection: {}", err);
+            false
+        },
+        Err(err) => {
+            tracing::warn!("health check failed: {}", err);
+            false
+        },
+    }
+}
+
+/// The badge font renders digits, which every badge needs.
+fn font_ok(font: &FontArc) -> bool {
+    ('0'..='9').all(|c| font.glyph_id(c).0 != 0)
+}
+
+fn status_response(checks: serde_json::Value, ok: bool) -> HttpResponse {
+    let mut builder = if ok { HttpResponse::Ok() } else { HttpResponse::ServiceUnavailable() };
+    builder
+        .insert_header(("Cache-Control", "no-store"))
+        .json(json!({ "status": if ok { "ok" } else { "unavailable" }, "checks": checks }))
+}
+
+/// Liveness: the database is reachable.
+#[get("/healthz")]
+async fn healthz(pool: Option<web::Data<DbPool>>) -> impl Responder {
+    let database = database_ok(pool, false).await;
+    status_response(json!({ "database": database }), database)
+}
+
+/// Readiness: the database is reachable and serves the counters table, and
+/// the badge font is usable. A database that connects but is broken, say
+/// with its migrations missing, keeps the instance live but not ready.
+#[get("/readyz")]
+async fn readyz(pool: Option<web::Data<DbPool>>, font: web::Data<FontArc>) -> impl Responder {
+    let database = database_ok(pool, true).await;
+    let font = font_ok(font.get_ref());
+    status_response(json!({ "database": database, "font": font }), database && font)
+}
+
+#[cfg(all(test, not(feature = "postgres")))]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::App;
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use diesel_migrations::MigrationHarness;
+
+    use super::*;
+
+    fn font() -> FontArc {
+        FontArc::try_from_vec(std::fs::read("src/fonts/DejaVuSans.ttf").unwrap()).unwrap()
+    }
+
+    fn pool(url: &str) -> DbPool {
+        Pool::builder()
+            .max_size(1)
+            .connection_timeout(POOL_CHECK_TIMEOUT)
+            .build_unchecked(ConnectionManager::new(url))
+    }
+
+    /// Status and `checks.database` of `/healthz` and `/readyz`.
+    async fn check(pool: DbPool) -> [(StatusCode, serde_json::Value); 2] {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(pool))
+                .app_data(web::Data::new(font()))
+                .service(healthz)
+                .service(readyz),
+        )
+        .await;
+        let mut results = Vec::new();
+        for path in ["/healthz", "/readyz"] {
+            let response = call_service(&app, TestRequest::get().uri(path).to_request()).await;
+            let status = response.status();
+            let body: serde_json::Value = read_body_json(response).await;
+            results.push((status, body["checks"]["database"].clone()));
+        }
+        results.try_into().unwrap()
+    }
+
+    #[actix_web::test]
+    async fn a_working_database_is_live_and_ready() {
+        let pool = pool(":memory:");
+        pool.get().unwrap().run_pending_migrations(crate::MIGRATIONS).unwrap();
+        assert_eq!(check(pool).await, [(StatusCode::OK, true.into()), (StatusCode::OK, true.into())]);
+    }
+
+    #[actix_web::test]
+    async fn a_broken_database_is_live_but_not_ready() {
+        // Connects fine, but the migrations never ran.
+        assert_eq!(
+            check(pool(":memory:")).await,
+            [(StatusCode::OK, true.into()), (StatusCode::SERVICE_UNAVAILABLE, false.into())],
+        );
+    }
+
+    #[actix_web::test]
+    async fn an_unreachable_database_is_neither() {
+        let url = std::env::temp_dir().join("visitor-badge-no-such-dir/counters.sqlite");
+        assert_eq!(
+            check(pool(url.to_str().unwrap())).await,
+            [(StatusCode::SERVICE_UNAVAILABLE, false.into()), (StatusCode::SERVICE_UNAVAILABLE, false.into())],
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use sha2::{Digest, Sha256};
+
+/// Index bits; 2^12 one-byte registers give about 1.6% standard error in
+/// 4 KiB per counter.
+const PRECISION: u32 = 12;
+const REGISTERS: usize = 1 << PRECISION;
+
+/// HyperLogLog sketch estimating how many distinct fingerprints were added,
+/// in constant space and without keeping any of them.
+#[derive(Debug, Clone)]
+pub struct Sketch {
+    registers: Vec<u8>,
+}
+
+impl Default for Sketch {
+    fn default() -> Self {
+        Sketch { registers: vec![0; REGISTERS] }
+    }
+}
+
+impl Sketch {
+    /// Sketch from stored registers, or an empty one when they do not have
+    /// the expected size.
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        if bytes.len() == REGISTERS {
+            Sketch { registers: bytes }
+        } else {
+            Sketch::default()
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.registers
+    }
+
+    pub fn insert(&mut self, fingerprint: &str) {
+        let digest = Sha256::digest(fingerprint.as_bytes());
+        let hash = u64::from_be_bytes(digest[..8].try_into().expect("digest has 8 bytes"));
+        let index = (hash >> (64 - PRECISION)) as usize;
+        // Position of the first set bit after the index bits, capped for an
+        // all-zero remainder.
+        let rank = ((hash << PRECISION).leading_zeros() + 1).min(64 - PRECISION + 1) as u8;
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+        }
+    }
+
+    /// Estimated number of distinct fingerprints added.
+    pub fn estimate(&self) -> i64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|&rank| 2f64.powi(-i32::from(rank))).sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&rank| rank == 0).count();
+        // Linear counting is more accurate while many registers are empty.
+        let estimate = if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        };
+        estimate.round() as i64
+    }
+}
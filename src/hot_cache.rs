@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::{BadgeView, Visit};
+
+/// Hit/miss counters for an in-memory cache.
+#[derive(Default)]
+pub struct HitStats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HitRate {
+    pub hits: u64,
+    pub misses: u64,
+    pub rate: Option<f64>,
+}
+
+impl HitStats {
+    pub fn record(&self, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HitRate {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let total = hits + misses;
+        HitRate {
+            hits,
+            misses,
+            rate: (total > 0).then(|| hits as f64 / total as f64),
+        }
+    }
+}
+
+/// Badges kept in `HotBadgeCache` at most. Keys include the requested look,
+/// so without a bound clients could grow the cache by varying the query.
+const MAX_ENTRIES: usize = 10_000;
+
+/// Last rendered badge per counter, served stale while a background task
+/// applies the pending increments and re-renders. Each entry keeps the view
+/// it was rendered for, so its pending views can be applied without a hit.
+pub struct HotBadgeCache<V = BadgeView> {
+    max_stale: Option<Duration>,
+    entries: Mutex<HashMap<String, Entry<V>>>,
+    pub stats: HitStats,
+}
+
+/// A rendered badge, the count it shows and its validator.
+#[derive(Debug, Clone)]
+pub struct CachedBadge {
+    pub svg: String,
+    pub view_count: i64,
+    pub etag: String,
+}
+
+struct Entry<V> {
+    view: V,
+    badge: CachedBadge,
+    rendered_at: Instant,
+    pending: i32,
+    /// Visits of the pending views worth recording, applied by the refresh
+    /// so a stalled database holds one connection per key, not per view.
+    visits: Vec<Visit>,
+    refreshing: bool,
+}
+
+impl<V> Entry<V> {
+    /// Whether dropping the entry loses no views.
+    fn is_idle(&self) -> bool {
+        self.pending == 0 && !self.refreshing
+    }
+}
+
+impl<V: Clone> HotBadgeCache<V> {
+    pub fn new(max_stale: Option<Duration>) -> Self {
+        HotBadgeCache {
+            max_stale,
+            entries: Mutex::new(HashMap::new()),
+            stats: HitStats::default(),
+        }
+    }
+
+    /// Read `HOT_CACHE_MAX_STALE_MS`; unset or 0 disables the cache.
+    pub fn from_env() -> Result<Self, String> {
+        let max_stale = match std::env::var("HOT_CACHE_MAX_STALE_MS") {
+            Ok(ms) => ms.parse::<u64>()
+                .map_err(|_| format!("HOT_CACHE_MAX_STALE_MS should be a non-negative number of milliseconds, got {:?}", ms))?,
+            Err(_) => 0,
+        };
+        Ok(HotBadgeCache::new(Some(Duration::from_millis(max_stale)).filter(|max_stale| !max_stale.is_zero())))
+    }
+
+    pub fn max_stale(&self) -> Option<Duration> {
+        self.max_stale
+    }
+
+    /// Record a view against a cached badge. Returns the cached badge when it
+    /// is recent enough, along with whether the caller must start the
+    /// refresh task (only one runs per key at a time).
+    pub fn hit(&self, key: &str, visit: &Visit) -> Option<(CachedBadge, bool)> {
+        let max_stale = self.max_stale?;
+        let mut entries = self.entries.lock().unwrap();
+        let entry = match entries.get_mut(key) {
+            Some(entry) if entry.rendered_at.elapsed() <= max_stale => entry,
+            _ => {
+                self.stats.record(false);
+                return None;
+            },
+        };
+        self.stats.record(true);
+        entry.pending += 1;
+        if !visit.is_empty() {
+            entry.visits.push(visit.clone());
+        }
+        let start_refresh = !entry.refreshing;
+        entry.refreshing = true;
+        Some((entry.badge.clone(), start_refresh))
+    }
+
+    /// Take the increments and visits accumulated since the last refresh.
+    /// Returning 0 increments ends the refresh for this key.
+    pub fn take_pending(&self, key: &str) -> (i32, Vec<Visit>) {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get_mut(key) {
+            Some(entry) => {
+                let pending = std::mem::take(&mut entry.pending);
+                if pending == 0 {
+                    entry.refreshing = false;
+                }
+                (pending, std::mem::take(&mut entry.visits))
+            }
+            None => (0, Vec::new()),
+        }
+    }
+
+    /// Give back increments and visits a failed refresh could not apply, so
+    /// the next hit or `stalled` retries them.
+    pub fn requeue(&self, key: &str, pending: i32, visits: Vec<Visit>) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.get_mut(key) {
+            entry.pending += pending;
+            entry.visits.extend(visits);
+            entry.refreshing = false;
+        }
+    }
+
+    /// Views of the entries holding views that no refresh is applying, such
+    /// as those a failed refresh gave back. They are marked as refreshing, so
+    /// the caller has to start a refresh for each.
+    pub fn stalled(&self) -> Vec<V> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.values_mut()
+            .filter(|entry| entry.pending > 0 && !entry.refreshing)
+            .map(|entry| {
+                entry.refreshing = true;
+                entry.view.clone()
+            })
+            .collect()
+    }
+
+    /// Take the pending increments and visits of every entry, for applying
+    /// them directly on shutdown.
+    pub fn drain(&self) -> Vec<(V, i32, Vec<Visit>)> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.values_mut()
+            .filter(|entry| entry.pending > 0)
+            .map(|entry| {
+                entry.refreshing = false;
+                (entry.view.clone(), std::mem::take(&mut entry.pending), std::mem::take(&mut entry.visits))
+            })
+            .collect()
+    }
+
+    /// Cache a freshly rendered badge. When the cache is full, expired and
+    /// then the oldest idle entries make room; entries with views still to
+    /// apply are never dropped, and the badge is not cached if all are busy.
+    pub fn store(&self, key: &str, view: &V, badge: CachedBadge) {
+        let max_stale = match self.max_stale {
+            Some(max_stale) => max_stale,
+            None => return,
+        };
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains_key(key) && entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, entry| !entry.is_idle() || entry.rendered_at.elapsed() <= max_stale);
+        }
+        if !entries.contains_key(key) && entries.len() >= MAX_ENTRIES {
+            let oldest = entries.iter()
+                .filter(|(_, entry)| entry.is_idle())
+                .min_by_key(|(_, entry)| entry.rendered_at)
+                .map(|(key, _)| key.clone());
+            match oldest {
+                Some(oldest) => {
+                    entries.remove(&oldest);
+                },
+                None => return,
+            }
+        }
+        let entry = entries.entry(key.to_string()).or_insert_with(|| Entry {
+            view: view.clone(),
+            badge: badge.clone(),
+            rendered_at: Instant::now(),
+            pending: 0,
+            visits: Vec::new(),
+            refreshing: false,
+        });
+        entry.badge = badge;
+        entry.rendered_at = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn badge(view_count: i64) -> CachedBadge {
+        CachedBadge { svg: format!("<svg>{}</svg>", view_count), view_count, etag: format!("\"{}\"", view_count) }
+    }
+
+    fn visit(referrer: Option<&str>) -> Visit {
+        Visit { unique: None, referrer: referrer.map(str::to_string), country: None }
+    }
+
+    fn fill(cache: &HotBadgeCache<()>, count: usize) {
+        for i in 0..count {
+            cache.store(&format!("filler-{}", i), &(), badge(1));
+        }
+    }
+
+    #[test]
+    fn disabled_cache_never_hits() {
+        let cache = HotBadgeCache::new(None);
+        cache.store("octocat", &(), badge(1));
+        assert!(cache.hit("octocat", &visit(None)).is_none());
+    }
+
+    #[test]
+    fn every_hit_is_handed_to_exactly_one_refresh() {
+        let cache = HotBadgeCache::new(Some(Duration::from_secs(60)));
+        assert!(cache.hit("octocat", &visit(None)).is_none());
+        cache.store("octocat", &(), badge(1));
+
+        let (cached, start_refresh) = cache.hit("octocat", &visit(Some("https://example.com/"))).unwrap();
+        assert_eq!(cached.view_count, 1);
+        assert!(start_refresh);
+        let (_, start_refresh) = cache.hit("octocat", &visit(None)).unwrap();
+        assert!(!start_refresh);
+
+        let (pending, visits) = cache.take_pending("octocat");
+        assert_eq!(pending, 2);
+        assert_eq!(visits.len(), 1, "empty visits are not queued");
+        cache.store("octocat", &(), badge(3));
+        assert_eq!(cache.take_pending("octocat").0, 0);
+
+        // The refresh ended, so the next hit starts another.
+        let (cached, start_refresh) = cache.hit("octocat", &visit(None)).unwrap();
+        assert_eq!(cached.view_count, 3);
+        assert!(start_refresh);
+
+        let stats = cache.stats.snapshot();
+        assert_eq!((stats.hits, stats.misses), (3, 1));
+    }
+
+    #[test]
+    fn failed_refresh_keeps_its_views() {
+        let cache = HotBadgeCache::new(Some(Duration::from_secs(60)));
+        cache.store("octocat", &(), badge(1));
+        cache.hit("octocat", &visit(Some("https://example.com/"))).unwrap();
+        let (pending, visits) = cache.take_pending("octocat");
+        cache.requeue("octocat", pending, visits);
+
+        let (_, start_refresh) = cache.hit("octocat", &visit(None)).unwrap();
+        assert!(start_refresh);
+        let (pending, visits) = cache.take_pending("octocat");
+        assert_eq!((pending, visits.len()), (2, 1));
+    }
+
+    #[test]
+    fn stalled_entries_are_handed_out_once_and_drained() {
+        let cache = HotBadgeCache::new(Some(Duration::from_secs(60)));
+        cache.store("octocat", &"octocat", badge(1));
+        cache.store("hubot", &"hubot", badge(1));
+        cache.hit("octocat", &visit(Some("https://example.com/"))).unwrap();
+        cache.hit("hubot", &visit(None)).unwrap();
+        let (pending, visits) = cache.take_pending("octocat");
+        cache.requeue("octocat", pending, visits);
+
+        // hubot's refresh is still running, so only octocat needs one.
+        assert_eq!(cache.stalled(), ["octocat"]);
+        assert!(cache.stalled().is_empty());
+
+        let mut drained: Vec<_> = cache.drain().into_iter()
+            .map(|(view, pending, visits)| (view, pending, visits.len()))
+            .collect();
+        drained.sort();
+        assert_eq!(drained, [("hubot", 1, 0), ("octocat", 1, 1)]);
+        assert!(cache.drain().is_empty());
+    }
+
+    #[test]
+    fn full_cache_evicts_the_oldest_idle_entry() {
+        let cache = HotBadgeCache::new(Some(Duration::from_secs(60)));
+        cache.store("busy", &(), badge(1));
+        std::thread::sleep(Duration::from_millis(2));
+        cache.store("oldest", &(), badge(1));
+        std::thread::sleep(Duration::from_millis(2));
+        fill(&cache, MAX_ENTRIES - 2);
+        cache.hit("busy", &visit(None)).unwrap();
+
+        cache.store("new", &(), badge(1));
+        assert_eq!(cache.entries.lock().unwrap().len(), MAX_ENTRIES);
+        assert!(cache.hit("new", &visit(None)).is_some());
+        assert!(cache.hit("oldest", &visit(None)).is_none());
+        // Its pending view still reaches the refresh.
+        assert_eq!(cache.take_pending("busy").0, 1);
+    }
+
+    #[test]
+    fn full_cache_drops_expired_entries_first() {
+        let cache = HotBadgeCache::new(Some(Duration::from_millis(50)));
+        fill(&cache, MAX_ENTRIES);
+        std::thread::sleep(Duration::from_millis(60));
+        cache.store("new", &(), badge(1));
+        assert_eq!(cache.entries.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn full_cache_of_busy_entries_does_not_cache() {
+        let cache = HotBadgeCache::new(Some(Duration::from_secs(60)));
+        fill(&cache, MAX_ENTRIES);
+        for i in 0..MAX_ENTRIES {
+            cache.hit(&format!("filler-{}", i), &visit(None)).unwrap();
+        }
+        cache.store("new", &(), badge(1));
+        assert!(cache.hit("new", &visit(None)).is_none());
+        assert_eq!(cache.entries.lock().unwrap().len(), MAX_ENTRIES);
+    }
+}
@@ -0,0 +1,278 @@
+use ab_glyph::{Font, FontArc};
+
+/// Localized strings used on badges.
+#[derive(Debug)]
+pub struct Bundle {
+    pub lang: &'static str,
+    pub label: &'static str,
+    pub today_label: &'static str,
+    pub unique_label: &'static str,
+    pub week_label: &'static str,
+    pub month_label: &'static str,
+    pub last_visit_label: &'static str,
+    pub streak_label: &'static str,
+    pub countries_label: &'static str,
+    pub just_now: &'static str,
+    pub minutes_ago: &'static str,
+    pub hours_ago: &'static str,
+    pub days_ago: &'static str,
+    /// Singular forms of `hours_ago` and `days_ago`, for n == 1.
+    pub hour_ago: &'static str,
+    pub day_ago: &'static str,
+    pub never: &'static str,
+    /// A number of days, e.g. a streak length.
+    pub days: &'static str,
+    /// A number of countries.
+    pub countries: &'static str,
+}
+
+impl Bundle {
+    /// Humanize an elapsed duration in seconds, e.g. "3 hours ago".
+    pub fn relative_time(&self, secs: i64) -> String {
+        let (n, template) = match secs {
+            s if s < 60 => return self.just_now.to_string(),
+            s if s < 3600 => (s / 60, self.minutes_ago),
+            s if s < 7200 => (1, self.hour_ago),
+            s if s < 86400 => (s / 3600, self.hours_ago),
+            s if s < 2 * 86400 => (1, self.day_ago),
+            s => (s / 86400, self.days_ago),
+        };
+        template.replace("{n}", &n.to_string())
+    }
+}
+
+pub const ENGLISH: Bundle = Bundle {
+    lang: "en",
+    label: "Profile views",
+    today_label: "Views today",
+    unique_label: "Unique visitors",
+    week_label: "Views this week",
+    month_label: "Views this month",
+    last_visit_label: "Last visit",
+    streak_label: "Visit streak",
+    countries_label: "Visitors from",
+    just_now: "just now",
+    minutes_ago: "{n} min ago",
+    hours_ago: "{n} hours ago",
+    days_ago: "{n} days ago",
+    hour_ago: "{n} hour ago",
+    day_ago: "{n} day ago",
+    never: "never",
+    days: "{n} days",
+    countries: "{n} countries",
+};
+
+pub const BUNDLES: &[Bundle] = &[
+    ENGLISH,
+    Bundle {
+        lang: "de",
+        label: "Profilaufrufe",
+        today_label: "Aufrufe heute",
+        unique_label: "Einzelne Besucher",
+        week_label: "Aufrufe diese Woche",
+        month_label: "Aufrufe diesen Monat",
+        last_visit_label: "Letzter Besuch",
+        streak_label: "Besuchsserie",
+        countries_label: "Besucher aus",
+        just_now: "gerade eben",
+        minutes_ago: "vor {n} Min.",
+        hours_ago: "vor {n} Std.",
+        days_ago: "vor {n} Tagen",
+        hour_ago: "vor {n} Std.",
+        day_ago: "vor {n} Tag",
+        never: "nie",
+        days: "{n} Tage",
+        countries: "{n} Ländern",
+    },
+    Bundle {
+        lang: "fr",
+        label: "Vues du profil",
+        today_label: "Vues aujourd'hui",
+        unique_label: "Visiteurs uniques",
+        week_label: "Vues cette semaine",
+        month_label: "Vues ce mois-ci",
+        last_visit_label: "Dernière visite",
+        streak_label: "Série de visites",
+        countries_label: "Visiteurs de",
+        just_now: "à l'instant",
+        minutes_ago: "il y a {n} min",
+        hours_ago: "il y a {n} h",
+        days_ago: "il y a {n} j",
+        hour_ago: "il y a {n} h",
+        day_ago: "il y a {n} j",
+        never: "jamais",
+        days: "{n} jours",
+        countries: "{n} pays",
+    },
+    Bundle {
+        lang: "es",
+        label: "Visitas al perfil",
+        today_label: "Visitas hoy",
+        unique_label: "Visitantes únicos",
+        week_label: "Visitas esta semana",
+        month_label: "Visitas este mes",
+        last_visit_label: "Última visita",
+        streak_label: "Racha de visitas",
+        countries_label: "Visitantes de",
+        just_now: "ahora mismo",
+        minutes_ago: "hace {n} min",
+        hours_ago: "hace {n} h",
+        days_ago: "hace {n} días",
+        hour_ago: "hace {n} h",
+        day_ago: "hace {n} día",
+        never: "nunca",
+        days: "{n} días",
+        countries: "{n} países",
+    },
+    Bundle {
+        lang: "ja",
+        label: "プロフィール閲覧数",
+        today_label: "今日の閲覧数",
+        unique_label: "ユニーク訪問者数",
+        week_label: "今週の閲覧数",
+        month_label: "今月の閲覧数",
+        last_visit_label: "最終訪問",
+        streak_label: "連続訪問",
+        countries_label: "訪問者の国数",
+        just_now: "たった今",
+        minutes_ago: "{n}分前",
+        hours_ago: "{n}時間前",
+        days_ago: "{n}日前",
+        hour_ago: "{n}時間前",
+        day_ago: "{n}日前",
+        never: "なし",
+        days: "{n}日",
+        countries: "{n}か国",
+    },
+    Bundle {
+        lang: "zh",
+        label: "主页访问量",
+        today_label: "今日访问量",
+        unique_label: "独立访客",
+        week_label: "本周访问量",
+        month_label: "本月访问量",
+        last_visit_label: "最近访问",
+        streak_label: "连续访问",
+        countries_label: "访客来自",
+        just_now: "刚刚",
+        minutes_ago: "{n}分钟前",
+        hours_ago: "{n}小时前",
+        days_ago: "{n}天前",
+        hour_ago: "{n}小时前",
+        day_ago: "{n}天前",
+        never: "从未",
+        days: "{n}天",
+        countries: "{n}个国家",
+    },
+];
+
+/// Pick the bundle for a request. The `?lang=` override wins over the
+/// `Accept-Language` header; languages the font cannot draw are skipped and
+/// English is used when nothing else matches.
+pub fn select(
+    override_lang: Option<&str>,
+    accept_language: Option<&str>,
+    font: &FontArc,
+) -> &'static Bundle {
+    let mut candidates: Vec<String> = Vec::new();
+    if let Some(lang) = override_lang {
+        candidates.push(lang.to_string());
+    }
+    if let Some(header) = accept_language {
+        candidates.extend(parse_accept_language(header));
+    }
+
+    candidates
+        .iter()
+        .filter_map(|tag| lookup(tag))
+        .find(|bundle| {
+            [
+                bundle.label,
+                bundle.today_label,
+                bundle.unique_label,
+                bundle.week_label,
+                bundle.month_label,
+                bundle.last_visit_label,
+                bundle.streak_label,
+                bundle.countries_label,
+            ]
+                .iter()
+                .all(|text| font_covers(font, text))
+        })
+        .unwrap_or(&ENGLISH)
+}
+
+fn lookup(tag: &str) -> Option<&'static Bundle> {
+    let primary = tag.split(['-', '_']).next()?.trim().to_ascii_lowercase();
+    BUNDLES.iter().find(|bundle| bundle.lang == primary)
+}
+
+/// Return the language tags of an `Accept-Language` header ordered by
+/// q-value, dropping wildcards and tags with `q=0`.
+fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut tags: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|part| {
+            let mut pieces = part.split(';');
+            let tag = pieces.next()?.trim();
+            if tag.is_empty() || tag == "*" {
+                return None;
+            }
+            let q = pieces
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .map(|q| q.parse::<f32>().unwrap_or(0.0))
+                .unwrap_or(1.0);
+            (q > 0.0).then(|| (tag.to_string(), q))
+        })
+        .collect();
+    // stable sort keeps header order between equal q-values
+    tags.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    tags.into_iter().map(|(tag, _)| tag).collect()
+}
+
+fn font_covers(font: &FontArc, text: &str) -> bool {
+    text.chars()
+        .filter(|c| !c.is_whitespace())
+        .all(|c| font.glyph_id(c).0 != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn font() -> FontArc {
+        let bytes = std::fs::read("src/fonts/DejaVuSans.ttf").expect("bundled font");
+        FontArc::try_from_vec(bytes).expect("valid font")
+    }
+
+    #[test]
+    fn accept_language_orders_by_q_value() {
+        assert_eq!(parse_accept_language("en;q=0.5, de, fr;q=0.8"), vec!["de", "fr", "en"]);
+        assert_eq!(parse_accept_language("es, *, de;q=0"), vec!["es"]);
+        assert_eq!(select(None, Some("fr-CH, de;q=0.9"), &font()).lang, "fr");
+        assert_eq!(select(None, Some("it, de;q=0.5"), &font()).lang, "de");
+    }
+
+    #[test]
+    fn lang_override_wins_over_header() {
+        assert_eq!(select(Some("es"), Some("de"), &font()).lang, "es");
+        assert_eq!(select(Some("xx"), Some("de"), &font()).lang, "de");
+    }
+
+    #[test]
+    fn languages_the_font_cannot_draw_fall_back_to_english() {
+        // DejaVu Sans has no CJK glyphs.
+        assert_eq!(select(Some("ja"), None, &font()).lang, "en");
+        assert_eq!(select(None, Some("zh-CN"), &font()).lang, "en");
+    }
+
+    #[test]
+    fn relative_time_uses_singular_for_one() {
+        assert_eq!(ENGLISH.relative_time(30), "just now");
+        assert_eq!(ENGLISH.relative_time(3600), "1 hour ago");
+        assert_eq!(ENGLISH.relative_time(7199), "1 hour ago");
+        assert_eq!(ENGLISH.relative_time(7200), "2 hours ago");
+        assert_eq!(ENGLISH.relative_time(86400), "1 day ago");
+        assert_eq!(ENGLISH.relative_time(3 * 86400), "3 days ago");
+    }
+}
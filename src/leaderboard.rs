@@ -0,0 +1,64 @@
+use actix_web::{delete, error, get, put, web, HttpRequest, HttpResponse, Responder, Result};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{actions, claim, DbPool};
+
+/// Largest `?limit=`.
+const MAX_LIMIT: i64 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct LeaderboardRequest {
+    limit: Option<i64>,
+}
+
+/// The most-viewed counters of this instance, 20 by default, with views
+/// summed over their pages and rounded to each counter's display step.
+/// Counters whose owners opted out are left out. Counts are as stored, so views still buffered by write-behind are not
+/// included yet.
+#[utoipa::path(
+    tag = "counters",
+    params(("limit" = Option<i64>, Query, description = "Number of counters, 20 by default, at most 100")),
+    responses((status = 200, description = "Counters by views, most first", body = [LeaderboardEntry])),
+)]
+#[get("/api/leaderboard")]
+async fn get_leaderboard(
+    pool: web::Data<DbPool>,
+    req: web::Query<LeaderboardRequest>,
+) -> Result<impl Responder> {
+    let limit = req.limit.unwrap_or(20).clamp(1, MAX_LIMIT);
+    let entries = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::public_leaderboard(&mut conn, limit)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+/// Leave the counter off the public leaderboard. Requires the counter's
+/// owner key.
+#[put("/api/leaderboard/{user}/opt-out")]
+async fn opt_out(pool: web::Data<DbPool>, path: web::Path<String>, http_req: HttpRequest) -> Result<impl Responder> {
+    set_hidden(pool, path.into_inner(), &http_req, true).await
+}
+
+/// List the counter on the public leaderboard again. Requires the counter's
+/// owner key.
+#[delete("/api/leaderboard/{user}/opt-out")]
+async fn opt_in(pool: web::Data<DbPool>, path: web::Path<String>, http_req: HttpRequest) -> Result<impl Responder> {
+    set_hidden(pool, path.into_inner(), &http_req, false).await
+}
+
+async fn set_hidden(pool: web::Data<DbPool>, user: String, http_req: &HttpRequest, hidden: bool) -> Result<HttpResponse> {
+    if !claim::is_owner(&pool, http_req, &user).await? {
+        return Ok(HttpResponse::Unauthorized().json(json!({ "error": "owner key required" })));
+    }
+    web::block(move || {
+        let mut conn = pool.get()?;
+        actions::set_leaderboard_hidden(&mut conn, &user, hidden)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,385 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::{error, get, web, HttpRequest, HttpResponse, Responder, Result};
+use actix_ws::Message;
+use actix_web::web::Bytes;
+use chrono::NaiveDate;
+use futures_util::stream;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::actions::DbError;
+use crate::daily::Granularity;
+use crate::format::round_count;
+use crate::models::{Aggregate, Countries, DailyCount, History, Referrers, Series, Visitors};
+use crate::store::CounterStore;
+
+/// Updates a slow subscriber may fall behind by before it skips ahead.
+const CHANNEL_CAPACITY: usize = 1024;
+/// Comment line sent on idle streams so proxies keep the connection open.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+/// Most counter ids one WebSocket may follow.
+const MAX_SUBSCRIPTIONS: usize = 100;
+
+/// New count of one counter, as sent to live subscribers.
+#[derive(Debug, Clone, Serialize)]
+pub struct CounterUpdate {
+    pub id: String,
+    pub page: String,
+    pub view_count: i64,
+}
+
+impl From<&Visitors> for CounterUpdate {
+    fn from(visitor: &Visitors) -> Self {
+        CounterUpdate {
+            id: visitor.id.clone(),
+            page: visitor.page.clone(),
+            view_count: visitor.view_count,
+        }
+    }
+}
+
+impl CounterUpdate {
+    /// The update as shown to subscribers, with the count rounded to the
+    /// owner's display step. The channel itself carries exact counts.
+    fn rounded(mut self, display_step: i64) -> Self {
+        self.view_count = round_count(self.view_count, display_step);
+        self
+    }
+}
+
+/// In-process fan-out of counter changes to every open live stream.
+pub struct LiveUpdates {
+    sender: broadcast::Sender<CounterUpdate>,
+}
+
+impl Default for LiveUpdates {
+    fn default() -> Self {
+        LiveUpdates { sender: broadcast::channel(CHANNEL_CAPACITY).0 }
+    }
+}
+
+impl LiveUpdates {
+    pub fn publish(&self, visitor: &Visitors) {
+        // An error only means nobody is listening right now.
+        let _ = self.sender.send(CounterUpdate::from(visitor));
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<CounterUpdate> {
+        self.sender.subscribe()
+    }
+}
+
+/// Store wrapper that publishes every changed counter to `LiveUpdates`.
+/// It wraps the outermost store, so write-behind counts are announced as
+/// soon as they are queued.
+pub struct PublishingStore {
+    inner: Arc<dyn CounterStore>,
+    live: Arc<LiveUpdates>,
+}
+
+impl PublishingStore {
+    pub fn new(inner: Arc<dyn CounterStore>, live: Arc<LiveUpdates>) -> Self {
+        PublishingStore { inner, live }
+    }
+}
+
+impl CounterStore for PublishingStore {
+    fn get(&self, user: &str, page: &str) -> Result<Option<Visitors>, DbError> {
+        self.inner.get(user, page)
+    }
+
+    fn increment_and_get(&self, user: &str, page: &str, delta: i32) -> Result<Option<Visitors>, DbError> {
+        let visitor = self.inner.increment_and_get(user, page, delta)?;
+        if let Some(visitor) = &visitor {
+            self.live.publish(visitor);
+        }
+        Ok(visitor)
+    }
+
+    fn upsert_and_get(&self, user: &str, page: &str, delta: i32) -> Result<Visitors, DbError> {
+        let visitor = self.inner.upsert_and_get(user, page, delta)?;
+        self.live.publish(&visitor);
+        Ok(visitor)
+    }
+
+    fn create(&self, user: &str, page: &str) -> Result<Visitors, DbError> {
+        let visitor = self.inner.create(user, page)?;
+        self.live.publish(&visitor);
+        Ok(visitor)
+    }
+
+    fn set(&self, user: &str, page: &str, view_count: i64) -> Result<Visitors, DbError> {
+        let visitor = self.inner.set(user, page, view_count)?;
+        self.live.publish(&visitor);
+        Ok(visitor)
+    }
+
+    fn delete(&self, user: &str, page: &str) -> Result<bool, DbError> {
+        self.inner.delete(user, page)
+    }
+
+    fn iter(&self) -> Result<Vec<Visitors>, DbError> {
+        self.inner.iter()
+    }
+
+    fn user_total(&self, user: &str) -> Result<Option<i64>, DbError> {
+        self.inner.user_total(user)
+    }
+
+    fn pages(&self, user: &str, after: Option<&str>, limit: usize) -> Result<Vec<Visitors>, DbError> {
+        self.inner.pages(user, after, limit)
+    }
+
+    fn today(&self, user: &str, page: &str) -> Result<Option<DailyCount>, DbError> {
+        self.inner.today(user, page)
+    }
+
+    fn history(&self, user: &str, page: &str, days: u32) -> Result<Option<History>, DbError> {
+        self.inner.history(user, page, days)
+    }
+
+    fn aggregate(
+        &self,
+        user: &str,
+        page: &str,
+        granularity: Granularity,
+        periods: u32,
+    ) -> Result<Option<Aggregate>, DbError> {
+        self.inner.aggregate(user, page, granularity, periods)
+    }
+
+    fn series(&self, user: &str, page: &str, from: NaiveDate, to: NaiveDate) -> Result<Option<Series>, DbError> {
+        self.inner.series(user, page, from, to)
+    }
+
+    fn mark_seen(&self, user: &str, page: &str, fingerprint: &str, now: i64, window_secs: i64) -> Result<bool, DbError> {
+        self.inner.mark_seen(user, page, fingerprint, now, window_secs)
+    }
+
+    fn record_unique(&self, user: &str, page: &str, fingerprint: &str, now: i64, window_secs: i64) -> Result<i64, DbError> {
+        self.inner.record_unique(user, page, fingerprint, now, window_secs)
+    }
+
+    fn record_referrer(&self, user: &str, page: &str, referrer: &str) -> Result<(), DbError> {
+        self.inner.record_referrer(user, page, referrer)
+    }
+
+    fn referrers(&self, user: &str, page: &str, limit: u32) -> Result<Option<Referrers>, DbError> {
+        self.inner.referrers(user, page, limit)
+    }
+
+    fn record_country(&self, user: &str, page: &str, country: &str) -> Result<(), DbError> {
+        self.inner.record_country(user, page, country)
+    }
+
+    fn countries(&self, user: &str, page: &str) -> Result<Option<Countries>, DbError> {
+        self.inner.countries(user, page)
+    }
+
+    fn display_step(&self, user: &str) -> Result<i64, DbError> {
+        self.inner.display_step(user)
+    }
+
+    fn alias_target(&self, user: &str) -> Result<Option<String>, DbError> {
+        self.inner.alias_target(user)
+    }
+
+    fn flush(&self) -> Result<(), DbError> {
+        self.inner.flush()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventsRequest {
+    page: Option<String>,
+}
+
+/// Server-Sent Events stream of one counter: a `count` event with the
+/// current value right away, then one per change. Does not count a view.
+#[get("/api/counters/{id}/events")]
+async fn get_events(
+    store: web::Data<dyn CounterStore>,
+    live: web::Data<LiveUpdates>,
+    path: web::Path<String>,
+    req: web::Query<EventsRequest>,
+) -> Result<impl Responder> {
+    let user = path.into_inner();
+    let page = match crate::page_param(req.page.as_deref()) {
+        Ok(page) => page,
+        Err(response) => return Ok(response),
+    };
+    // Subscribe before reading so no change slips in between.
+    let updates = live.subscribe();
+    let (lookup_user, lookup_page) = (user.clone(), page.clone());
+    let found = web::block(move || {
+        let visitor = store.get(&lookup_user, &lookup_page)?;
+        let step = store.display_step(&lookup_user)?;
+        Ok::<_, DbError>(visitor.map(|visitor| (visitor, step)))
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    let (visitor, step) = match found {
+        Some(found) => found,
+        None => return Ok(HttpResponse::NotFound().json(serde_json::json!({ "error": "counter not found" }))),
+    };
+
+    let first = count_event(&CounterUpdate::from(&visitor).rounded(step));
+    let events = stream::unfold((Some(first), updates), move |(first, mut updates)| {
+        let (user, page) = (user.clone(), page.clone());
+        async move {
+            if let Some(first) = first {
+                return Some((Ok::<_, Infallible>(first), (None, updates)));
+            }
+            loop {
+                match actix_web::rt::time::timeout(KEEP_ALIVE, updates.recv()).await {
+                    Ok(Ok(update)) if update.id == user && update.page == page => {
+                        return Some((Ok(count_event(&update.rounded(step))), (None, updates)));
+                    },
+                    Ok(Ok(_)) | Ok(Err(broadcast::error::RecvError::Lagged(_))) => continue,
+                    Ok(Err(broadcast::error::RecvError::Closed)) => return None,
+                    Err(_) => return Some((Ok(Bytes::from_static(b": keep-alive\n\n")), (None, updates))),
+                }
+            }
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(events))
+}
+
+fn count_event(update: &CounterUpdate) -> Bytes {
+    let data = serde_json::to_string(update).expect("counter update should serialize");
+    Bytes::from(format!("event: count\ndata: {}\n\n", data))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SocketRequest {
+    /// Comma-separated counter ids to follow from the start.
+    ids: Option<String>,
+}
+
+/// Change to the set of counters a WebSocket follows, sent by the client as
+/// `{"subscribe": ["a", "b"]}` or `{"unsubscribe": ["a"]}`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SubscriptionChange {
+    subscribe: Vec<String>,
+    unsubscribe: Vec<String>,
+}
+
+/// Counter ids one WebSocket follows. Every page of a followed id is sent.
+/// Display steps are looked up on the first update of each id.
+#[derive(Debug, Default)]
+struct Subscriptions {
+    ids: HashSet<String>,
+    steps: HashMap<String, i64>,
+}
+
+impl Subscriptions {
+    fn apply(&mut self, change: SubscriptionChange) -> Result<(), String> {
+        for id in change.unsubscribe {
+            self.ids.remove(&id);
+            self.steps.remove(&id);
+        }
+        for id in change.subscribe {
+            if !crate::is_valid_page(&id) {
+                return Err(format!("invalid counter id {:?}", id));
+            }
+            if self.ids.len() >= MAX_SUBSCRIPTIONS && !self.ids.contains(&id) {
+                return Err(format!("at most {} counters per connection", MAX_SUBSCRIPTIONS));
+            }
+            self.ids.insert(id);
+        }
+        Ok(())
+    }
+
+    fn matches(&self, update: &CounterUpdate) -> bool {
+        self.ids.contains(&update.id)
+    }
+
+    async fn display_step(&mut self, store: &web::Data<dyn CounterStore>, id: &str) -> Result<i64, DbError> {
+        if let Some(step) = self.steps.get(id) {
+            return Ok(*step);
+        }
+        let (store, user) = (store.clone(), id.to_string());
+        let step = web::block(move || store.display_step(&user)).await??;
+        self.steps.insert(id.to_string(), step);
+        Ok(step)
+    }
+}
+
+/// WebSocket that pushes a JSON `CounterUpdate` for every change to the
+/// counters it follows, for dashboards watching many counters at once.
+/// Follow counters with `?ids=a,b` and change the set later by sending
+/// `{"subscribe": [...]}` or `{"unsubscribe": [...]}`.
+#[get("/api/live")]
+async fn get_live_socket(
+    store: web::Data<dyn CounterStore>,
+    live: web::Data<LiveUpdates>,
+    req: web::Query<SocketRequest>,
+    http_req: HttpRequest,
+    body: web::Payload,
+) -> Result<HttpResponse> {
+    let mut subscriptions = Subscriptions::default();
+    let initial = SubscriptionChange {
+        subscribe: req.ids.as_deref()
+            .map(|ids| ids.split(',').map(str::trim).filter(|id| !id.is_empty()).map(str::to_string).collect())
+            .unwrap_or_default(),
+        ..SubscriptionChange::default()
+    };
+    if let Err(err) = subscriptions.apply(initial) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": err })));
+    }
+
+    let (response, mut session, mut messages) = actix_ws::handle(&http_req, body)?;
+    let mut updates = live.subscribe();
+    actix_web::rt::spawn(async move {
+        loop {
+            tokio::select! {
+                message = messages.recv() => match message {
+                    Some(Ok(Message::Text(text))) => {
+                        let result = serde_json::from_str::<SubscriptionChange>(&text)
+                            .map_err(|err| err.to_string())
+                            .and_then(|change| subscriptions.apply(change));
+                        if let Err(err) = result {
+                            let reply = serde_json::json!({ "error": err }).to_string();
+                            if session.text(reply).await.is_err() {
+                                break;
+                            }
+                        }
+                    },
+                    Some(Ok(Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            break;
+                        }
+                    },
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {},
+                },
+                update = updates.recv() => match update {
+                    Ok(update) if subscriptions.matches(&update) => {
+                        let Ok(step) = subscriptions.display_step(&store, &update.id).await else {
+                            break;
+                        };
+                        let update = update.rounded(step);
+                        let text = serde_json::to_string(&update).expect("counter update should serialize");
+                        if session.text(text).await.is_err() {
+                            break;
+                        }
+                    },
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {},
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            }
+        }
+        let _ = session.close(None).await;
+    });
+    Ok(response)
+}
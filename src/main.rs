@@ -1,96 +1,2548 @@
+// Handlers short-circuit with `Result<_, HttpResponse>` on purpose; the
+// response is built once per request, so its size does not matter.
+#![allow(clippy::result_large_err)]
+
 use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[macro_use]
 extern crate diesel;
-use actix_web::{error, get, web, middleware, App, HttpResponse, HttpServer, Responder, Result};
+use actix_web::{
+    dev::HttpServiceFactory, error, get, post, put, route, http::{Method, StatusCode}, middleware::{from_fn, Condition}, web,
+    App, HttpRequest, HttpResponse, HttpServer, Responder, Result,
+};
 use serde::Deserialize;
-use diesel::{prelude::*, r2d2};
+use diesel::r2d2;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+#[cfg(not(feature = "postgres"))]
+use diesel::{connection::SimpleConnection, SqliteConnection};
 
 use ab_glyph::FontArc;
+use chrono::NaiveDate;
 extern crate shield_maker;
-use shield_maker::{Renderer, Metadata, Style, FontFamily};
+use shield_maker::Renderer;
+
+use badge::{BadgeConfig, BadgeSpec, BadgeStyle, Metric, OutputFormat};
+use cors::CorsConfig;
+use daily::Granularity;
+use format::{format_count, round_count, CountFormat};
+use hot_cache::{CachedBadge, HotBadgeCache};
+use live::{LiveUpdates, PublishingStore};
+use rate_limit::RateLimiter;
+use render::{OgImageCache, Rasterizer};
+use response::{
+    badge_etag, conditional_svg_response, image_response, not_modified, svg_response, with_etag, CachePolicy,
+    PNG_CONTENT_TYPE,
+};
+use store::{CounterStore, FileStore, SqlStore, Unsupported, WriteBehindStore, DEFAULT_PAGE};
+use telemetry::BadgeRootSpan;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+use tracing_actix_web::TracingLogger;
 
 mod actions;
+mod admin;
+mod aliases;
+mod api_keys;
+mod badge;
+mod batch;
+mod bots;
+mod builder;
+mod claim;
+mod cors;
+mod daily;
+mod dedup;
+mod dynamic;
+mod embed;
+mod format;
+mod geoip;
+mod graphql;
+mod health;
+mod hll;
+mod hot_cache;
+mod i18n;
+mod leaderboard;
+mod live;
+mod milestones;
 mod models;
+mod openapi;
+mod own_visits;
+mod params;
+mod pixel;
+mod rate_limit;
+mod referrers;
+mod render;
+mod response;
 mod schema;
+mod signing;
+mod sparkline;
+mod static_badge;
+mod store;
+mod telemetry;
+
+type DbPool = r2d2::Pool<r2d2::ConnectionManager<actions::DbConnection>>;
+
+#[cfg(not(feature = "postgres"))]
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+#[cfg(feature = "postgres")]
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations_postgres");
 
-type DbPool = r2d2::Pool<r2d2::ConnectionManager<SqliteConnection>>;
+/// `STORAGE_BACKEND` name of the SQL backend compiled in.
+#[cfg(not(feature = "postgres"))]
+const DB_BACKEND: &str = "sqlite";
+#[cfg(feature = "postgres")]
+const DB_BACKEND: &str = "postgres";
 
+/// Query string of the badge routes; see `BadgeParams::validate`.
 #[derive(Debug, Deserialize)]
-pub struct Request {
-   key: String,
+pub struct BadgeParams {
+   /// The deployment's `BADGE_KEY`; optional only with `page_id`.
+   key: Option<String>,
+   /// Counter id in the style of the original visitor-badge service, e.g.
+   /// `owner.repo`, used instead of the badge's own counter. Those URLs,
+   /// `/?page_id=` and `/badge?page_id=`, carry no key; other routes ignore
+   /// it.
+   page_id: Option<String>,
+   page: Option<String>,
+   lang: Option<String>,
+   cache: Option<String>,
+   /// Lifetime for `Cache-Control`, clamped to the configured bounds.
+   cache_seconds: Option<u32>,
+   metric: Option<Metric>,
+   /// Show views of the current day, week or month; shorthand for `metric`.
+   period: Option<Granularity>,
+   style: Option<String>,
+   label: Option<String>,
+   /// Unparseable colors fall back to the defaults instead of failing.
+   color: Option<String>,
+   label_color: Option<String>,
+   count_format: Option<CountFormat>,
+   format: Option<OutputFormat>,
+   scale: Option<u8>,
+   /// Show the current count without counting this view.
+   read_only: Option<bool>,
+   /// Append the change of the last 7 days' views over the 7 before.
+   trend: Option<bool>,
+   /// Owner's skip token; views carrying it are not counted.
+   skip: Option<String>,
+   /// Show `below` instead of view counts under this.
+   min_count: Option<i64>,
+   /// Placeholder for counts under `min_count`, `< <min_count>` by default.
+   below: Option<String>,
+   /// URL signature, required when `BADGE_SIGNING_SECRET` is set.
+   sig: Option<String>,
+}
+
+impl BadgeParams {
+    /// Reject out-of-range values up front with a 400 naming each bad field.
+    fn validate(&self) -> Result<(), HttpResponse> {
+        params::Validator::default()
+            .check_opt(self.key.as_deref(), "key", |key: &str| {
+                if key.len() <= 256 { Ok(()) } else { Err("must be at most 256 characters".to_string()) }
+            })
+            .check_opt(self.page_id.as_deref(), "page_id", params::slug)
+            .check_opt(self.page.as_deref(), "page", params::slug)
+            .check_opt(self.lang.as_deref(), "lang", |lang: &str| {
+                let ok = (1..=35).contains(&lang.len())
+                    && lang.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+                if ok { Ok(()) } else { Err("must be a language tag like en or pt-BR".to_string()) }
+            })
+            .check_opt(self.cache.as_deref(), "cache", |cache: &str| match cache {
+                "on" | "off" => Ok(()),
+                _ => Err("must be on or off".to_string()),
+            })
+            .check(self.metric.is_none() || self.period.is_none(), "period", "cannot be combined with metric")
+            .check_opt(self.style.as_deref(), "style", |style: &str| {
+                style.parse::<BadgeStyle>().map(drop).map_err(|err| err.to_string())
+            })
+            .check_opt(self.label.as_deref(), "label", params::text(badge::MAX_LABEL_CHARS))
+            .check_opt(self.min_count.as_ref(), "min_count", |min_count: &i64| {
+                if *min_count > 0 { Ok(()) } else { Err("must be positive".to_string()) }
+            })
+            .check_opt(self.below.as_deref(), "below", params::text(badge::MAX_LABEL_CHARS))
+            .check_opt(self.scale.as_ref(), "scale", |scale: &u8| {
+                if (1..=4).contains(scale) { Ok(()) } else { Err("must be between 1 and 4".to_string()) }
+            })
+            .check_opt(self.sig.as_deref(), "sig", |sig: &str| {
+                let ok = sig.len() <= 64 && sig.chars().all(|c| c.is_ascii_hexdigit());
+                if ok { Ok(()) } else { Err("must be a hex signature".to_string()) }
+            })
+            .finish()
+    }
+}
+
+fn color_check(color: &str) -> Result<(), String> {
+    if badge::is_valid_color(color) {
+        Ok(())
+    } else {
+        Err("must be a shields.io color name, CSS color or hex code".to_string())
+    }
+}
+
+/// Everything about a counter badge except the number, shared between the
+/// request and the hot cache refresh.
+#[derive(Debug, Clone)]
+struct BadgeView {
+    user: String,
+    page: String,
+    metric: Metric,
+    label: String,
+    style: BadgeStyle,
+    color: Option<String>,
+    label_color: Option<String>,
+    count_format: CountFormat,
+    output: OutputFormat,
+    /// PNG resolution multiplier, 1 to 4.
+    scale: u8,
+    /// Whether counting a view may create the counter.
+    create: bool,
+    /// Language of relative times in `Metric::LastVisit` badges.
+    bundle: &'static i18n::Bundle,
+    /// Whether to append the week-over-week trend.
+    trend: bool,
+    /// Whether the counter recently reached a milestone.
+    celebrate: bool,
+    /// Multiple view counts are rounded to, 1 to show them exactly.
+    display_step: i64,
+    /// View counts below this show `below` instead.
+    min_count: Option<i64>,
+    below: String,
+}
+
+impl BadgeView {
+    fn cache_key(&self) -> String {
+        format!(
+            "{}:{}:{:?}:{:?}:{}:{:?}:{:?}:{:?}:{:?}:{}:{}:{}:{}:{:?}:{}",
+            self.user, self.page, self.metric, self.style, self.label, self.color, self.label_color, self.count_format,
+            self.output, self.scale, self.trend, self.celebrate, self.display_step, self.min_count, self.below,
+        )
+    }
+
+    /// The number as shown publicly: view counts rounded to the counter's
+    /// display step, times and day or country counts as they are.
+    fn shown(&self, view_count: i64) -> i64 {
+        match self.metric {
+            Metric::LastVisit | Metric::Streak | Metric::Countries => view_count,
+            _ => round_count(view_count, self.display_step),
+        }
+    }
+
+    /// Text on the right of the badge, with last-visit times relative to the
+    /// Unix time `now`.
+    fn message(&self, view_count: i64, trend: Option<i64>, now: i64) -> String {
+        let below_minimum = !matches!(self.metric, Metric::LastVisit | Metric::Streak | Metric::Countries)
+            && self.min_count.is_some_and(|min_count| view_count < min_count);
+        let mut message = match self.metric {
+            Metric::LastVisit if view_count <= 0 => self.bundle.never.to_string(),
+            Metric::LastVisit => self.bundle.relative_time(now - view_count),
+            Metric::Streak => self.bundle.days.replace("{n}", &view_count.to_string()),
+            Metric::Countries => self.bundle.countries.replace("{n}", &view_count.to_string()),
+            _ if below_minimum => self.below.clone(),
+            _ => format_count(view_count, self.count_format),
+        };
+        if let Some(percent) = trend.filter(|_| !below_minimum) {
+            let arrow = match percent.signum() {
+                1 => '▲',
+                -1 => '▼',
+                _ => '▶',
+            };
+            message.push_str(&format!(" {}{}%", arrow, percent.abs()));
+        }
+        if self.celebrate {
+            message = format!("{} {}", milestones::celebration_emoji(), message);
+        }
+        message
+    }
+
+    fn render(&self, font: &FontArc, view_count: i64, trend: Option<i64>, now: i64) -> String {
+        let mut spec = BadgeSpec::new(self.label.clone(), self.message(view_count, trend, now));
+        spec.style = Some(self.style.name().to_string());
+        if let Some(color) = &self.color {
+            spec.color = Some(color.clone());
+        }
+        spec.label_color = self.label_color.clone();
+        render_spec(font, &spec)
+    }
+
+    /// Hashes the message rather than only the count, since a last-visit
+    /// badge's relative time moves on without any new view.
+    fn etag(&self, view_count: i64, trend: Option<i64>, now: i64) -> String {
+        badge_etag(&format!("{}:{}", self.cache_key(), self.message(view_count, trend, now)), view_count)
+    }
+}
+
+/// Cap a user-supplied label. shield_maker escapes text as it renders, so
+/// escaping here too would show entities like `&lt;` on the badge.
+fn sanitize_label(label: &str) -> String {
+    label.chars().take(badge::MAX_LABEL_CHARS).collect()
+}
+
+/// Whether `page` can name a counter namespace: 1 to 64 ASCII letters,
+/// digits, `.`, `_` or `-`.
+fn is_valid_page(page: &str) -> bool {
+    (1..=64).contains(&page.len())
+        && page.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+}
+
+/// Page namespace from an optional query parameter, or an error response.
+fn page_param(page: Option<&str>) -> Result<String, HttpResponse> {
+    params::Validator::default().check_opt(page, "page", params::slug).finish()?;
+    Ok(page.unwrap_or(DEFAULT_PAGE).to_string())
+}
+
+/// Keep a user-supplied color only if shield_maker can parse it.
+fn valid_color(color: Option<&str>) -> Option<String> {
+    color.filter(|color| badge::is_valid_color(color)).map(str::to_string)
+}
+
+/// Counter badge described by `config`, mounted at its path.
+fn badge_scope(config: BadgeConfig) -> impl HttpServiceFactory {
+    web::resource(config.path.clone())
+        .app_data(web::Data::new(config))
+        .route(web::get().to(get_scoped_badge))
+        .route(web::head().to(get_scoped_badge))
+}
+
+/// Badge mounted by `badge_scope`. The one at `/` also answers the original
+/// service's `/?page_id=` URLs.
+#[allow(clippy::too_many_arguments)]
+async fn get_scoped_badge(
+    config: web::Data<BadgeConfig>,
+    store: web::Data<dyn CounterStore>,
+    font: web::Data<FontArc>,
+    hot_cache: web::Data<HotBadgeCache>,
+    rate_limiter: web::Data<RateLimiter>,
+    rasterizer: web::Data<Rasterizer>,
+    req: web::Query<BadgeParams>,
+    http_req: HttpRequest,
+) -> HttpResponse {
+    let legacy = config.path == "/";
+    get_badge(config, store, font, hot_cache, rate_limiter, rasterizer, req, http_req, legacy).await
+}
+
+/// Badge for any counter, so one deployment can serve many profiles and
+/// pages. Unknown ids are created on their first view, or get a "not found"
+/// badge when `AUTO_CREATE_COUNTERS` is off.
+#[route("/badge/{id}", method = "GET", method = "HEAD")]
+#[allow(clippy::too_many_arguments)]
+async fn get_counter_badge(
+    store: web::Data<dyn CounterStore>,
+    font: web::Data<FontArc>,
+    hot_cache: web::Data<HotBadgeCache>,
+    rate_limiter: web::Data<RateLimiter>,
+    rasterizer: web::Data<Rasterizer>,
+    path: web::Path<String>,
+    req: web::Query<BadgeParams>,
+    http_req: HttpRequest,
+) -> impl Responder {
+    let config = web::Data::new(BadgeConfig {
+        path: http_req.path().to_string(),
+        user: path.into_inner(),
+        create: badge::auto_create(),
+        ..BadgeConfig::default()
+    });
+    get_badge(config, store, font, hot_cache, rate_limiter, rasterizer, req, http_req, false).await
+}
+
+/// Badge at the original visitor-badge service's URL,
+/// `/badge?page_id=owner.repo`, counting the `page_id` counter.
+#[route("/badge", method = "GET", method = "HEAD")]
+#[allow(clippy::too_many_arguments)]
+async fn get_legacy_badge(
+    store: web::Data<dyn CounterStore>,
+    font: web::Data<FontArc>,
+    hot_cache: web::Data<HotBadgeCache>,
+    rate_limiter: web::Data<RateLimiter>,
+    rasterizer: web::Data<Rasterizer>,
+    req: web::Query<BadgeParams>,
+    http_req: HttpRequest,
+) -> impl Responder {
+    if req.page_id.is_none() {
+        return render_error_badge(&font, StatusCode::BAD_REQUEST, "page_id required");
+    }
+    let config = web::Data::new(BadgeConfig {
+        path: http_req.path().to_string(),
+        create: badge::auto_create(),
+        ..BadgeConfig::default()
+    });
+    get_badge(config, store, font, hot_cache, rate_limiter, rasterizer, req, http_req, true).await
+}
+
+/// Badge for one of several named counters of a user, e.g.
+/// `/badge/octocat/blog`. The name is the counter's page namespace and the
+/// badge's default label.
+#[route("/badge/{id}/{name}", method = "GET", method = "HEAD")]
+#[allow(clippy::too_many_arguments)]
+async fn get_named_counter_badge(
+    store: web::Data<dyn CounterStore>,
+    font: web::Data<FontArc>,
+    hot_cache: web::Data<HotBadgeCache>,
+    rate_limiter: web::Data<RateLimiter>,
+    rasterizer: web::Data<Rasterizer>,
+    path: web::Path<(String, String)>,
+    req: web::Query<BadgeParams>,
+    http_req: HttpRequest,
+) -> impl Responder {
+    let (user, name) = path.into_inner();
+    if !is_valid_page(&name) {
+        return render_error_badge(&font, StatusCode::BAD_REQUEST, "invalid counter name");
+    }
+    let config = web::Data::new(BadgeConfig {
+        path: http_req.path().to_string(),
+        user,
+        label: Some(name.clone()),
+        page: name,
+        create: badge::auto_create(),
+        ..BadgeConfig::default()
+    });
+    get_badge(config, store, font, hot_cache, rate_limiter, rasterizer, req, http_req, false).await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn get_badge(
+    config: web::Data<BadgeConfig>,
+    store: web::Data<dyn CounterStore>,
+    font: web::Data<FontArc>,
+    hot_cache: web::Data<HotBadgeCache>,
+    rate_limiter: web::Data<RateLimiter>,
+    rasterizer: web::Data<Rasterizer>,
+    req: web::Query<BadgeParams>,
+    http_req: HttpRequest,
+    legacy: bool,
+) -> HttpResponse {
+    let error_font = font.clone();
+    match serve_badge(config, store, font, hot_cache, rate_limiter, rasterizer, req, http_req, legacy).await {
+        Ok(response) => response,
+        Err(err) if err.as_response_error().status_code() == StatusCode::BAD_REQUEST => {
+            render_error_badge(&error_font, StatusCode::BAD_REQUEST, "unsupported")
+        },
+        Err(err) => {
+            tracing::warn!(error = %err, "badge request failed");
+            render_error_badge(&error_font, StatusCode::INTERNAL_SERVER_ERROR, "error")
+        },
+    }
 }
 
-#[get("/")]
-async fn get_badge(pool: web::Data<DbPool>, font: web::Data<FontArc>, req: web::Query<Request>) -> Result<impl Responder> {
+#[allow(clippy::too_many_arguments)]
+async fn serve_badge(
+    config: web::Data<BadgeConfig>,
+    store: web::Data<dyn CounterStore>,
+    font: web::Data<FontArc>,
+    hot_cache: web::Data<HotBadgeCache>,
+    rate_limiter: web::Data<RateLimiter>,
+    rasterizer: web::Data<Rasterizer>,
+    req: web::Query<BadgeParams>,
+    http_req: HttpRequest,
+    legacy: bool,
+) -> Result<HttpResponse> {
+    // Only the original service's URLs may name their counter with page_id.
+    let page_id = req.page_id.clone().filter(|_| legacy);
     let badge_key = std::env::var("BADGE_KEY").expect("BADGE_KEY should be set");
-    if &req.key != &badge_key {
-        return Ok(HttpResponse::NotFound().body("error"));
+    let key_ok = match req.key.as_deref() {
+        Some(key) => key == badge_key,
+        None => page_id.is_some(),
+    };
+    if !key_ok {
+        return Ok(render_error_badge(&font, StatusCode::NOT_FOUND, "not found"));
     }
+    let accept_language = http_req.headers()
+        .get("Accept-Language")
+        .and_then(|value| value.to_str().ok());
+    let bundle = i18n::select(req.lang.as_deref(), accept_language, font.get_ref());
+    if let Err(response) = req.validate() {
+        return Ok(response);
+    }
+    let style = match req.style.as_deref() {
+        Some(style) => style.parse::<BadgeStyle>().unwrap_or_default(),
+        None => config.style,
+    };
+    let page = req.page.clone().unwrap_or_else(|| config.page.clone());
+    let user = page_id.unwrap_or_else(|| config.user.clone());
+    if let Some(secret) = signing::secret() {
+        if !signing::verify(&secret, &user, &page, req.sig.as_deref().unwrap_or("")) {
+            return Ok(render_error_badge(&font, StatusCode::FORBIDDEN, "invalid signature"));
+        }
+    }
+    let alias_store = store.clone();
+    let (user, display_step) = web::block(move || {
+        let user = resolve_counter(alias_store.get_ref(), user)?;
+        let display_step = alias_store.display_step(&user)?;
+        Ok::<_, actions::DbError>((user, display_step))
+    })
+    .await?
+    .map_err(store_error)?;
+    let metric = req.metric.or(req.period.map(Metric::from)).unwrap_or_default();
+    let min_count = req.min_count.or(config.min_count);
+    let celebrate = http_req.app_data::<web::Data<milestones::Milestones>>()
+        .is_some_and(|milestones| milestones.celebrating(&user, &page));
+    let view = BadgeView {
+        user,
+        page,
+        metric,
+        label: match (req.label.as_ref().or(config.label.as_ref()), metric) {
+            (Some(label), _) => sanitize_label(label),
+            (None, Metric::Total) => bundle.label.to_string(),
+            (None, Metric::Today) => bundle.today_label.to_string(),
+            (None, Metric::Unique) => bundle.unique_label.to_string(),
+            (None, Metric::Week) => bundle.week_label.to_string(),
+            (None, Metric::Month) => bundle.month_label.to_string(),
+            (None, Metric::LastVisit) => bundle.last_visit_label.to_string(),
+            (None, Metric::Streak) => bundle.streak_label.to_string(),
+            (None, Metric::Countries) => bundle.countries_label.to_string(),
+        },
+        style,
+        color: valid_color(req.color.as_deref()).or_else(|| {
+            // A recently reached milestone outranks the configured color,
+            // but not one asked for in the URL.
+            celebrate.then(milestones::celebration_color).or_else(|| config.color.clone())
+        }),
+        label_color: valid_color(req.label_color.as_deref()),
+        count_format: req.count_format.unwrap_or_else(CountFormat::from_env),
+        output: OutputFormat::negotiate(
+            req.format,
+            http_req.headers().get("Accept").and_then(|value| value.to_str().ok()),
+        ),
+        scale: req.scale.unwrap_or(1),
+        create: config.create,
+        bundle,
+        trend: req.trend.unwrap_or(false),
+        celebrate,
+        display_step,
+        min_count,
+        below: match (req.below.as_deref(), min_count) {
+            (Some(below), _) => sanitize_label(below),
+            (None, Some(min_count)) => sanitize_label(&format!("< {}", min_count)),
+            (None, None) => String::new(),
+        },
+    };
+    let cache_key = view.cache_key();
+    let cache_policy = CachePolicy::for_request(req.cache.as_deref(), req.cache_seconds, hot_cache.max_stale());
+    let span = tracing::Span::current();
+    span.record("user", view.user.as_str());
+    span.record("style", view.style.name());
+
+    // Rate-limited and repeat views still get a badge, just not a count.
+    // HEAD requests from link checkers, crawlers, `?read_only=true` and the
+    // owner's own views never count.
+    let read_only = http_req.method() == Method::HEAD
+        || req.read_only.unwrap_or(false)
+        || bots::is_bot(&http_req)
+        || own_visits::is_own_visit(&http_req, &view.user, req.skip.as_deref()).await?;
+    let mut counted = !read_only && rate_limiter.allow(&http_req);
+    if let Some((window_secs, fingerprint)) = dedup::seen_key(&http_req).filter(|_| counted) {
+        let dedup_store = store.clone();
+        let user = view.user.clone();
+        let page = view.page.clone();
+        let now = chrono::Utc::now().timestamp();
+        counted = web::block(move || dedup_store.mark_seen(&user, &page, &fingerprint, now, window_secs))
+            .await?
+            .map_err(store_error)?;
+    }
+    if !counted {
+        let lookup_view = view.clone();
+        let (view_count, trend) = web::block(move || {
+            let view_count = current_count(store.get_ref(), &lookup_view.user, &lookup_view.page, metric)?
+                .map(|view_count| lookup_view.shown(view_count));
+            Ok::<_, actions::DbError>((view_count, view_trend(store.get_ref(), &lookup_view)?))
+        })
+        .await?
+        .map_err(store_error)?;
+        return Ok(match view_count {
+            Some(view_count) => {
+                span.record("view_count", view_count);
+                let now = chrono::Utc::now().timestamp();
+                let etag = view.etag(view_count, trend, now);
+                badge_response(&http_req, &rasterizer, &view, view_count, &etag, cache_policy, || {
+                    view.render(font.get_ref(), view_count, trend, now)
+                })
+                .await
+            },
+            None => render_error_badge(&font, StatusCode::NOT_FOUND, "not found"),
+        });
+    }
+
+    let visit = Visit::from_request(&http_req);
+    if let Some((badge, start_refresh)) = hot_cache.hit(&cache_key, &visit) {
+        if start_refresh {
+            actix_web::rt::spawn(refresh_hot_badge(
+                store.clone(), font.clone(), hot_cache.clone(), view.clone(),
+            ));
+        }
+        let (view_count, etag) = (badge.view_count, badge.etag.clone());
+        return Ok(badge_response(&http_req, &rasterizer, &view, view_count, &etag, cache_policy, || badge.svg).await);
+    }
+
+    let count_view = view.clone();
+    let (view_count, trend) = web::block(move || {
+        let (user, page) = (&count_view.user, &count_view.page);
+        let view_count =
+            increment_and_count(store.get_ref(), user, page, 1, metric, count_view.create, &[visit])?
+                .map(|view_count| count_view.shown(view_count));
+        Ok::<_, actions::DbError>((view_count, view_trend(store.get_ref(), &count_view)?))
+    })
+    .await?
+    .map_err(store_error)?;
+
+    Ok(match view_count {
+        Some(view_count) => {
+            span.record("view_count", view_count);
+            let now = chrono::Utc::now().timestamp();
+            let etag = view.etag(view_count, trend, now);
+            badge_response(&http_req, &rasterizer, &view, view_count, &etag, cache_policy, || {
+                let badge_output = view.render(font.get_ref(), view_count, trend, now);
+                hot_cache.store(&cache_key, &view, CachedBadge {
+                    svg: badge_output.clone(),
+                    view_count,
+                    etag: etag.clone(),
+                });
+                badge_output
+            })
+            .await
+        },
+        None => render_error_badge(&font, StatusCode::NOT_FOUND, "not found"),
+    })
+}
+
+/// Send the badge in the view's output format, or `304 Not Modified` when
+/// the client already has this `etag`. PNG badges fall back to the SVG if
+/// rasterizing fails, so the image never breaks. JSON and plain text carry
+/// just the count and never render.
+#[allow(clippy::too_many_arguments)]
+async fn badge_response(
+    http_req: &HttpRequest,
+    rasterizer: &web::Data<Rasterizer>,
+    view: &BadgeView,
+    view_count: i64,
+    etag: &str,
+    cache_policy: CachePolicy,
+    render: impl FnOnce() -> String,
+) -> HttpResponse {
+    if view.output == OutputFormat::Svg {
+        return conditional_svg_response(http_req, etag, cache_policy, render);
+    }
+    if let Some(response) = not_modified(http_req, etag, cache_policy) {
+        return response;
+    }
+    let data = match view.output {
+        OutputFormat::Json => {
+            let body = serde_json::json!({ "id": view.user, "page": view.page, "count": view_count });
+            Some(("application/json", body.to_string()))
+        },
+        OutputFormat::Text => Some(("text/plain; charset=utf-8", view_count.to_string())),
+        OutputFormat::Svg | OutputFormat::Png => None,
+    };
+    if let Some((content_type, body)) = data {
+        return with_etag(image_response(StatusCode::OK, content_type, web::Bytes::from(body), cache_policy), etag);
+    }
+    let badge_output = render();
+    let svg = badge_output.clone();
+    let rasterizer = rasterizer.clone();
+    let scale = f32::from(view.scale);
+    match web::block(move || rasterizer.png(&svg, scale)).await {
+        Ok(Ok(png)) => with_etag(
+            image_response(StatusCode::OK, PNG_CONTENT_TYPE, web::Bytes::from(png), cache_policy),
+            etag,
+        ),
+        Ok(Err(err)) => {
+            tracing::warn!("could not rasterize badge, sending SVG instead: {}", err);
+            svg_response(StatusCode::OK, badge_output, cache_policy)
+        },
+        Err(err) => {
+            tracing::warn!("could not rasterize badge, sending SVG instead: {}", err);
+            svg_response(StatusCode::OK, badge_output, cache_policy)
+        },
+    }
+}
+
+/// Social preview card for a counter, sized for `og:image`/Twitter cards.
+#[get("/og/{user}.png")]
+async fn get_og_image(
+    store: web::Data<dyn CounterStore>,
+    font: web::Data<FontArc>,
+    rasterizer: web::Data<Rasterizer>,
+    og_cache: web::Data<OgImageCache>,
+    path: web::Path<String>,
+) -> Result<impl Responder> {
+    let user = path.into_inner();
+    let lookup_user = user.clone();
     let visitor_info = web::block(move || {
-        let mut conn = pool.get()?;
-        let user = "me".to_string();
-        actions::update_user_viewcount(&mut conn, &user)
-            .map_err(|err| println!("{:?}", err)).ok();
-        actions::get_user_viewcount(&mut conn, &user)
+        let display_step = store.display_step(&lookup_user)?;
+        Ok::<_, actions::DbError>(store.get(&lookup_user, DEFAULT_PAGE)?.map(|visitor| visitor.rounded(display_step)))
     })
     .await?
-    .map_err(error::ErrorInternalServerError)?;
+    .map_err(store_error)?;
+    let visitor = match visitor_info {
+        Some(visitor) => visitor,
+        None => return Ok(HttpResponse::NotFound().json(serde_json::json!({ "error": "counter not found" }))),
+    };
+
+    let message = visitor.view_count.to_string();
+    let png = match og_cache.get(&user, &message) {
+        Some(png) => png,
+        None => {
+            let svg = render::og_svg(font.get_ref(), &user, i18n::ENGLISH.label, &message);
+            let png = web::block(move || rasterizer.png(&svg, 1.0))
+                .await?
+                .map_err(error::ErrorInternalServerError)?;
+            let png = web::Bytes::from(png);
+            og_cache.store(&user, &message, png.clone());
+            png
+        },
+    };
+    Ok(HttpResponse::Ok()
+        .insert_header(("Content-Type", "image/png"))
+        .insert_header(("Cache-Control", "public, max-age=3600"))
+        .body(png))
+}
+
+/// Apply the views served from the hot cache to the database and re-render
+/// the cached badge, until no more views are pending.
+async fn refresh_hot_badge(
+    store: web::Data<dyn CounterStore>,
+    font: web::Data<FontArc>,
+    hot_cache: web::Data<HotBadgeCache>,
+    view: BadgeView,
+) {
+    let cache_key = view.cache_key();
+    loop {
+        let (pending, visits) = hot_cache.take_pending(&cache_key);
+        if pending == 0 {
+            break;
+        }
+        let store = store.clone();
+        let count_view = view.clone();
+        let block_visits = visits.clone();
+        let result = web::block(move || {
+            let (user, page) = (&count_view.user, &count_view.page);
+            let (metric, create) = (count_view.metric, count_view.create);
+            let view_count = increment_and_count(store.get_ref(), user, page, pending, metric, create, &block_visits)?
+                .map(|view_count| count_view.shown(view_count));
+            Ok::<_, actions::DbError>((view_count, view_trend(store.get_ref(), &count_view)?))
+        })
+        .await;
+        match result {
+            Ok(Ok((Some(view_count), trend))) => {
+                let now = chrono::Utc::now().timestamp();
+                hot_cache.store(&cache_key, &view, CachedBadge {
+                    svg: view.render(font.get_ref(), view_count, trend, now),
+                    view_count,
+                    etag: view.etag(view_count, trend, now),
+                });
+            },
+            Ok(Ok((None, _))) => break,
+            Ok(Err(err)) => {
+                tracing::warn!("hot cache refresh failed: {:?}", err);
+                hot_cache.requeue(&cache_key, pending, visits);
+                break;
+            },
+            Err(err) => {
+                tracing::warn!("hot cache refresh failed: {:?}", err);
+                hot_cache.requeue(&cache_key, pending, visits);
+                break;
+            },
+        }
+    }
+}
+
+/// Every `HOT_CACHE_MAX_STALE_MS`, start a refresh for hot badges whose
+/// views a failed refresh gave back, so they do not wait for another view.
+fn spawn_hot_cache_retry_task(
+    store: web::Data<dyn CounterStore>,
+    font: web::Data<FontArc>,
+    hot_cache: web::Data<HotBadgeCache>,
+) {
+    let Some(interval) = hot_cache.max_stale() else {
+        return;
+    };
+    actix_web::rt::spawn(async move {
+        let mut ticker = actix_web::rt::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            for view in hot_cache.stalled() {
+                actix_web::rt::spawn(refresh_hot_badge(store.clone(), font.clone(), hot_cache.clone(), view));
+            }
+        }
+    });
+}
+
+/// Apply the views served from the hot cache that no refresh has written
+/// yet, before the store's own flush on shutdown.
+fn drain_hot_cache(store: &dyn CounterStore, hot_cache: &HotBadgeCache) {
+    for (view, pending, visits) in hot_cache.drain() {
+        let result = increment_and_count(store, &view.user, &view.page, pending, Metric::Total, view.create, &visits);
+        if let Err(err) = result {
+            tracing::error!("could not apply {} hot badge views of {} on shutdown: {}", pending, view.user, err);
+        }
+    }
+}
+
+/// Record `delta` views, and `visits` toward unique visitors, referrers and
+/// countries, then return the number the badge should show for `metric`. With `create` the first
+/// view creates the counter; otherwise unknown counters yield `None`.
+fn increment_and_count(
+    store: &dyn CounterStore,
+    user: &str,
+    page: &str,
+    delta: i32,
+    metric: Metric,
+    create: bool,
+    visits: &[Visit],
+) -> Result<Option<i64>, actions::DbError> {
+    // The view about to be counted would always be "just now".
+    let previous_view = match metric {
+        Metric::LastVisit => store.get(user, page)?.and_then(|visitor| visitor.last_viewed_at),
+        _ => None,
+    };
+    let mut visitor = if create {
+        store.upsert_and_get(user, page, delta)?
+    } else {
+        match store.increment_and_get(user, page, delta)? {
+            Some(visitor) => visitor,
+            None => return Ok(None),
+        }
+    };
+    for visit in visits {
+        visitor.unique_count += visit.record(store, user, page)?;
+    }
+    Ok(match metric {
+        Metric::Total => Some(visitor.view_count),
+        Metric::Today => store.today(user, page)?.map(|today| today.view_count),
+        Metric::Unique => Some(visitor.unique_count),
+        Metric::Week => period_count(store, user, page, Granularity::Week)?,
+        Metric::Month => period_count(store, user, page, Granularity::Month)?,
+        Metric::LastVisit => Some(previous_view.unwrap_or(0)),
+        Metric::Streak => streak_count(store, user, page)?,
+        Metric::Countries => country_count(store, user, page)?,
+    })
+}
+
+/// What is kept about a counted view besides the count: its fingerprint
+/// for unique visitors, and where it came from for the referrer and
+/// country breakdowns.
+#[derive(Debug, Clone)]
+struct Visit {
+    unique: Option<dedup::UniqueVisit>,
+    referrer: Option<String>,
+    country: Option<String>,
+}
+
+impl Visit {
+    /// Nothing but the count for visitors who opted out of tracking.
+    fn from_request(http_req: &HttpRequest) -> Self {
+        if !dedup::may_track(http_req) {
+            return Visit { unique: None, referrer: None, country: None };
+        }
+        Visit {
+            unique: dedup::UniqueVisit::from_request(http_req),
+            referrer: referrers::from_request(http_req),
+            country: geoip::country(http_req),
+        }
+    }
 
+    fn is_empty(&self) -> bool {
+        self.unique.is_none() && self.referrer.is_none() && self.country.is_none()
+    }
+
+    /// Record the visit to an existing counter. Returns how much it raised
+    /// the unique visitor count.
+    fn record(&self, store: &dyn CounterStore, user: &str, page: &str) -> Result<i64, actions::DbError> {
+        let added = match &self.unique {
+            Some(unique) => store.record_unique(user, page, &unique.fingerprint, unique.now, unique.window_secs)?,
+            None => 0,
+        };
+        if let Some(referrer) = &self.referrer {
+            store.record_referrer(user, page, referrer)?;
+        }
+        if let Some(country) = &self.country {
+            store.record_country(user, page, country)?;
+        }
+        Ok(added)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CountRequest {
+    /// `text` for the bare number, for shell scripts and widgets.
+    format: Option<OutputFormat>,
+}
+
+/// Views of a user summed over all of their pages as `{"id", "view_count"}`,
+/// or as plain text with `?format=text`. Never counts a view; counting goes
+/// through the badge and `/pixel` routes with their checks.
+#[utoipa::path(
+    tag = "counters",
+    params(
+        ("user" = String, Path, description = "Counter id"),
+        ("format" = Option<String>, Query, description = "`json` (default) or `text`"),
+    ),
+    responses(
+        (status = 200, description = "Views summed over all pages, as JSON or text/plain"),
+        (status = 400, description = "Unsupported format"),
+        (status = 404, description = "Counter not found"),
+    ),
+)]
+#[get("/count/{user}")]
+async fn get_count(
+    store: web::Data<dyn CounterStore>,
+    path: web::Path<String>,
+    req: web::Query<CountRequest>,
+) -> Result<impl Responder> {
+    let format = req.format.unwrap_or(OutputFormat::Json);
+    if !matches!(format, OutputFormat::Json | OutputFormat::Text) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": "format must be json or text" })));
+    }
+    let user = path.into_inner();
+    let lookup_user = user.clone();
+    let total = web::block(move || {
+        let display_step = store.display_step(&lookup_user)?;
+        Ok::<_, actions::DbError>(store.user_total(&lookup_user)?.map(|total| round_count(total, display_step)))
+    })
+    .await?
+    .map_err(store_error)?;
+    Ok(match (total, format) {
+        (Some(view_count), OutputFormat::Text) => HttpResponse::Ok()
+            .content_type("text/plain; charset=utf-8")
+            .insert_header(("Cache-Control", "no-store"))
+            .body(view_count.to_string()),
+        (Some(view_count), _) => HttpResponse::Ok().json(serde_json::json!({ "id": user, "view_count": view_count })),
+        (None, _) => HttpResponse::NotFound().json(serde_json::json!({ "error": "counter not found" })),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EndpointRequest {
+    page: Option<String>,
+    label: Option<String>,
+    color: Option<String>,
+}
+
+/// The counter in the shields.io endpoint schema, for
+/// `img.shields.io/endpoint?url=...`. Does not count a view, since shields.io
+/// fetches and caches it on its own schedule.
+#[utoipa::path(
+    tag = "counters",
+    params(
+        ("user" = String, Path, description = "Counter id"),
+        ("page" = Option<String>, Query, description = "Page namespace"),
+        ("label" = Option<String>, Query, description = "Left-hand label"),
+        ("color" = Option<String>, Query, description = "Right-hand color"),
+    ),
+    responses(
+        (status = 200, description = "shields.io endpoint schema"),
+        (status = 404, description = "Counter not found, as an error endpoint badge"),
+    ),
+)]
+#[get("/endpoint/{user}")]
+async fn get_endpoint(
+    store: web::Data<dyn CounterStore>,
+    path: web::Path<String>,
+    req: web::Query<EndpointRequest>,
+) -> Result<impl Responder> {
+    let user = path.into_inner();
+    let page = match page_param(req.page.as_deref()) {
+        Ok(page) => page,
+        Err(response) => return Ok(response),
+    };
+    let view_count = web::block(move || {
+        let display_step = store.display_step(&user)?;
+        let visitor = store.get(&user, &page)?;
+        Ok::<_, actions::DbError>(visitor.map(|visitor| round_count(visitor.view_count, display_step)))
+    })
+    .await?
+    .map_err(store_error)?;
+    Ok(match view_count {
+        Some(view_count) => HttpResponse::Ok()
+            .insert_header(("Cache-Control", "no-cache"))
+            .json(serde_json::json!({
+                "schemaVersion": 1,
+                "label": req.label.as_deref().unwrap_or(i18n::ENGLISH.label),
+                "message": format_count(view_count, CountFormat::from_env()),
+                "color": valid_color(req.color.as_deref()).as_deref().unwrap_or(badge::DEFAULT_COLOR),
+            })),
+        None => HttpResponse::NotFound().json(serde_json::json!({
+            "schemaVersion": 1,
+            "label": req.label.as_deref().unwrap_or(i18n::ENGLISH.label),
+            "message": "not found",
+            "color": "red",
+            "isError": true,
+        })),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatsRequest {
+    page: Option<String>,
+}
+
+/// Summary of one counter (total, today, last 7 days, last view time) as
+/// JSON, without counting a view.
+#[utoipa::path(
+    tag = "counters",
+    params(
+        ("id" = String, Path, description = "Counter id"),
+        ("page" = Option<String>, Query, description = "Page namespace"),
+    ),
+    responses(
+        (status = 200, description = "Counter summary", body = models::CounterStats),
+        (status = 404, description = "Counter not found"),
+    ),
+)]
+#[get("/api/stats/{id}")]
+async fn get_counter_stats(
+    store: web::Data<dyn CounterStore>,
+    path: web::Path<String>,
+    req: web::Query<StatsRequest>,
+) -> Result<impl Responder> {
+    let user = path.into_inner();
+    let page = match page_param(req.page.as_deref()) {
+        Ok(page) => page,
+        Err(response) => return Ok(response),
+    };
+    let stats = web::block(move || counter_stats(store.get_ref(), &user, &page))
+        .await?
+        .map_err(store_error)?;
+    Ok(match stats {
+        Some(stats) => HttpResponse::Ok().json(stats),
+        None => HttpResponse::NotFound().json(serde_json::json!({ "error": "counter not found" })),
+    })
+}
+
+fn counter_stats(
+    store: &dyn CounterStore,
+    user: &str,
+    page: &str,
+) -> Result<Option<models::CounterStats>, actions::DbError> {
+    let visitor = match store.get(user, page)? {
+        Some(visitor) => visitor,
+        None => return Ok(None),
+    };
+    let display_step = store.display_step(user)?;
+    let week = store.history(user, page, 7)?.map_or_else(Vec::new, |history| history.days);
+    Ok(Some(models::CounterStats {
+        user_id: visitor.id,
+        page: visitor.page,
+        total: round_count(visitor.view_count, display_step),
+        today: round_count(week.last().map_or(0, |day| day.view_count), display_step),
+        this_week: round_count(week.iter().map(|day| day.view_count).fold(0, i64::saturating_add), display_step),
+        last_viewed_at: visitor.last_viewed_at,
+        unique_visitors: round_count(visitor.unique_count, display_step),
+        unique_estimated: dedup::is_sketched(visitor.unique_count),
+    }))
+}
+
+/// Views of a counter as `{ "id", "count" }`, for dashboards and scripts.
+/// Sums over all pages like `/count/{user}`.
+#[utoipa::path(
+    tag = "counters",
+    params(("id" = String, Path, description = "Counter id")),
+    responses(
+        (status = 200, description = "`{ \"id\", \"count\" }` summed over all pages"),
+        (status = 404, description = "Counter not found"),
+    ),
+)]
+#[get("/api/count/{id}")]
+async fn get_api_count(
+    store: web::Data<dyn CounterStore>,
+    path: web::Path<String>,
+) -> Result<impl Responder> {
+    let id = path.into_inner();
+    let lookup_id = id.clone();
+    let total = web::block(move || {
+        let display_step = store.display_step(&lookup_id)?;
+        Ok::<_, actions::DbError>(store.user_total(&lookup_id)?.map(|total| round_count(total, display_step)))
+    })
+    .await?
+    .map_err(store_error)?;
+    Ok(match total {
+        Some(count) => HttpResponse::Ok().json(serde_json::json!({ "id": id, "count": count })),
+        None => HttpResponse::NotFound().json(serde_json::json!({ "error": "counter not found" })),
+    })
+}
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub struct IncrementRequest {
+    /// How much to add, 1 by default.
+    #[serde(default = "default_increment")]
+    by: i32,
+}
+
+fn default_increment() -> i32 {
+    1
+}
+
+/// Add `by` to a counter, creating it if needed, for counting things other
+/// than page views such as downloads or deployments. Needs the `write`
+/// scope.
+#[utoipa::path(
+    tag = "counters",
+    params(
+        ("id" = String, Path, description = "Counter id"),
+        ("page" = Option<String>, Query, description = "Page namespace"),
+    ),
+    request_body = IncrementRequest,
+    responses(
+        (status = 200, description = "The updated counter", body = models::Visitors),
+        (status = 400, description = "`by` is not positive or the page is invalid"),
+        (status = 401, description = "No API key"),
+        (status = 403, description = "The API key lacks the write scope"),
+    ),
+)]
+#[post("/api/counters/{id}/increment")]
+async fn increment_counter(
+    store: web::Data<dyn CounterStore>,
+    path: web::Path<String>,
+    req: web::Query<StatsRequest>,
+    body: Option<web::Json<IncrementRequest>>,
+    http_req: HttpRequest,
+) -> Result<impl Responder> {
+    api_keys::check_scope(&http_req, api_keys::Scope::Write).await?;
+    let user = path.into_inner();
+    let page = match page_param(req.page.as_deref()) {
+        Ok(page) => page,
+        Err(response) => return Ok(response),
+    };
+    let by = body.map_or_else(default_increment, |body| body.by);
+    if by < 1 {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": "by must be positive" })));
+    }
+    let visitor = web::block(move || store.upsert_and_get(&resolve_counter(store.get_ref(), user)?, &page, by))
+        .await?
+        .map_err(store_error)?;
+    Ok(HttpResponse::Ok().json(visitor))
+}
+
+/// View count for one page of a user as JSON, without counting a view.
+#[utoipa::path(
+    tag = "counters",
+    params(
+        ("user" = String, Path, description = "Counter id"),
+        ("page" = String, Path, description = "Page namespace"),
+    ),
+    responses(
+        (status = 200, description = "The counter", body = models::Visitors),
+        (status = 404, description = "Counter not found"),
+    ),
+)]
+#[get("/count/{user}/{page}")]
+async fn get_page_count(
+    store: web::Data<dyn CounterStore>,
+    path: web::Path<(String, String)>,
+) -> Result<impl Responder> {
+    let (user, page) = path.into_inner();
+    if !is_valid_page(&page) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": "invalid page" })));
+    }
+    let visitor_info = web::block(move || {
+        let display_step = store.display_step(&user)?;
+        Ok::<_, actions::DbError>(store.get(&user, &page)?.map(|visitor| visitor.rounded(display_step)))
+    })
+    .await?
+    .map_err(store_error)?;
     Ok(match visitor_info {
-        Some(visitor) => {
-            let count = visitor.view_count.to_string();
-            let count_slice = &count[..];
-
-            let badge_meta = &Metadata {
-                style: Style::FlatSquare,
-                label: "Profile views",
-                message: count_slice,
-                font: font.get_ref().clone(),
-                font_family: FontFamily::Default,
-                label_color: None,
-                color: Some("orange"),
-            };
-            let badge_output = Renderer::render(badge_meta);
-            HttpResponse::Ok()
-                .insert_header(("Content-Type", "image/svg+xml;charset=utf-8"))
-                .insert_header(("Cache-Control", "max-age=120, s-maxage=120"))
-                .body(badge_output)
+        Some(visitor) => HttpResponse::Ok().json(visitor),
+        None => HttpResponse::NotFound().json(serde_json::json!({ "error": "counter not found" })),
+    })
+}
+
+/// The counter views of `user` land on: its alias target, or `user` itself.
+fn resolve_counter(store: &dyn CounterStore, user: String) -> Result<String, actions::DbError> {
+    Ok(store.alias_target(&user)?.unwrap_or(user))
+}
+
+/// The number the badge should show for `metric` without counting a view.
+fn current_count(
+    store: &dyn CounterStore,
+    user: &str,
+    page: &str,
+    metric: Metric,
+) -> Result<Option<i64>, actions::DbError> {
+    Ok(match metric {
+        Metric::Total => store.get(user, page)?.map(|visitor| visitor.view_count),
+        Metric::Today => store.today(user, page)?.map(|today| today.view_count),
+        Metric::Unique => store.get(user, page)?.map(|visitor| visitor.unique_count),
+        Metric::Week => period_count(store, user, page, Granularity::Week)?,
+        Metric::Month => period_count(store, user, page, Granularity::Month)?,
+        Metric::LastVisit => store.get(user, page)?.map(|visitor| visitor.last_viewed_at.unwrap_or(0)),
+        Metric::Streak => streak_count(store, user, page)?,
+        Metric::Countries => country_count(store, user, page)?,
+    })
+}
+
+/// Percent change of the last 7 days' views over the 7 days before, for
+/// `?trend=true` badges. `None` when not asked for or when there is nothing
+/// to compare with.
+fn view_trend(store: &dyn CounterStore, view: &BadgeView) -> Result<Option<i64>, actions::DbError> {
+    if !view.trend {
+        return Ok(None);
+    }
+    let history = store.history(&view.user, &view.page, 14)?;
+    Ok(history.and_then(|history| daily::week_over_week(&history.days)))
+}
+
+/// Current run of days with views, from the daily rollups.
+fn streak_count(store: &dyn CounterStore, user: &str, page: &str) -> Result<Option<i64>, actions::DbError> {
+    let history = store.history(user, page, history_days(Some(u32::MAX)))?;
+    Ok(history.map(|history| daily::current_streak(&history.days)))
+}
+
+/// Distinct countries views came from, from the GeoIP aggregates.
+fn country_count(store: &dyn CounterStore, user: &str, page: &str) -> Result<Option<i64>, actions::DbError> {
+    let countries = store.countries(user, page)?;
+    Ok(countries.map(|countries| countries.countries.len() as i64))
+}
+
+/// Views in the current week or month, from the daily rollups.
+fn period_count(
+    store: &dyn CounterStore,
+    user: &str,
+    page: &str,
+    granularity: Granularity,
+) -> Result<Option<i64>, actions::DbError> {
+    let aggregate = store.aggregate(user, page, granularity, 1)?;
+    Ok(aggregate.map(|aggregate| aggregate.periods.last().map_or(0, |current| current.view_count)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TodayRequest {
+    user: String,
+    page: Option<String>,
+}
+
+/// Today's views for a counter as JSON, without counting a view.
+#[utoipa::path(
+    tag = "daily",
+    params(
+        ("user" = String, Query, description = "Counter id"),
+        ("page" = Option<String>, Query, description = "Page namespace"),
+    ),
+    responses(
+        (status = 200, description = "Views today in the counter's time zone", body = models::DailyCount),
+        (status = 404, description = "Counter not found"),
+    ),
+)]
+#[get("/api/today")]
+async fn get_today(
+    store: web::Data<dyn CounterStore>,
+    req: web::Query<TodayRequest>,
+) -> Result<impl Responder> {
+    let TodayRequest { user, page } = req.into_inner();
+    let page = match page_param(page.as_deref()) {
+        Ok(page) => page,
+        Err(response) => return Ok(response),
+    };
+    let today = web::block(move || {
+        let display_step = store.display_step(&user)?;
+        Ok::<_, actions::DbError>(store.today(&user, &page)?.map(|today| today.rounded(display_step)))
+    })
+    .await?
+    .map_err(store_error)?;
+    Ok(match today {
+        Some(today) => HttpResponse::Ok().json(today),
+        None => HttpResponse::NotFound().json(serde_json::json!({ "error": "counter not found" })),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryRequest {
+    days: Option<u32>,
+    page: Option<String>,
+}
+
+impl HistoryRequest {
+    fn days(&self) -> u32 {
+        history_days(self.days)
+    }
+}
+
+/// Requested number of days, 30 by default and never more than are kept.
+fn history_days(days: Option<u32>) -> u32 {
+    let retained = u32::try_from(daily::retention_days()).unwrap_or(u32::MAX).max(1);
+    days.unwrap_or(30).clamp(1, retained)
+}
+
+/// Views per day over the last `days` days as JSON, without counting a view.
+#[utoipa::path(
+    tag = "daily",
+    params(
+        ("user" = String, Path, description = "Counter id"),
+        ("days" = Option<u32>, Query, description = "Number of days, 30 by default"),
+        ("page" = Option<String>, Query, description = "Page namespace"),
+    ),
+    responses(
+        (status = 200, description = "Views per day, oldest first", body = models::History),
+        (status = 404, description = "Counter not found"),
+    ),
+)]
+#[get("/history/{user}")]
+async fn get_history(
+    store: web::Data<dyn CounterStore>,
+    path: web::Path<String>,
+    req: web::Query<HistoryRequest>,
+) -> Result<impl Responder> {
+    history_response(store, path.into_inner(), &req).await
+}
+
+/// Views per day over the last `days` days, the JSON API spelling of
+/// `/history/{user}`.
+#[utoipa::path(
+    tag = "daily",
+    params(
+        ("id" = String, Path, description = "Counter id"),
+        ("days" = Option<u32>, Query, description = "Number of days, 30 by default"),
+        ("page" = Option<String>, Query, description = "Page namespace"),
+    ),
+    responses(
+        (status = 200, description = "Views per day, oldest first", body = models::History),
+        (status = 404, description = "Counter not found"),
+    ),
+)]
+#[get("/api/stats/{id}/daily")]
+async fn get_daily_stats(
+    store: web::Data<dyn CounterStore>,
+    path: web::Path<String>,
+    req: web::Query<HistoryRequest>,
+) -> Result<impl Responder> {
+    history_response(store, path.into_inner(), &req).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AggregateRequest {
+    granularity: Option<Granularity>,
+    periods: Option<u32>,
+    page: Option<String>,
+}
+
+/// Views summed per day, week or month over the last `periods` buckets,
+/// without counting a view. Weeks start on Monday in the counter's time
+/// zone.
+#[utoipa::path(
+    tag = "daily",
+    params(
+        ("id" = String, Path, description = "Counter id"),
+        ("granularity" = Option<Granularity>, Query, description = "`day` (default), `week` or `month`"),
+        ("periods" = Option<u32>, Query, description = "Number of buckets, 30 days or 12 weeks or months by default"),
+        ("page" = Option<String>, Query, description = "Page namespace"),
+    ),
+    responses(
+        (status = 200, description = "Views per bucket, oldest first", body = models::Aggregate),
+        (status = 404, description = "Counter not found"),
+    ),
+)]
+#[get("/api/stats/{id}/aggregate")]
+async fn get_aggregate_stats(
+    store: web::Data<dyn CounterStore>,
+    path: web::Path<String>,
+    req: web::Query<AggregateRequest>,
+) -> Result<impl Responder> {
+    let user = path.into_inner();
+    let page = match page_param(req.page.as_deref()) {
+        Ok(page) => page,
+        Err(response) => return Ok(response),
+    };
+    let granularity = req.granularity.unwrap_or_default();
+    let default_periods = match granularity {
+        Granularity::Day => 30,
+        Granularity::Week | Granularity::Month => 12,
+    };
+    let periods = req.periods.unwrap_or(default_periods).clamp(1, granularity.max_periods());
+    let aggregate = web::block(move || {
+        let display_step = store.display_step(&user)?;
+        let aggregate = store.aggregate(&user, &page, granularity, periods)?;
+        Ok::<_, actions::DbError>(aggregate.map(|aggregate| aggregate.rounded(display_step)))
+    })
+    .await?
+    .map_err(store_error)?;
+    Ok(match aggregate {
+        Some(aggregate) => HttpResponse::Ok().json(aggregate),
+        None => HttpResponse::NotFound().json(serde_json::json!({ "error": "counter not found" })),
+    })
+}
+
+/// Most days one `/api/stats/{id}/series` request may span.
+const MAX_SERIES_DAYS: i64 = 366;
+
+#[derive(Debug, Deserialize)]
+pub struct SeriesRequest {
+    from: String,
+    to: String,
+    page: Option<String>,
+}
+
+impl SeriesRequest {
+    /// The inclusive day range, or a 400 listing what is wrong with it.
+    fn range(&self) -> Result<(NaiveDate, NaiveDate), HttpResponse> {
+        let parse = |value: &str| NaiveDate::parse_from_str(value, "%Y-%m-%d");
+        let (from, to) = (parse(&self.from), parse(&self.to));
+        let mut validator = params::Validator::default();
+        validator
+            .check(from.is_ok(), "from", "must be a date like 2024-01-31")
+            .check(to.is_ok(), "to", "must be a date like 2024-01-31")
+            .check_opt(self.page.as_deref(), "page", params::slug);
+        if let (Ok(from), Ok(to)) = (from, to) {
+            let days = (to - from).num_days() + 1;
+            validator
+                .check(from <= to, "to", "must not be before from")
+                .check(days <= MAX_SERIES_DAYS, "to", format!("must be at most {} days after from", MAX_SERIES_DAYS - 1));
+        }
+        validator.finish()?;
+        Ok((from.expect("from was validated"), to.expect("to was validated")))
+    }
+}
+
+/// Views per local day from `from` through `to` (inclusive, `YYYY-MM-DD`)
+/// with the Unix time each day starts at, for charting tools. Spans at most
+/// a year; days after today and before the retention window have no views.
+#[utoipa::path(
+    tag = "daily",
+    params(
+        ("id" = String, Path, description = "Counter id"),
+        ("from" = String, Query, description = "First day, `YYYY-MM-DD`"),
+        ("to" = String, Query, description = "Last day, `YYYY-MM-DD`"),
+        ("page" = Option<String>, Query, description = "Page namespace"),
+    ),
+    responses(
+        (status = 200, description = "Views per day, oldest first", body = models::Series),
+        (status = 400, description = "Invalid or too long range"),
+        (status = 404, description = "Counter not found"),
+    ),
+)]
+#[get("/api/stats/{id}/series")]
+async fn get_series(
+    store: web::Data<dyn CounterStore>,
+    path: web::Path<String>,
+    req: web::Query<SeriesRequest>,
+) -> Result<impl Responder> {
+    let user = path.into_inner();
+    let (from, to) = match req.range() {
+        Ok(range) => range,
+        Err(response) => return Ok(response),
+    };
+    let page = req.page.clone().unwrap_or_else(|| DEFAULT_PAGE.to_string());
+    let series = web::block(move || {
+        let display_step = store.display_step(&user)?;
+        Ok::<_, actions::DbError>(store.series(&user, &page, from, to)?.map(|series| series.rounded(display_step)))
+    })
+    .await?
+    .map_err(store_error)?;
+    Ok(match series {
+        Some(series) => HttpResponse::Ok().json(series),
+        None => HttpResponse::NotFound().json(serde_json::json!({ "error": "counter not found" })),
+    })
+}
+
+async fn history_response(
+    store: web::Data<dyn CounterStore>,
+    user: String,
+    req: &HistoryRequest,
+) -> Result<HttpResponse> {
+    let days = req.days();
+    let page = match page_param(req.page.as_deref()) {
+        Ok(page) => page,
+        Err(response) => return Ok(response),
+    };
+    let history = web::block(move || {
+        let display_step = store.display_step(&user)?;
+        Ok::<_, actions::DbError>(store.history(&user, &page, days)?.map(|history| history.rounded(display_step)))
+    })
+    .await?
+    .map_err(store_error)?;
+    Ok(match history {
+        Some(history) => HttpResponse::Ok().json(history),
+        None => HttpResponse::NotFound().json(serde_json::json!({ "error": "counter not found" })),
+    })
+}
+
+/// Inline SVG trend line of the daily views, to embed next to the badge.
+#[get("/sparkline/{user}")]
+async fn get_sparkline(
+    store: web::Data<dyn CounterStore>,
+    font: web::Data<FontArc>,
+    path: web::Path<String>,
+    req: web::Query<HistoryRequest>,
+) -> Result<impl Responder> {
+    let user = path.into_inner();
+    let days = req.days();
+    let page = match page_param(req.page.as_deref()) {
+        Ok(page) => page,
+        Err(response) => return Ok(response),
+    };
+    let history = web::block(move || store.history(&user, &page, days))
+        .await?
+        .map_err(store_error)?;
+    Ok(match history {
+        Some(history) => {
+            let counts: Vec<i64> = history.days.iter().map(|day| day.view_count).collect();
+            let svg = sparkline::sparkline_svg(&counts, sparkline::WIDTH, sparkline::HEIGHT);
+            svg_response(StatusCode::OK, svg, CachePolicy::for_request(None, None, None))
         },
-        None => HttpResponse::NotFound().body("query error"),
+        None => render_error_badge(&font, StatusCode::NOT_FOUND, "not found"),
     })
 }
 
+#[derive(Debug, Deserialize)]
+pub struct TimezoneRequest {
+    timezone: String,
+}
+
+/// Set the IANA time zone a counter's daily views roll over in. Requires the
+/// counter's owner key.
+#[put("/api/today/{user}/timezone")]
+async fn set_timezone(
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+    body: web::Json<TimezoneRequest>,
+    http_req: HttpRequest,
+) -> Result<impl Responder> {
+    let user = path.into_inner();
+    if !claim::is_owner(&pool, &http_req, &user).await? {
+        return Ok(HttpResponse::Unauthorized().json(serde_json::json!({ "error": "owner key required" })));
+    }
+    let timezone = body.into_inner().timezone;
+    if let Err(err) = daily::parse_timezone(&timezone) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": err })));
+    }
+    web::block(move || {
+        let mut conn = pool.get()?;
+        actions::set_user_timezone(&mut conn, &user, &timezone)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DisplayStepRequest {
+    step: i64,
+}
+
+/// Round the counter's public counts, on badges, the count, stats and daily
+/// endpoints, GraphQL, the leaderboard and live updates, to the nearest
+/// multiple of 10, 100 or 1000, or show them exactly again with 1. The exact
+/// count is still kept. Requires the counter's owner key.
+#[put("/api/display-step/{user}")]
+async fn set_display_step(
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+    body: web::Json<DisplayStepRequest>,
+    http_req: HttpRequest,
+) -> Result<impl Responder> {
+    let user = path.into_inner();
+    if !claim::is_owner(&pool, &http_req, &user).await? {
+        return Ok(HttpResponse::Unauthorized().json(serde_json::json!({ "error": "owner key required" })));
+    }
+    let step = body.into_inner().step;
+    if !format::DISPLAY_STEPS.contains(&step) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": "step must be 1, 10, 100 or 1000" })));
+    }
+    web::block(move || {
+        let mut conn = pool.get()?;
+        actions::set_display_step(&mut conn, &user, step)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Every route except health checks and the configured badges. Mounted under
+/// `/v1` so breaking changes can ship as `/v2`, and at the root for the
+/// badge URLs already embedded in READMEs.
+fn routes(cfg: &mut web::ServiceConfig, has_pool: bool) {
+    cfg.service(get_legacy_badge)
+        .service(get_counter_badge)
+        .service(get_named_counter_badge)
+        .service(get_og_image)
+        .service(batch::get_badges)
+        .service(static_badge::get_static_badge)
+        .service(dynamic::get_dynamic_badge)
+        .service(get_count)
+        .service(get_page_count)
+        .service(get_api_count)
+        .service(get_counter_stats)
+        .service(get_daily_stats)
+        .service(get_aggregate_stats)
+        .service(get_series)
+        .service(referrers::get_referrers)
+        .service(geoip::get_countries)
+        .service(increment_counter)
+        .service(live::get_events)
+        .service(live::get_live_socket)
+        .service(graphql::graphql)
+        .service(get_endpoint)
+        .service(get_today)
+        .service(get_history)
+        .service(get_sparkline)
+        .service(embed::get_embed)
+        .service(pixel::get_pixel)
+        .service(builder::get_builder)
+        .service(builder::get_preview)
+        .service(admin::set_count)
+        .service(admin::delete_count)
+        .service(admin::create_counter)
+        .service(admin::list_counters)
+        .service(admin::get_counter)
+        .service(admin::reset_counter)
+        .service(admin::delete_counter);
+    // routes that need SQL beyond the counter store
+    if has_pool {
+        cfg.service(admin::get_stats)
+            .service(admin::get_stats_badge)
+            .service(api_keys::create_key)
+            .service(api_keys::list_keys)
+            .service(api_keys::delete_key)
+            .service(aliases::create_alias)
+            .service(aliases::list_aliases)
+            .service(aliases::delete_alias)
+            .service(claim::create_claim)
+            .service(claim::verify_claim)
+            .service(leaderboard::get_leaderboard)
+            .service(leaderboard::opt_out)
+            .service(leaderboard::opt_in)
+            .service(milestones::put_hook)
+            .service(milestones::get_hook)
+            .service(milestones::delete_hook)
+            .service(own_visits::create_skip_token)
+            .service(own_visits::delete_skip_token)
+            .service(own_visits::set_skip_cookie)
+            .service(set_display_step)
+            .service(set_timezone);
+    }
+}
+
+/// Response error for a failed store call: 400 for features the storage
+/// backend does not support, 500 for everything else.
+fn store_error(err: actions::DbError) -> error::Error {
+    if err.is::<Unsupported>() {
+        error::ErrorBadRequest(err)
+    } else {
+        error::ErrorInternalServerError(err)
+    }
+}
+
+/// Red "Profile views | <message>" badge for failures, so READMEs never show
+/// a broken image. `message` may echo request input; shield_maker escapes
+/// it. Never cached.
+fn render_error_badge(font: &FontArc, status: StatusCode, message: &str) -> HttpResponse {
+    let mut spec = BadgeSpec::new(i18n::ENGLISH.label, message);
+    spec.color = Some("red".to_string());
+    svg_response(status, render_spec(font, &spec), CachePolicy::NoStore)
+}
+
+fn render_spec(font: &FontArc, spec: &BadgeSpec) -> String {
+    let mut spec = spec.clone();
+    spec.color = spec.color.as_deref().map(badge::css_color);
+    spec.label_color = spec.label_color.as_deref().map(badge::css_color);
+    let badge_meta = spec.to_metadata(font.clone())
+        .expect("badge spec should be validated before rendering");
+    Renderer::render(&badge_meta)
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
 
     dotenv::dotenv().ok();
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+    telemetry::init();
+
+    // Badge handlers read it per request; refuse to start without it.
+    exit_on_error(std::env::var("BADGE_KEY").map(drop).map_err(|_| "BADGE_KEY should be set".to_string()));
+    let bind_addrs = exit_on_error(bind_addresses());
+    exit_on_error(dedup::init());
+    exit_on_error(response::init());
+    exit_on_error(milestones::init());
+    exit_on_error(daily::init());
+    exit_on_error(referrers::init());
+    let font_path = std::env::var("FONT_PATH").unwrap_or_else(|_| "src/fonts/DejaVuSans.ttf".to_string());
+    let (font_bytes, font) = exit_on_error(load_font(&font_path));
+    let rasterizer = web::Data::new(Rasterizer::new(font_bytes));
+    let (store, pool) = initialize_store();
+    let live = web::Data::new(LiveUpdates::default());
+    let graphql_schema = web::Data::new(graphql::schema());
+    let store: Arc<dyn CounterStore> = Arc::new(PublishingStore::new(store, live.clone().into_inner()));
+    let milestones = pool.as_ref().map(|pool| {
+        let milestones = web::Data::new(exit_on_error(
+            milestones::Milestones::load(pool).map_err(|err| format!("could not load milestone webhooks: {}", err)),
+        ));
+        milestones::spawn_notifier(milestones.clone().into_inner(), pool.clone(), &live);
+        milestones
+    });
+    let og_cache = web::Data::new(OgImageCache::default());
+    let stats_cache = web::Data::new(exit_on_error(admin::StatsCache::from_env()));
+    let started_at = web::Data::new(admin::StartedAt(Instant::now()));
+
+    let badge_configs = exit_on_error(BadgeConfig::list_from_env());
+    let cors = exit_on_error(CorsConfig::from_env());
+    let hot_cache = web::Data::new(exit_on_error(HotBadgeCache::from_env()));
+    let rate_limiter = web::Data::new(exit_on_error(RateLimiter::from_env()));
+    let json_cache = web::Data::new(exit_on_error(dynamic::JsonCache::from_env()));
+    let dynamic_hosts = web::Data::new(dynamic::AllowedHosts::from_env());
+    if dynamic_hosts.is_empty() {
+        tracing::warn!("DYNAMIC_BADGE_HOSTS is not set, so /dynamic/json refuses every URL");
+    }
+    let claim_config = web::Data::new(exit_on_error(claim::ClaimConfig::from_env()));
+    let geoip = exit_on_error(geoip::GeoIp::from_env()).map(web::Data::new);
 
-    let pool = initialize_db_pool();
-    let font_bytes = fs::read("src/fonts/DejaVuSans.ttf")
-        .expect("could not read DejaVuSans.ttf");
-    let font = FontArc::try_from_vec(font_bytes)
-        .expect("could not parse DejaVuSans.ttf");
+    let base_path = base_path();
+    let mut api_doc = openapi::ApiDoc::openapi();
+    if !base_path.is_empty() {
+        api_doc.servers = Some(vec![utoipa::openapi::Server::new(base_path.clone())]);
+    }
 
-    log::info!("starting Actix HTTP server at http://localhost:8080");
+    spawn_hot_cache_retry_task(web::Data::from(store.clone()), web::Data::new(font.clone()), hot_cache.clone());
 
-    HttpServer::new(move || {
+    let app_store = store.clone();
+    let app_hot_cache = hot_cache.clone();
+    let mut server = HttpServer::new(move || {
+        let has_pool = pool.is_some();
+        let scope = badge_configs.iter()
+            .fold(
+                web::scope(&base_path).service(health::healthz).service(health::readyz),
+                |scope, config| scope.service(badge_scope(config.clone())),
+            )
+            .service(web::scope("/v1").configure(|cfg| routes(cfg, has_pool)))
+            .configure(|cfg| routes(cfg, has_pool));
         App::new()
-            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::from(app_store.clone()))
             .app_data(web::Data::new(font.clone()))
-            .wrap(middleware::Logger::default())
-            .service(get_badge)
+            .app_data(app_hot_cache.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(rasterizer.clone())
+            .app_data(og_cache.clone())
+            .app_data(live.clone())
+            .app_data(graphql_schema.clone())
+            .app_data(json_cache.clone())
+            .app_data(dynamic_hosts.clone())
+            .app_data(claim_config.clone())
+            .app_data(started_at.clone())
+            .app_data(web::QueryConfig::default().error_handler(params::query_error))
+            .configure(|cfg| {
+                if let Some(pool) = &pool {
+                    cfg.app_data(web::Data::new(pool.clone()))
+                        .app_data(stats_cache.clone());
+                }
+                if let Some(milestones) = &milestones {
+                    cfg.app_data(milestones.clone());
+                }
+                if let Some(geoip) = &geoip {
+                    cfg.app_data(geoip.clone());
+                }
+            })
+            .wrap(from_fn(api_keys::guard))
+            .wrap(Condition::new(cors.enabled(), cors.build()))
+            .wrap(TracingLogger::<BadgeRootSpan>::new())
+            .service(
+                SwaggerUi::new(format!("{}/docs/{{_:.*}}", base_path))
+                    .url(format!("{}/openapi.json", base_path), api_doc.clone()),
+            )
+            .service(scope)
+    })
+    // On SIGTERM/SIGINT stop accepting connections and give in-flight
+    // requests, including their blocking DB work, this long to finish.
+    .shutdown_timeout(env_or("SHUTDOWN_TIMEOUT_SECS", 30));
+    for addr in &bind_addrs {
+        tracing::info!("starting Actix HTTP server at http://{}", addr);
+        server = server.bind(addr)?;
+    }
+    server.run().await?;
+
+    drain_hot_cache(store.as_ref(), &hot_cache);
+    if let Err(err) = store.flush() {
+        tracing::error!("could not flush counters on shutdown: {}", err);
+    }
+    Ok(())
+}
+
+/// Prefix every route is mounted under, from `BASE_PATH` (e.g.
+/// `/visitor-badge`), so the service can sit behind a reverse proxy on a
+/// sub-path. Empty, the default, serves from the root.
+fn base_path() -> String {
+    let path = std::env::var("BASE_PATH").unwrap_or_default();
+    let path = path.trim_matches('/');
+    if path.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", path)
+    }
+}
+
+/// Log a startup configuration error and exit instead of panicking.
+fn exit_on_error<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|err| {
+        tracing::error!("{}", err);
+        std::process::exit(1);
     })
-    .bind(("0.0.0.0", 8080))?
-    .run()
-    .await
+}
+
+/// Addresses from `BIND_ADDR` (comma-separated, default `0.0.0.0`). Entries
+/// without a port use `PORT` (default 8080).
+fn bind_addresses() -> Result<Vec<SocketAddr>, String> {
+    let port = match std::env::var("PORT") {
+        Ok(port) => port.parse::<u16>()
+            .map_err(|_| format!("PORT should be a port number, got {:?}", port))?,
+        Err(_) => 8080,
+    };
+    let addrs = std::env::var("BIND_ADDR").unwrap_or_else(|_| "0.0.0.0".to_string());
+    addrs.split(',')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .map(|addr| {
+            addr.parse::<SocketAddr>()
+                .or_else(|_| addr.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, port)))
+                .map_err(|_| format!("BIND_ADDR entry {:?} is not an IP address or IP:port", addr))
+        })
+        .collect()
+}
+
+/// Read and parse the badge font, returning the raw bytes for the
+/// rasterizer alongside the parsed font.
+fn load_font(path: &str) -> Result<(Vec<u8>, FontArc), String> {
+    let font_bytes = fs::read(path)
+        .map_err(|err| format!("could not read font file {} (FONT_PATH): {}", path, err))?;
+    let font = FontArc::try_from_vec(font_bytes.clone())
+        .map_err(|_| format!("font file {} (FONT_PATH) is not a valid TrueType/OpenType font", path))?;
+    Ok((font_bytes, font))
+}
+
+/// Build the counter store selected by `STORAGE_BACKEND` (`sqlite`, or
+/// `postgres` when built with that feature, by default; `file` for a flat
+/// JSON file at `FILE_STORE_PATH`). The database pool is returned too, for
+/// features that only the SQL backend supports.
+fn initialize_store() -> (Arc<dyn CounterStore>, Option<DbPool>) {
+    let backend = std::env::var("STORAGE_BACKEND").unwrap_or_else(|_| DB_BACKEND.to_string());
+    match backend.as_str() {
+        DB_BACKEND => {
+            let pool = initialize_db_pool();
+            exit_on_error(run_migrations(&pool));
+            spawn_prune_task(pool.clone());
+            let store: Arc<dyn CounterStore> = Arc::new(SqlStore::new(pool.clone()));
+            match exit_on_error(WriteBehindStore::from_env(store.clone())) {
+                Some(write_behind) => {
+                    spawn_flush_task(write_behind.clone(), write_behind.flush_interval());
+                    (write_behind, Some(pool))
+                },
+                None => (store, Some(pool)),
+            }
+        },
+        "file" => {
+            let path = std::env::var("FILE_STORE_PATH").unwrap_or_else(|_| "counters.json".to_string());
+            let store = Arc::new(exit_on_error(FileStore::from_env(path)));
+            spawn_flush_task(store.clone(), store.flush_interval());
+            (store, None)
+        },
+        other => exit_on_error(Err(format!("unsupported STORAGE_BACKEND {:?}, expected {} or file", other, DB_BACKEND))),
+    }
+}
+
+/// Hourly removal of daily rows older than `DAILY_RETENTION_DAYS` and of
+/// dedup fingerprints outside the dedup window.
+fn spawn_prune_task(pool: DbPool) {
+    actix_web::rt::spawn(async move {
+        let mut ticker = actix_web::rt::time::interval(Duration::from_secs(60 * 60));
+        loop {
+            ticker.tick().await;
+            let pool = pool.clone();
+            let now = chrono::Utc::now();
+            let cutoff = daily::retention_cutoff(now).to_string();
+            let seen_cutoff = now.timestamp() - dedup::retention_secs();
+            let unique_cutoff = now.timestamp() - dedup::unique_window_secs();
+            let result = web::block(move || {
+                let mut conn = pool.get()?;
+                actions::prune_daily_views(&mut conn, &cutoff)?;
+                actions::prune_recent_views(&mut conn, seen_cutoff)?;
+                actions::prune_unique_views(&mut conn, unique_cutoff)
+            })
+            .await;
+            if let Ok(Err(err)) = result {
+                tracing::warn!("could not prune old rows: {}", err);
+            }
+        }
+    });
+}
+
+/// Periodically persist batched writes so an idle store still reaches disk.
+fn spawn_flush_task(store: Arc<dyn CounterStore>, interval: Duration) {
+    actix_web::rt::spawn(async move {
+        let mut ticker = actix_web::rt::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let store = store.clone();
+            if let Ok(Err(err)) = web::block(move || store.flush()).await {
+                tracing::warn!("could not flush counters: {}", err);
+            }
+        }
+    });
 }
 
 fn initialize_db_pool() -> DbPool {
-    let conn_spec = std::env::var("DATABASE_URL").expect("DATABASE_URL should be set");
-    let manager = r2d2::ConnectionManager::<SqliteConnection>::new(conn_spec);
-    r2d2::Pool::builder()
-        .build(manager)
-        .expect("database URL should be valid path to SQLite DB file")
+    let conn_spec = exit_on_error(std::env::var("DATABASE_URL").map_err(|_| "DATABASE_URL should be set".to_string()));
+    let manager = r2d2::ConnectionManager::<actions::DbConnection>::new(conn_spec);
+    let builder = r2d2::Pool::builder()
+        .max_size(env_or("DB_POOL_MAX_SIZE", 8))
+        .min_idle(Some(env_or("DB_POOL_MIN_IDLE", 1)))
+        .connection_timeout(Duration::from_secs(env_or("DB_POOL_TIMEOUT_SECS", 5)));
+    #[cfg(not(feature = "postgres"))]
+    let builder = builder.connection_customizer(Box::new(ConnectionOptions {
+        busy_timeout: Duration::from_millis(env_or("DB_BUSY_TIMEOUT_MS", 5000)),
+    }));
+    exit_on_error(builder.build(manager).map_err(|err| format!("could not connect to DATABASE_URL: {}", err)))
+}
+
+/// Apply the embedded migrations that have not run yet, unless
+/// `AUTO_MIGRATE` is `false`.
+fn run_migrations(pool: &DbPool) -> Result<(), String> {
+    let enabled = std::env::var("AUTO_MIGRATE")
+        .map(|value| value != "false" && value != "0")
+        .unwrap_or(true);
+    if !enabled {
+        return Ok(());
+    }
+    let mut conn = pool.get()
+        .map_err(|err| format!("could not connect to the database to run migrations: {}", err))?;
+    let applied = conn.run_pending_migrations(MIGRATIONS)
+        .map_err(|err| format!("database migration failed: {}", err))?;
+    if applied.is_empty() {
+        tracing::info!("database schema is up to date");
+    }
+    for version in applied {
+        tracing::info!("applied migration {}", version);
+    }
+    Ok(())
+}
+
+/// Parse an environment variable, falling back to `default` when unset and
+/// exiting when it does not parse.
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(value) => exit_on_error(value.parse().map_err(|_| format!("{} should be a valid number, got {:?}", name, value))),
+        Err(_) => default,
+    }
+}
+
+/// Per-connection SQLite settings so concurrent writers wait for the lock
+/// instead of failing with "database is locked".
+#[cfg(not(feature = "postgres"))]
+#[derive(Debug)]
+struct ConnectionOptions {
+    busy_timeout: Duration,
+}
+
+#[cfg(not(feature = "postgres"))]
+impl r2d2::CustomizeConnection<SqliteConnection, r2d2::Error> for ConnectionOptions {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), r2d2::Error> {
+        conn.batch_execute(&format!(
+            "PRAGMA busy_timeout = {}; PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;",
+            self.busy_timeout.as_millis()
+        ))
+        .map_err(r2d2::Error::QueryError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use actix_web::body::MessageBody;
+    use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+    use actix_web::test::{call_service, init_service, read_body, read_body_json, TestRequest};
+    use resvg::usvg::{self, TreeParsing};
+
+    use super::*;
+    use crate::models::Visitors;
+
+    const TEST_BADGE_KEY: &str = "test-badge-key";
+
+    fn font() -> FontArc {
+        FontArc::try_from_vec(std::fs::read("src/fonts/DejaVuSans.ttf").unwrap()).unwrap()
+    }
+
+    /// A counter file in the temp dir, removed on drop.
+    struct TempStore(std::path::PathBuf);
+
+    impl TempStore {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("visitor-badge-main-{}-{}.json", std::process::id(), name));
+            let _ = fs::remove_file(&path);
+            TempStore(path)
+        }
+
+        fn open(&self) -> Arc<dyn CounterStore> {
+            Arc::new(FileStore::open(&self.0).unwrap())
+        }
+    }
+
+    impl Drop for TempStore {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// Every call fails, like a database that went away.
+    struct FailingStore;
+
+    impl CounterStore for FailingStore {
+        fn get(&self, _user: &str, _page: &str) -> Result<Option<Visitors>, actions::DbError> {
+            Err("database is down".into())
+        }
+
+        fn increment_and_get(&self, _user: &str, _page: &str, _delta: i32) -> Result<Option<Visitors>, actions::DbError> {
+            Err("database is down".into())
+        }
+
+        fn upsert_and_get(&self, _user: &str, _page: &str, _delta: i32) -> Result<Visitors, actions::DbError> {
+            Err("database is down".into())
+        }
+
+        fn create(&self, _user: &str, _page: &str) -> Result<Visitors, actions::DbError> {
+            Err("database is down".into())
+        }
+
+        fn set(&self, _user: &str, _page: &str, _view_count: i64) -> Result<Visitors, actions::DbError> {
+            Err("database is down".into())
+        }
+
+        fn delete(&self, _user: &str, _page: &str) -> Result<bool, actions::DbError> {
+            Err("database is down".into())
+        }
+
+        fn iter(&self) -> Result<Vec<Visitors>, actions::DbError> {
+            Err("database is down".into())
+        }
+
+        fn mark_seen(&self, _user: &str, _page: &str, _fingerprint: &str, _now: i64, _window_secs: i64) -> Result<bool, actions::DbError> {
+            Err("database is down".into())
+        }
+
+        fn display_step(&self, _user: &str) -> Result<i64, actions::DbError> {
+            Err("database is down".into())
+        }
+    }
+
+    /// The app as `main` builds it, minus the SQL-only routes and
+    /// middleware, serving `configs` as badge scopes over `store`.
+    fn app(
+        store: Arc<dyn CounterStore>,
+        configs: Vec<BadgeConfig>,
+    ) -> App<
+        impl ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse<impl MessageBody>,
+            Error = error::Error,
+            InitError = (),
+        >,
+    > {
+        std::env::set_var("BADGE_KEY", TEST_BADGE_KEY);
+        let font_bytes = std::fs::read("src/fonts/DejaVuSans.ttf").unwrap();
+        let scope = configs.into_iter()
+            .fold(web::scope(""), |scope, config| scope.service(badge_scope(config)))
+            .configure(|cfg| routes(cfg, false));
+        App::new()
+            .app_data(web::Data::from(store))
+            .app_data(web::Data::new(font()))
+            .app_data(web::Data::new(HotBadgeCache::<BadgeView>::new(None)))
+            .app_data(web::Data::new(RateLimiter::from_env().unwrap()))
+            .app_data(web::Data::new(Rasterizer::new(font_bytes)))
+            .app_data(web::QueryConfig::default().error_handler(params::query_error))
+            .service(scope)
+    }
+
+    /// Width and height of a PNG response body.
+    fn png_size(png: &[u8]) -> (u32, u32) {
+        let pixmap = resvg::tiny_skia::Pixmap::decode_png(png).unwrap();
+        (pixmap.width(), pixmap.height())
+    }
+
+    fn badge_uri(path: &str) -> String {
+        format!("{}?key={}", path, TEST_BADGE_KEY)
+    }
+
+    fn view() -> BadgeView {
+        BadgeView {
+            user: "octocat".to_string(),
+            page: DEFAULT_PAGE.to_string(),
+            metric: Metric::Total,
+            label: "Profile views".to_string(),
+            style: BadgeStyle::default(),
+            color: None,
+            label_color: None,
+            count_format: CountFormat::Plain,
+            output: OutputFormat::Svg,
+            scale: 1,
+            create: true,
+            bundle: &i18n::ENGLISH,
+            trend: false,
+            celebrate: false,
+            display_step: 1,
+            min_count: None,
+            below: String::new(),
+        }
+    }
+
+    /// Fill of the message half of a rendered badge.
+    fn message_fill(svg: &str) -> &str {
+        let rect = svg.match_indices("<rect").nth(1).map(|(i, _)| &svg[i..]).unwrap();
+        let fill = &rect[rect.find("fill=\"").unwrap() + 6..];
+        &fill[..fill.find('"').unwrap()]
+    }
+
+    #[test]
+    fn badge_colors_accept_hex_and_css_names() {
+        let font = font();
+        let default_fill = message_fill(&view().render(&font, 1, None, 0)).to_string();
+        for (color, fill) in [
+            ("#ff69b4", "rgba(255,105,180,1)"),
+            ("ff69b4", "rgba(255,105,180,1)"),
+            ("hotpink", "rgba(255,105,180,1)"),
+            ("blue", "rgba(0,126,198,1)"),
+        ] {
+            let view = BadgeView { color: valid_color(Some(color)), ..view() };
+            assert_eq!(message_fill(&view.render(&font, 1, None, 0)), fill, "{}", color);
+        }
+        for color in ["notacolor", "#12345", "url(#x)"] {
+            assert_eq!(valid_color(Some(color)), None, "{}", color);
+            let view = BadgeView { color: valid_color(Some(color)), ..view() };
+            assert_eq!(message_fill(&view.render(&font, 1, None, 0)), default_fill, "{}", color);
+        }
+    }
+
+    #[test]
+    fn labels_are_capped_and_escaped_once() {
+        let long = "x".repeat(badge::MAX_LABEL_CHARS + 10);
+        assert_eq!(sanitize_label(&long).chars().count(), badge::MAX_LABEL_CHARS);
+
+        let view = BadgeView { label: sanitize_label("<script> & co"), ..view() };
+        let svg = view.render(&font(), 1, None, 0);
+        assert!(!svg.contains("<script>"));
+        assert!(svg.contains(">&lt;script&gt; &amp; co</text>"));
+        assert!(!svg.contains("&amp;lt;"));
+    }
+
+    #[actix_web::test]
+    async fn error_badges_escape_the_message_once() {
+        let response = render_error_badge(&font(), StatusCode::BAD_REQUEST, "bad <style>");
+        let body = actix_web::body::to_bytes(response.into_body()).await.unwrap();
+        let svg = std::str::from_utf8(&body).unwrap();
+        assert!(!svg.contains("<style>"));
+        assert!(svg.contains(">bad &lt;style&gt;</text>"));
+    }
+
+    #[actix_web::test]
+    async fn failed_lookups_still_get_a_well_formed_uncached_badge() {
+        let temp = TempStore::new("error-badges");
+        let ok = init_service(app(temp.open(), vec![BadgeConfig::default()])).await;
+        let failing = init_service(app(Arc::new(FailingStore), vec![BadgeConfig::default()])).await;
+
+        for (response, status) in [
+            (call_service(&ok, TestRequest::get().uri("/?key=wrong").to_request()).await, StatusCode::NOT_FOUND),
+            (call_service(&failing, TestRequest::get().uri(&badge_uri("/")).to_request()).await, StatusCode::INTERNAL_SERVER_ERROR),
+        ] {
+            assert_eq!(response.status(), status);
+            assert_eq!(response.headers().get("Content-Type").unwrap(), response::SVG_CONTENT_TYPE);
+            assert!(response.headers().get("Cache-Control").unwrap().to_str().unwrap().contains("no-store"));
+            let body = read_body(response).await;
+            let svg = std::str::from_utf8(&body).unwrap();
+            assert!(usvg::Tree::from_str(svg, &usvg::Options::default()).is_ok(), "{}", svg);
+            assert!(svg.contains(">Profile views</text>"));
+        }
+    }
+
+    #[actix_web::test]
+    async fn count_returns_views_without_counting_one() {
+        let temp = TempStore::new("count");
+        let store = temp.open();
+        store.set("octocat", DEFAULT_PAGE, 41).unwrap();
+        let app = init_service(app(store.clone(), vec![])).await;
+
+        for _ in 0..2 {
+            let response = call_service(&app, TestRequest::get().uri("/count/octocat").to_request()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let body: serde_json::Value = read_body_json(response).await;
+            assert_eq!(body, serde_json::json!({ "id": "octocat", "view_count": 41 }));
+        }
+        assert_eq!(store.get("octocat", DEFAULT_PAGE).unwrap().unwrap().view_count, 41);
+    }
+
+    #[actix_web::test]
+    async fn count_of_an_unknown_user_is_a_json_404() {
+        let temp = TempStore::new("count-missing");
+        let app = init_service(app(temp.open(), vec![])).await;
+
+        let response = call_service(&app, TestRequest::get().uri("/count/nobody").to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(body, serde_json::json!({ "error": "counter not found" }));
+    }
+
+    #[test]
+    fn etags_change_with_the_count_and_the_look() {
+        let etag = view().etag(5, None, 0);
+        assert_eq!(view().etag(5, None, 0), etag);
+        for changed in [
+            view().etag(6, None, 0),
+            view().etag(5, Some(10), 0),
+            BadgeView { style: BadgeStyle::Flat, ..view() }.etag(5, None, 0),
+            BadgeView { color: Some("blue".to_string()), ..view() }.etag(5, None, 0),
+            BadgeView { label_color: Some("blue".to_string()), ..view() }.etag(5, None, 0),
+            BadgeView { label: "Downloads".to_string(), ..view() }.etag(5, None, 0),
+            BadgeView { user: "hubot".to_string(), ..view() }.etag(5, None, 0),
+        ] {
+            assert_ne!(changed, etag);
+        }
+    }
+
+    #[test]
+    fn last_visit_etags_change_as_the_relative_time_does() {
+        let last_visit = BadgeView { metric: Metric::LastVisit, ..view() };
+        let viewed_at = 1_700_000_000;
+        let (before, after) = (viewed_at + 58, viewed_at + 61);
+        assert_eq!(last_visit.message(viewed_at, None, before), "just now");
+        assert_eq!(last_visit.message(viewed_at, None, after), "1 min ago");
+        assert_eq!(last_visit.etag(viewed_at, None, before), last_visit.etag(viewed_at, None, viewed_at + 30));
+        assert_ne!(last_visit.etag(viewed_at, None, after), last_visit.etag(viewed_at, None, before));
+    }
+
+    #[actix_web::test]
+    async fn matching_etags_get_not_modified_but_views_still_count() {
+        let temp = TempStore::new("etag");
+        let store = temp.open();
+        let app = init_service(app(store.clone(), vec![BadgeConfig::default()])).await;
+
+        let response = call_service(&app, TestRequest::get().uri(&badge_uri("/")).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers().get("ETag").unwrap().to_str().unwrap().to_string();
+
+        let read_only = badge_uri("/") + "&read_only=true";
+        let response = call_service(
+            &app,
+            TestRequest::get().uri(&read_only).insert_header(("If-None-Match", etag.as_str())).to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert!(read_body(response).await.is_empty());
+
+        // A counted view changes the count, so the old ETag no longer matches.
+        let response = call_service(
+            &app,
+            TestRequest::get().uri(&badge_uri("/")).insert_header(("If-None-Match", etag.as_str())).to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(response.headers().get("ETag").unwrap().to_str().unwrap(), etag);
+        assert_eq!(store.get("me", DEFAULT_PAGE).unwrap().unwrap().view_count, 2);
+    }
+
+    #[actix_web::test]
+    async fn views_over_the_rate_limit_are_served_but_not_counted() {
+        let temp = TempStore::new("rate-limit");
+        let store = temp.open();
+        let app = init_service(
+            app(store.clone(), vec![BadgeConfig::default()]).app_data(web::Data::new(RateLimiter::new(Some(3), false))),
+        )
+        .await;
+
+        for shown in [1, 2, 3, 3, 3] {
+            let request = TestRequest::get().uri(&badge_uri("/")).peer_addr("192.0.2.1:1000".parse().unwrap());
+            let response = call_service(&app, request.to_request()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = read_body(response).await;
+            assert!(std::str::from_utf8(&body).unwrap().contains(&format!(">{}</text>", shown)));
+        }
+        assert_eq!(store.get("me", DEFAULT_PAGE).unwrap().unwrap().view_count, 3);
+    }
+
+    #[actix_web::test]
+    async fn badges_for_different_pages_count_independently() {
+        let temp = TempStore::new("pages");
+        let app = init_service(app(temp.open(), vec![BadgeConfig::default()])).await;
+
+        for page in ["repo-a", "repo-a", "repo-b"] {
+            let uri = format!("{}&page={}", badge_uri("/"), page);
+            assert_eq!(call_service(&app, TestRequest::get().uri(&uri).to_request()).await.status(), StatusCode::OK);
+        }
+        for (uri, view_count) in [("/count/me/repo-a", 2), ("/count/me/repo-b", 1), ("/count/me", 3)] {
+            let response = call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+            let body: serde_json::Value = read_body_json(response).await;
+            assert_eq!(body["view_count"], view_count, "{}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn badge_scopes_keep_their_own_label_and_counter() {
+        let temp = TempStore::new("scopes");
+        let store = temp.open();
+        let downloads = BadgeConfig {
+            path: "/downloads".to_string(),
+            user: "downloads".to_string(),
+            label: Some("Downloads".to_string()),
+            color: Some("blue".to_string()),
+            ..BadgeConfig::default()
+        };
+        let app = init_service(app(store.clone(), vec![BadgeConfig::default(), downloads])).await;
+
+        for (path, label, shown) in [
+            ("/", "Profile views", 1),
+            ("/downloads", "Downloads", 1),
+            ("/downloads", "Downloads", 2),
+            ("/", "Profile views", 2),
+            ("/downloads", "Downloads", 3),
+        ] {
+            let response = call_service(&app, TestRequest::get().uri(&badge_uri(path)).to_request()).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", path);
+            let body = read_body(response).await;
+            let svg = std::str::from_utf8(&body).unwrap();
+            assert!(svg.contains(&format!(">{}</text>", label)), "{}", path);
+            assert!(svg.contains(&format!(">{}</text>", shown)), "{}", path);
+            assert_eq!(message_fill(svg) == "rgba(0,126,198,1)", path == "/downloads", "{}", path);
+        }
+        assert_eq!(store.get("me", DEFAULT_PAGE).unwrap().unwrap().view_count, 2);
+        assert_eq!(store.get("downloads", DEFAULT_PAGE).unwrap().unwrap().view_count, 3);
+    }
+
+    #[actix_web::test]
+    async fn increment_refuses_requests_without_a_key() {
+        let temp = TempStore::new("increment");
+        let store = temp.open();
+        let app = init_service(app(store.clone(), vec![])).await;
+
+        let request = TestRequest::post().uri("/api/counters/octocat/increment").set_json(serde_json::json!({ "by": 1000 }));
+        let response = call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(store.get("octocat", DEFAULT_PAGE).unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn blank_labels_are_rejected_instead_of_rendered() {
+        let temp = TempStore::new("blank-label");
+        let app = init_service(app(temp.open(), vec![BadgeConfig::default()])).await;
+
+        for uri in [badge_uri("/") + "&label=", badge_uri("/") + "&label=%20%20", badge_uri("/badge/octocat/blog") + "&label="] {
+            let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+            let body: serde_json::Value = read_body_json(response).await;
+            assert_eq!(body["fields"][0]["field"], "label", "{}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn below_placeholders_must_not_be_blank() {
+        let temp = TempStore::new("below");
+        let app = init_service(app(temp.open(), vec![BadgeConfig::default()])).await;
+
+        for below in ["", "%20"] {
+            let uri = format!("{}&min_count=50&below={}", badge_uri("/"), below);
+            let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+            let body: serde_json::Value = read_body_json(response).await;
+            assert_eq!(body["fields"][0]["field"], "below", "{}", uri);
+        }
+
+        for (below, shown) in [("&below=soon", ">soon</text>"), ("", ">&lt; 50</text>")] {
+            let uri = format!("{}&min_count=50{}", badge_uri("/"), below);
+            let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+            let body = read_body(response).await;
+            assert!(std::str::from_utf8(&body).unwrap().contains(shown), "{}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn og_images_are_cached_per_count() {
+        let temp = TempStore::new("og");
+        let store = temp.open();
+        store.set("octocat", DEFAULT_PAGE, 42).unwrap();
+        let og_cache = web::Data::new(OgImageCache::default());
+        let app = init_service(app(store.clone(), Vec::new()).app_data(og_cache.clone())).await;
+
+        let response = call_service(&app, TestRequest::get().uri("/og/octocat.png").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("Content-Type").unwrap(), "image/png");
+        let png = read_body(response).await;
+        assert_eq!(png_size(&png), (1200, 630));
+
+        // Same count: served from the cache. A new count renders afresh.
+        let again = read_body(call_service(&app, TestRequest::get().uri("/og/octocat.png").to_request()).await).await;
+        assert_eq!(again, png);
+        let stats = og_cache.stats.snapshot();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        store.set("octocat", DEFAULT_PAGE, 43).unwrap();
+        let changed = read_body(call_service(&app, TestRequest::get().uri("/og/octocat.png").to_request()).await).await;
+        assert_ne!(changed, png);
+        assert_eq!(og_cache.stats.snapshot().misses, 2);
+
+        let response = call_service(&app, TestRequest::get().uri("/og/nobody.png").to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(body, serde_json::json!({ "error": "counter not found" }));
+    }
+
+    #[actix_web::test]
+    async fn png_badges_scale_with_the_query() {
+        let temp = TempStore::new("png-scale");
+        let store = temp.open();
+        store.set("octocat", DEFAULT_PAGE, 1234).unwrap();
+        let app = init_service(app(store, Vec::new())).await;
+        let mut sizes = Vec::new();
+        for scale in 1..=4 {
+            let uri = format!("{}&format=png&scale={}&read_only=true", badge_uri("/badge/octocat"), scale);
+            let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers().get("Content-Type").unwrap(), PNG_CONTENT_TYPE);
+            sizes.push(png_size(&read_body(response).await));
+        }
+        let (width, height) = sizes[0];
+        assert_eq!(height, 20);
+        for (scale, size) in (1..=4).zip(sizes) {
+            assert_eq!(size, (width * scale, height * scale), "scale {}", scale);
+        }
+
+        let uri = format!("{}&format=png&scale=5", badge_uri("/badge/octocat"));
+        let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    /// Fields of every closed span, as `name -> value` strings.
+    #[derive(Clone, Default)]
+    struct SpanFields(Arc<std::sync::Mutex<Vec<HashMap<String, String>>>>);
+
+    struct FieldVisitor<'a>(&'a mut HashMap<String, String>);
+
+    impl tracing::field::Visit for FieldVisitor<'_> {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.insert(field.name().to_string(), format!("{:?}", value).trim_matches('"').to_string());
+        }
+    }
+
+    impl<S> tracing_subscriber::Layer<S> for SpanFields
+    where
+        S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            id: &tracing::span::Id,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut fields = HashMap::new();
+            attrs.record(&mut FieldVisitor(&mut fields));
+            ctx.span(id).unwrap().extensions_mut().insert(fields);
+        }
+
+        fn on_record(
+            &self,
+            id: &tracing::span::Id,
+            values: &tracing::span::Record<'_>,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let span = ctx.span(id).unwrap();
+            let mut extensions = span.extensions_mut();
+            values.record(&mut FieldVisitor(extensions.get_mut::<HashMap<String, String>>().unwrap()));
+        }
+
+        fn on_close(&self, id: tracing::span::Id, ctx: tracing_subscriber::layer::Context<'_, S>) {
+            let span = ctx.span(&id).unwrap();
+            let fields = span.extensions().get::<HashMap<String, String>>().cloned().unwrap_or_default();
+            self.0.lock().unwrap().push(fields);
+        }
+    }
+
+    #[actix_web::test]
+    async fn request_spans_carry_the_badge_fields() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let spans = SpanFields::default();
+        let _subscriber = tracing::subscriber::set_default(tracing_subscriber::registry().with(spans.clone()));
+        let temp = TempStore::new("tracing");
+        let store = temp.open();
+        store.set("octocat", DEFAULT_PAGE, 41).unwrap();
+        let app = init_service(app(store, Vec::new()).wrap(TracingLogger::<BadgeRootSpan>::new())).await;
+
+        let uri = format!("{}&style=flat-square", badge_uri("/badge/octocat"));
+        let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        drop(read_body(response).await);
+        drop(app);
+
+        let spans = spans.0.lock().unwrap();
+        let root = spans.iter()
+            .find(|fields| fields.contains_key("http.method"))
+            .expect("the request should have a root span");
+        assert_eq!(root["http.method"], "GET");
+        assert_eq!(root["http.route"], "/badge/{id}");
+        assert_eq!(root["http.status_code"], "200");
+        assert_eq!(root["user"], "octocat");
+        assert_eq!(root["style"], "flat-square");
+        assert_eq!(root["view_count"], "42");
+        assert!(!root["request_id"].is_empty());
+    }
+
+    #[actix_web::test]
+    async fn api_count_sums_the_pages_of_a_counter() {
+        let temp = TempStore::new("api-count");
+        let store = temp.open();
+        store.set("octocat", DEFAULT_PAGE, 40).unwrap();
+        store.set("octocat", "blog", 2).unwrap();
+        let app = init_service(app(store, Vec::new())).await;
+
+        let response = call_service(&app, TestRequest::get().uri("/api/count/octocat").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(body, serde_json::json!({ "id": "octocat", "count": 42 }));
+        let response = call_service(&app, TestRequest::get().uri("/api/count/nobody").to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn text_counts_are_plain_and_uncached() {
+        let temp = TempStore::new("text-count");
+        let store = temp.open();
+        store.set("octocat", DEFAULT_PAGE, 1234).unwrap();
+        let app = init_service(app(store, Vec::new())).await;
+
+        let response = call_service(&app, TestRequest::get().uri("/count/octocat?format=text").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("Content-Type").unwrap(), "text/plain; charset=utf-8");
+        assert_eq!(response.headers().get("Cache-Control").unwrap(), "no-store");
+        assert_eq!(read_body(response).await, "1234");
+        // Reading is not a view.
+        let response = call_service(&app, TestRequest::get().uri("/count/octocat?format=text").to_request()).await;
+        assert_eq!(read_body(response).await, "1234");
+
+        let response = call_service(&app, TestRequest::get().uri("/count/octocat?format=svg").to_request()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(body["error"], "format must be json or text");
+    }
+
+    #[actix_web::test]
+    async fn endpoint_json_follows_the_shields_schema() {
+        let temp = TempStore::new("endpoint");
+        let store = temp.open();
+        store.set("octocat", DEFAULT_PAGE, 1234).unwrap();
+        let app = init_service(app(store, Vec::new())).await;
+
+        let response = call_service(&app, TestRequest::get().uri("/endpoint/octocat").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("Cache-Control").unwrap(), "no-cache");
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(body["schemaVersion"], 1);
+        assert_eq!(body["label"], i18n::ENGLISH.label);
+        assert_eq!(body["color"], badge::DEFAULT_COLOR);
+        assert_eq!(body["message"], format_count(1234, CountFormat::from_env()));
+
+        let uri = "/endpoint/octocat?label=Readers&color=blue";
+        let body: serde_json::Value = read_body_json(call_service(&app, TestRequest::get().uri(uri).to_request()).await).await;
+        assert_eq!((body["label"].as_str(), body["color"].as_str()), (Some("Readers"), Some("blue")));
+        // Colors shields.io would not understand fall back to the default.
+        let uri = "/endpoint/octocat?color=not-a-color";
+        let body: serde_json::Value = read_body_json(call_service(&app, TestRequest::get().uri(uri).to_request()).await).await;
+        assert_eq!(body["color"], badge::DEFAULT_COLOR);
+
+        let response = call_service(&app, TestRequest::get().uri("/endpoint/nobody").to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(body["isError"], true);
+        assert_eq!(body["message"], "not found");
+    }
 }
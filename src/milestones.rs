@@ -0,0 +1,441 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use actix_web::{delete, error, get, put, web, HttpRequest, HttpResponse, Responder, Result};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::broadcast;
+use url::Url;
+
+use crate::actions::{self, DbError};
+use crate::claim;
+use crate::live::{CounterUpdate, LiveUpdates};
+use crate::models::{Milestone, MilestoneHook};
+use crate::store::CounterStore;
+use crate::DbPool;
+
+/// Most thresholds one counter may register.
+const MAX_THRESHOLDS: usize = 20;
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Pages read at a time when backfilling milestones a new hook has passed.
+const PAGES_PER_BATCH: usize = 100;
+/// Waits before each retry of a failed delivery.
+const RETRY_DELAYS: [Duration; 3] = [Duration::from_secs(5), Duration::from_secs(60), Duration::from_secs(600)];
+
+/// Registered milestone webhooks by counter id, and milestones known to be
+/// recorded, so views of counters without hooks never touch the database.
+#[derive(Default)]
+pub struct Milestones {
+    hooks: Mutex<HashMap<String, Hook>>,
+    reached: Mutex<HashSet<(String, String, i64)>>,
+    /// When each page last reached a milestone, for celebratory badges.
+    latest: Mutex<HashMap<(String, String), i64>>,
+}
+
+#[derive(Debug, Clone)]
+struct Hook {
+    url: String,
+    thresholds: Vec<i64>,
+}
+
+impl From<MilestoneHook> for Hook {
+    fn from(hook: MilestoneHook) -> Self {
+        Hook { url: hook.url, thresholds: parse_thresholds(&hook.thresholds) }
+    }
+}
+
+impl Milestones {
+    pub fn load(pool: &DbPool) -> Result<Self, DbError> {
+        let mut conn = pool.get()?;
+        let hooks = actions::list_milestone_hooks(&mut conn)?
+            .into_iter()
+            .map(|hook| (hook.user_id.clone(), Hook::from(hook)))
+            .collect();
+        let mut latest = HashMap::new();
+        for milestone in actions::recent_milestones(&mut conn, Utc::now().timestamp() - celebration_secs())? {
+            let reached_at = latest.entry((milestone.user_id, milestone.page)).or_insert(milestone.reached_at);
+            *reached_at = (*reached_at).max(milestone.reached_at);
+        }
+        Ok(Milestones { hooks: Mutex::new(hooks), reached: Mutex::default(), latest: Mutex::new(latest) })
+    }
+
+    /// Whether the page reached a milestone within `CELEBRATION_HOURS`.
+    pub fn celebrating(&self, user: &str, page: &str) -> bool {
+        let latest = self.latest.lock().unwrap();
+        latest.get(&(user.to_string(), page.to_string()))
+            .is_some_and(|reached_at| *reached_at > Utc::now().timestamp() - celebration_secs())
+    }
+
+    /// Thresholds of the counter's hook that `update` has passed and that are
+    /// not known to be recorded yet, with the hook URL.
+    fn passed(&self, update: &CounterUpdate) -> Option<(String, Vec<i64>)> {
+        let hook = self.hooks.lock().unwrap().get(&update.id).cloned()?;
+        let reached = self.reached.lock().unwrap();
+        let passed: Vec<i64> = hook.thresholds.iter()
+            .copied()
+            .filter(|threshold| *threshold <= update.view_count)
+            .filter(|threshold| !reached.contains(&(update.id.clone(), update.page.clone(), *threshold)))
+            .collect();
+        (!passed.is_empty()).then_some((hook.url, passed))
+    }
+
+    fn mark_reached(&self, user: &str, page: &str, thresholds: &[i64]) {
+        let mut reached = self.reached.lock().unwrap();
+        for threshold in thresholds {
+            reached.insert((user.to_string(), page.to_string(), *threshold));
+        }
+    }
+}
+
+/// Watch counter updates and announce each newly passed threshold to the
+/// counter's webhook, once.
+pub fn spawn_notifier(milestones: Arc<Milestones>, pool: DbPool, live: &LiveUpdates) {
+    let mut updates = live.subscribe();
+    actix_web::rt::spawn(async move {
+        loop {
+            let update = match updates.recv().await {
+                Ok(update) => update,
+                // Skipped updates are made up for by the next one: thresholds
+                // are compared against the current count.
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            if let Err(err) = notify(&milestones, &pool, update).await {
+                tracing::warn!("could not record milestones: {}", err);
+            }
+        }
+    });
+}
+
+async fn notify(milestones: &Milestones, pool: &DbPool, update: CounterUpdate) -> Result<(), DbError> {
+    let (url, passed) = match milestones.passed(&update) {
+        Some(passed) => passed,
+        None => return Ok(()),
+    };
+    let pool = pool.clone();
+    let (user, page) = (update.id.clone(), update.page.clone());
+    let thresholds = passed.clone();
+    let new = web::block(move || {
+        let mut conn = pool.get()?;
+        let now = Utc::now().timestamp();
+        let mut new = Vec::new();
+        for threshold in thresholds {
+            let milestone = Milestone { user_id: user.clone(), page: page.clone(), threshold, reached_at: now };
+            if actions::record_milestone(&mut conn, &milestone)? {
+                new.push(threshold);
+            }
+        }
+        Ok::<_, DbError>(new)
+    })
+    .await??;
+    milestones.mark_reached(&update.id, &update.page, &passed);
+    // Announce only the highest one when a jump passes several at once.
+    if let Some(threshold) = new.into_iter().max() {
+        let key = (update.id.clone(), update.page.clone());
+        milestones.latest.lock().unwrap().insert(key, Utc::now().timestamp());
+        actix_web::rt::spawn(deliver(url, payload(&update, threshold)));
+    }
+    Ok(())
+}
+
+/// Body understood by Slack (`text`) and Discord (`content`) incoming
+/// webhooks, with the raw fields for anything else.
+fn payload(update: &CounterUpdate, threshold: i64) -> serde_json::Value {
+    let counter = if update.page.is_empty() {
+        update.id.clone()
+    } else {
+        format!("{}/{}", update.id, update.page)
+    };
+    let message = format!("🎉 {} reached {} views", counter, threshold);
+    json!({
+        "text": message,
+        "content": message,
+        "id": update.id,
+        "page": update.page,
+        "milestone": threshold,
+        "view_count": update.view_count,
+    })
+}
+
+/// POST `body` to `url`, retrying failures after each of `RETRY_DELAYS`.
+async fn deliver(url: String, body: serde_json::Value) {
+    let client = awc::Client::builder()
+        .timeout(DELIVERY_TIMEOUT)
+        .disable_redirects()
+        .finish();
+    let mut delays = RETRY_DELAYS.iter();
+    loop {
+        let error = match send(&client, &url, &body).await {
+            Ok(()) => return,
+            Err(err) => err,
+        };
+        match delays.next() {
+            Some(delay) => {
+                tracing::info!("milestone webhook {} failed: {}, retrying in {:?}", url, error, delay);
+                actix_web::rt::time::sleep(*delay).await;
+            },
+            None => {
+                tracing::warn!("giving up on milestone webhook {}: {}", url, error);
+                return;
+            },
+        }
+    }
+}
+
+/// One delivery attempt. The host is resolved and checked again each time,
+/// since its name may point somewhere private by now, and the request goes
+/// to the checked address.
+async fn send(client: &awc::Client, url: &str, body: &serde_json::Value) -> Result<(), String> {
+    let target = Url::parse(url).map_err(|err| err.to_string())?;
+    let addr = crate::dynamic::public_address(&target).await?;
+    let response = client.post(url).address(addr).send_json(body).await.map_err(|err| err.to_string())?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("status {}", response.status()))
+    }
+}
+
+static CELEBRATION_SECS: OnceLock<i64> = OnceLock::new();
+
+/// Read `CELEBRATION_HOURS`; called once at startup so a bad value stops the
+/// server instead of silently celebrating for the default time.
+pub fn init() -> Result<(), String> {
+    let _ = CELEBRATION_SECS.set(celebration_secs_from_env()?);
+    Ok(())
+}
+
+/// How long badges celebrate a reached milestone (`CELEBRATION_HOURS`,
+/// default 24, 0 turns celebrations off).
+fn celebration_secs_from_env() -> Result<i64, String> {
+    let hours = match std::env::var("CELEBRATION_HOURS") {
+        Ok(hours) => hours.parse::<u32>()
+            .map_err(|_| format!("CELEBRATION_HOURS should be a non-negative number of hours, got {:?}", hours))?,
+        Err(_) => 24,
+    };
+    Ok(i64::from(hours) * 60 * 60)
+}
+
+fn celebration_secs() -> i64 {
+    *CELEBRATION_SECS.get_or_init(|| celebration_secs_from_env().expect("CELEBRATION_HOURS should be valid"))
+}
+
+/// Badge color while celebrating (`CELEBRATION_COLOR`, default gold).
+pub fn celebration_color() -> String {
+    std::env::var("CELEBRATION_COLOR")
+        .ok()
+        .filter(|color| crate::badge::is_valid_color(color))
+        .unwrap_or_else(|| "gold".to_string())
+}
+
+/// Decoration put before the count while celebrating
+/// (`CELEBRATION_EMOJI`, default ★; the badge font must have the glyph).
+pub fn celebration_emoji() -> String {
+    std::env::var("CELEBRATION_EMOJI").unwrap_or_else(|_| "★".to_string())
+}
+
+fn parse_thresholds(thresholds: &str) -> Vec<i64> {
+    thresholds.split(',').filter_map(|threshold| threshold.trim().parse().ok()).collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HookRequest {
+    url: String,
+    thresholds: Vec<i64>,
+}
+
+/// Register the webhook that is sent a POST when a page of the counter
+/// passes one of `thresholds` views, replacing any earlier one. Thresholds
+/// a page has already passed are recorded without being announced. Requires
+/// the counter's owner key.
+#[put("/api/milestones/{user}")]
+async fn put_hook(
+    pool: web::Data<DbPool>,
+    store: web::Data<dyn CounterStore>,
+    milestones: web::Data<Milestones>,
+    path: web::Path<String>,
+    body: web::Json<HookRequest>,
+    http_req: HttpRequest,
+) -> Result<impl Responder> {
+    let user = path.into_inner();
+    if !claim::is_owner(&pool, &http_req, &user).await? {
+        return Ok(HttpResponse::Unauthorized().json(json!({ "error": "owner key required" })));
+    }
+    let HookRequest { url, mut thresholds } = body.into_inner();
+    let target = match Url::parse(&url) {
+        Ok(target) => target,
+        Err(_) => return Ok(HttpResponse::BadRequest().json(json!({ "error": "url must be a public https URL" }))),
+    };
+    if let Err(err) = crate::dynamic::public_address(&target).await {
+        return Ok(HttpResponse::BadRequest().json(json!({ "error": format!("url must be a public https URL: {}", err) })));
+    }
+    thresholds.sort_unstable();
+    thresholds.dedup();
+    if thresholds.is_empty() || thresholds.len() > MAX_THRESHOLDS || thresholds[0] < 1 {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "error": format!("thresholds must be 1 to {} positive view counts", MAX_THRESHOLDS),
+        })));
+    }
+
+    let hook = MilestoneHook {
+        user_id: user.clone(),
+        url,
+        thresholds: thresholds.iter().map(i64::to_string).collect::<Vec<_>>().join(","),
+        created_at: Utc::now().timestamp(),
+    };
+    let saved = hook.clone();
+    let passed = thresholds.clone();
+    web::block(move || {
+        let mut conn = pool.get()?;
+        actions::upsert_milestone_hook(&mut conn, &saved)?;
+        let mut after = None;
+        loop {
+            let pages = store.pages(&saved.user_id, after.as_deref(), PAGES_PER_BATCH)?;
+            for visitor in &pages {
+                for threshold in passed.iter().copied().filter(|threshold| *threshold <= visitor.view_count) {
+                    let milestone = Milestone {
+                        user_id: visitor.id.clone(),
+                        page: visitor.page.clone(),
+                        threshold,
+                        reached_at: saved.created_at,
+                    };
+                    actions::record_milestone(&mut conn, &milestone)?;
+                }
+            }
+            if pages.len() < PAGES_PER_BATCH {
+                break;
+            }
+            after = pages.last().map(|visitor| visitor.page.clone());
+        }
+        Ok::<_, DbError>(())
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    milestones.hooks.lock().unwrap().insert(user, Hook { url: hook.url.clone(), thresholds: thresholds.clone() });
+    Ok(HttpResponse::Ok().json(json!({ "url": hook.url, "thresholds": thresholds })))
+}
+
+/// The counter's webhook and every milestone its pages have reached, newest
+/// first. Requires the counter's owner key.
+#[get("/api/milestones/{user}")]
+async fn get_hook(
+    pool: web::Data<DbPool>,
+    milestones: web::Data<Milestones>,
+    path: web::Path<String>,
+    http_req: HttpRequest,
+) -> Result<impl Responder> {
+    let user = path.into_inner();
+    if !claim::is_owner(&pool, &http_req, &user).await? {
+        return Ok(HttpResponse::Unauthorized().json(json!({ "error": "owner key required" })));
+    }
+    let hook = milestones.hooks.lock().unwrap().get(&user).cloned();
+    let reached = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::list_milestones(&mut conn, &user)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(json!({
+        "url": hook.as_ref().map(|hook| &hook.url),
+        "thresholds": hook.as_ref().map_or(&[][..], |hook| &hook.thresholds[..]),
+        "reached": reached,
+    })))
+}
+
+/// Stop announcing milestones. Reached milestones stay recorded. Requires
+/// the counter's owner key.
+#[delete("/api/milestones/{user}")]
+async fn delete_hook(
+    pool: web::Data<DbPool>,
+    milestones: web::Data<Milestones>,
+    path: web::Path<String>,
+    http_req: HttpRequest,
+) -> Result<impl Responder> {
+    let user = path.into_inner();
+    if !claim::is_owner(&pool, &http_req, &user).await? {
+        return Ok(HttpResponse::Unauthorized().json(json!({ "error": "owner key required" })));
+    }
+    let removed_user = user.clone();
+    let deleted = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::delete_milestone_hook(&mut conn, &removed_user)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    milestones.hooks.lock().unwrap().remove(&user);
+    Ok(if deleted {
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::NotFound().json(json!({ "error": "no milestone webhook" }))
+    })
+}
+
+#[cfg(all(test, not(feature = "postgres")))]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::App;
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use diesel_migrations::MigrationHarness;
+
+    use super::*;
+    use crate::models::OwnerKey;
+    use crate::store::FileStore;
+
+    const OWNER_KEY: &str = "vb_test-owner-key";
+
+    #[actix_web::test]
+    async fn hooks_must_resolve_to_public_addresses() {
+        let pool: DbPool = Pool::builder()
+            .max_size(1)
+            .build(ConnectionManager::<actions::DbConnection>::new(":memory:"))
+            .unwrap();
+        pool.get().unwrap().run_pending_migrations(crate::MIGRATIONS).unwrap();
+        let owner_key = OwnerKey { key_hash: claim::hash_key(OWNER_KEY), user_id: "octocat".to_string(), created_at: 0 };
+        actions::issue_owner_key(&mut pool.get().unwrap(), &owner_key).unwrap();
+        let path = std::env::temp_dir().join(format!("visitor-badge-milestones-{}.json", std::process::id()));
+        let store: Arc<dyn CounterStore> = Arc::new(FileStore::open(&path).unwrap());
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(pool))
+                .app_data(web::Data::from(store))
+                .app_data(web::Data::new(Milestones::default()))
+                .service(put_hook),
+        )
+        .await;
+
+        for (url, status) in [
+            ("https://127.0.0.1/hook", StatusCode::BAD_REQUEST),
+            ("https://localhost/hook", StatusCode::BAD_REQUEST),
+            ("https://169.254.169.254/latest", StatusCode::BAD_REQUEST),
+            ("https://10.0.0.5/hook", StatusCode::BAD_REQUEST),
+            ("https://[fd00::1]/hook", StatusCode::BAD_REQUEST),
+            ("http://1.1.1.1/hook", StatusCode::BAD_REQUEST),
+            ("not a url", StatusCode::BAD_REQUEST),
+            ("https://1.1.1.1/hook", StatusCode::OK),
+        ] {
+            let request = TestRequest::put()
+                .uri("/api/milestones/octocat")
+                .insert_header(("Authorization", format!("Bearer {}", OWNER_KEY)))
+                .set_json(json!({ "url": url, "thresholds": [100] }));
+            let response = call_service(&app, request.to_request()).await;
+            assert_eq!(response.status(), status, "{}", url);
+            let body: serde_json::Value = read_body_json(response).await;
+            if status == StatusCode::BAD_REQUEST {
+                assert!(body["error"].as_str().unwrap().starts_with("url must be a public https URL"), "{}", url);
+            }
+        }
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[actix_web::test]
+    async fn deliveries_to_private_addresses_are_not_sent() {
+        let client = awc::Client::default();
+        for url in ["https://127.0.0.1:9/hook", "https://localhost/hook", "https://192.168.0.1/hook"] {
+            let err = send(&client, url, &json!({})).await.unwrap_err();
+            assert!(err.contains("not a public https URL"), "{}: {}", url, err);
+        }
+    }
+}
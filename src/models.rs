@@ -1,9 +1,306 @@
+use async_graphql::SimpleObject;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::daily::Granularity;
+use crate::format::round_count;
+use crate::schema::{
+    aliases, api_keys, claims, milestone_hooks, milestones, owner_keys, skip_tokens, unique_sketches,
+    visitors,
+};
 
 /// User details.
-#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, ToSchema, SimpleObject)]
 #[diesel(table_name = visitors)]
 pub struct Visitors {
     pub id: String,
-    pub view_count: i32,
+    pub page: String,
+    pub view_count: i64,
+    /// Unix seconds of the last counted view, if known.
+    pub last_viewed_at: Option<i64>,
+    /// Distinct visitors, each counted once per `UNIQUE_WINDOW_SECS`;
+    /// estimated once past `UNIQUE_SKETCH_THRESHOLD`.
+    pub unique_count: i64,
+}
+
+impl Visitors {
+    /// The counter as shown publicly, its counts rounded to the owner's
+    /// display step.
+    pub fn rounded(self, display_step: i64) -> Self {
+        Visitors {
+            view_count: round_count(self.view_count, display_step),
+            unique_count: round_count(self.unique_count, display_step),
+            ..self
+        }
+    }
+}
+
+/// New counter row.
+#[derive(Debug, Insertable)]
+#[diesel(table_name = visitors)]
+pub struct NewVisitor<'a> {
+    pub id: &'a str,
+    pub page: &'a str,
+    pub view_count: i64,
+    pub last_viewed_at: Option<i64>,
+}
+
+/// Pending ownership claim on a counter.
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[diesel(table_name = claims)]
+pub struct Claim {
+    pub user_id: String,
+    pub token: String,
+    pub created_at: i64,
+    /// SHA-256 of the secret only the claimant was shown.
+    pub secret_hash: String,
+}
+
+/// Owner API key, stored as a SHA-256 hash.
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[diesel(table_name = owner_keys)]
+pub struct OwnerKey {
+    pub key_hash: String,
+    pub user_id: String,
+    pub created_at: i64,
+}
+
+/// Scoped API key for admin and write endpoints, stored as a SHA-256 hash.
+/// `scopes` is a comma-separated list such as `admin,write`.
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[diesel(table_name = api_keys)]
+pub struct ApiKey {
+    pub key_hash: String,
+    pub name: String,
+    pub scopes: String,
+    pub created_at: i64,
+}
+
+/// Token that keeps an owner's own views of a counter from being counted,
+/// stored as a SHA-256 hash.
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[diesel(table_name = skip_tokens)]
+pub struct SkipToken {
+    pub token_hash: String,
+    pub user_id: String,
+    pub created_at: i64,
+}
+
+/// Second id for a counter: views of `alias` are counted on `target`.
+#[derive(Debug, Clone, Serialize, Queryable, Insertable)]
+#[diesel(table_name = aliases)]
+pub struct Alias {
+    pub alias: String,
+    pub target: String,
+    pub created_at: i64,
+}
+
+/// Where and at which view counts a counter's milestones are announced.
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[diesel(table_name = milestone_hooks)]
+pub struct MilestoneHook {
+    pub user_id: String,
+    pub url: String,
+    /// Comma-separated view counts, ascending.
+    pub thresholds: String,
+    pub created_at: i64,
+}
+
+/// A threshold one page of a counter has passed. Each is recorded once.
+#[derive(Debug, Clone, Serialize, Queryable, Insertable)]
+#[diesel(table_name = milestones)]
+pub struct Milestone {
+    pub user_id: String,
+    pub page: String,
+    pub threshold: i64,
+    pub reached_at: i64,
+}
+
+/// HyperLogLog sketch of the visitors of one counter in the current
+/// unique window, kept instead of per-visitor rows once the counter is
+/// popular.
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[diesel(table_name = unique_sketches)]
+pub struct UniqueSketch {
+    pub user_id: String,
+    pub page: String,
+    /// Unix time divided by the unique window the sketch covers.
+    pub period: i64,
+    pub registers: Vec<u8>,
+    /// Part of the sketch's estimate already added to `unique_count`.
+    pub estimate: i64,
+}
+
+/// One counter on the public leaderboard, with views summed over its pages.
+#[derive(Debug, Clone, Serialize, QueryableByName, ToSchema, SimpleObject)]
+pub struct LeaderboardEntry {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub id: String,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub view_count: i64,
+}
+
+/// Views of one counter on one local day.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DailyCount {
+    pub user_id: String,
+    pub page: String,
+    pub day: String,
+    pub timezone: String,
+    pub view_count: i64,
+}
+
+impl DailyCount {
+    pub fn rounded(self, display_step: i64) -> Self {
+        DailyCount { view_count: round_count(self.view_count, display_step), ..self }
+    }
+}
+
+/// Views on one local day, as part of a `History`.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, SimpleObject)]
+pub struct DayCount {
+    pub day: String,
+    pub view_count: i64,
+}
+
+/// Daily views of one counter over a run of consecutive days, oldest first.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, SimpleObject)]
+pub struct History {
+    pub user_id: String,
+    pub page: String,
+    pub timezone: String,
+    pub days: Vec<DayCount>,
+}
+
+impl History {
+    pub fn rounded(mut self, display_step: i64) -> Self {
+        for day in &mut self.days {
+            day.view_count = round_count(day.view_count, display_step);
+        }
+        self
+    }
+}
+
+/// Views on one local day, as part of a `Series`.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SeriesPoint {
+    /// Unix seconds at which the day starts in the counter's time zone.
+    pub timestamp: i64,
+    pub day: String,
+    pub view_count: i64,
+}
+
+/// Daily views of one counter between two days, oldest first.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Series {
+    pub user_id: String,
+    pub page: String,
+    pub timezone: String,
+    pub points: Vec<SeriesPoint>,
+}
+
+impl Series {
+    pub fn rounded(mut self, display_step: i64) -> Self {
+        for point in &mut self.points {
+            point.view_count = round_count(point.view_count, display_step);
+        }
+        self
+    }
+}
+
+/// Views summed over one bucket of an `Aggregate`.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PeriodCount {
+    /// First local day of the bucket.
+    pub start: String,
+    pub view_count: i64,
+}
+
+/// Views of one counter per day, week or month over consecutive buckets,
+/// oldest first.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Aggregate {
+    pub user_id: String,
+    pub page: String,
+    pub timezone: String,
+    pub granularity: Granularity,
+    pub periods: Vec<PeriodCount>,
+}
+
+impl Aggregate {
+    pub fn rounded(mut self, display_step: i64) -> Self {
+        for period in &mut self.periods {
+            period.view_count = round_count(period.view_count, display_step);
+        }
+        self
+    }
+}
+
+/// Views that arrived from one referring page.
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, ToSchema)]
+pub struct ReferrerCount {
+    /// Host and leading path of the referring page, such as
+    /// `github.com/octocat/hello`.
+    pub referrer: String,
+    pub view_count: i64,
+}
+
+/// Where the counted views of one counter came from, most views first.
+/// Views without a usable `Referer` are not attributed.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Referrers {
+    pub user_id: String,
+    pub page: String,
+    pub referrers: Vec<ReferrerCount>,
+}
+
+impl Referrers {
+    pub fn rounded(mut self, display_step: i64) -> Self {
+        for referrer in &mut self.referrers {
+            referrer.view_count = round_count(referrer.view_count, display_step);
+        }
+        self
+    }
+}
+
+/// Views from clients in one country.
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, ToSchema)]
+pub struct CountryCount {
+    /// ISO 3166-1 alpha-2 code, such as `DE`.
+    pub country: String,
+    pub view_count: i64,
+}
+
+/// Counted views of one counter per visitor country, most views first.
+/// Views whose address could not be located are not included.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Countries {
+    pub user_id: String,
+    pub page: String,
+    pub countries: Vec<CountryCount>,
+}
+
+impl Countries {
+    pub fn rounded(mut self, display_step: i64) -> Self {
+        for country in &mut self.countries {
+            country.view_count = round_count(country.view_count, display_step);
+        }
+        self
+    }
+}
+
+/// Summary of one counter for `/api/stats`.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, SimpleObject)]
+pub struct CounterStats {
+    pub user_id: String,
+    pub page: String,
+    pub total: i64,
+    pub today: i64,
+    /// Views over the last 7 local days, today included.
+    pub this_week: i64,
+    pub last_viewed_at: Option<i64>,
+    pub unique_visitors: i64,
+    /// Whether `unique_visitors` is a HyperLogLog estimate rather than an
+    /// exact count.
+    pub unique_estimated: bool,
 }
@@ -0,0 +1,76 @@
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::{admin, geoip, leaderboard, models, referrers};
+
+/// OpenAPI document for the JSON, stats and admin endpoints, served at
+/// `/openapi.json` and browsable at `/docs`. Badge images are left out.
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        crate::get_count,
+        crate::get_page_count,
+        crate::get_api_count,
+        crate::get_counter_stats,
+        crate::increment_counter,
+        crate::get_endpoint,
+        crate::get_today,
+        crate::get_history,
+        crate::get_daily_stats,
+        crate::get_aggregate_stats,
+        crate::get_series,
+        referrers::get_referrers,
+        geoip::get_countries,
+        leaderboard::get_leaderboard,
+        admin::get_stats,
+        admin::set_count,
+        admin::delete_count,
+        admin::create_counter,
+        admin::list_counters,
+        admin::get_counter,
+        admin::reset_counter,
+        admin::delete_counter,
+    ),
+    components(schemas(
+        models::Visitors,
+        models::DailyCount,
+        models::DayCount,
+        models::History,
+        models::SeriesPoint,
+        models::Series,
+        models::PeriodCount,
+        models::Aggregate,
+        models::ReferrerCount,
+        models::Referrers,
+        models::CountryCount,
+        models::Countries,
+        crate::daily::Granularity,
+        models::CounterStats,
+        models::LeaderboardEntry,
+        crate::IncrementRequest,
+        admin::InstanceStats,
+        admin::SetCountRequest,
+        admin::CreateCounterRequest,
+        admin::ResetRequest,
+    )),
+    modifiers(&AdminToken),
+    tags(
+        (name = "counters", description = "Read counters without rendering a badge"),
+        (name = "daily", description = "Per-day views in the counter's time zone"),
+        (name = "admin", description = "Operator endpoints, need `Authorization: Bearer <ADMIN_TOKEN>`"),
+    ),
+)]
+pub struct ApiDoc;
+
+/// Declares the `admin_token` bearer scheme the admin paths refer to.
+struct AdminToken;
+
+impl Modify for AdminToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "admin_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
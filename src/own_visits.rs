@@ -0,0 +1,129 @@
+use actix_web::cookie::{time, Cookie, SameSite};
+use actix_web::{delete, error, get, post, web, HttpRequest, HttpResponse, Responder, Result};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::models::SkipToken;
+use crate::{actions, claim, DbPool};
+
+/// Cookie carrying the skip token of one counter.
+fn cookie_name(user: &str) -> String {
+    format!("vb_skip_{}", user)
+}
+
+/// Issue a skip token for the counter, replacing any earlier one. Views
+/// sent with it, as `?skip=` or in the cookie set by
+/// `/api/skip-token/{user}/cookie`, are not counted, so owners refreshing
+/// their own profile do not inflate it. Requires the counter's owner key,
+/// so like `/api/milestones` it lives outside the API-key guarded
+/// `/api/counters`.
+#[post("/api/skip-token/{user}")]
+async fn create_skip_token(
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+    http_req: HttpRequest,
+) -> Result<impl Responder> {
+    let user = path.into_inner();
+    if !claim::is_owner(&pool, &http_req, &user).await? {
+        return Ok(HttpResponse::Unauthorized().json(json!({ "error": "owner key required" })));
+    }
+    let token = format!("vbs_{}", claim::random_hex());
+    let stored = SkipToken {
+        token_hash: claim::hash_key(&token),
+        user_id: user.clone(),
+        created_at: chrono::Utc::now().timestamp(),
+    };
+    web::block(move || {
+        let mut conn = pool.get()?;
+        actions::replace_skip_token(&mut conn, &stored)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Created().json(json!({
+        "user": user,
+        "token": token,
+        "cookie_url": format!("{}/api/skip-token/{}/cookie?token={}", crate::base_path(), user, token),
+    })))
+}
+
+/// Revoke the counter's skip token. Requires the counter's owner key.
+#[delete("/api/skip-token/{user}")]
+async fn delete_skip_token(
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+    http_req: HttpRequest,
+) -> Result<impl Responder> {
+    let user = path.into_inner();
+    if !claim::is_owner(&pool, &http_req, &user).await? {
+        return Ok(HttpResponse::Unauthorized().json(json!({ "error": "owner key required" })));
+    }
+    let deleted = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::delete_skip_tokens(&mut conn, &user)
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    Ok(if deleted {
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::NotFound().json(json!({ "error": "no skip token" }))
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SkipRequest {
+    token: String,
+}
+
+/// Open in the owner's browser to store the skip token in a cookie, so
+/// badges and pixels this browser loads straight from the service are not
+/// counted. Badges proxied by GitHub's camo never carry the cookie.
+#[get("/api/skip-token/{user}/cookie")]
+async fn set_skip_cookie(
+    pool: web::Data<DbPool>,
+    path: web::Path<String>,
+    req: web::Query<SkipRequest>,
+) -> Result<impl Responder> {
+    let user = path.into_inner();
+    let token = req.into_inner().token;
+    if !token_matches(pool, user.clone(), token.clone()).await? {
+        return Ok(HttpResponse::Forbidden().json(json!({ "error": "invalid skip token" })));
+    }
+    // Badges are loaded cross-site, so the cookie must allow that.
+    let cookie = Cookie::build(cookie_name(&user), token)
+        .path("/")
+        .max_age(time::Duration::days(365))
+        .http_only(true)
+        .secure(true)
+        .same_site(SameSite::None)
+        .finish();
+    Ok(HttpResponse::Ok()
+        .cookie(cookie)
+        .json(json!({ "user": user, "skipping": true })))
+}
+
+/// Whether the view comes from the counter's owner, by a skip token in
+/// `skip` or the owner's cookie. Always false without a database.
+pub async fn is_own_visit(http_req: &HttpRequest, user: &str, skip: Option<&str>) -> Result<bool> {
+    let token = match skip.map(str::to_string).or_else(|| {
+        http_req.cookie(&cookie_name(user)).map(|cookie| cookie.value().to_string())
+    }) {
+        Some(token) => token,
+        None => return Ok(false),
+    };
+    match http_req.app_data::<web::Data<DbPool>>() {
+        Some(pool) => token_matches(pool.clone(), user.to_string(), token).await,
+        None => Ok(false),
+    }
+}
+
+async fn token_matches(pool: web::Data<DbPool>, user: String, token: String) -> Result<bool> {
+    let stored = web::block(move || {
+        let mut conn = pool.get()?;
+        actions::find_skip_token(&mut conn, &claim::hash_key(&token))
+    })
+    .await?
+    .map_err(error::ErrorInternalServerError)?;
+    Ok(stored.is_some_and(|stored| stored.user_id == user))
+}
@@ -0,0 +1,109 @@
+use actix_web::{error, HttpRequest, HttpResponse};
+use serde::Serialize;
+
+/// Why one query parameter was rejected.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, message: impl Into<String>) -> Self {
+        FieldError { field, message: message.into() }
+    }
+}
+
+/// Collects field errors while a request is validated.
+#[derive(Debug, Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    /// Record an error for `field` unless `ok`.
+    pub fn check(&mut self, ok: bool, field: &'static str, message: impl Into<String>) -> &mut Self {
+        if !ok {
+            self.errors.push(FieldError::new(field, message));
+        }
+        self
+    }
+
+    /// Check an optional value only when it is present.
+    pub fn check_opt<T: ?Sized>(
+        &mut self,
+        value: Option<&T>,
+        field: &'static str,
+        valid: impl Fn(&T) -> Result<(), String>,
+    ) -> &mut Self {
+        if let Some(Err(message)) = value.map(valid) {
+            self.errors.push(FieldError::new(field, message));
+        }
+        self
+    }
+
+    pub fn finish(&mut self) -> Result<(), HttpResponse> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(invalid_params(std::mem::take(&mut self.errors)))
+        }
+    }
+}
+
+/// `400 Bad Request` listing every rejected field.
+pub fn invalid_params(errors: Vec<FieldError>) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "error": "invalid query parameters",
+        "fields": errors,
+    }))
+}
+
+/// `QueryConfig` error handler so query strings that do not even
+/// deserialize get the same JSON shape as failed validation.
+pub fn query_error(err: error::QueryPayloadError, _req: &HttpRequest) -> error::Error {
+    let message = err.to_string();
+    let response = invalid_params(vec![FieldError::new("query", message.clone())]);
+    error::InternalError::from_response(message, response).into()
+}
+
+/// Not blank, at most `max` characters and no control characters. Badge
+/// text must have something to draw; shield_maker panics on empty text.
+pub fn text(max: usize) -> impl Fn(&str) -> Result<(), String> {
+    move |value| {
+        if value.trim().is_empty() {
+            Err("must not be empty".to_string())
+        } else if value.chars().count() > max {
+            Err(format!("must be at most {} characters", max))
+        } else if value.chars().any(char::is_control) {
+            Err("must not contain control characters".to_string())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// 1 to 64 ASCII letters, digits, `.`, `_` or `-`, as in counter ids and
+/// page names.
+pub fn slug(value: &str) -> Result<(), String> {
+    if crate::is_valid_page(value) {
+        Ok(())
+    } else {
+        Err("must be 1 to 64 letters, digits, '.', '_' or '-'".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_must_have_something_to_draw() {
+        let text = text(5);
+        assert!(text("views").is_ok());
+        assert!(text(" a ").is_ok());
+        for value in ["", " ", "\t", "toolong", "a\nb"] {
+            assert!(text(value).is_err(), "{:?}", value);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use actix_web::{error, get, http::StatusCode, web, HttpRequest, HttpResponse, Result};
+use serde::Deserialize;
+
+use crate::badge::{self, Metric};
+use crate::bots;
+use crate::dedup;
+use crate::own_visits;
+use crate::rate_limit::RateLimiter;
+use crate::response::{image_response, CachePolicy};
+use crate::signing;
+use crate::store::CounterStore;
+
+const GIF_CONTENT_TYPE: &str = "image/gif";
+
+/// Transparent 1×1 GIF89a.
+const PIXEL: &[u8] = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff\
+    \x21\xf9\x04\x01\x00\x00\x00\x00\x2c\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02\x44\x01\x00\x3b";
+
+#[derive(Debug, Deserialize)]
+pub struct PixelRequest {
+    key: String,
+    page: Option<String>,
+    sig: Option<String>,
+    skip: Option<String>,
+}
+
+/// Count a view and answer with an invisible GIF, for pages that want the
+/// count but not a badge. Rate limiting, crawler filtering, owner skip
+/// tokens and de-duplication apply as for badges; the pixel is sent either
+/// way and is never cached.
+#[get("/pixel/{id}.gif")]
+async fn get_pixel(
+    store: web::Data<dyn CounterStore>,
+    rate_limiter: web::Data<RateLimiter>,
+    path: web::Path<String>,
+    req: web::Query<PixelRequest>,
+    http_req: HttpRequest,
+) -> Result<HttpResponse> {
+    let badge_key = std::env::var("BADGE_KEY").expect("BADGE_KEY should be set");
+    if req.key != badge_key {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({ "error": "counter not found" })));
+    }
+    let user = path.into_inner();
+    if !crate::is_valid_page(&user) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": "invalid id" })));
+    }
+    let page = match crate::page_param(req.page.as_deref()) {
+        Ok(page) => page,
+        Err(response) => return Ok(response),
+    };
+    if let Some(secret) = signing::secret() {
+        if !signing::verify(&secret, &user, &page, req.sig.as_deref().unwrap_or("")) {
+            return Ok(HttpResponse::Forbidden().json(serde_json::json!({ "error": "invalid signature" })));
+        }
+    }
+    let alias_store = store.clone();
+    let user = web::block(move || crate::resolve_counter(alias_store.get_ref(), user))
+        .await?
+        .map_err(error::ErrorInternalServerError)?;
+
+    let own_visit = own_visits::is_own_visit(&http_req, &user, req.skip.as_deref()).await?;
+    let mut counted = !own_visit && !bots::is_bot(&http_req) && rate_limiter.allow(&http_req);
+    if let Some((window_secs, fingerprint)) = dedup::seen_key(&http_req).filter(|_| counted) {
+        let dedup_store = store.clone();
+        let (user, page) = (user.clone(), page.clone());
+        let now = chrono::Utc::now().timestamp();
+        counted = web::block(move || dedup_store.mark_seen(&user, &page, &fingerprint, now, window_secs))
+            .await?
+            .map_err(error::ErrorInternalServerError)?;
+    }
+    if counted {
+        let create = badge::auto_create();
+        let visit = crate::Visit::from_request(&http_req);
+        web::block(move || {
+            crate::increment_and_count(store.get_ref(), &user, &page, 1, Metric::Total, create, &[visit])
+        })
+            .await?
+            .map_err(error::ErrorInternalServerError)?;
+    }
+    Ok(image_response(StatusCode::OK, GIF_CONTENT_TYPE, web::Bytes::from_static(PIXEL), CachePolicy::NoCache))
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use actix_web::HttpRequest;
+
+/// Buckets idle for this long are full again and can be dropped.
+const IDLE_SECS: f64 = 60.0;
+/// Sweep idle buckets once the table grows past this many clients.
+const SWEEP_AT: usize = 10_000;
+
+/// Per-client token buckets. Each client may count `RATE_LIMIT_PER_MINUTE`
+/// views in a burst, refilled evenly over a minute; views beyond that are
+/// still served but not counted.
+pub struct RateLimiter {
+    per_minute: Option<f64>,
+    trusted_proxy: bool,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl RateLimiter {
+    /// Read `RATE_LIMIT_PER_MINUTE` (unset or 0 disables limiting) and
+    /// `TRUSTED_PROXY`, which makes the client address come from
+    /// `Forwarded`/`X-Forwarded-For` instead of the socket.
+    pub fn from_env() -> Result<Self, String> {
+        let per_minute = match std::env::var("RATE_LIMIT_PER_MINUTE") {
+            Ok(n) => Some(n.parse::<u32>()
+                .map_err(|_| format!("RATE_LIMIT_PER_MINUTE should be a non-negative number, got {:?}", n))?),
+            Err(_) => None,
+        };
+        Ok(RateLimiter::new(per_minute, trusted_proxy()))
+    }
+
+    /// `per_minute` of `None` or 0 disables limiting.
+    pub fn new(per_minute: Option<u32>, trusted_proxy: bool) -> Self {
+        RateLimiter {
+            per_minute: per_minute.filter(|n| *n > 0).map(f64::from),
+            trusted_proxy,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token for the requesting client. Returns false when the client
+    /// is over its limit. Requests without a usable address are allowed.
+    pub fn allow(&self, http_req: &HttpRequest) -> bool {
+        let per_minute = match self.per_minute {
+            Some(per_minute) => per_minute,
+            None => return true,
+        };
+        let ip = match client_ip(http_req, self.trusted_proxy) {
+            Some(ip) => ip,
+            None => return true,
+        };
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= SWEEP_AT {
+            buckets.retain(|_, bucket| now.duration_since(bucket.updated_at).as_secs_f64() < IDLE_SECS);
+        }
+        let bucket = buckets.entry(ip).or_insert(Bucket { tokens: per_minute, updated_at: now });
+        let refill = now.duration_since(bucket.updated_at).as_secs_f64() * per_minute / 60.0;
+        bucket.tokens = (bucket.tokens + refill).min(per_minute);
+        bucket.updated_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Whether `TRUSTED_PROXY` is set, making client addresses come from
+/// `Forwarded`/`X-Forwarded-For` instead of the socket.
+pub fn trusted_proxy() -> bool {
+    std::env::var("TRUSTED_PROXY")
+        .map(|value| value == "true" || value == "1")
+        .unwrap_or(false)
+}
+
+/// Address of the requesting client, from the forwarding headers when
+/// `trusted_proxy` and from the socket otherwise.
+pub fn client_ip(http_req: &HttpRequest, trusted_proxy: bool) -> Option<IpAddr> {
+    if trusted_proxy {
+        let connection_info = http_req.connection_info();
+        let addr = connection_info.realip_remote_addr()?;
+        addr.parse::<IpAddr>()
+            .ok()
+            .or_else(|| addr.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+    } else {
+        http_req.peer_addr().map(|addr| addr.ip())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    fn request(peer: &str) -> HttpRequest {
+        TestRequest::default().peer_addr(peer.parse().unwrap()).to_http_request()
+    }
+
+    #[test]
+    fn requests_over_the_limit_are_denied_per_client() {
+        let limiter = RateLimiter::new(Some(3), false);
+        for _ in 0..3 {
+            assert!(limiter.allow(&request("192.0.2.1:1000")));
+        }
+        assert!(!limiter.allow(&request("192.0.2.1:1001")));
+        assert!(limiter.allow(&request("192.0.2.2:1000")));
+    }
+
+    #[test]
+    fn unset_or_zero_limits_allow_everything() {
+        for limiter in [RateLimiter::new(None, false), RateLimiter::new(Some(0), false)] {
+            for _ in 0..100 {
+                assert!(limiter.allow(&request("192.0.2.1:1000")));
+            }
+        }
+    }
+
+    #[test]
+    fn forwarding_headers_count_only_behind_a_trusted_proxy() {
+        let forwarded = TestRequest::default()
+            .peer_addr("10.0.0.1:1000".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "203.0.113.7, 10.0.0.1"))
+            .to_http_request();
+        assert_eq!(client_ip(&forwarded, false), Some("10.0.0.1".parse().unwrap()));
+        assert_eq!(client_ip(&forwarded, true), Some("203.0.113.7".parse().unwrap()));
+
+        let forwarded = TestRequest::default()
+            .peer_addr("10.0.0.1:1000".parse().unwrap())
+            .insert_header(("Forwarded", "for=\"[2001:db8::1]:4711\""))
+            .to_http_request();
+        assert_eq!(client_ip(&forwarded, true), Some("2001:db8::1".parse().unwrap()));
+
+        // Spoofed headers do not dodge the limit without a trusted proxy.
+        let limiter = RateLimiter::new(Some(1), false);
+        for (i, spoofed) in ["198.51.100.1", "198.51.100.2"].into_iter().enumerate() {
+            let req = TestRequest::default()
+                .peer_addr("192.0.2.1:1000".parse().unwrap())
+                .insert_header(("X-Forwarded-For", spoofed))
+                .to_http_request();
+            assert_eq!(limiter.allow(&req), i == 0);
+        }
+    }
+}
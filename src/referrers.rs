@@ -0,0 +1,112 @@
+use std::sync::OnceLock;
+
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder, Result};
+use serde::Deserialize;
+use url::Url;
+
+use crate::actions::DbError;
+use crate::store::CounterStore;
+
+/// Longest referrer kept, in bytes.
+const MAX_LEN: usize = 200;
+/// Largest `?limit=`.
+const MAX_LIMIT: u32 = 100;
+
+static PATH_SEGMENTS: OnceLock<usize> = OnceLock::new();
+
+/// Read `REFERRER_PATH_SEGMENTS`; called once at startup so a bad value
+/// stops the server instead of silently trimming to the default.
+pub fn init() -> Result<(), String> {
+    let _ = PATH_SEGMENTS.set(path_segments_from_env()?);
+    Ok(())
+}
+
+fn path_segments_from_env() -> Result<usize, String> {
+    match std::env::var("REFERRER_PATH_SEGMENTS") {
+        Ok(segments) => segments.parse::<usize>()
+            .map_err(|_| format!("REFERRER_PATH_SEGMENTS should be a non-negative number, got {:?}", segments)),
+        Err(_) => Ok(2),
+    }
+}
+
+/// Leading path segments kept of a referring page (`REFERRER_PATH_SEGMENTS`,
+/// default 2, enough for `github.com/<owner>/<repo>`; 0 keeps only the host).
+pub fn path_segments() -> usize {
+    *PATH_SEGMENTS.get_or_init(|| path_segments_from_env().expect("REFERRER_PATH_SEGMENTS should be valid"))
+}
+
+/// The page the request was embedded in, from its `Referer` header, trimmed
+/// to host and leading path. `None` without a usable header; GitHub's camo
+/// proxy never sends one.
+pub fn from_request(http_req: &HttpRequest) -> Option<String> {
+    let referer = http_req.headers().get("Referer")?.to_str().ok()?;
+    normalize(referer, path_segments())
+}
+
+/// Host, without `www.`, and the first `segments` path segments of an
+/// http(s) address. Scheme, credentials, port, query and fragment are
+/// dropped so no per-visitor detail is stored.
+fn normalize(referer: &str, segments: usize) -> Option<String> {
+    let url = Url::parse(referer).ok()?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+    let host = url.host_str()?.trim_end_matches('.');
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    if host.is_empty() {
+        return None;
+    }
+    let mut referrer = host.to_string();
+    for segment in url.path_segments().into_iter().flatten().filter(|segment| !segment.is_empty()).take(segments) {
+        referrer.push('/');
+        referrer.push_str(segment);
+    }
+    // Hosts are punycode and paths percent-encoded, so this is ASCII.
+    referrer.truncate(MAX_LEN);
+    Some(referrer)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReferrersRequest {
+    limit: Option<u32>,
+    page: Option<String>,
+}
+
+/// Which pages the counted views came from, most views first, for owners
+/// who embed one badge in several places. Only views with a `Referer` are
+/// attributed, so the counts may add up to less than the total.
+#[utoipa::path(
+    tag = "counters",
+    params(
+        ("id" = String, Path, description = "Counter id"),
+        ("limit" = Option<u32>, Query, description = "Number of referrers, 20 by default, at most 100"),
+        ("page" = Option<String>, Query, description = "Page namespace"),
+    ),
+    responses(
+        (status = 200, description = "Views per referring page, most first", body = Referrers),
+        (status = 404, description = "Counter not found"),
+    ),
+)]
+#[get("/api/stats/{id}/referrers")]
+async fn get_referrers(
+    store: web::Data<dyn CounterStore>,
+    path: web::Path<String>,
+    req: web::Query<ReferrersRequest>,
+) -> Result<impl Responder> {
+    let user = path.into_inner();
+    let page = match crate::page_param(req.page.as_deref()) {
+        Ok(page) => page,
+        Err(response) => return Ok(response),
+    };
+    let limit = req.limit.unwrap_or(20).clamp(1, MAX_LIMIT);
+    let referrers = web::block(move || {
+        let display_step = store.display_step(&user)?;
+        Ok::<_, DbError>(store.referrers(&user, &page, limit)?.map(|referrers| referrers.rounded(display_step)))
+    })
+    .await?
+    .map_err(crate::store_error)?;
+    Ok(match referrers {
+        Some(referrers) => HttpResponse::Ok().json(referrers),
+        None => HttpResponse::NotFound().json(serde_json::json!({ "error": "counter not found" })),
+    })
+}
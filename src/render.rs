@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use ab_glyph::{Font, FontArc, PxScale, ScaleFont};
+use actix_web::web::Bytes;
+use resvg::{tiny_skia, usvg};
+use usvg::{TreeParsing, TreeTextToPath};
+
+use crate::hot_cache::HitStats;
+
+pub type RasterError = Box<dyn std::error::Error + Send + Sync>;
+
+pub const OG_WIDTH: u32 = 1200;
+pub const OG_HEIGHT: u32 = 630;
+
+/// Turns SVG documents into PNGs using the bundled badge font.
+pub struct Rasterizer {
+    fontdb: usvg::fontdb::Database,
+}
+
+impl Rasterizer {
+    pub fn new(font_bytes: Vec<u8>) -> Self {
+        let mut fontdb = usvg::fontdb::Database::new();
+        fontdb.load_font_data(font_bytes);
+        let family = fontdb.faces().next().map(|face| face.families[0].0.clone());
+        if let Some(family) = family {
+            fontdb.set_sans_serif_family(family.clone());
+            fontdb.set_serif_family(family);
+        }
+        Rasterizer { fontdb }
+    }
+
+    /// Rasterize `svg` at `scale` times its intrinsic size.
+    pub fn png(&self, svg: &str, scale: f32) -> Result<Vec<u8>, RasterError> {
+        let mut tree = usvg::Tree::from_str(svg, &usvg::Options::default())?;
+        tree.convert_text(&self.fontdb);
+        let size = tree.size.to_int_size();
+        let width = (size.width() as f32 * scale).ceil() as u32;
+        let height = (size.height() as f32 * scale).ceil() as u32;
+        let mut pixmap = tiny_skia::Pixmap::new(width, height)
+            .ok_or("badge has an empty canvas")?;
+        resvg::Tree::from_usvg(&tree)
+            .render(tiny_skia::Transform::from_scale(scale, scale), &mut pixmap.as_mut());
+        Ok(pixmap.encode_png()?)
+    }
+}
+
+/// Width in pixels of `text` set in `font` at `size`.
+pub fn measure(font: &FontArc, text: &str, size: f32) -> f32 {
+    let scaled = font.as_scaled(PxScale::from(size));
+    text.chars()
+        .map(|c| scaled.h_advance(scaled.glyph_id(c)))
+        .sum()
+}
+
+/// Escape text for markup assembled here, like the OG card and embed
+/// snippets. Badge text must not go through it: shield_maker escapes every
+/// text node and attribute it writes, so this would escape it twice.
+pub fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Compose the large social-card SVG: label on top, the count in the middle
+/// sized to fit the canvas, and the counter id in the footer.
+pub fn og_svg(font: &FontArc, user: &str, label: &str, message: &str) -> String {
+    let max_text_width = OG_WIDTH as f32 - 200.0;
+    let message_size = fit_font_size(font, message, 240.0, max_text_width);
+    let label_size = fit_font_size(font, label, 64.0, max_text_width);
+    format!(
+        concat!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"##,
+            r##"<rect width="{w}" height="{h}" fill="#24292f"/>"##,
+            r##"<rect y="{footer_y}" width="{w}" height="70" fill="#fe7d37"/>"##,
+            r##"<text x="{cx}" y="180" fill="#c9d1d9" font-family="sans-serif" font-size="{label_size}" text-anchor="middle">{label}</text>"##,
+            r##"<text x="{cx}" y="420" fill="#ffffff" font-family="sans-serif" font-size="{message_size}" text-anchor="middle">{message}</text>"##,
+            r##"<text x="{cx}" y="608" fill="#ffffff" font-family="sans-serif" font-size="36" text-anchor="middle">{user}</text>"##,
+            "</svg>",
+        ),
+        w = OG_WIDTH,
+        h = OG_HEIGHT,
+        cx = OG_WIDTH / 2,
+        footer_y = OG_HEIGHT - 70,
+        label_size = label_size,
+        message_size = message_size,
+        label = escape_xml(label),
+        message = escape_xml(message),
+        user = escape_xml(user),
+    )
+}
+
+fn fit_font_size(font: &FontArc, text: &str, preferred: f32, max_width: f32) -> f32 {
+    let width = measure(font, text, preferred);
+    if width <= max_width {
+        preferred
+    } else {
+        (preferred * max_width / width).floor()
+    }
+}
+
+/// Rendered social cards keyed by counter id. An entry is reused as long as
+/// the message it was rendered for has not changed.
+#[derive(Default)]
+pub struct OgImageCache {
+    entries: Mutex<HashMap<String, (String, Bytes)>>,
+    pub stats: HitStats,
+}
+
+impl OgImageCache {
+    pub fn get(&self, user: &str, message: &str) -> Option<Bytes> {
+        let entries = self.entries.lock().unwrap();
+        let png = entries.get(user)
+            .filter(|(cached_message, _)| cached_message == message)
+            .map(|(_, png)| png.clone());
+        self.stats.record(png.is_some());
+        png
+    }
+
+    pub fn store(&self, user: &str, message: &str, png: Bytes) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(user.to_string(), (message.to_string(), png));
+    }
+}
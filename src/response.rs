@@ -0,0 +1,263 @@
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::web::Bytes;
+use actix_web::{http::StatusCode, HttpRequest, HttpResponse, HttpResponseBuilder};
+use sha2::{Digest, Sha256};
+
+pub const SVG_CONTENT_TYPE: &str = "image/svg+xml; charset=utf-8";
+pub const PNG_CONTENT_TYPE: &str = "image/png";
+/// Badges differ by language and, through `?format=` negotiation, by `Accept`.
+const VARY: &str = "Accept-Language, Accept";
+/// Seconds badges may be cached unless `?cache_seconds=` says otherwise.
+pub const DEFAULT_MAX_AGE: u32 = 120;
+
+/// How a badge response may be cached downstream.
+#[derive(Debug, Clone, Copy)]
+pub enum CachePolicy {
+    /// Shared cache for `max_age` seconds, optionally served stale while we
+    /// refresh.
+    MaxAge { max_age: u32, stale_while_revalidate: Option<Duration> },
+    /// GitHub camo compatibility: never cache.
+    NoCache,
+    /// Error badges, which must never be stored anywhere.
+    NoStore,
+}
+
+impl CachePolicy {
+    /// Pick the policy for a request. `?cache=off|on` overrides the
+    /// `GITHUB_COMPAT` default, which is on unless set to `false`, and so
+    /// does asking for a lifetime with `?cache_seconds=`. A lifetime of 0
+    /// means no caching.
+    pub fn for_request(
+        cache_param: Option<&str>,
+        cache_seconds: Option<u32>,
+        stale_while_revalidate: Option<Duration>,
+    ) -> Self {
+        Self::choose(cache_param, cache_seconds, stale_while_revalidate, github_compat())
+    }
+
+    /// `for_request` with the `GITHUB_COMPAT` default given.
+    fn choose(
+        cache_param: Option<&str>,
+        cache_seconds: Option<u32>,
+        stale_while_revalidate: Option<Duration>,
+        github_compat: bool,
+    ) -> Self {
+        let compat = match cache_param {
+            Some("off") => true,
+            Some("on") => false,
+            _ => cache_seconds.is_none() && github_compat,
+        };
+        let max_age = cache_seconds.map_or(DEFAULT_MAX_AGE, clamp_cache_seconds);
+        if compat || max_age == 0 {
+            CachePolicy::NoCache
+        } else {
+            CachePolicy::MaxAge { max_age, stale_while_revalidate }
+        }
+    }
+}
+
+static CACHE_SECONDS_BOUNDS: OnceLock<(u32, u32)> = OnceLock::new();
+
+/// Read the `?cache_seconds=` bounds; called once at startup so bad values
+/// stop the server instead of silently falling back to the defaults.
+pub fn init() -> Result<(), String> {
+    let _ = CACHE_SECONDS_BOUNDS.set(cache_seconds_bounds()?);
+    Ok(())
+}
+
+/// `CACHE_SECONDS_MIN` (default 0) and `CACHE_SECONDS_MAX` (default one
+/// day).
+fn cache_seconds_bounds() -> Result<(u32, u32), String> {
+    let bound = |name: &str, default: u32| match std::env::var(name) {
+        Ok(value) => value.parse::<u32>()
+            .map_err(|_| format!("{} should be a non-negative number of seconds, got {:?}", name, value)),
+        Err(_) => Ok(default),
+    };
+    let min = bound("CACHE_SECONDS_MIN", 0)?;
+    let max = bound("CACHE_SECONDS_MAX", 24 * 60 * 60)?;
+    if max < min {
+        return Err(format!("CACHE_SECONDS_MAX ({}) should not be below CACHE_SECONDS_MIN ({})", max, min));
+    }
+    Ok((min, max))
+}
+
+/// Keep a requested lifetime within the configured bounds.
+fn clamp_cache_seconds(secs: u32) -> u32 {
+    let (min, max) = *CACHE_SECONDS_BOUNDS
+        .get_or_init(|| cache_seconds_bounds().expect("cache lifetime bounds should be valid"));
+    secs.clamp(min, max)
+}
+
+fn github_compat() -> bool {
+    std::env::var("GITHUB_COMPAT")
+        .map(|value| value != "false" && value != "0")
+        .unwrap_or(true)
+}
+
+/// Build a response for a rendered badge. Every SVG the service sends goes
+/// through here so the header set stays identical across success and error
+/// paths: exact content type, explicit Content-Length and no chunking.
+pub fn svg_response(status: StatusCode, badge_output: String, cache: CachePolicy) -> HttpResponse {
+    image_response(status, SVG_CONTENT_TYPE, Bytes::from(badge_output), cache)
+}
+
+/// `svg_response` for any image type, e.g. rasterized badges.
+pub fn image_response(status: StatusCode, content_type: &str, body: Bytes, cache: CachePolicy) -> HttpResponse {
+    let mut builder = HttpResponse::build(status);
+    builder.insert_header(("Content-Type", content_type));
+    cache_headers(&mut builder, cache);
+    builder
+        .insert_header(("Vary", VARY))
+        .no_chunking(body.len() as u64)
+        .body(body)
+}
+
+/// Weak validator for a badge: it changes whenever the count or anything
+/// in `cache_key` (counter, style, colors, label) does.
+pub fn badge_etag(cache_key: &str, view_count: i64) -> String {
+    let digest = Sha256::digest(format!("{}:{}", cache_key, view_count).as_bytes());
+    let hex: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("W/\"{}\"", hex)
+}
+
+/// Answer with `304 Not Modified` when the request's `If-None-Match` names
+/// `etag`, otherwise with the badge produced by `render`. Rendering is
+/// skipped entirely on a match.
+pub fn conditional_svg_response(
+    http_req: &HttpRequest,
+    etag: &str,
+    cache: CachePolicy,
+    render: impl FnOnce() -> String,
+) -> HttpResponse {
+    match not_modified(http_req, etag, cache) {
+        Some(response) => response,
+        None => with_etag(svg_response(StatusCode::OK, render(), cache), etag),
+    }
+}
+
+/// `304 Not Modified` when the request's `If-None-Match` names `etag`.
+pub fn not_modified(http_req: &HttpRequest, etag: &str, cache: CachePolicy) -> Option<HttpResponse> {
+    if !etag_matches(http_req, etag) {
+        return None;
+    }
+    let mut builder = HttpResponse::NotModified();
+    builder.insert_header((header::ETAG, etag_header(etag)));
+    cache_headers(&mut builder, cache);
+    Some(builder.insert_header(("Vary", VARY)).finish())
+}
+
+pub fn with_etag(mut response: HttpResponse, etag: &str) -> HttpResponse {
+    response.headers_mut().insert(header::ETAG, etag_header(etag));
+    response
+}
+
+fn etag_header(etag: &str) -> HeaderValue {
+    HeaderValue::from_str(etag).expect("badge ETag should be a valid header value")
+}
+
+/// Weak comparison, as RFC 9110 requires for `If-None-Match`.
+fn etag_matches(http_req: &HttpRequest, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    http_req.headers()
+        .get_all(header::IF_NONE_MATCH)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag))
+}
+
+fn cache_headers(builder: &mut HttpResponseBuilder, cache: CachePolicy) {
+    match cache {
+        CachePolicy::MaxAge { max_age, stale_while_revalidate: Some(stale) } => {
+            builder.insert_header((
+                "Cache-Control",
+                format!(
+                    "max-age={0}, s-maxage={0}, stale-while-revalidate={1}",
+                    max_age,
+                    stale.as_millis().div_ceil(1000)
+                ),
+            ));
+        },
+        CachePolicy::MaxAge { max_age, stale_while_revalidate: None } => {
+            builder.insert_header(("Cache-Control", format!("max-age={0}, s-maxage={0}", max_age)));
+        },
+        CachePolicy::NoCache => {
+            builder
+                .insert_header(("Cache-Control", "max-age=0, no-cache, no-store, must-revalidate"))
+                .insert_header(("Expires", "0"));
+        },
+        CachePolicy::NoStore => {
+            builder.insert_header(("Cache-Control", "no-store"));
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::body::{BodySize, MessageBody};
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    fn header<'a>(response: &'a HttpResponse, name: &str) -> Option<&'a str> {
+        response.headers().get(name).and_then(|value| value.to_str().ok())
+    }
+
+    #[test]
+    fn github_compat_emits_no_cache_headers() {
+        let policy = CachePolicy::choose(None, None, None, true);
+        let response = svg_response(StatusCode::OK, "<svg/>".to_string(), policy);
+        assert_eq!(header(&response, "Cache-Control"), Some("max-age=0, no-cache, no-store, must-revalidate"));
+        assert_eq!(header(&response, "Expires"), Some("0"));
+        assert_eq!(header(&response, "Content-Type"), Some("image/svg+xml; charset=utf-8"));
+        assert_eq!(header(&response, "Vary"), Some(VARY));
+        assert_eq!(response.body().size(), BodySize::Sized(6));
+    }
+
+    #[test]
+    fn without_github_compat_badges_are_cacheable() {
+        let policy = CachePolicy::choose(None, None, None, false);
+        let response = svg_response(StatusCode::OK, "<svg/>".to_string(), policy);
+        assert_eq!(header(&response, "Cache-Control"), Some("max-age=120, s-maxage=120"));
+        assert_eq!(header(&response, "Expires"), None);
+
+        let stale = Some(Duration::from_millis(1500));
+        let response = svg_response(StatusCode::OK, String::new(), CachePolicy::choose(None, None, stale, false));
+        assert_eq!(header(&response, "Cache-Control"), Some("max-age=120, s-maxage=120, stale-while-revalidate=2"));
+    }
+
+    #[test]
+    fn cache_param_and_seconds_override_the_default() {
+        assert!(matches!(CachePolicy::choose(Some("on"), None, None, true), CachePolicy::MaxAge { max_age: 120, .. }));
+        assert!(matches!(CachePolicy::choose(Some("off"), None, None, false), CachePolicy::NoCache));
+        assert!(matches!(CachePolicy::choose(None, Some(60), None, true), CachePolicy::MaxAge { max_age: 60, .. }));
+        assert!(matches!(CachePolicy::choose(None, Some(0), None, false), CachePolicy::NoCache));
+    }
+
+    #[test]
+    fn error_badges_are_never_stored() {
+        let response = svg_response(StatusCode::NOT_FOUND, "<svg/>".to_string(), CachePolicy::NoStore);
+        assert_eq!(header(&response, "Cache-Control"), Some("no-store"));
+        assert_eq!(header(&response, "Content-Type"), Some(SVG_CONTENT_TYPE));
+    }
+
+    #[test]
+    fn matching_etag_gets_not_modified() {
+        let etag = badge_etag("me::Total", 5);
+        let fresh = TestRequest::default().to_http_request();
+        let response = conditional_svg_response(&fresh, &etag, CachePolicy::NoCache, || "<svg/>".to_string());
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, "ETag"), Some(etag.as_str()));
+
+        let cached = TestRequest::default().insert_header(("If-None-Match", etag.as_str())).to_http_request();
+        let response = conditional_svg_response(&cached, &etag, CachePolicy::NoCache, || unreachable!());
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(header(&response, "Expires"), Some("0"));
+
+        let changed = badge_etag("me::Total", 6);
+        let response = conditional_svg_response(&cached, &changed, CachePolicy::NoCache, || "<svg/>".to_string());
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
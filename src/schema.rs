@@ -1,8 +1,153 @@
 // @generated automatically by Diesel CLI.
 
 diesel::table! {
-    visitors (id) {
-        id -> Text,
+    aliases (alias) {
+        alias -> Text,
+        target -> Text,
+        created_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    api_keys (key_hash) {
+        key_hash -> Text,
+        name -> Text,
+        scopes -> Text,
+        created_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    claims (user_id) {
+        user_id -> Text,
+        token -> Text,
+        created_at -> BigInt,
+        secret_hash -> Text,
+    }
+}
+
+diesel::table! {
+    country_views (user_id, page, country) {
+        user_id -> Text,
+        page -> Text,
+        country -> Text,
+        view_count -> BigInt,
+    }
+}
+
+diesel::table! {
+    daily_views (user_id, page, day) {
+        user_id -> Text,
+        page -> Text,
+        day -> Text,
         view_count -> Integer,
     }
 }
+
+diesel::table! {
+    milestone_hooks (user_id) {
+        user_id -> Text,
+        url -> Text,
+        thresholds -> Text,
+        created_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    milestones (user_id, page, threshold) {
+        user_id -> Text,
+        page -> Text,
+        threshold -> BigInt,
+        reached_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    owner_keys (key_hash) {
+        key_hash -> Text,
+        user_id -> Text,
+        created_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    recent_views (user_id, page, fingerprint) {
+        user_id -> Text,
+        page -> Text,
+        fingerprint -> Text,
+        last_seen -> BigInt,
+    }
+}
+
+diesel::table! {
+    referrers (user_id, page, referrer) {
+        user_id -> Text,
+        page -> Text,
+        referrer -> Text,
+        view_count -> BigInt,
+    }
+}
+
+diesel::table! {
+    skip_tokens (token_hash) {
+        token_hash -> Text,
+        user_id -> Text,
+        created_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    unique_sketches (user_id, page) {
+        user_id -> Text,
+        page -> Text,
+        period -> BigInt,
+        registers -> Binary,
+        estimate -> BigInt,
+    }
+}
+
+diesel::table! {
+    unique_views (user_id, page, fingerprint) {
+        user_id -> Text,
+        page -> Text,
+        fingerprint -> Text,
+        last_seen -> BigInt,
+    }
+}
+
+diesel::table! {
+    visitor_settings (user_id) {
+        user_id -> Text,
+        timezone -> Text,
+        leaderboard_hidden -> Bool,
+        display_step -> BigInt,
+    }
+}
+
+diesel::table! {
+    visitors (id, page) {
+        id -> Text,
+        page -> Text,
+        view_count -> BigInt,
+        last_viewed_at -> Nullable<BigInt>,
+        unique_count -> BigInt,
+    }
+}
+
+diesel::allow_tables_to_appear_in_same_query!(
+    aliases,
+    api_keys,
+    claims,
+    country_views,
+    daily_views,
+    milestone_hooks,
+    milestones,
+    owner_keys,
+    recent_views,
+    referrers,
+    skip_tokens,
+    unique_sketches,
+    unique_views,
+    visitor_settings,
+    visitors,
+);
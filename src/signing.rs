@@ -0,0 +1,79 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Hex characters of the HMAC kept in URLs.
+const SIG_HEX_LEN: usize = 32;
+
+/// `BADGE_SIGNING_SECRET`; when set, badge URLs must carry `?sig=` so that
+/// counters cannot be inflated through guessed URLs. Signed URLs come from
+/// `/embed/{id}` called with admin credentials or the counter's owner key.
+pub fn secret() -> Option<String> {
+    std::env::var("BADGE_SIGNING_SECRET").ok().filter(|secret| !secret.is_empty())
+}
+
+/// Signature for the badge of `user`'s counter on `page`.
+pub fn sign(secret: &str, user: &str, page: &str) -> String {
+    let digest = mac(secret, user, page).finalize().into_bytes();
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    hex[..SIG_HEX_LEN].to_string()
+}
+
+/// Check `sig` in constant time.
+pub fn verify(secret: &str, user: &str, page: &str, sig: &str) -> bool {
+    if sig.len() != SIG_HEX_LEN || !sig.is_ascii() {
+        return false;
+    }
+    let bytes: Option<Vec<u8>> = (0..sig.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&sig[i..i + 2], 16).ok())
+        .collect();
+    match bytes {
+        Some(bytes) => mac(secret, user, page).verify_truncated_left(&bytes).is_ok(),
+        None => false,
+    }
+}
+
+/// Whether a presented token equals the configured one, compared in
+/// constant time through their HMACs so neither content nor length leaks
+/// through response timing.
+pub fn tokens_match(expected: &str, provided: &str) -> bool {
+    let digest = |token: &str| {
+        let mut mac = HmacSha256::new_from_slice(b"token-comparison").expect("HMAC accepts keys of any length");
+        mac.update(token.as_bytes());
+        mac
+    };
+    let expected = digest(expected).finalize().into_bytes();
+    digest(provided).verify_slice(&expected).is_ok()
+}
+
+fn mac(secret: &str, user: &str, page: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(user.as_bytes());
+    mac.update(b"\n");
+    mac.update(page.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures_verify_only_for_their_counter() {
+        let sig = sign("secret", "octocat", "default");
+        assert!(verify("secret", "octocat", "default", &sig));
+        assert!(!verify("secret", "octocat", "blog", &sig));
+        assert!(!verify("other", "octocat", "default", &sig));
+        assert!(!verify("secret", "octocat", "default", "zz"));
+    }
+
+    #[test]
+    fn tokens_match_compares_whole_tokens() {
+        assert!(tokens_match("admin-token", "admin-token"));
+        assert!(!tokens_match("admin-token", "admin-toke"));
+        assert!(!tokens_match("admin-token", "admin-tokem"));
+        assert!(!tokens_match("admin-token", ""));
+    }
+}
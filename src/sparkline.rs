@@ -0,0 +1,49 @@
+/// Default size of the sparkline, chosen to sit next to a 20px badge.
+pub const WIDTH: u32 = 100;
+pub const HEIGHT: u32 = 20;
+const PADDING: f64 = 2.0;
+const STROKE: &str = "#fe7d37";
+
+/// Tiny line chart of `counts`, oldest first, scaled so the busiest day
+/// touches the top edge. An empty or all-zero series draws a flat line.
+pub fn sparkline_svg(counts: &[i64], width: u32, height: u32) -> String {
+    let max = counts.iter().copied().max().unwrap_or(0).max(1) as f64;
+    let plot_width = f64::from(width) - 2.0 * PADDING;
+    let plot_height = f64::from(height) - 2.0 * PADDING;
+    let point = |i: usize, count: i64| {
+        let x = if counts.len() > 1 {
+            PADDING + plot_width * i as f64 / (counts.len() - 1) as f64
+        } else {
+            PADDING + plot_width
+        };
+        let y = PADDING + plot_height * (1.0 - count.max(0) as f64 / max);
+        (x, y)
+    };
+
+    let mut points: Vec<(f64, f64)> = counts.iter().enumerate()
+        .map(|(i, count)| point(i, *count))
+        .collect();
+    if points.len() < 2 {
+        let y = points.first().map_or(PADDING + plot_height, |(_, y)| *y);
+        points = vec![(PADDING, y), (PADDING + plot_width, y)];
+    }
+    let (last_x, last_y) = points[points.len() - 1];
+    let polyline: Vec<String> = points.iter()
+        .map(|(x, y)| format!("{:.1},{:.1}", x, y))
+        .collect();
+
+    format!(
+        concat!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"##,
+            r##"<polyline points="{points}" fill="none" stroke="{stroke}" stroke-width="1.5" stroke-linejoin="round" stroke-linecap="round"/>"##,
+            r##"<circle cx="{last_x:.1}" cy="{last_y:.1}" r="1.5" fill="{stroke}"/>"##,
+            "</svg>",
+        ),
+        w = width,
+        h = height,
+        points = polyline.join(" "),
+        stroke = STROKE,
+        last_x = last_x,
+        last_y = last_y,
+    )
+}
@@ -0,0 +1,164 @@
+use ab_glyph::FontArc;
+use actix_web::{get, http::StatusCode, web, Responder};
+use serde::Deserialize;
+
+use crate::badge::{self, BadgeSpec, BadgeStyle};
+use crate::response::{svg_response, CachePolicy, DEFAULT_MAX_AGE};
+
+#[derive(Debug, Deserialize)]
+pub struct StaticRequest {
+    style: Option<String>,
+    label_color: Option<String>,
+}
+
+/// Label of `/static/{message}-{color}.svg` badges, since shield_maker
+/// cannot draw a badge without one.
+const DEFAULT_LABEL: &str = "badge";
+
+/// Badge with a fixed label, message and color, shields.io style:
+/// `/static/{label}-{message}-{color}.svg` or `/static/{message}-{color}.svg`.
+/// In each part `--` stands for `-`, `__` for `_` and `_` for a space.
+/// Never touches the counters.
+#[get("/static/{spec}.svg")]
+async fn get_static_badge(
+    font: web::Data<FontArc>,
+    path: web::Path<String>,
+    req: web::Query<StaticRequest>,
+) -> impl Responder {
+    match static_spec(&path.into_inner(), &req) {
+        Ok(spec) => svg_response(
+            StatusCode::OK,
+            crate::render_spec(font.get_ref(), &spec),
+            CachePolicy::MaxAge { max_age: DEFAULT_MAX_AGE, stale_while_revalidate: None },
+        ),
+        Err(message) => crate::render_error_badge(&font, StatusCode::BAD_REQUEST, message),
+    }
+}
+
+fn static_spec(path: &str, req: &StaticRequest) -> Result<BadgeSpec, &'static str> {
+    let parts = split_parts(path);
+    let (label, message, color) = match parts.as_slice() {
+        [label, message, color] => (label.as_str(), message.as_str(), color.as_str()),
+        [message, color] => (DEFAULT_LABEL, message.as_str(), color.as_str()),
+        _ => return Err("expected label-message-color"),
+    };
+    // shield_maker panics on text without a single glyph to measure.
+    let blank = |text: &str| text.chars().all(|c| c.is_whitespace() || c.is_control());
+    if blank(label) {
+        return Err("empty label");
+    }
+    if blank(message) {
+        return Err("empty message");
+    }
+    if label.chars().chain(message.chars()).any(char::is_control) {
+        return Err("control characters");
+    }
+    let too_long = |text: &str| text.chars().count() > badge::MAX_LABEL_CHARS;
+    if too_long(label) || too_long(message) {
+        return Err("text too long");
+    }
+    if !badge::is_valid_color(color) {
+        return Err("invalid color");
+    }
+    if req.label_color.as_deref().is_some_and(|color| !badge::is_valid_color(color)) {
+        return Err("invalid label_color");
+    }
+    if let Some(Err(_)) = req.style.as_deref().map(str::parse::<BadgeStyle>) {
+        return Err("invalid style");
+    }
+
+    let mut spec = BadgeSpec::new(crate::sanitize_label(label), crate::sanitize_label(message));
+    spec.color = Some(color.to_string());
+    spec.label_color = req.label_color.clone();
+    spec.style = req.style.clone();
+    Ok(spec)
+}
+
+/// Split on single dashes, then unescape `--`, `__` and `_` in each part.
+fn split_parts(path: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = path.chars().peekable();
+    while let Some(c) = chars.next() {
+        let part = parts.last_mut().expect("parts should never be empty");
+        match c {
+            '-' if chars.peek() == Some(&'-') => {
+                chars.next();
+                part.push('-');
+            },
+            '-' => parts.push(String::new()),
+            '_' if chars.peek() == Some(&'_') => {
+                chars.next();
+                part.push('_');
+            },
+            '_' => part.push(' '),
+            c => part.push(c),
+        }
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::App;
+
+    use super::*;
+
+    fn request() -> StaticRequest {
+        StaticRequest { style: None, label_color: None }
+    }
+
+    #[test]
+    fn three_parts_are_label_message_and_color() {
+        let spec = static_spec("build-passing-green", &request()).unwrap();
+        assert_eq!((spec.label.as_str(), spec.message.as_str()), ("build", "passing"));
+        assert_eq!(spec.color.as_deref(), Some("green"));
+    }
+
+    #[test]
+    fn two_parts_get_the_default_label() {
+        let spec = static_spec("ok-green", &request()).unwrap();
+        assert_eq!((spec.label.as_str(), spec.message.as_str()), (DEFAULT_LABEL, "ok"));
+        assert_eq!(spec.color.as_deref(), Some("green"));
+    }
+
+    #[test]
+    fn doubled_dashes_and_underscores_are_literal() {
+        let spec = static_spec("a--b__c_d-e_f--g-blue", &request()).unwrap();
+        assert_eq!((spec.label.as_str(), spec.message.as_str()), ("a-b_c d", "e f-g"));
+        assert_eq!(split_parts("--__-_"), vec!["-_", " "]);
+    }
+
+    #[test]
+    fn empty_parts_are_rejected() {
+        for (path, err) in [
+            ("-ok-green", "empty label"),
+            ("build-_-green", "empty message"),
+            ("_-green", "empty message"),
+            ("build-\n-green", "empty message"),
+            ("build-a\tb-green", "control characters"),
+            ("green", "expected label-message-color"),
+            ("a-b-c-d", "expected label-message-color"),
+            ("build-ok-notacolor", "invalid color"),
+        ] {
+            assert_eq!(static_spec(path, &request()).unwrap_err(), err, "{}", path);
+        }
+    }
+
+    #[actix_web::test]
+    async fn every_form_renders() {
+        let font = FontArc::try_from_vec(std::fs::read("src/fonts/DejaVuSans.ttf").unwrap()).unwrap();
+        let app = init_service(App::new().app_data(web::Data::new(font)).service(get_static_badge)).await;
+        for (uri, status, text) in [
+            ("/static/ok-green.svg", StatusCode::OK, ">ok</text>"),
+            ("/static/build-passing-green.svg", StatusCode::OK, ">passing</text>"),
+            ("/static/-ok-green.svg", StatusCode::BAD_REQUEST, ">empty label</text>"),
+            ("/static/build-_-green.svg", StatusCode::BAD_REQUEST, ">empty message</text>"),
+        ] {
+            let response = call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(response.status(), status, "{}", uri);
+            let body = read_body(response).await;
+            assert!(std::str::from_utf8(&body).unwrap().contains(text), "{}", uri);
+        }
+    }
+}
@@ -0,0 +1,1331 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{NaiveDate, Utc};
+use diesel::prelude::*;
+use diesel::result::DatabaseErrorKind;
+
+use crate::actions::{self, DbConnection, DbError};
+use crate::daily::{self, Granularity};
+use crate::dedup;
+use crate::models::{
+    Aggregate, Countries, DailyCount, DayCount, History, PeriodCount, Referrers, Series, SeriesPoint, Visitors,
+};
+use crate::DbPool;
+
+/// Namespace of counters that were created without a page.
+pub const DEFAULT_PAGE: &str = "";
+
+/// Error for a feature the storage backend does not have, answered with
+/// 400 instead of 500 by `crate::store_error`.
+#[derive(Debug)]
+pub struct Unsupported(pub &'static str);
+
+impl std::fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} are not supported by this storage backend", self.0)
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+/// Error from `CounterStore::create` when the counter already exists.
+#[derive(Debug)]
+pub struct AlreadyExists;
+
+impl std::fmt::Display for AlreadyExists {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("counter already exists")
+    }
+}
+
+impl std::error::Error for AlreadyExists {}
+
+/// Storage for view counters. Handlers only talk to this trait so the
+/// backend can be swapped through `STORAGE_BACKEND` without route changes.
+///
+/// A counter is addressed by its user and a page namespace; `DEFAULT_PAGE`
+/// is the namespace counters had before pages existed.
+pub trait CounterStore: Send + Sync {
+    fn get(&self, user: &str, page: &str) -> Result<Option<Visitors>, DbError>;
+    /// Add `delta` views and return the updated counter, or `None` when the
+    /// counter does not exist.
+    fn increment_and_get(&self, user: &str, page: &str, delta: i32) -> Result<Option<Visitors>, DbError>;
+    /// Add `delta` views, creating the counter if needed, and return it.
+    fn upsert_and_get(&self, user: &str, page: &str, delta: i32) -> Result<Visitors, DbError>;
+    /// Create the counter at 0 views, failing with `AlreadyExists` when it
+    /// exists. Callers rely on this rather than checking first, so two
+    /// concurrent creates cannot both succeed.
+    fn create(&self, user: &str, page: &str) -> Result<Visitors, DbError>;
+    /// Overwrite the counter with `view_count`, creating it if needed.
+    fn set(&self, user: &str, page: &str, view_count: i64) -> Result<Visitors, DbError>;
+    fn delete(&self, user: &str, page: &str) -> Result<bool, DbError>;
+    fn iter(&self) -> Result<Vec<Visitors>, DbError>;
+    /// Views summed over all of the user's pages, or `None` when the user has
+    /// no counters.
+    fn user_total(&self, user: &str) -> Result<Option<i64>, DbError> {
+        let counts: Vec<i64> = self.iter()?
+            .into_iter()
+            .filter(|visitor| visitor.id == user)
+            .map(|visitor| visitor.view_count)
+            .collect();
+        Ok((!counts.is_empty()).then(|| counts.into_iter().fold(0, i64::saturating_add)))
+    }
+    /// Up to `limit` of the user's counters in page order, starting after
+    /// page `after` if given.
+    fn pages(&self, user: &str, after: Option<&str>, limit: usize) -> Result<Vec<Visitors>, DbError> {
+        let mut pages: Vec<Visitors> = self.iter()?
+            .into_iter()
+            .filter(|visitor| visitor.id == user && after.is_none_or(|after| visitor.page.as_str() > after))
+            .collect();
+        pages.sort_by(|a, b| a.page.cmp(&b.page));
+        pages.truncate(limit);
+        Ok(pages)
+    }
+    /// Views on the current day in the user's time zone, or `None` when the
+    /// counter does not exist.
+    fn today(&self, _user: &str, _page: &str) -> Result<Option<DailyCount>, DbError> {
+        Err(Box::new(Unsupported("daily counts")))
+    }
+    /// Views per day for the last `days` days in the user's time zone, today
+    /// included, or `None` when the counter does not exist.
+    fn history(&self, _user: &str, _page: &str, _days: u32) -> Result<Option<History>, DbError> {
+        Err(Box::new(Unsupported("daily counts")))
+    }
+    /// Views summed per `granularity` over the last `periods` buckets, or
+    /// `None` when the counter does not exist.
+    fn aggregate(
+        &self,
+        _user: &str,
+        _page: &str,
+        _granularity: Granularity,
+        _periods: u32,
+    ) -> Result<Option<Aggregate>, DbError> {
+        Err(Box::new(Unsupported("daily counts")))
+    }
+    /// Views per local day from `from` through `to`, stopping at today, or
+    /// `None` when the counter does not exist.
+    fn series(&self, _user: &str, _page: &str, _from: NaiveDate, _to: NaiveDate) -> Result<Option<Series>, DbError> {
+        Err(Box::new(Unsupported("daily counts")))
+    }
+    /// Record a view by `fingerprint` at `now` (unix seconds). Returns false
+    /// when the same fingerprint was seen less than `window_secs` ago, in
+    /// which case the view should not be counted.
+    fn mark_seen(&self, user: &str, page: &str, fingerprint: &str, now: i64, window_secs: i64) -> Result<bool, DbError>;
+    /// Record a view of an existing counter by `fingerprint` at `now` and,
+    /// unless the same fingerprint was recorded within `window_secs`, raise
+    /// its `unique_count`. Returns how much it was raised: 0 or 1, or for
+    /// sketched counters however much the estimate grew. Backends without
+    /// unique counting never raise it.
+    fn record_unique(
+        &self,
+        _user: &str,
+        _page: &str,
+        _fingerprint: &str,
+        _now: i64,
+        _window_secs: i64,
+    ) -> Result<i64, DbError> {
+        Ok(0)
+    }
+    /// Count a view of an existing counter as coming from `referrer`, a
+    /// trimmed page address from `referrer::from_request`. Backends without
+    /// referrer attribution ignore it.
+    fn record_referrer(&self, _user: &str, _page: &str, _referrer: &str) -> Result<(), DbError> {
+        Ok(())
+    }
+    /// The `limit` referrers with the most views, or `None` when the counter
+    /// does not exist.
+    fn referrers(&self, _user: &str, _page: &str, _limit: u32) -> Result<Option<Referrers>, DbError> {
+        Err(Box::new(Unsupported("referrer counts")))
+    }
+    /// Count a view of an existing counter as coming from `country`, an ISO
+    /// 3166-1 alpha-2 code. Backends without country counts ignore it.
+    fn record_country(&self, _user: &str, _page: &str, _country: &str) -> Result<(), DbError> {
+        Ok(())
+    }
+    /// Views per visitor country, or `None` when the counter does not exist.
+    fn countries(&self, _user: &str, _page: &str) -> Result<Option<Countries>, DbError> {
+        Err(Box::new(Unsupported("country counts")))
+    }
+    /// Multiple the user's public counts are rounded to, 1 when they are
+    /// shown exactly. Backends without settings always show them exactly.
+    fn display_step(&self, _user: &str) -> Result<i64, DbError> {
+        Ok(1)
+    }
+    /// Counter id that `user` is an alias of, or `None` when it is not an
+    /// alias. Backends without aliases never have any.
+    fn alias_target(&self, _user: &str) -> Result<Option<String>, DbError> {
+        Ok(None)
+    }
+    /// Persist buffered writes, for backends that batch them.
+    fn flush(&self) -> Result<(), DbError> {
+        Ok(())
+    }
+}
+
+/// Default backend: the Diesel `visitors` table, in SQLite or, with the
+/// `postgres` feature, PostgreSQL.
+pub struct SqlStore {
+    pool: DbPool,
+}
+
+impl SqlStore {
+    pub fn new(pool: DbPool) -> Self {
+        SqlStore { pool }
+    }
+}
+
+impl CounterStore for SqlStore {
+    fn get(&self, user: &str, page: &str) -> Result<Option<Visitors>, DbError> {
+        let mut conn = self.pool.get()?;
+        actions::get_user_viewcount(&mut conn, user, page)
+    }
+
+    fn increment_and_get(&self, user: &str, page: &str, delta: i32) -> Result<Option<Visitors>, DbError> {
+        let mut conn = self.pool.get()?;
+        conn.transaction(|conn| {
+            if actions::add_user_viewcount(conn, user, page, delta, Utc::now().timestamp())? == 0 {
+                return Ok(None);
+            }
+            record_daily(conn, user, page, delta)?;
+            actions::get_user_viewcount(conn, user, page)
+        })
+    }
+
+    fn upsert_and_get(&self, user: &str, page: &str, delta: i32) -> Result<Visitors, DbError> {
+        let mut conn = self.pool.get()?;
+        conn.transaction(|conn| {
+            let visitor = actions::upsert_and_get_user_viewcount(conn, user, page, delta, Utc::now().timestamp())?;
+            record_daily(conn, user, page, delta)?;
+            Ok(visitor)
+        })
+    }
+
+    fn create(&self, user: &str, page: &str) -> Result<Visitors, DbError> {
+        let mut conn = self.pool.get()?;
+        actions::create_user(&mut conn, user, page).map_err(|err| -> DbError {
+            let conflict = matches!(
+                err.downcast_ref::<diesel::result::Error>(),
+                Some(diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)),
+            );
+            if conflict { Box::new(AlreadyExists) } else { err }
+        })
+    }
+
+    fn set(&self, user: &str, page: &str, view_count: i64) -> Result<Visitors, DbError> {
+        let mut conn = self.pool.get()?;
+        actions::set_user_viewcount(&mut conn, user, page, view_count)
+    }
+
+    fn delete(&self, user: &str, page: &str) -> Result<bool, DbError> {
+        let mut conn = self.pool.get()?;
+        actions::delete_user(&mut conn, user, page)
+    }
+
+    fn iter(&self) -> Result<Vec<Visitors>, DbError> {
+        let mut conn = self.pool.get()?;
+        actions::list_users(&mut conn)
+    }
+
+    fn user_total(&self, user: &str) -> Result<Option<i64>, DbError> {
+        let mut conn = self.pool.get()?;
+        actions::get_user_total(&mut conn, user)
+    }
+
+    fn pages(&self, user: &str, after: Option<&str>, limit: usize) -> Result<Vec<Visitors>, DbError> {
+        let mut conn = self.pool.get()?;
+        actions::list_pages(&mut conn, user, after, i64::try_from(limit).unwrap_or(i64::MAX))
+    }
+
+    fn mark_seen(&self, user: &str, page: &str, fingerprint: &str, now: i64, window_secs: i64) -> Result<bool, DbError> {
+        let mut conn = self.pool.get()?;
+        actions::mark_seen_if_not_recent(&mut conn, user, page, fingerprint, now, window_secs)
+    }
+
+    fn record_unique(&self, user: &str, page: &str, fingerprint: &str, now: i64, window_secs: i64) -> Result<i64, DbError> {
+        let mut conn = self.pool.get()?;
+        // Popular counters trade exact counts for constant storage; their
+        // windows are aligned to multiples of `window_secs`.
+        let sketched = actions::get_user_viewcount(&mut conn, user, page)?
+            .is_some_and(|visitor| dedup::is_sketched(visitor.unique_count));
+        if sketched {
+            let period = now.div_euclid(window_secs.max(1));
+            return actions::record_sketched_visit(&mut conn, user, page, fingerprint, period);
+        }
+        Ok(i64::from(actions::record_unique_visit(&mut conn, user, page, fingerprint, now, window_secs)?))
+    }
+
+    fn record_referrer(&self, user: &str, page: &str, referrer: &str) -> Result<(), DbError> {
+        let mut conn = self.pool.get()?;
+        actions::add_referrer_viewcount(&mut conn, user, page, referrer)
+    }
+
+    fn referrers(&self, user: &str, page: &str, limit: u32) -> Result<Option<Referrers>, DbError> {
+        let mut conn = self.pool.get()?;
+        if actions::get_user_viewcount(&mut conn, user, page)?.is_none() {
+            return Ok(None);
+        }
+        let referrers = actions::top_referrers(&mut conn, user, page, i64::from(limit))?;
+        Ok(Some(Referrers { user_id: user.to_string(), page: page.to_string(), referrers }))
+    }
+
+    fn record_country(&self, user: &str, page: &str, country: &str) -> Result<(), DbError> {
+        let mut conn = self.pool.get()?;
+        actions::add_country_viewcount(&mut conn, user, page, country)
+    }
+
+    fn countries(&self, user: &str, page: &str) -> Result<Option<Countries>, DbError> {
+        let mut conn = self.pool.get()?;
+        if actions::get_user_viewcount(&mut conn, user, page)?.is_none() {
+            return Ok(None);
+        }
+        let countries = actions::get_country_views(&mut conn, user, page)?;
+        Ok(Some(Countries { user_id: user.to_string(), page: page.to_string(), countries }))
+    }
+
+    fn display_step(&self, user: &str) -> Result<i64, DbError> {
+        let mut conn = self.pool.get()?;
+        actions::get_display_step(&mut conn, user)
+    }
+
+    fn alias_target(&self, user: &str) -> Result<Option<String>, DbError> {
+        let mut conn = self.pool.get()?;
+        Ok(actions::find_alias(&mut conn, user)?.map(|alias| alias.target))
+    }
+
+    fn today(&self, user: &str, page: &str) -> Result<Option<DailyCount>, DbError> {
+        let mut conn = self.pool.get()?;
+        if actions::get_user_viewcount(&mut conn, user, page)?.is_none() {
+            return Ok(None);
+        }
+        let timezone = actions::get_user_timezone(&mut conn, user)?;
+        let day = daily::local_day(daily::parse_timezone(&timezone)?, Utc::now()).to_string();
+        let view_count = i64::from(actions::get_daily_viewcount(&mut conn, user, page, &day)?);
+        Ok(Some(DailyCount { user_id: user.to_string(), page: page.to_string(), day, timezone, view_count }))
+    }
+
+    fn history(&self, user: &str, page: &str, days: u32) -> Result<Option<History>, DbError> {
+        let mut conn = self.pool.get()?;
+        if actions::get_user_viewcount(&mut conn, user, page)?.is_none() {
+            return Ok(None);
+        }
+        let timezone = actions::get_user_timezone(&mut conn, user)?;
+        let today = daily::local_day(daily::parse_timezone(&timezone)?, Utc::now());
+        let first_day = today - chrono::Duration::days(i64::from(days.max(1)) - 1);
+        let rows: HashMap<String, i32> = actions::get_daily_history(&mut conn, user, page, &first_day.to_string())?
+            .into_iter()
+            .collect();
+        let days = first_day.iter_days()
+            .take_while(|day| *day <= today)
+            .map(|day| {
+                let day = day.to_string();
+                let view_count = rows.get(&day).copied().map_or(0, i64::from);
+                DayCount { day, view_count }
+            })
+            .collect();
+        Ok(Some(History { user_id: user.to_string(), page: page.to_string(), timezone, days }))
+    }
+
+    fn aggregate(
+        &self,
+        user: &str,
+        page: &str,
+        granularity: Granularity,
+        periods: u32,
+    ) -> Result<Option<Aggregate>, DbError> {
+        let mut conn = self.pool.get()?;
+        if actions::get_user_viewcount(&mut conn, user, page)?.is_none() {
+            return Ok(None);
+        }
+        let timezone = actions::get_user_timezone(&mut conn, user)?;
+        let today = daily::local_day(daily::parse_timezone(&timezone)?, Utc::now());
+        let first = granularity.first_bucket(today, periods);
+        let rows: HashMap<String, i64> =
+            actions::get_period_history(&mut conn, user, page, &first.to_string(), granularity)?
+                .into_iter()
+                .collect();
+        let periods = std::iter::successors(Some(first), |start| Some(granularity.next(*start)))
+            .take_while(|start| *start <= today)
+            .map(|start| {
+                let start = start.to_string();
+                let view_count = rows.get(&start).copied().unwrap_or(0);
+                PeriodCount { start, view_count }
+            })
+            .collect();
+        Ok(Some(Aggregate { user_id: user.to_string(), page: page.to_string(), timezone, granularity, periods }))
+    }
+
+    fn series(&self, user: &str, page: &str, from: NaiveDate, to: NaiveDate) -> Result<Option<Series>, DbError> {
+        let mut conn = self.pool.get()?;
+        if actions::get_user_viewcount(&mut conn, user, page)?.is_none() {
+            return Ok(None);
+        }
+        let timezone = actions::get_user_timezone(&mut conn, user)?;
+        let tz = daily::parse_timezone(&timezone)?;
+        let last = to.min(daily::local_day(tz, Utc::now()));
+        let rows: HashMap<String, i32> = actions::get_daily_history(&mut conn, user, page, &from.to_string())?
+            .into_iter()
+            .collect();
+        let points = from.iter_days()
+            .take_while(|day| *day <= last)
+            .map(|day| {
+                let timestamp = daily::day_start(tz, day);
+                let day = day.to_string();
+                let view_count = rows.get(&day).copied().map_or(0, i64::from);
+                SeriesPoint { timestamp, day, view_count }
+            })
+            .collect();
+        Ok(Some(Series { user_id: user.to_string(), page: page.to_string(), timezone, points }))
+    }
+}
+
+/// Count `delta` views on today's row in the user's time zone.
+fn record_daily(conn: &mut DbConnection, user: &str, page: &str, delta: i32) -> Result<(), DbError> {
+    let tz = daily::parse_timezone(&actions::get_user_timezone(conn, user)?)?;
+    let day = daily::local_day(tz, Utc::now()).to_string();
+    actions::add_daily_viewcount(conn, user, page, &day, delta)
+}
+
+/// Flat JSON file backend for tiny deployments. Counters live in memory and
+/// are written back with an atomic tempfile + rename once enough writes or
+/// time have accumulated. Counters outside the default page are keyed
+/// `user/page`, so files written before pages existed still load; see
+/// `file_key` for how `/` in ids is escaped.
+pub struct FileStore {
+    path: PathBuf,
+    flush_every: usize,
+    flush_interval: Duration,
+    state: Mutex<FileState>,
+    /// Dedup fingerprints are kept in memory only.
+    recent: Mutex<RecentViews>,
+}
+
+/// Seconds between sweeps of expired dedup fingerprints.
+const RECENT_PRUNE_SECS: i64 = 60;
+
+#[derive(Default)]
+struct RecentViews {
+    /// Last counted view per counter and fingerprint.
+    seen: HashMap<(String, String), i64>,
+    pruned_at: i64,
+}
+
+struct FileState {
+    counters: BTreeMap<String, i64>,
+    dirty: usize,
+    last_flush: Instant,
+}
+
+impl FileStore {
+    /// Open the counter file at `path`, writing it back after
+    /// `FILE_FLUSH_EVERY` writes (default 50) or `FILE_FLUSH_INTERVAL_MS`
+    /// (default 5000), whichever comes first.
+    pub fn from_env(path: impl Into<PathBuf>) -> Result<Self, String> {
+        let path = path.into();
+        let flush_every = env_number("FILE_FLUSH_EVERY", 50)?;
+        let flush_interval = Duration::from_millis(env_number("FILE_FLUSH_INTERVAL_MS", 5000)?);
+        let store = FileStore::open(&path)
+            .map_err(|err| format!("could not open counter file {} (FILE_STORE_PATH): {}", path.display(), err))?;
+        Ok(FileStore { flush_every, flush_interval, ..store })
+    }
+
+    /// Open the counter file at `path`, starting empty if it does not exist,
+    /// with the default flush settings.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, DbError> {
+        let path = path.into();
+        let counters = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(FileStore {
+            path,
+            flush_every: 50,
+            flush_interval: Duration::from_secs(5),
+            state: Mutex::new(FileState {
+                counters,
+                dirty: 0,
+                last_flush: Instant::now(),
+            }),
+            recent: Mutex::new(RecentViews::default()),
+        })
+    }
+
+    pub fn flush_interval(&self) -> Duration {
+        self.flush_interval
+    }
+
+    fn mark_dirty(&self, state: &mut FileState) -> Result<(), DbError> {
+        state.dirty += 1;
+        if state.dirty >= self.flush_every || state.last_flush.elapsed() >= self.flush_interval {
+            self.write(state)?;
+        }
+        Ok(())
+    }
+
+    fn write(&self, state: &mut FileState) -> Result<(), DbError> {
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_vec(&state.counters)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+        state.dirty = 0;
+        state.last_flush = Instant::now();
+        Ok(())
+    }
+}
+
+/// `name` parsed as a non-negative number, or `default` when unset.
+fn env_number<T: std::str::FromStr>(name: &str, default: T) -> Result<T, String> {
+    match std::env::var(name) {
+        Ok(value) => value.parse().map_err(|_| format!("{} should be a non-negative number, got {:?}", name, value)),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Key of a counter in the JSON file. `%` and `/` are escaped in both parts,
+/// so an id containing `/` cannot be read back as another user's page.
+fn file_key(user: &str, page: &str) -> String {
+    if page == DEFAULT_PAGE {
+        escape_key_part(user)
+    } else {
+        format!("{}/{}", escape_key_part(user), escape_key_part(page))
+    }
+}
+
+fn escape_key_part(part: &str) -> String {
+    part.replace('%', "%25").replace('/', "%2F")
+}
+
+fn unescape_key_part(part: &str) -> String {
+    part.replace("%2F", "/").replace("%25", "%")
+}
+
+fn file_visitor(key: &str, view_count: i64) -> Visitors {
+    let (id, page) = key.split_once('/').unwrap_or((key, DEFAULT_PAGE));
+    // The file only keeps counts, so there is no last view time.
+    Visitors {
+        id: unescape_key_part(id),
+        page: unescape_key_part(page),
+        view_count,
+        last_viewed_at: None,
+        unique_count: 0,
+    }
+}
+
+impl CounterStore for FileStore {
+    fn get(&self, user: &str, page: &str) -> Result<Option<Visitors>, DbError> {
+        let state = self.state.lock().unwrap();
+        let key = file_key(user, page);
+        Ok(state.counters.get(&key).map(|count| file_visitor(&key, *count)))
+    }
+
+    fn increment_and_get(&self, user: &str, page: &str, delta: i32) -> Result<Option<Visitors>, DbError> {
+        let mut state = self.state.lock().unwrap();
+        let key = file_key(user, page);
+        let view_count = match state.counters.get_mut(&key) {
+            Some(count) => {
+                *count = count.saturating_add(i64::from(delta));
+                *count
+            },
+            None => return Ok(None),
+        };
+        self.mark_dirty(&mut state)?;
+        Ok(Some(file_visitor(&key, view_count)))
+    }
+
+    fn upsert_and_get(&self, user: &str, page: &str, delta: i32) -> Result<Visitors, DbError> {
+        let mut state = self.state.lock().unwrap();
+        let key = file_key(user, page);
+        let count = state.counters.entry(key.clone()).or_insert(0);
+        *count = count.saturating_add(i64::from(delta));
+        let view_count = *count;
+        self.mark_dirty(&mut state)?;
+        Ok(file_visitor(&key, view_count))
+    }
+
+    fn create(&self, user: &str, page: &str) -> Result<Visitors, DbError> {
+        let mut state = self.state.lock().unwrap();
+        let key = file_key(user, page);
+        if state.counters.contains_key(&key) {
+            return Err(Box::new(AlreadyExists));
+        }
+        state.counters.insert(key.clone(), 0);
+        self.mark_dirty(&mut state)?;
+        Ok(file_visitor(&key, 0))
+    }
+
+    fn set(&self, user: &str, page: &str, view_count: i64) -> Result<Visitors, DbError> {
+        let mut state = self.state.lock().unwrap();
+        let key = file_key(user, page);
+        state.counters.insert(key.clone(), view_count);
+        self.mark_dirty(&mut state)?;
+        Ok(file_visitor(&key, view_count))
+    }
+
+    fn delete(&self, user: &str, page: &str) -> Result<bool, DbError> {
+        let mut state = self.state.lock().unwrap();
+        let existed = state.counters.remove(&file_key(user, page)).is_some();
+        if existed {
+            self.mark_dirty(&mut state)?;
+        }
+        Ok(existed)
+    }
+
+    fn iter(&self) -> Result<Vec<Visitors>, DbError> {
+        let state = self.state.lock().unwrap();
+        Ok(state.counters.iter()
+            .map(|(key, count)| file_visitor(key, *count))
+            .collect())
+    }
+
+    fn mark_seen(&self, user: &str, page: &str, fingerprint: &str, now: i64, window_secs: i64) -> Result<bool, DbError> {
+        let mut recent = self.recent.lock().unwrap();
+        if now - recent.pruned_at >= RECENT_PRUNE_SECS {
+            let cutoff = now - dedup::retention_secs();
+            recent.seen.retain(|_, last_seen| *last_seen > cutoff);
+            recent.pruned_at = now;
+        }
+        let key = (file_key(user, page), fingerprint.to_string());
+        if recent.seen.get(&key).is_some_and(|last_seen| *last_seen > now - window_secs) {
+            return Ok(false);
+        }
+        recent.seen.insert(key, now);
+        Ok(true)
+    }
+
+    fn flush(&self) -> Result<(), DbError> {
+        let mut state = self.state.lock().unwrap();
+        if state.dirty > 0 {
+            self.write(&mut state)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write-behind wrapper for a SQL store. Views are added to an in-memory
+/// pending delta and written in one upsert per counter every
+/// `WRITE_BEHIND_INTERVAL_MS` or once `WRITE_BEHIND_MAX_PENDING` views are
+/// waiting across all counters. Reads return the last stored count plus the pending delta.
+/// Daily rows are written at flush time, so a view pending across midnight
+/// lands on the next day.
+pub struct WriteBehindStore {
+    inner: Arc<dyn CounterStore>,
+    max_pending: i32,
+    interval: Duration,
+    state: Mutex<WriteBehindState>,
+    /// Serializes flushes so `in_flight` belongs to exactly one of them,
+    /// and keeps `set` and `delete` from running while views are in flight.
+    flushing: Mutex<()>,
+}
+
+#[derive(Default)]
+struct WriteBehindState {
+    entries: HashMap<(String, String), PendingCount>,
+    total_pending: i32,
+}
+
+/// Views of one counter that are not in the database yet.
+#[derive(Default)]
+struct PendingCount {
+    /// Count last read from or written to the inner store.
+    stored: i64,
+    pending: i32,
+    /// Views taken by a flush that has not finished writing them.
+    in_flight: i32,
+    last_viewed_at: Option<i64>,
+    /// Unique visitors, always written straight through to the inner store.
+    unique_count: i64,
+}
+
+impl PendingCount {
+    fn visitor(&self, user: &str, page: &str) -> Visitors {
+        Visitors {
+            id: user.to_string(),
+            page: page.to_string(),
+            view_count: self.view_count(),
+            last_viewed_at: self.last_viewed_at,
+            unique_count: self.unique_count,
+        }
+    }
+
+    fn view_count(&self) -> i64 {
+        self.stored
+            .saturating_add(i64::from(self.in_flight))
+            .saturating_add(i64::from(self.pending))
+    }
+}
+
+impl WriteBehindStore {
+    /// Wrap `inner` when `WRITE_BEHIND_INTERVAL_MS` is set to a non-zero
+    /// value, flushing every counter early once `WRITE_BEHIND_MAX_PENDING`
+    /// (default 50) views are queued in total; `None` when it is unset or 0.
+    pub fn from_env(inner: Arc<dyn CounterStore>) -> Result<Option<Arc<Self>>, String> {
+        let interval = env_number::<u64>("WRITE_BEHIND_INTERVAL_MS", 0)?;
+        if interval == 0 {
+            return Ok(None);
+        }
+        let max_pending = env_number::<u32>("WRITE_BEHIND_MAX_PENDING", 50)?;
+        let max_pending = i32::try_from(max_pending).unwrap_or(i32::MAX);
+        Ok(Some(Arc::new(WriteBehindStore::new(inner, Duration::from_millis(interval), max_pending))))
+    }
+
+    fn new(inner: Arc<dyn CounterStore>, interval: Duration, max_pending: i32) -> Self {
+        WriteBehindStore {
+            inner,
+            max_pending,
+            interval,
+            state: Mutex::new(WriteBehindState::default()),
+            flushing: Mutex::new(()),
+        }
+    }
+
+    pub fn flush_interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Queue `delta` views on a counter known to exist. Returns the counter
+    /// including them and whether the pending total calls for a flush.
+    fn queue(&self, user: &str, page: &str, stored: Option<&Visitors>, delta: i32) -> (Visitors, bool) {
+        let mut state = self.state.lock().unwrap();
+        let entry = state.entries.entry(pending_key(user, page)).or_default();
+        if let Some(stored) = stored {
+            entry.stored = stored.view_count;
+            entry.unique_count = stored.unique_count;
+            entry.last_viewed_at = entry.last_viewed_at.max(stored.last_viewed_at);
+        }
+        if delta > 0 {
+            entry.last_viewed_at = Some(Utc::now().timestamp());
+        }
+        entry.pending = entry.pending.saturating_add(delta);
+        let visitor = entry.visitor(user, page);
+        state.total_pending = state.total_pending.saturating_add(delta);
+        (visitor, state.total_pending >= self.max_pending)
+    }
+
+    /// The counter if it is tracked here, without touching the database.
+    fn cached(&self, user: &str, page: &str) -> Option<Visitors> {
+        let state = self.state.lock().unwrap();
+        state.entries.get(&pending_key(user, page)).map(|entry| entry.visitor(user, page))
+    }
+
+    fn pending(&self, user: &str, page: &str) -> i64 {
+        let state = self.state.lock().unwrap();
+        state.entries.get(&pending_key(user, page))
+            .map_or(0, |entry| i64::from(entry.in_flight) + i64::from(entry.pending))
+    }
+
+    /// Drop whatever is queued for the counter, e.g. before overwriting it.
+    fn forget(&self, user: &str, page: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(entry) = state.entries.remove(&pending_key(user, page)) {
+            state.total_pending = state.total_pending.saturating_sub(entry.pending);
+        }
+    }
+
+    fn flush_pending(&self) -> Result<(), DbError> {
+        let _flushing = self.flushing.lock().unwrap();
+        let batch: Vec<((String, String), i32)> = {
+            let mut state = self.state.lock().unwrap();
+            state.total_pending = 0;
+            state.entries.iter_mut()
+                .filter(|(_, entry)| entry.pending != 0)
+                .map(|(key, entry)| {
+                    entry.in_flight = std::mem::take(&mut entry.pending);
+                    (key.clone(), entry.in_flight)
+                })
+                .collect()
+        };
+
+        let mut first_err = None;
+        for (key, delta) in batch {
+            let result = self.inner.upsert_and_get(&key.0, &key.1, delta);
+            let mut state = self.state.lock().unwrap();
+            match result {
+                Ok(visitor) => {
+                    if let Some(entry) = state.entries.get_mut(&key) {
+                        entry.stored = visitor.view_count;
+                        entry.unique_count = entry.unique_count.max(visitor.unique_count);
+                        entry.in_flight = 0;
+                        entry.last_viewed_at = entry.last_viewed_at.max(visitor.last_viewed_at);
+                    }
+                },
+                Err(err) => {
+                    // Keep the views queued for the next flush.
+                    if let Some(entry) = state.entries.get_mut(&key) {
+                        entry.pending = entry.pending.saturating_add(entry.in_flight);
+                        entry.in_flight = 0;
+                        state.total_pending = state.total_pending.saturating_add(delta);
+                    }
+                    first_err.get_or_insert(err);
+                },
+            }
+        }
+        match first_err {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+fn pending_key(user: &str, page: &str) -> (String, String) {
+    (user.to_string(), page.to_string())
+}
+
+impl CounterStore for WriteBehindStore {
+    fn get(&self, user: &str, page: &str) -> Result<Option<Visitors>, DbError> {
+        if let Some(visitor) = self.cached(user, page) {
+            return Ok(Some(visitor));
+        }
+        self.inner.get(user, page)
+    }
+
+    fn increment_and_get(&self, user: &str, page: &str, delta: i32) -> Result<Option<Visitors>, DbError> {
+        let stored = match self.cached(user, page) {
+            Some(_) => None,
+            None => match self.inner.get(user, page)? {
+                Some(visitor) => Some(visitor),
+                None => return Ok(None),
+            },
+        };
+        let (visitor, flush) = self.queue(user, page, stored.as_ref(), delta);
+        if flush {
+            self.flush_pending()?;
+        }
+        Ok(Some(visitor))
+    }
+
+    fn upsert_and_get(&self, user: &str, page: &str, delta: i32) -> Result<Visitors, DbError> {
+        let stored = match self.cached(user, page) {
+            Some(_) => None,
+            None => match self.inner.get(user, page)? {
+                Some(visitor) => Some(visitor),
+                // New counters are written straight away so they exist for
+                // every other reader of the database.
+                None => {
+                    let visitor = self.inner.upsert_and_get(user, page, delta)?;
+                    self.queue(user, page, Some(&visitor), 0);
+                    return Ok(visitor);
+                },
+            },
+        };
+        let (visitor, flush) = self.queue(user, page, stored.as_ref(), delta);
+        if flush {
+            self.flush_pending()?;
+        }
+        Ok(visitor)
+    }
+
+    fn create(&self, user: &str, page: &str) -> Result<Visitors, DbError> {
+        self.inner.create(user, page)
+    }
+
+    fn set(&self, user: &str, page: &str, view_count: i64) -> Result<Visitors, DbError> {
+        // Wait out a running flush, which would otherwise write views it
+        // took before the reset on top of the new count.
+        let _flushing = self.flushing.lock().unwrap();
+        self.forget(user, page);
+        self.inner.set(user, page, view_count)
+    }
+
+    fn delete(&self, user: &str, page: &str) -> Result<bool, DbError> {
+        let _flushing = self.flushing.lock().unwrap();
+        self.forget(user, page);
+        self.inner.delete(user, page)
+    }
+
+    fn iter(&self) -> Result<Vec<Visitors>, DbError> {
+        let mut visitors = self.inner.iter()?;
+        for visitor in &mut visitors {
+            let pending = self.pending(&visitor.id, &visitor.page);
+            visitor.view_count = visitor.view_count.saturating_add(pending);
+        }
+        Ok(visitors)
+    }
+
+    fn user_total(&self, user: &str) -> Result<Option<i64>, DbError> {
+        let pending: i64 = {
+            let state = self.state.lock().unwrap();
+            state.entries.iter()
+                .filter(|((id, _), _)| id == user)
+                .map(|(_, entry)| i64::from(entry.in_flight) + i64::from(entry.pending))
+                .sum()
+        };
+        Ok(self.inner.user_total(user)?.map(|total| total.saturating_add(pending)))
+    }
+
+    fn pages(&self, user: &str, after: Option<&str>, limit: usize) -> Result<Vec<Visitors>, DbError> {
+        let mut pages = self.inner.pages(user, after, limit)?;
+        for visitor in &mut pages {
+            visitor.view_count = visitor.view_count.saturating_add(self.pending(&visitor.id, &visitor.page));
+        }
+        Ok(pages)
+    }
+
+    fn today(&self, user: &str, page: &str) -> Result<Option<DailyCount>, DbError> {
+        let mut today = self.inner.today(user, page)?;
+        if let Some(today) = &mut today {
+            today.view_count = today.view_count.saturating_add(self.pending(user, page));
+        }
+        Ok(today)
+    }
+
+    fn history(&self, user: &str, page: &str, days: u32) -> Result<Option<History>, DbError> {
+        let mut history = self.inner.history(user, page, days)?;
+        if let Some(today) = history.as_mut().and_then(|history| history.days.last_mut()) {
+            today.view_count = today.view_count.saturating_add(self.pending(user, page));
+        }
+        Ok(history)
+    }
+
+    fn aggregate(
+        &self,
+        user: &str,
+        page: &str,
+        granularity: Granularity,
+        periods: u32,
+    ) -> Result<Option<Aggregate>, DbError> {
+        let mut aggregate = self.inner.aggregate(user, page, granularity, periods)?;
+        if let Some(current) = aggregate.as_mut().and_then(|aggregate| aggregate.periods.last_mut()) {
+            current.view_count = current.view_count.saturating_add(self.pending(user, page));
+        }
+        Ok(aggregate)
+    }
+
+    fn series(&self, user: &str, page: &str, from: NaiveDate, to: NaiveDate) -> Result<Option<Series>, DbError> {
+        let mut series = self.inner.series(user, page, from, to)?;
+        if let Some(series) = &mut series {
+            let today = daily::local_day(daily::parse_timezone(&series.timezone)?, Utc::now()).to_string();
+            if let Some(point) = series.points.iter_mut().find(|point| point.day == today) {
+                point.view_count = point.view_count.saturating_add(self.pending(user, page));
+            }
+        }
+        Ok(series)
+    }
+
+    fn mark_seen(&self, user: &str, page: &str, fingerprint: &str, now: i64, window_secs: i64) -> Result<bool, DbError> {
+        self.inner.mark_seen(user, page, fingerprint, now, window_secs)
+    }
+
+    fn record_unique(&self, user: &str, page: &str, fingerprint: &str, now: i64, window_secs: i64) -> Result<i64, DbError> {
+        let added = self.inner.record_unique(user, page, fingerprint, now, window_secs)?;
+        if added > 0 {
+            let mut state = self.state.lock().unwrap();
+            if let Some(entry) = state.entries.get_mut(&pending_key(user, page)) {
+                entry.unique_count += added;
+            }
+        }
+        Ok(added)
+    }
+
+    fn record_referrer(&self, user: &str, page: &str, referrer: &str) -> Result<(), DbError> {
+        self.inner.record_referrer(user, page, referrer)
+    }
+
+    fn referrers(&self, user: &str, page: &str, limit: u32) -> Result<Option<Referrers>, DbError> {
+        self.inner.referrers(user, page, limit)
+    }
+
+    fn record_country(&self, user: &str, page: &str, country: &str) -> Result<(), DbError> {
+        self.inner.record_country(user, page, country)
+    }
+
+    fn countries(&self, user: &str, page: &str) -> Result<Option<Countries>, DbError> {
+        self.inner.countries(user, page)
+    }
+
+    fn display_step(&self, user: &str) -> Result<i64, DbError> {
+        self.inner.display_step(user)
+    }
+
+    fn alias_target(&self, user: &str) -> Result<Option<String>, DbError> {
+        self.inner.alias_target(user)
+    }
+
+    fn flush(&self) -> Result<(), DbError> {
+        self.flush_pending()?;
+        self.inner.flush()
+    }
+}
+
+/// Checks every `CounterStore` must pass, run against each backend by the
+/// backends' own test modules. `backend` names the store in failures.
+#[cfg(test)]
+mod conformance {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    pub fn counter_lifecycle(backend: &str, store: &Arc<dyn CounterStore>) {
+        // Migrations may seed counters of their own.
+        let seeded = store.iter().unwrap().len();
+        assert!(store.get("octocat", DEFAULT_PAGE).unwrap().is_none(), "{}", backend);
+        assert!(store.increment_and_get("octocat", DEFAULT_PAGE, 1).unwrap().is_none(), "{}", backend);
+
+        assert_eq!(store.upsert_and_get("octocat", DEFAULT_PAGE, 1).unwrap().view_count, 1, "{}", backend);
+        assert_eq!(store.upsert_and_get("octocat", DEFAULT_PAGE, 1).unwrap().view_count, 2, "{}", backend);
+        let visitor = store.increment_and_get("octocat", DEFAULT_PAGE, 3).unwrap().unwrap();
+        assert_eq!((visitor.id.as_str(), visitor.view_count), ("octocat", 5), "{}", backend);
+        assert_eq!(store.get("octocat", DEFAULT_PAGE).unwrap().unwrap().view_count, 5, "{}", backend);
+
+        assert_eq!(store.upsert_and_get("octocat", "docs", 1).unwrap().view_count, 1, "{}", backend);
+        assert_eq!(store.create("hubot", DEFAULT_PAGE).unwrap().view_count, 0, "{}", backend);
+        assert!(store.create("hubot", DEFAULT_PAGE).unwrap_err().is::<AlreadyExists>(), "{}", backend);
+
+        assert_eq!(store.set("octocat", DEFAULT_PAGE, 42).unwrap().view_count, 42, "{}", backend);
+        store.flush().unwrap();
+        let mut counters: Vec<_> = store.iter().unwrap().into_iter()
+            .filter(|visitor| ["hubot", "octocat"].contains(&visitor.id.as_str()))
+            .map(|visitor| (visitor.id, visitor.page, visitor.view_count))
+            .collect();
+        counters.sort();
+        assert_eq!(counters, [
+            ("hubot".to_string(), "".to_string(), 0),
+            ("octocat".to_string(), "".to_string(), 42),
+            ("octocat".to_string(), "docs".to_string(), 1),
+        ], "{}", backend);
+        assert_eq!(store.iter().unwrap().len(), seeded + 3, "{}", backend);
+
+        assert!(store.delete("octocat", DEFAULT_PAGE).unwrap(), "{}", backend);
+        assert!(!store.delete("octocat", DEFAULT_PAGE).unwrap(), "{}", backend);
+        assert!(store.get("octocat", DEFAULT_PAGE).unwrap().is_none(), "{}", backend);
+        assert_eq!(store.get("octocat", "docs").unwrap().unwrap().view_count, 1, "{}", backend);
+    }
+
+    pub fn dedup_within_the_window(backend: &str, store: &Arc<dyn CounterStore>) {
+        store.upsert_and_get("octocat", DEFAULT_PAGE, 1).unwrap();
+        assert!(store.mark_seen("octocat", DEFAULT_PAGE, "abc", 1_000, 60).unwrap(), "{}", backend);
+        assert!(!store.mark_seen("octocat", DEFAULT_PAGE, "abc", 1_030, 60).unwrap(), "{}", backend);
+        assert!(store.mark_seen("octocat", DEFAULT_PAGE, "def", 1_030, 60).unwrap(), "{}", backend);
+        assert!(store.mark_seen("octocat", DEFAULT_PAGE, "abc", 1_061, 60).unwrap(), "{}", backend);
+    }
+
+    pub fn concurrent_increments_are_all_counted(backend: &str, store: &Arc<dyn CounterStore>) {
+        const THREADS: i64 = 8;
+        const VIEWS: i64 = 50;
+        store.create("octocat", DEFAULT_PAGE).unwrap();
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    for _ in 0..VIEWS {
+                        store.upsert_and_get("octocat", DEFAULT_PAGE, 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        store.flush().unwrap();
+        assert_eq!(store.get("octocat", DEFAULT_PAGE).unwrap().unwrap().view_count, THREADS * VIEWS, "{}", backend);
+    }
+}
+
+#[cfg(all(test, not(feature = "postgres")))]
+mod tests {
+    use std::path::Path;
+    use std::sync::mpsc;
+    use std::thread;
+
+    use diesel_migrations::MigrationHarness;
+
+    use super::*;
+
+    /// A scratch SQLite file, removed with its WAL files on drop.
+    struct TempDb(PathBuf);
+
+    impl TempDb {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("visitor-badge-store-{}-{}.sqlite", std::process::id(), name));
+            remove_db(&path);
+            TempDb(path)
+        }
+
+        fn pool(&self) -> DbPool {
+            let manager = diesel::r2d2::ConnectionManager::<DbConnection>::new(self.0.to_str().unwrap());
+            let pool = diesel::r2d2::Pool::builder()
+                .max_size(8)
+                .connection_customizer(Box::new(crate::ConnectionOptions { busy_timeout: Duration::from_secs(5) }))
+                .build(manager)
+                .unwrap();
+            pool.get().unwrap().run_pending_migrations(crate::MIGRATIONS).unwrap();
+            pool
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            remove_db(&self.0);
+        }
+    }
+
+    fn remove_db(path: &Path) {
+        for suffix in ["", "-wal", "-shm", ".tmp"] {
+            let _ = fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    /// One of each backend, each over its own scratch file.
+    fn stores(name: &str) -> Vec<(&'static str, Arc<dyn CounterStore>, TempDb)> {
+        let sqlite = TempDb::new(&format!("{}-sqlite", name));
+        let file = TempDb::new(&format!("{}-file", name));
+        let write_behind = TempDb::new(&format!("{}-write-behind", name));
+        let inner: Arc<dyn CounterStore> = Arc::new(SqlStore::new(write_behind.pool()));
+        vec![
+            ("sqlite", Arc::new(SqlStore::new(sqlite.pool())) as Arc<dyn CounterStore>, sqlite),
+            ("file", Arc::new(FileStore::open(&file.0).unwrap()), file),
+            ("write-behind", Arc::new(WriteBehindStore::new(inner, Duration::from_secs(60), i32::MAX)), write_behind),
+        ]
+    }
+
+    #[test]
+    fn backends_agree_on_counter_lifecycle() {
+        for (backend, store, _db) in stores("lifecycle") {
+            conformance::counter_lifecycle(backend, &store);
+        }
+    }
+
+    #[test]
+    fn backends_dedup_within_the_window() {
+        for (backend, store, _db) in stores("dedup") {
+            conformance::dedup_within_the_window(backend, &store);
+        }
+    }
+
+    #[test]
+    fn concurrent_increments_are_all_counted() {
+        for (backend, store, _db) in stores("concurrent") {
+            conformance::concurrent_increments_are_all_counted(backend, &store);
+        }
+    }
+
+    #[test]
+    fn backends_without_daily_counts_say_so() {
+        let db = TempDb::new("unsupported");
+        let store = FileStore::open(&db.0).unwrap();
+        store.upsert_and_get("octocat", DEFAULT_PAGE, 1).unwrap();
+        let err = store.today("octocat", DEFAULT_PAGE).unwrap_err();
+        assert!(err.is::<Unsupported>());
+        assert_eq!(err.to_string(), "daily counts are not supported by this storage backend");
+    }
+
+    #[test]
+    fn file_store_reloads_what_it_wrote() {
+        let db = TempDb::new("reload");
+        let store = FileStore::open(&db.0).unwrap();
+        store.upsert_and_get("octocat", DEFAULT_PAGE, 3).unwrap();
+        store.upsert_and_get("octocat", "docs", 1).unwrap();
+        store.flush().unwrap();
+        let reopened = FileStore::open(&db.0).unwrap();
+        assert_eq!(reopened.get("octocat", DEFAULT_PAGE).unwrap().unwrap().view_count, 3);
+        assert_eq!(reopened.get("octocat", "docs").unwrap().unwrap().view_count, 1);
+    }
+
+    #[test]
+    fn file_store_keeps_slashed_ids_apart_from_pages() {
+        let db = TempDb::new("slashed");
+        let store = FileStore::open(&db.0).unwrap();
+        store.upsert_and_get("octocat/docs", DEFAULT_PAGE, 2).unwrap();
+        store.upsert_and_get("octocat", "docs", 1).unwrap();
+        store.upsert_and_get("100%", "a/b", 4).unwrap();
+        store.flush().unwrap();
+
+        let reopened = FileStore::open(&db.0).unwrap();
+        assert_eq!(reopened.get("octocat/docs", DEFAULT_PAGE).unwrap().unwrap().view_count, 2);
+        assert_eq!(reopened.get("octocat", "docs").unwrap().unwrap().view_count, 1);
+        let mut counters: Vec<_> = reopened.iter().unwrap().into_iter()
+            .map(|visitor| (visitor.id, visitor.page, visitor.view_count))
+            .collect();
+        counters.sort();
+        assert_eq!(counters, [
+            ("100%".to_string(), "a/b".to_string(), 4),
+            ("octocat".to_string(), "docs".to_string(), 1),
+            ("octocat/docs".to_string(), DEFAULT_PAGE.to_string(), 2),
+        ]);
+    }
+
+    #[test]
+    fn max_pending_counts_views_across_counters() {
+        let db = TempDb::new("max-pending");
+        let inner = Arc::new(SqlStore::new(db.pool()));
+        inner.create("octocat", DEFAULT_PAGE).unwrap();
+        inner.create("hubot", DEFAULT_PAGE).unwrap();
+        let store = WriteBehindStore::new(inner.clone(), Duration::from_secs(60), 3);
+        store.upsert_and_get("octocat", DEFAULT_PAGE, 1).unwrap();
+        store.upsert_and_get("octocat", DEFAULT_PAGE, 1).unwrap();
+        assert_eq!(inner.get("octocat", DEFAULT_PAGE).unwrap().unwrap().view_count, 0);
+
+        // The third view is the first of hubot's, yet it flushes both.
+        store.upsert_and_get("hubot", DEFAULT_PAGE, 1).unwrap();
+        assert_eq!(inner.get("octocat", DEFAULT_PAGE).unwrap().unwrap().view_count, 2);
+        assert_eq!(inner.get("hubot", DEFAULT_PAGE).unwrap().unwrap().view_count, 1);
+    }
+
+    /// Inner store whose flush writes stall, announcing each one first.
+    struct SlowStore {
+        inner: SqlStore,
+        delay: Duration,
+        writing: Mutex<mpsc::Sender<()>>,
+    }
+
+    impl CounterStore for SlowStore {
+        fn get(&self, user: &str, page: &str) -> Result<Option<Visitors>, DbError> {
+            self.inner.get(user, page)
+        }
+
+        fn increment_and_get(&self, user: &str, page: &str, delta: i32) -> Result<Option<Visitors>, DbError> {
+            self.inner.increment_and_get(user, page, delta)
+        }
+
+        fn upsert_and_get(&self, user: &str, page: &str, delta: i32) -> Result<Visitors, DbError> {
+            let _ = self.writing.lock().unwrap().send(());
+            thread::sleep(self.delay);
+            self.inner.upsert_and_get(user, page, delta)
+        }
+
+        fn create(&self, user: &str, page: &str) -> Result<Visitors, DbError> {
+            self.inner.create(user, page)
+        }
+
+        fn set(&self, user: &str, page: &str, view_count: i64) -> Result<Visitors, DbError> {
+            self.inner.set(user, page, view_count)
+        }
+
+        fn delete(&self, user: &str, page: &str) -> Result<bool, DbError> {
+            self.inner.delete(user, page)
+        }
+
+        fn iter(&self) -> Result<Vec<Visitors>, DbError> {
+            self.inner.iter()
+        }
+
+        fn mark_seen(&self, user: &str, page: &str, fingerprint: &str, now: i64, window_secs: i64) -> Result<bool, DbError> {
+            self.inner.mark_seen(user, page, fingerprint, now, window_secs)
+        }
+    }
+
+    #[test]
+    fn set_during_a_flush_is_not_overwritten() {
+        let db = TempDb::new("set-during-flush");
+        let (writing, flush_started) = mpsc::channel();
+        let slow = Arc::new(SlowStore {
+            inner: SqlStore::new(db.pool()),
+            delay: Duration::from_millis(200),
+            writing: Mutex::new(writing),
+        });
+        slow.inner.create("octocat", DEFAULT_PAGE).unwrap();
+        let store = Arc::new(WriteBehindStore::new(slow.clone(), Duration::from_secs(60), i32::MAX));
+        for _ in 0..5 {
+            store.upsert_and_get("octocat", DEFAULT_PAGE, 1).unwrap();
+        }
+
+        let flushing = thread::spawn({
+            let store = store.clone();
+            move || store.flush().unwrap()
+        });
+        flush_started.recv().unwrap();
+        // The flush is writing the five views; the reset has to land after them.
+        assert_eq!(store.set("octocat", DEFAULT_PAGE, 0).unwrap().view_count, 0);
+        flushing.join().unwrap();
+        assert_eq!(store.get("octocat", DEFAULT_PAGE).unwrap().unwrap().view_count, 0);
+        assert_eq!(slow.get("octocat", DEFAULT_PAGE).unwrap().unwrap().view_count, 0);
+
+        for _ in 0..3 {
+            store.upsert_and_get("octocat", DEFAULT_PAGE, 1).unwrap();
+        }
+        assert_eq!(slow.get("octocat", DEFAULT_PAGE).unwrap().unwrap().view_count, 0);
+        store.flush().unwrap();
+        assert_eq!(store.get("octocat", DEFAULT_PAGE).unwrap().unwrap().view_count, 3);
+        assert_eq!(slow.get("octocat", DEFAULT_PAGE).unwrap().unwrap().view_count, 3);
+    }
+
+    #[test]
+    fn views_during_a_flush_reach_the_database_on_the_next_one() {
+        let db = TempDb::new("views-during-flush");
+        let (writing, flush_started) = mpsc::channel();
+        let slow = Arc::new(SlowStore {
+            inner: SqlStore::new(db.pool()),
+            delay: Duration::from_millis(200),
+            writing: Mutex::new(writing),
+        });
+        slow.inner.create("octocat", DEFAULT_PAGE).unwrap();
+        let store = Arc::new(WriteBehindStore::new(slow.clone(), Duration::from_secs(60), i32::MAX));
+        for _ in 0..5 {
+            store.upsert_and_get("octocat", DEFAULT_PAGE, 1).unwrap();
+        }
+
+        let flushing = thread::spawn({
+            let store = store.clone();
+            move || store.flush().unwrap()
+        });
+        flush_started.recv().unwrap();
+        for expected in 6..=8 {
+            assert_eq!(store.upsert_and_get("octocat", DEFAULT_PAGE, 1).unwrap().view_count, expected);
+        }
+        flushing.join().unwrap();
+        assert_eq!(slow.get("octocat", DEFAULT_PAGE).unwrap().unwrap().view_count, 5);
+        assert_eq!(store.get("octocat", DEFAULT_PAGE).unwrap().unwrap().view_count, 8);
+
+        store.flush().unwrap();
+        assert_eq!(slow.get("octocat", DEFAULT_PAGE).unwrap().unwrap().view_count, 8);
+    }
+}
+
+/// The conformance suite against PostgreSQL, at `TEST_DATABASE_URL`. The
+/// tables are cleared before each check, so point it at a scratch database.
+#[cfg(all(test, feature = "postgres"))]
+mod postgres_tests {
+    use std::sync::{Arc, Mutex, MutexGuard};
+
+    use diesel::RunQueryDsl;
+    use diesel_migrations::MigrationHarness;
+
+    use super::*;
+
+    /// The checks share one database, so they take turns.
+    static DATABASE: Mutex<()> = Mutex::new(());
+
+    /// A store over a freshly cleared database, or `None` with a note when
+    /// `TEST_DATABASE_URL` is not set.
+    fn store() -> Option<(Arc<dyn CounterStore>, MutexGuard<'static, ()>)> {
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL is not set; skipping the PostgreSQL conformance suite");
+            return None;
+        };
+        let guard = DATABASE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let pool = diesel::r2d2::Pool::builder()
+            .max_size(8)
+            .build(diesel::r2d2::ConnectionManager::<DbConnection>::new(url))
+            .unwrap();
+        let mut conn = pool.get().unwrap();
+        conn.run_pending_migrations(crate::MIGRATIONS).unwrap();
+        diesel::sql_query("DELETE FROM recent_views").execute(&mut conn).unwrap();
+        diesel::sql_query("DELETE FROM visitors WHERE id IN ('octocat', 'hubot')").execute(&mut conn).unwrap();
+        Some((Arc::new(SqlStore::new(pool.clone())), guard))
+    }
+
+    #[test]
+    fn postgres_passes_the_counter_lifecycle() {
+        if let Some((store, _guard)) = store() {
+            conformance::counter_lifecycle("postgres", &store);
+        }
+    }
+
+    #[test]
+    fn postgres_dedups_within_the_window() {
+        if let Some((store, _guard)) = store() {
+            conformance::dedup_within_the_window("postgres", &store);
+        }
+    }
+
+    #[test]
+    fn postgres_counts_concurrent_increments() {
+        if let Some((store, _guard)) = store() {
+            conformance::concurrent_increments_are_all_counted("postgres", &store);
+        }
+    }
+}
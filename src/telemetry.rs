@@ -0,0 +1,41 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::Error;
+use tracing::Span;
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
+
+/// Install the global subscriber: human-readable lines by default, one
+/// JSON object per event with `LOG_FORMAT=json`. `RUST_LOG` filters as
+/// before, defaulting to `info`. Closed spans are logged with their
+/// duration so slow queries stand out at `debug`.
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE);
+    match std::env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => builder.json().init(),
+        _ => builder.init(),
+    }
+}
+
+/// Request spans with room for the badge fields handlers fill in through
+/// `Span::current().record(...)`.
+pub struct BadgeRootSpan;
+
+impl RootSpanBuilder for BadgeRootSpan {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        tracing_actix_web::root_span!(
+            request,
+            user = tracing::field::Empty,
+            style = tracing::field::Empty,
+            view_count = tracing::field::Empty,
+        )
+    }
+
+    fn on_request_end<B: MessageBody>(span: Span, outcome: &Result<ServiceResponse<B>, Error>) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
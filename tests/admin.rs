@@ -0,0 +1,201 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use common::{badge_message, badge_path, TestServer, ADMIN_TOKEN};
+use diesel::sql_types::{BigInt, Integer, Text};
+use diesel::RunQueryDsl;
+
+fn admin() -> String {
+    format!("Bearer {}", ADMIN_TOKEN)
+}
+
+async fn view(server: &TestServer, user: &str, times: usize) {
+    let client = awc::Client::default();
+    for _ in 0..times {
+        let response = client.get(server.url(&badge_path(user, ""))).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
+
+#[actix_web::test]
+async fn stats_need_the_admin_token() {
+    let server = TestServer::start().await;
+    let client = awc::Client::default();
+    for path in ["/admin/stats", "/admin/stats/badge"] {
+        let response = client.get(server.url(path)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", path);
+        assert_eq!(response.headers().get("WWW-Authenticate").unwrap(), "Bearer");
+        let response = client.get(server.url(path))
+            .insert_header(("Authorization", "Bearer wrong"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", path);
+    }
+}
+
+#[actix_web::test]
+async fn stats_sum_the_instance_and_are_cached() {
+    let server = TestServer::start().await;
+    let client = awc::Client::default();
+    view(&server, "octocat", 3).await;
+    view(&server, "hubot", 1).await;
+
+    let mut response = client.get(server.url("/admin/stats"))
+        .insert_header(("Authorization", admin()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let stats = &body["stats"];
+    // The migrations seed the default `me` counter at 0.
+    assert_eq!(stats["total_counters"], 3);
+    assert_eq!(stats["total_views"], 4);
+    assert_eq!(stats["views_since_yesterday"], 4);
+    assert_eq!(stats["top_counters"][0]["id"], "octocat");
+    assert_eq!(stats["top_counters"][0]["view_count"], 3);
+    assert_eq!(stats["top_counters"][1]["id"], "hubot");
+    assert!(body["uptime_secs"].is_u64());
+    assert!(body["cache"]["hot_badge"]["hits"].is_u64());
+
+    // Within the TTL both endpoints answer from the cached aggregates.
+    view(&server, "octocat", 2).await;
+    let body: serde_json::Value = client.get(server.url("/admin/stats"))
+        .insert_header(("Authorization", admin()))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["stats"]["total_views"], 4);
+    let mut response = client.get(server.url("/admin/stats/badge"))
+        .insert_header(("Authorization", admin()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(badge_message(&response.body().await.unwrap()), "4");
+
+    // The counters themselves did move on.
+    let count: serde_json::Value =
+        client.get(server.url("/count/octocat")).send().await.unwrap().json().await.unwrap();
+    assert_eq!(count["view_count"], 5);
+}
+
+#[actix_web::test]
+async fn stats_rank_the_top_ten_of_many_counters() {
+    let server = TestServer::start_with(&[("ADMIN_STATS_TTL_SECS", "0")]).await;
+    let client = awc::Client::default();
+    let mut conn = server.connect();
+    // 300 counters at 10, 20, ... 3000 views, and `aaa` tied with user299.
+    let seeded = (1..=300).map(|n| (format!("user{:03}", n), n * 10)).chain([("aaa".to_string(), 2990)]);
+    diesel::sql_query("BEGIN").execute(&mut conn).unwrap();
+    for (id, views) in seeded {
+        diesel::sql_query("INSERT INTO visitors (id, page, view_count) VALUES (?, '', ?)")
+            .bind::<Text, _>(id)
+            .bind::<BigInt, _>(views)
+            .execute(&mut conn)
+            .unwrap();
+    }
+    diesel::sql_query("COMMIT").execute(&mut conn).unwrap();
+    let today = chrono::Utc::now().date_naive();
+    for (days_ago, views) in [(0, 5), (1, 7), (3, 11)] {
+        diesel::sql_query("INSERT INTO daily_views (user_id, page, day, view_count) VALUES ('user300', '', ?, ?)")
+            .bind::<Text, _>((today - chrono::Duration::days(days_ago)).to_string())
+            .bind::<Integer, _>(views)
+            .execute(&mut conn)
+            .unwrap();
+    }
+
+    let get_stats = || async {
+        let mut response = client.get(server.url("/admin/stats"))
+            .insert_header(("Authorization", admin()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = response.json().await.unwrap();
+        body["stats"].clone()
+    };
+    let body = get_stats().await;
+    // Plus the `me` counter the migrations seed at 0.
+    assert_eq!(body["total_counters"], 302);
+    assert_eq!(body["total_views"], (1..=300).map(|n| n * 10).sum::<i64>() + 2990);
+    assert_eq!(body["views_since_yesterday"], 12);
+    let top: Vec<(String, i64)> = body["top_counters"].as_array().unwrap().iter()
+        .map(|visitor| (visitor["id"].as_str().unwrap().to_string(), visitor["view_count"].as_i64().unwrap()))
+        .collect();
+    let mut expected = vec![("user300".to_string(), 3000), ("aaa".to_string(), 2990)];
+    expected.extend((292..=299).rev().map(|n| (format!("user{:03}", n), n * 10)));
+    assert_eq!(top, expected);
+
+    // With no TTL every request sees the latest views.
+    let response = client.get(server.url(&badge_path("user300", ""))).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = get_stats().await;
+    assert_eq!(body["top_counters"][0]["view_count"], 3001);
+    assert_eq!(body["views_since_yesterday"], 13);
+}
+
+#[actix_web::test]
+async fn set_and_delete_refuse_requests_without_the_token() {
+    let server = TestServer::start().await;
+    let client = awc::Client::default();
+    view(&server, "octocat", 1).await;
+
+    for (auth, status) in [(None, StatusCode::UNAUTHORIZED), (Some("Bearer wrong"), StatusCode::FORBIDDEN)] {
+        let mut set = client.post(server.url("/admin/count/octocat"));
+        let mut delete = client.delete(server.url("/admin/count/octocat"));
+        if let Some(auth) = auth {
+            set = set.insert_header(("Authorization", auth));
+            delete = delete.insert_header(("Authorization", auth));
+        }
+        let response = set.send_json(&serde_json::json!({ "view_count": 999 })).await.unwrap();
+        assert_eq!(response.status(), status, "set with {:?}", auth);
+        let response = delete.send().await.unwrap();
+        assert_eq!(response.status(), status, "delete with {:?}", auth);
+    }
+    let count: serde_json::Value =
+        client.get(server.url("/count/octocat")).send().await.unwrap().json().await.unwrap();
+    assert_eq!(count["view_count"], 1);
+}
+
+#[actix_web::test]
+async fn admins_can_set_and_delete_counters() {
+    let server = TestServer::start().await;
+    let client = awc::Client::default();
+
+    let mut response = client.post(server.url("/admin/count/octocat?page=blog"))
+        .insert_header(("Authorization", admin()))
+        .send_json(&serde_json::json!({ "view_count": 1234 }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let counter: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(counter["id"], "octocat");
+    assert_eq!(counter["page"], "blog");
+    assert_eq!(counter["view_count"], 1234);
+    let count: serde_json::Value =
+        client.get(server.url("/count/octocat/blog")).send().await.unwrap().json().await.unwrap();
+    assert_eq!(count["view_count"], 1234);
+
+    let response = client.post(server.url("/admin/count/octocat?page=blog"))
+        .insert_header(("Authorization", admin()))
+        .send_json(&serde_json::json!({ "view_count": -1 }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    for status in [StatusCode::NO_CONTENT, StatusCode::NOT_FOUND] {
+        let response = client.delete(server.url("/admin/count/octocat?page=blog"))
+            .insert_header(("Authorization", admin()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status);
+    }
+    let response = client.get(server.url("/count/octocat/blog")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,190 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use common::{badge_message, badge_path, TestServer, BADGE_KEY};
+
+#[actix_web::test]
+async fn badge_counts_views_against_a_fresh_database() {
+    let server = TestServer::start().await;
+    let client = awc::Client::default();
+
+    for expected in ["1", "2", "3"] {
+        let mut response = client.get(server.url(&badge_path("octocat", ""))).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("Content-Type").unwrap(), "image/svg+xml; charset=utf-8");
+        let body = response.body().await.unwrap();
+        assert!(response.headers().get("Transfer-Encoding").is_none());
+        assert_eq!(response.headers().get("Content-Length").unwrap(), &body.len().to_string());
+        assert_eq!(badge_message(&body), expected);
+    }
+}
+
+#[actix_web::test]
+async fn wrong_badge_key_gets_an_uncached_error_badge() {
+    let server = TestServer::start().await;
+    let mut response = awc::Client::default()
+        .get(server.url("/badge/octocat?key=wrong"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers().get("Cache-Control").unwrap(), "no-store");
+    assert_eq!(badge_message(&response.body().await.unwrap()), "not found");
+}
+
+#[actix_web::test]
+async fn invalid_colors_fall_back_to_the_defaults() {
+    let server = TestServer::start().await;
+    let client = awc::Client::default();
+    let badge = |query: &'static str| {
+        let client = client.clone();
+        let url = server.url(&badge_path("me", &format!("&read_only=true{}", query)));
+        async move {
+            let mut response = client.get(url).send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", query);
+            response.body().await.unwrap()
+        }
+    };
+
+    let default = badge("").await;
+    assert_eq!(badge("&color=notacolor&label_color=url(%23x)").await, default);
+    assert_ne!(badge("&color=hotpink").await, default);
+}
+
+#[actix_web::test]
+async fn pages_of_a_user_count_independently() {
+    let server = TestServer::start().await;
+    let client = awc::Client::default();
+    for _ in 0..2 {
+        client.get(server.url(&badge_path("octocat", "&page=repo-a"))).send().await.unwrap();
+    }
+    client.get(server.url(&badge_path("octocat", "&page=repo-b"))).send().await.unwrap();
+
+    let count = |page: &'static str| {
+        let client = client.clone();
+        let url = server.url(&format!("/count/octocat/{}", page));
+        async move { client.get(url).send().await.unwrap().json::<serde_json::Value>().await.unwrap() }
+    };
+    assert_eq!(count("repo-a").await["view_count"], 2);
+    assert_eq!(count("repo-b").await["view_count"], 1);
+}
+
+#[actix_web::test]
+async fn count_endpoint_is_read_only_json() {
+    let server = TestServer::start().await;
+    let client = awc::Client::default();
+    client.get(server.url(&badge_path("octocat", ""))).send().await.unwrap();
+
+    for _ in 0..2 {
+        let mut response = client.get(server.url("/count/octocat")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body, serde_json::json!({ "id": "octocat", "view_count": 1 }));
+    }
+    let mut response = client.get(server.url("/count/octocat?format=text")).send().await.unwrap();
+    assert_eq!(response.body().await.unwrap(), "1");
+
+    let mut response = client.get(server.url("/count/nobody")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"], "counter not found");
+}
+
+#[actix_web::test]
+async fn etag_answers_not_modified_until_the_count_changes() {
+    let server = TestServer::start().await;
+    let client = awc::Client::default();
+    client.get(server.url(&badge_path("octocat", ""))).send().await.unwrap();
+
+    let read_only = server.url(&badge_path("octocat", "&read_only=true"));
+    let response = client.get(&read_only).send().await.unwrap();
+    let etag = response.headers().get("ETag").unwrap().to_str().unwrap().to_string();
+
+    let response = client.get(&read_only).insert_header(("If-None-Match", etag.as_str())).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    client.get(server.url(&badge_path("octocat", ""))).send().await.unwrap();
+    let response = client.get(&read_only).insert_header(("If-None-Match", etag.as_str())).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn views_over_the_rate_limit_are_served_but_not_counted() {
+    let server = TestServer::start_with(&[("RATE_LIMIT_PER_MINUTE", "3")]).await;
+    let client = awc::Client::default();
+    let mut last = String::new();
+    for _ in 0..5 {
+        let mut response = client.get(server.url(&badge_path("octocat", ""))).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        last = badge_message(&response.body().await.unwrap());
+    }
+    assert_eq!(last, "3");
+}
+
+#[actix_web::test]
+async fn error_paths_escape_markup_from_the_request() {
+    let server = TestServer::start().await;
+    let client = awc::Client::default();
+    let paths = [
+        "/builder/preview?style=%3Cscript%3E".to_string(),
+        "/builder/preview?color=%3Cscript%3E".to_string(),
+        "/static/a-b-%3Cscript%3E.svg".to_string(),
+        "/dynamic/json?url=%3Cscript%3E&query=%3Cscript%3E".to_string(),
+        format!("/badge/octocat?key={}&style=%3Cscript%3E", BADGE_KEY),
+        format!("/badge/octocat?key={}&label=%3Cscript%3E", BADGE_KEY),
+        format!("/badge/octocat?key={}&below=%3Cscript%3E&min_count=100", BADGE_KEY),
+    ];
+    for path in paths {
+        let mut response = client.get(server.url(&path)).send().await.unwrap();
+        let is_svg = response.headers()
+            .get("Content-Type")
+            .is_some_and(|value| value.as_bytes().starts_with(b"image/svg+xml"));
+        let body = response.body().await.unwrap();
+        let body = std::str::from_utf8(&body).unwrap();
+        // JSON errors may quote the input; only SVG would run it.
+        if is_svg {
+            assert!(!body.contains("<script"), "{} reflected markup: {}", path, body);
+        }
+    }
+}
+
+#[actix_web::test]
+async fn legacy_page_id_urls_count_without_a_key() {
+    let server = TestServer::start().await;
+    let client = awc::Client::default();
+
+    for (path, expected) in [
+        ("/?page_id=owner.repo", "1"),
+        ("/badge?page_id=owner.repo", "2"),
+        ("/v1/badge?page_id=owner.repo", "3"),
+    ] {
+        let mut response = client.get(server.url(path)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "{}", path);
+        assert_eq!(badge_message(&response.body().await.unwrap()), expected, "{}", path);
+    }
+    let count: serde_json::Value =
+        client.get(server.url("/count/owner.repo")).send().await.unwrap().json().await.unwrap();
+    assert_eq!(count["view_count"], 3);
+
+    let mut response = client.get(server.url("/badge")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(badge_message(&response.body().await.unwrap()), "page_id required");
+    // A key, if given, must still be right, and only page_id drops it.
+    let mut response = client.get(server.url("/?page_id=owner.repo&key=wrong")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(badge_message(&response.body().await.unwrap()), "not found");
+    let response = client.get(server.url("/")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = client.get(server.url(&format!("/?key={}", BADGE_KEY))).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Other badge routes ignore page_id, so it cannot stand in for the key.
+    for path in ["/badge/octocat?page_id=owner.repo", "/badge/octocat/blog?page_id=owner.repo"] {
+        let mut response = client.get(server.url(path)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", path);
+        assert_eq!(badge_message(&response.body().await.unwrap()), "not found", "{}", path);
+    }
+    let count: serde_json::Value =
+        client.get(server.url("/count/owner.repo")).send().await.unwrap().json().await.unwrap();
+    assert_eq!(count["view_count"], 3);
+}
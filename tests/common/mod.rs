@@ -0,0 +1,125 @@
+//! Runs the server binary on a free port against a fresh SQLite database,
+//! which the embedded migrations set up on startup.
+
+// Each test binary uses its own subset of the helpers.
+#![allow(dead_code)]
+
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use diesel::sql_types::Text;
+use diesel::{Connection, RunQueryDsl, SqliteConnection};
+use sha2::{Digest, Sha256};
+
+pub const BADGE_KEY: &str = "test-badge-key";
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+
+pub struct TestServer {
+    child: Child,
+    dir: PathBuf,
+    base_url: String,
+}
+
+impl TestServer {
+    pub async fn start() -> Self {
+        TestServer::start_with(&[]).await
+    }
+
+    /// Start with extra environment variables on top of the test defaults.
+    pub async fn start_with(env: &[(&str, &str)]) -> Self {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let dir = std::env::temp_dir().join(format!("visitor-badge-test-{}-{}", std::process::id(), port));
+        std::fs::create_dir_all(&dir).unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_visitor-badge"))
+            .current_dir(&dir)
+            .env_clear()
+            .env("DATABASE_URL", dir.join("counters.sqlite"))
+            .env("BADGE_KEY", BADGE_KEY)
+            .env("ADMIN_TOKEN", ADMIN_TOKEN)
+            .env("BIND_ADDR", "127.0.0.1")
+            .env("PORT", port.to_string())
+            .env("FONT_PATH", concat!(env!("CARGO_MANIFEST_DIR"), "/src/fonts/DejaVuSans.ttf"))
+            .envs(env.iter().copied())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("server binary should start");
+        let mut server = TestServer { child, dir, base_url: format!("http://127.0.0.1:{}", port) };
+        server.wait_until_healthy().await;
+        server
+    }
+
+    async fn wait_until_healthy(&mut self) {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let client = awc::Client::default();
+        loop {
+            if let Ok(response) = client.get(self.url("/healthz")).send().await {
+                if response.status().is_success() {
+                    return;
+                }
+            }
+            if let Ok(Some(status)) = self.child.try_wait() {
+                panic!("server exited during startup: {}", status);
+            }
+            assert!(Instant::now() < deadline, "server did not become healthy");
+            actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// Store an owner key for `user` the way a verified claim would.
+    pub fn insert_owner_key(&self, user: &str, key: &str) {
+        let hash: String = Sha256::digest(key.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+        let mut conn = self.connect();
+        diesel::sql_query("INSERT INTO owner_keys (key_hash, user_id, created_at) VALUES (?, ?, 0)")
+            .bind::<Text, _>(hash)
+            .bind::<Text, _>(user)
+            .execute(&mut conn)
+            .unwrap();
+    }
+
+    /// Shut the server down as SIGTERM would and wait for it to exit.
+    pub fn stop(&mut self) {
+        let status = Command::new("kill").arg("-TERM").arg(self.child.id().to_string()).status().unwrap();
+        assert!(status.success(), "could not signal the server");
+        self.child.wait().unwrap();
+    }
+
+    /// A connection of our own to the server's database.
+    pub fn connect(&self) -> SqliteConnection {
+        SqliteConnection::establish(self.dir.join("counters.sqlite").to_str().unwrap()).unwrap()
+    }
+
+    /// Take the database write lock until the returned connection drops, so
+    /// every write the server attempts waits on it.
+    pub fn lock_writes(&self) -> SqliteConnection {
+        let mut conn = self.connect();
+        diesel::sql_query("BEGIN IMMEDIATE").execute(&mut conn).unwrap();
+        conn
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub fn badge_path(user: &str, query: &str) -> String {
+    format!("/badge/{}?key={}{}", user, BADGE_KEY, query)
+}
+
+/// The message text of a rendered badge, its last `<text>` element.
+pub fn badge_message(svg: &[u8]) -> String {
+    let svg = std::str::from_utf8(svg).expect("badge should be UTF-8");
+    let last = svg.rfind("</text>").expect("badge should have text");
+    let start = svg[..last].rfind('>').expect("text element should open") + 1;
+    svg[start..last].to_string()
+}
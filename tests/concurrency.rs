@@ -0,0 +1,83 @@
+mod common;
+
+use std::time::Duration;
+
+use actix_web::http::StatusCode;
+use common::{badge_path, TestServer};
+use diesel::sql_types::Text;
+use diesel::{QueryableByName, RunQueryDsl};
+use futures_util::future::join_all;
+
+#[derive(QueryableByName)]
+struct JournalMode {
+    #[diesel(sql_type = Text)]
+    journal_mode: String,
+}
+
+#[actix_web::test]
+async fn concurrent_badges_never_fail_under_wal() {
+    const USERS: [&str; 4] = ["octocat", "hubot", "monalisa", "defunkt"];
+    const ROUNDS: usize = 25;
+    let server = TestServer::start().await;
+    let client = awc::Client::default();
+
+    let mode = diesel::sql_query("PRAGMA journal_mode").get_result::<JournalMode>(&mut server.connect()).unwrap();
+    assert_eq!(mode.journal_mode, "wal");
+
+    // Hold the write lock for a moment while the first requests arrive;
+    // busy_timeout makes them wait instead of failing.
+    let lock = server.lock_writes();
+    let release = async move {
+        actix_web::rt::time::sleep(Duration::from_millis(300)).await;
+        drop(lock);
+    };
+    let requests = (0..ROUNDS).flat_map(|_| USERS).map(|user| {
+        let request = client.get(server.url(&badge_path(user, "")));
+        async move { request.send().await.unwrap().status() }
+    });
+    let (statuses, ()) = futures_util::join!(join_all(requests), release);
+    let failed: Vec<_> = statuses.iter().filter(|status| **status != StatusCode::OK).collect();
+    assert!(failed.is_empty(), "{} of {} requests failed: {:?}", failed.len(), statuses.len(), failed);
+
+    for user in USERS {
+        let count: serde_json::Value =
+            client.get(server.url(&format!("/count/{}", user))).send().await.unwrap().json().await.unwrap();
+        assert_eq!(count["view_count"], ROUNDS, "{}", user);
+    }
+}
+
+#[actix_web::test]
+async fn concurrent_creates_make_one_counter() {
+    let server = TestServer::start().await;
+    let client = awc::Client::default();
+
+    let requests = (0..8).map(|_| {
+        let request = client.post(server.url("/admin/counters"))
+            .insert_header(("Authorization", format!("Bearer {}", common::ADMIN_TOKEN)));
+        async move { request.send_json(&serde_json::json!({ "id": "octocat" })).await.unwrap().status() }
+    });
+    let mut statuses = join_all(requests).await;
+    statuses.sort();
+    let mut expected = vec![StatusCode::CONFLICT; 7];
+    expected.insert(0, StatusCode::CREATED);
+    assert_eq!(statuses, expected);
+}
+
+#[actix_web::test]
+async fn concurrent_key_creates_issue_one_key() {
+    let server = TestServer::start().await;
+    let client = awc::Client::default();
+
+    let requests = (0..8).map(|_| {
+        let request = client.post(server.url("/admin/keys"))
+            .insert_header(("Authorization", format!("Bearer {}", common::ADMIN_TOKEN)));
+        async move {
+            request.send_json(&serde_json::json!({ "name": "deploy", "scopes": ["write"] })).await.unwrap().status()
+        }
+    });
+    let mut statuses = join_all(requests).await;
+    statuses.sort();
+    let mut expected = vec![StatusCode::CONFLICT; 7];
+    expected.insert(0, StatusCode::CREATED);
+    assert_eq!(statuses, expected);
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use common::{TestServer, ADMIN_TOKEN};
+
+async fn count(server: &TestServer, user: &str) -> Option<i64> {
+    let mut response = awc::Client::default().get(server.url(&format!("/count/{}", user))).send().await.unwrap();
+    if response.status() == StatusCode::NOT_FOUND {
+        return None;
+    }
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["view_count"].as_i64()
+}
+
+#[actix_web::test]
+async fn increment_needs_the_write_scope() {
+    let server = TestServer::start().await;
+    let client = awc::Client::default();
+    for (path, auth, status) in [
+        ("/api/counters/octocat/increment", None, StatusCode::UNAUTHORIZED),
+        ("/api/%63ounters/octocat/increment", None, StatusCode::UNAUTHORIZED),
+        ("/v1/api/counters/%6fctocat/increment", None, StatusCode::UNAUTHORIZED),
+        ("/api/counters/octocat/increment", Some("Bearer wrong"), StatusCode::FORBIDDEN),
+    ] {
+        let mut request = client.post(server.url(path));
+        if let Some(auth) = auth {
+            request = request.insert_header(("Authorization", auth));
+        }
+        let response = request.send_json(&serde_json::json!({ "by": 1000 })).await.unwrap();
+        assert_eq!(response.status(), status, "{} {:?}", path, auth);
+    }
+    assert_eq!(count(&server, "octocat").await, None);
+
+    let admin = format!("Bearer {}", ADMIN_TOKEN);
+    let mut response = client.post(server.url("/api/%63ounters/octocat/increment"))
+        .insert_header(("Authorization", admin.as_str()))
+        .send_json(&serde_json::json!({ "by": 1000 }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let counter: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(counter["view_count"], 1000);
+    assert_eq!(count(&server, "octocat").await, Some(1000));
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use common::{badge_path, TestServer};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+
+const OWNER_KEY: &str = "octocat-owner-key";
+
+/// A server where octocat has 15 views and shows them to the nearest 10.
+async fn stepped_server() -> TestServer {
+    let server = TestServer::start().await;
+    let client = awc::Client::default();
+    for _ in 0..15 {
+        client.get(server.url(&badge_path("octocat", ""))).send().await.unwrap().body().await.unwrap();
+    }
+    server.insert_owner_key("octocat", OWNER_KEY);
+    let response = client.put(server.url("/api/display-step/octocat"))
+        .insert_header(("Authorization", format!("Bearer {}", OWNER_KEY)))
+        .send_json(&serde_json::json!({ "step": 10 }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    server
+}
+
+async fn get_json(server: &TestServer, path: &str) -> Value {
+    let mut response = awc::Client::default().get(server.url(path)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK, "{}", path);
+    response.json().await.unwrap()
+}
+
+#[actix_web::test]
+async fn count_and_stats_are_rounded() {
+    let server = stepped_server().await;
+    let client = awc::Client::default();
+    for _ in 0..5 {
+        let path = format!("/badge/octocat/blog?key={}", common::BADGE_KEY);
+        client.get(server.url(&path)).send().await.unwrap().body().await.unwrap();
+    }
+
+    let count = get_json(&server, "/count/octocat/blog").await;
+    assert_eq!(count["view_count"], 10);
+    let stats = get_json(&server, "/api/stats/octocat").await;
+    assert_eq!(stats["total"], 20);
+    assert_eq!(stats["today"], 20);
+    assert_eq!(stats["this_week"], 20);
+}
+
+#[actix_web::test]
+async fn today_and_daily_counts_are_rounded() {
+    let server = stepped_server().await;
+
+    let today = get_json(&server, "/api/today?user=octocat").await;
+    assert_eq!(today["view_count"], 20);
+    for path in ["/history/octocat?days=7", "/api/stats/octocat/daily?days=7"] {
+        let history = get_json(&server, path).await;
+        assert_eq!(history["days"][6]["view_count"], 20, "{}", path);
+    }
+}
+
+#[actix_web::test]
+async fn aggregates_and_series_are_rounded() {
+    let server = stepped_server().await;
+    let today = chrono::Utc::now().date_naive();
+
+    let aggregate = get_json(&server, "/api/stats/octocat/aggregate?granularity=day&periods=1").await;
+    assert_eq!(aggregate["periods"][0]["view_count"], 20, "{}", aggregate);
+    let series = get_json(&server, &format!("/api/stats/octocat/series?from={}&to={}", today, today)).await;
+    assert_eq!(series["points"][0]["view_count"], 20, "{}", series);
+}
+
+#[actix_web::test]
+async fn graphql_counts_are_rounded() {
+    let server = stepped_server().await;
+    let query = r#"{
+        counter(id: "octocat") { viewCount }
+        pages(id: "octocat") { viewCount }
+        stats(id: "octocat") { total }
+        history(id: "octocat", days: 1) { days { viewCount } }
+    }"#;
+
+    let mut response = awc::Client::default()
+        .post(server.url("/graphql"))
+        .send_json(&serde_json::json!({ "query": query }))
+        .await
+        .unwrap();
+    let body: Value = response.json().await.unwrap();
+    assert!(body.get("errors").is_none(), "{}", body);
+    assert_eq!(body["data"]["counter"]["viewCount"], 20);
+    assert_eq!(body["data"]["pages"][0]["viewCount"], 20);
+    assert_eq!(body["data"]["stats"]["total"], 20);
+    assert_eq!(body["data"]["history"]["days"][0]["viewCount"], 20);
+}
+
+#[actix_web::test]
+async fn leaderboard_ranks_rounded_totals() {
+    let server = stepped_server().await;
+    let client = awc::Client::default();
+    for _ in 0..17 {
+        client.get(server.url(&badge_path("hubot", ""))).send().await.unwrap().body().await.unwrap();
+    }
+
+    let leaderboard = get_json(&server, "/api/leaderboard").await;
+    assert_eq!(
+        leaderboard,
+        serde_json::json!([
+            { "id": "octocat", "view_count": 20 },
+            { "id": "hubot", "view_count": 17 },
+            { "id": "me", "view_count": 0 },
+        ]),
+    );
+}
+
+#[actix_web::test]
+async fn live_updates_are_rounded() {
+    let server = stepped_server().await;
+    let client = awc::Client::default();
+
+    let mut events = client.get(server.url("/api/counters/octocat/events")).send().await.unwrap();
+    let first = events.next().await.unwrap().unwrap();
+    let first = std::str::from_utf8(&first).unwrap();
+    assert!(first.contains(r#""view_count":20"#), "{}", first);
+
+    let (_, mut socket) = client.ws(server.url("/api/live?ids=octocat")).connect().await.unwrap();
+    client.get(server.url(&badge_path("octocat", ""))).send().await.unwrap().body().await.unwrap();
+    let update = loop {
+        match socket.next().await.unwrap().unwrap() {
+            awc::ws::Frame::Text(text) => break serde_json::from_slice::<Value>(&text).unwrap(),
+            awc::ws::Frame::Ping(bytes) => socket.send(awc::ws::Message::Pong(bytes)).await.unwrap(),
+            _ => {},
+        }
+    };
+    assert_eq!(update["id"], "octocat");
+    assert_eq!(update["view_count"], 20);
+}
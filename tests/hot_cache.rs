@@ -0,0 +1,109 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{badge_message, badge_path, TestServer};
+use diesel::sql_types::BigInt;
+use diesel::{QueryableByName, RunQueryDsl};
+
+async fn view_count(server: &TestServer) -> i64 {
+    let mut response = awc::Client::default().get(server.url("/count/octocat")).send().await.unwrap();
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["view_count"].as_i64().unwrap()
+}
+
+#[actix_web::test]
+async fn hot_badges_are_served_while_the_database_is_stalled() {
+    let server = TestServer::start_with(&[("HOT_CACHE_MAX_STALE_MS", "60000"), ("DB_BUSY_TIMEOUT_MS", "10000")]).await;
+    let client = awc::Client::default();
+    let mut response = client.get(server.url(&badge_path("octocat", ""))).send().await.unwrap();
+    assert_eq!(badge_message(&response.body().await.unwrap()), "1");
+
+    let lock = server.lock_writes();
+    for _ in 0..20 {
+        let started = Instant::now();
+        let mut response = client.get(server.url(&badge_path("octocat", ""))).send().await.unwrap();
+        assert!(response.status().is_success());
+        response.body().await.unwrap();
+        assert!(started.elapsed() < Duration::from_millis(500), "hot badge waited on the database");
+    }
+    assert_eq!(view_count(&server).await, 1);
+    drop(lock);
+
+    // The refresh task applies every view served from the cache.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while view_count(&server).await != 21 {
+        assert!(Instant::now() < deadline, "pending views never reached the database");
+        actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+    }
+    let mut response = client.get(server.url(&badge_path("octocat", "&read_only=true"))).send().await.unwrap();
+    assert_eq!(badge_message(&response.body().await.unwrap()), "21");
+}
+
+#[actix_web::test]
+async fn hot_badges_count_every_request() {
+    let server = TestServer::start_with(&[("HOT_CACHE_MAX_STALE_MS", "60000")]).await;
+    let client = awc::Client::default();
+    for _ in 0..50 {
+        client.get(server.url(&badge_path("octocat", ""))).send().await.unwrap().body().await.unwrap();
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while view_count(&server).await != 50 {
+        assert!(Instant::now() < deadline, "hot cache lost views");
+        actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+    }
+    // Nothing is counted twice once the refresh settles.
+    actix_web::rt::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(view_count(&server).await, 50);
+}
+
+#[derive(QueryableByName)]
+struct StoredCount {
+    #[diesel(sql_type = BigInt)]
+    view_count: i64,
+}
+
+/// Serve one view from the hot cache while writes fail, so its refresh
+/// gives the view back, then let writes through again.
+async fn requeue_one_view(server: &TestServer) {
+    let client = awc::Client::default();
+    let mut response = client.get(server.url(&badge_path("octocat", ""))).send().await.unwrap();
+    assert_eq!(badge_message(&response.body().await.unwrap()), "1");
+
+    let lock = server.lock_writes();
+    let mut response = client.get(server.url(&badge_path("octocat", ""))).send().await.unwrap();
+    assert_eq!(badge_message(&response.body().await.unwrap()), "1");
+    actix_web::rt::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(view_count(server).await, 1);
+    drop(lock);
+}
+
+#[actix_web::test]
+async fn failed_refreshes_are_retried_without_another_view() {
+    let server = TestServer::start_with(&[("HOT_CACHE_MAX_STALE_MS", "500"), ("DB_BUSY_TIMEOUT_MS", "50")]).await;
+    requeue_one_view(&server).await;
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while view_count(&server).await != 2 {
+        assert!(Instant::now() < deadline, "the given back view was never retried");
+        actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[actix_web::test]
+async fn pending_hot_views_are_written_on_shutdown() {
+    let mut server = TestServer::start_with(&[
+        ("HOT_CACHE_MAX_STALE_MS", "60000"),
+        ("DB_BUSY_TIMEOUT_MS", "50"),
+        ("SHUTDOWN_TIMEOUT_SECS", "5"),
+    ])
+    .await;
+    requeue_one_view(&server).await;
+
+    server.stop();
+    let stored = diesel::sql_query("SELECT view_count FROM visitors WHERE id = 'octocat'")
+        .get_result::<StoredCount>(&mut server.connect())
+        .unwrap();
+    assert_eq!(stored.view_count, 2);
+}
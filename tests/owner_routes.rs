@@ -0,0 +1,88 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use common::{badge_message, badge_path, TestServer, ADMIN_TOKEN};
+
+const OWNER_KEY: &str = "vbo_test-owner-key";
+
+async fn server_with_counter() -> TestServer {
+    let server = TestServer::start().await;
+    awc::Client::default().get(server.url(&badge_path("octocat", ""))).send().await.unwrap();
+    server.insert_owner_key("octocat", OWNER_KEY);
+    server
+}
+
+#[actix_web::test]
+async fn owner_key_creates_and_revokes_a_skip_token() {
+    let server = server_with_counter().await;
+    let client = awc::Client::default();
+    let bearer = format!("Bearer {}", OWNER_KEY);
+
+    let mut response = client.post(server.url("/api/skip-token/octocat"))
+        .insert_header(("Authorization", bearer.as_str()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let created: serde_json::Value = response.json().await.unwrap();
+    let token = created["token"].as_str().unwrap().to_string();
+
+    // Views carrying the token are served but not counted.
+    let skipped = server.url(&badge_path("octocat", &format!("&skip={}", token)));
+    let mut response = client.get(&skipped).send().await.unwrap();
+    assert_eq!(badge_message(&response.body().await.unwrap()), "1");
+
+    let response = client.delete(server.url("/api/skip-token/octocat"))
+        .insert_header(("Authorization", bearer.as_str()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let mut response = client.get(&skipped).send().await.unwrap();
+    assert_eq!(badge_message(&response.body().await.unwrap()), "2");
+}
+
+#[actix_web::test]
+async fn skip_token_routes_reject_other_keys() {
+    let server = server_with_counter().await;
+    let client = awc::Client::default();
+    let admin = format!("Bearer {}", ADMIN_TOKEN);
+    for auth in [None, Some("Bearer wrong"), Some(admin.as_str())] {
+        let mut request = client.post(server.url("/api/skip-token/octocat"));
+        if let Some(auth) = auth {
+            request = request.insert_header(("Authorization", auth));
+        }
+        let response = request.send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{:?}", auth);
+    }
+}
+
+#[actix_web::test]
+async fn owner_key_sets_the_display_step() {
+    let server = server_with_counter().await;
+    let client = awc::Client::default();
+    let set_count = client.post(server.url("/admin/count/octocat"))
+        .insert_header(("Authorization", format!("Bearer {}", ADMIN_TOKEN)))
+        .send_json(&serde_json::json!({ "view_count": 1234 }))
+        .await
+        .unwrap();
+    assert_eq!(set_count.status(), StatusCode::OK);
+
+    let response = client.put(server.url("/api/display-step/octocat"))
+        .insert_header(("Authorization", format!("Bearer {}", OWNER_KEY)))
+        .send_json(&serde_json::json!({ "step": 100 }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let mut response = client.get(server.url(&badge_path("octocat", "&read_only=true"))).send().await.unwrap();
+    assert_eq!(badge_message(&response.body().await.unwrap()), "1200");
+
+    let response = client.put(server.url("/api/display-step/octocat"))
+        .insert_header(("Authorization", "Bearer wrong"))
+        .send_json(&serde_json::json!({ "step": 1 }))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
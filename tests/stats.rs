@@ -0,0 +1,105 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use chrono::Datelike;
+use common::{badge_path, TestServer};
+use serde_json::Value;
+
+async fn get_json(server: &TestServer, path: &str) -> (StatusCode, Value) {
+    let mut response = awc::Client::default().get(server.url(path)).send().await.unwrap();
+    (response.status(), response.json().await.unwrap())
+}
+
+async fn view(server: &TestServer, user: &str, times: usize) {
+    let client = awc::Client::default();
+    for _ in 0..times {
+        client.get(server.url(&badge_path(user, ""))).send().await.unwrap().body().await.unwrap();
+    }
+}
+
+#[actix_web::test]
+async fn stats_summarize_a_counter_without_counting() {
+    let server = TestServer::start().await;
+    view(&server, "octocat", 3).await;
+
+    for _ in 0..2 {
+        let (status, stats) = get_json(&server, "/api/stats/octocat").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(stats["user_id"], "octocat");
+        assert_eq!(stats["total"], 3);
+        assert_eq!(stats["today"], 3);
+        assert_eq!(stats["this_week"], 3);
+        assert!(stats["last_viewed_at"].as_i64().unwrap() > 0);
+    }
+    let (status, body) = get_json(&server, "/api/stats/nobody").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"], "counter not found");
+    let response = awc::Client::default()
+        .get(server.url("/api/stats/octocat?page=not%20a%20page"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn daily_stats_list_each_local_day() {
+    let server = TestServer::start().await;
+    view(&server, "octocat", 2).await;
+    let today = chrono::Utc::now().date_naive();
+
+    for path in ["/api/stats/octocat/daily?days=7", "/history/octocat?days=7"] {
+        let (status, history) = get_json(&server, path).await;
+        assert_eq!(status, StatusCode::OK, "{}", path);
+        assert_eq!(history["timezone"], "UTC");
+        let days = history["days"].as_array().unwrap();
+        assert_eq!(days.len(), 7, "{}", path);
+        assert_eq!(days[6]["day"], today.to_string());
+        assert_eq!(days[6]["view_count"], 2);
+        assert!(days[..6].iter().all(|day| day["view_count"] == 0), "{}", path);
+    }
+    let (status, today_count) = get_json(&server, "/api/today?user=octocat").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(today_count["day"], today.to_string());
+    assert_eq!(today_count["view_count"], 2);
+
+    let (status, _) = get_json(&server, "/api/stats/nobody/daily").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn aggregates_bucket_by_week_and_month() {
+    let server = TestServer::start().await;
+    view(&server, "octocat", 4).await;
+    let today = chrono::Utc::now().date_naive();
+
+    let (status, aggregate) = get_json(&server, "/api/stats/octocat/aggregate?granularity=week&periods=3").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(aggregate["granularity"], "week");
+    let periods = aggregate["periods"].as_array().unwrap();
+    assert_eq!(periods.len(), 3);
+    let monday = today - chrono::Duration::days(i64::from(today.weekday().num_days_from_monday()));
+    assert_eq!(periods[2]["start"], monday.to_string());
+    assert_eq!(periods[2]["view_count"], 4);
+
+    let (_, aggregate) = get_json(&server, "/api/stats/octocat/aggregate?granularity=month&periods=2").await;
+    let periods = aggregate["periods"].as_array().unwrap();
+    assert_eq!(periods.len(), 2);
+    assert_eq!(periods[1]["start"], today.format("%Y-%m-01").to_string());
+    assert_eq!(periods[1]["view_count"], 4);
+    assert_eq!(periods[0]["view_count"], 0);
+
+    // Days by default, 30 of them.
+    let (_, aggregate) = get_json(&server, "/api/stats/octocat/aggregate").await;
+    assert_eq!(aggregate["granularity"], "day");
+    assert_eq!(aggregate["periods"].as_array().unwrap().len(), 30);
+
+    let response = awc::Client::default()
+        .get(server.url("/api/stats/octocat/aggregate?granularity=year"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let (status, _) = get_json(&server, "/api/stats/nobody/aggregate").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}